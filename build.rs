@@ -0,0 +1,30 @@
+// build.rs
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    #[cfg(feature = "headers")]
+    write_header();
+}
+
+/// Generates the C header of the `ffi` module with cbindgen. Only the module is parsed, so the
+/// header declares nothing but the C interface.
+#[cfg(feature = "headers")]
+fn write_header() {
+    println!("cargo:rerun-if-changed=src/cycle_spitter/ffi.rs");
+    let crate_dir = std::env::var("CARGO_MANIFEST_DIR").expect("CARGO_MANIFEST_DIR is not set");
+    let config = cbindgen::Config {
+        language: cbindgen::Language::C,
+        include_guard: Some("CYCLESPITTER_H".to_string()),
+        header: Some(
+            "/* Generated by cbindgen from src/cycle_spitter/ffi.rs - do not edit. */".to_string(),
+        ),
+        cpp_compat: true,
+        ..Default::default()
+    };
+    cbindgen::Builder::new()
+        .with_src(format!("{}/src/cycle_spitter/ffi.rs", crate_dir))
+        .with_config(config)
+        .generate()
+        .expect("Failed to generate the C header")
+        .write_to_file(format!("{}/include/cyclespitter.h", crate_dir));
+}
@@ -0,0 +1,97 @@
+/* Generated by cbindgen from src/cycle_spitter/ffi.rs - do not edit. */
+
+#ifndef CYCLESPITTER_H
+#define CYCLESPITTER_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * The result of [`cyclespitter_split`]. Strings are NUL-terminated and null if absent.
+ */
+typedef struct CycleSpitterReport {
+  /**
+   * `true` if the output was generated.
+   */
+  bool ok;
+  /**
+   * The number of generated scanlines.
+   */
+  uintptr_t scanlines;
+  /**
+   * The generated assembler source.
+   */
+  char *output;
+  /**
+   * The number of warnings in `diagnostics`.
+   */
+  uintptr_t diagnostic_count;
+  /**
+   * The warnings, one per line, prefixed with the position of their input line
+   * (`input:line:column:`) where they refer to one.
+   */
+  char *diagnostics;
+  /**
+   * Why generation failed, if it did.
+   */
+  char *error;
+} CycleSpitterReport;
+
+/**
+ * Options of [`cyclespitter_split`]. Zero-initialized options, or a null pointer, select the
+ * defaults of the command line tool.
+ */
+typedef struct CycleSpitterOptions {
+  /**
+   * The cycles of a scanline; 0 for the 512 cycles of a 50 Hz ST.
+   */
+  uintptr_t cycles_per_line;
+  /**
+   * Fail if an instruction is missing from the cycle database instead of counting it as 0
+   * cycles.
+   */
+  bool strict;
+  /**
+   * Fail if a scanline exceeds its cycles instead of warning.
+   */
+  bool overflow_error;
+  /**
+   * The symbol receiving the scanline count, or null for `SCANLINES_CONSUMED`.
+   */
+  const char *label;
+} CycleSpitterOptions;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Splits the assembler source `input` into scanlines laid out by `template`, both given as
+ * file contents. Returns a report to be released with [`cyclespitter_report_free`]; it is
+ * never null.
+ *
+ * # Safety
+ * `input` and `template` must point to NUL-terminated strings, and `options` must be null or
+ * point to valid options whose `label` is null or a NUL-terminated string.
+ */
+struct CycleSpitterReport *cyclespitter_split(const char *input,
+                                              const char *template_,
+                                              const struct CycleSpitterOptions *options);
+
+/**
+ * Releases a report returned by [`cyclespitter_split`], including its strings. Null is
+ * ignored.
+ *
+ * # Safety
+ * `report` must be null or a report returned by [`cyclespitter_split`] that has not been
+ * released yet.
+ */
+void cyclespitter_report_free(struct CycleSpitterReport *report);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* CYCLESPITTER_H */
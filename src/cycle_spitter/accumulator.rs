@@ -1,7 +1,16 @@
 // src/cycle_spitter/accumulator.rs
 
+use crate::cycle_spitter::data::{DataDirective, parse_data_directive};
+use crate::cycle_spitter::database::CycleDatabase;
+use crate::cycle_spitter::diagnostics::{Diagnostic, DiagnosticKind};
+use crate::cycle_spitter::filler::Filler;
 use crate::cycle_spitter::helpers::extract_cycle_count;
-use crate::cycle_spitter::helpers::format_accumulated_instruction;
+use crate::cycle_spitter::helpers::{format_accumulated_instruction, format_remaining_instruction};
+use crate::cycle_spitter::models::{Instruction, LineKind};
+use crate::cycle_spitter::observer::{Observer, StderrObserver};
+use crate::cycle_spitter::options::{AnnotationFormat, Options, PaddingInstruction, PaddingStyle};
+use crate::cycle_spitter::pragma::{is_critical, parse_pragma};
+use crate::cycle_spitter::trace::{Decision, Deferral, Verdict};
 
 /// Parses and processes lines of assembly-like code to accumulate a target number of execution cycles,
 /// annotating the lines with cycle information, and adding padding (NOP instructions) if necessary
@@ -14,7 +23,7 @@ use crate::cycle_spitter::helpers::format_accumulated_instruction;
 /// - `start_index`: The starting index in the `lines` array to begin processing.
 /// - `target`: The target number of cycles to accumulate before stopping or padding.
 /// - `initial_offset`: The initial cycle count to start from, used for tracking execution states across blocks.
-/// - `number_re`: A compiled `Regex` to extract the cycle count from a line of code.
+/// - `db`: The cycle database used to look up instructions without an inline override.
 ///
 /// # Returns
 ///
@@ -26,11 +35,15 @@ use crate::cycle_spitter::helpers::format_accumulated_instruction;
 ///
 /// # Processing Details
 ///
+/// - Processing stops before a pragma line (see the `pragma` module); the rest of the target is padded.
 /// - Lines that are empty or start with a semicolon (`;`, typically used as a comment in assembly code) are added
 ///   to `chunk` unchanged, but they do not contribute to the cycle count.
-/// - For lines with extractable cycle information (as determined by `number_re` capturing group),
+/// - For lines with extractable cycle information (an inline `(n)` override or a database lookup),
 ///   the cycles are parsed and accumulated. If adding a line's cycle count would exceed the `target`,
 ///   padding with NOP (`no operation`) instructions is added to reach the `target`, and processing stops.
+/// - Data directives (`dc`, `ds`, `dcb` other than NOP blocks, `incbin`; see the `data` module) are
+///   kept without cycles and reported as a warning, since they do not belong in timed code. `even`
+///   is kept silently.
 /// - Lines where parsing fails or no cycle count is found are skipped.
 /// - If the accumulated cycles at the end of processing are less than `target`, the remaining cycles are padded
 ///   with additional NOP instructions.
@@ -44,17 +57,16 @@ use crate::cycle_spitter::helpers::format_accumulated_instruction;
 /// # Example
 ///
 /// ```rust
-/// use regex::Regex;
-/// use crate::cycle_spitter::accumulator::accumulate_chunk;
+/// use cycle_spitter::accumulator::accumulate_chunk;
+/// use cycle_spitter::database::CycleDatabase;
 ///
 /// let code_lines = vec![
-///     "MOVE.W A1,A2".to_string(),
+///     "move.w a1,a2".to_string(),
 ///     "; Comment line".to_string(),
-///     "ADD D1,D3".to_string(),
+///     "add.w d1,d3".to_string(),
 /// ];
 ///
-/// let regex = Regex::new(r"\b(\d+)\b").unwrap(); // Assume cycles are numbers in the line.
-/// let (chunk, next_index, accumulated_cycles) = accumulate_chunk(&code_lines, 0, 10, 0, &regex);
+/// let (chunk, next_index, accumulated_cycles) = accumulate_chunk(&code_lines, 0, 16, 0, &CycleDatabase::builtin());
 ///
 /// println!("Processed chunk: {:?}", chunk);
 /// println!("Next processing index: {}", next_index);
@@ -68,59 +80,233 @@ use crate::cycle_spitter::helpers::format_accumulated_instruction;
 /// - The cycle values and their annotations (e.g., `; 4 cycles`) are appended to the lines
 ///   for debugging and traceability purposes.
 /// - NOP instructions are assumed to consume 4 cycles each.
+/// - Uses the default [`Options`]; see [`accumulate_chunk_with`] to customize padding and annotations.
 pub fn accumulate_chunk(
     lines: &[String],
     start_index: usize,
     target: usize,
     initial_offset: usize,
+    db: &CycleDatabase,
 ) -> (Vec<String>, usize, usize) {
+    accumulate_chunk_with(
+        lines,
+        start_index,
+        target,
+        initial_offset,
+        db,
+        &Options::default(),
+    )
+}
+
+/// Same as [`accumulate_chunk`], but annotates instructions and emits padding according to
+/// the given `options` (annotation format, padding style and the CPU profile's NOP cost).
+/// Warnings are printed to standard error; see [`accumulate_chunk_with_observer`].
+pub fn accumulate_chunk_with(
+    lines: &[String],
+    start_index: usize,
+    target: usize,
+    initial_offset: usize,
+    db: &CycleDatabase,
+    options: &Options,
+) -> (Vec<String>, usize, usize) {
+    accumulate_chunk_with_observer(
+        lines,
+        start_index,
+        target,
+        initial_offset,
+        db,
+        options,
+        &mut StderrObserver,
+    )
+}
+
+/// Same as [`accumulate_chunk_with`], but reports warnings to `observer` (e.g. a
+/// [`Diagnostics`](crate::diagnostics::Diagnostics)) instead of standard error.
+pub fn accumulate_chunk_with_observer(
+    lines: &[String],
+    start_index: usize,
+    target: usize,
+    initial_offset: usize,
+    db: &CycleDatabase,
+    options: &Options,
+    observer: &mut dyn Observer,
+) -> (Vec<String>, usize, usize) {
+    let slot = Slot {
+        offset: initial_offset,
+        target,
+        scanline_end: options.cycles_per_line(),
+        hot: true,
+    };
+    let accumulated = accumulate(lines, start_index, slot, db, options);
+    for warning in &accumulated.warnings {
+        observer.on_diagnostic(warning);
+    }
+    (
+        accumulated.chunk.into_iter().map(|i| i.text).collect(),
+        accumulated.next_index,
+        accumulated.offset,
+    )
+}
+
+/// The outcome of accumulating one section's worth of user code.
+pub(crate) struct Accumulated {
+    /// The annotated and padded lines, with the index of their input line.
+    pub chunk: Vec<Instruction>,
+    /// The index of the first line that was not consumed.
+    pub next_index: usize,
+    /// The cycle offset after the chunk.
+    pub offset: usize,
+    /// The number of padding cycles that were inserted.
+    pub padding: usize,
+    /// Problems found while accumulating, e.g. accumulated cycles not matching the target.
+    pub warnings: Vec<Diagnostic>,
+    /// Every line accepted or deferred and the padding, in order (see the `trace` module).
+    pub decisions: Vec<Decision>,
+}
+
+/// The part of a scanline filled by one accumulation.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Slot {
+    /// The scanline offset at which the slot starts.
+    pub offset: usize,
+    /// The cycles to fill.
+    pub target: usize,
+    /// The scanline offset at which the scanline ends, for `AnnotationFormat::Remaining`.
+    pub scanline_end: usize,
+    /// Whether instructions marked `;@critical` may be placed.
+    pub hot: bool,
+}
+
+/// Performs the accumulation described in [`accumulate_chunk`] for `slot` and reports padding
+/// and warnings as data instead of printing them. Unless the slot is hot, processing also stops
+/// before an instruction marked `;@critical`.
+pub(crate) fn accumulate(
+    lines: &[String],
+    start_index: usize,
+    slot: Slot,
+    db: &CycleDatabase,
+    options: &Options,
+) -> Accumulated {
+    let Slot {
+        offset: initial_offset,
+        target,
+        scanline_end,
+        hot,
+    } = slot;
+    let nop_cycles = options.nop_cycles();
     let mut local_sum = initial_offset;
     // Pre-allocate chunk vector based on estimated size
-    // Assuming average instruction takes 4 cycles, allocate target/4 + some padding for comments
-    let estimated_size = (target / 4) + 10;
+    // Assuming average instruction takes one NOP's worth of cycles, allocate target/nop + some padding for comments
+    let estimated_size = (target / nop_cycles) + 10;
     let mut chunk = Vec::with_capacity(estimated_size);
+    let mut padding = 0;
     let mut i = start_index;
+    let context = lines.get(start_index).map_or("", String::as_str);
+    let mut warnings = Vec::new();
+    let mut decisions = Vec::new();
+    let free = |local_sum: usize| target - (local_sum - initial_offset);
+    let mut deferred = None;
+    let scheduler = options.packer().scheduler();
 
     while i < lines.len() && (local_sum - initial_offset) < target {
         let line = &lines[i];
+        // Pragmas take effect at the next scanline, so leave them for the generator
+        if parse_pragma(line).is_some() {
+            decisions.push(Decision {
+                index: Some(i),
+                cycles: 0,
+                free_before: free(local_sum),
+                free_after: free(local_sum),
+                verdict: Verdict::Deferred(Deferral::Pragma),
+            });
+            deferred = Some(i);
+            break;
+        }
+
         if line.trim().is_empty() || line.trim().starts_with(";") {
-            chunk.push(line.clone());
+            chunk.push(Instruction::other(line.clone(), Some(i)));
             i += 1;
             continue;
         }
 
         // Handle set lines first, before any cycle extraction
         if line.contains(" set ") {
-            chunk.push(line.clone());
+            chunk.push(Instruction::other(line.clone(), Some(i)));
             i += 1;
             continue;
         }
 
+        // Data directives take up space but no cycles
+        if let Some(directive) = parse_data_directive(line) {
+            if directive != DataDirective::Even {
+                warnings.push(
+                    Diagnostic::new(
+                        DiagnosticKind::Other,
+                        format!("Data directive inside a timed scanline: {}", line.trim()),
+                    )
+                    .at(i),
+                );
+            }
+            chunk.push(Instruction::other(line.clone(), Some(i)));
+            i += 1;
+            continue;
+        }
+
+        // Critical instructions wait for a hot section
+        if !hot && is_critical(line) {
+            decisions.push(Decision {
+                index: Some(i),
+                cycles: extract_cycle_count(line, db, |_| false).map_or(0, |c| c.total()),
+                free_before: free(local_sum),
+                free_after: free(local_sum),
+                verdict: Verdict::Deferred(Deferral::NotHot),
+            });
+            deferred = Some(i);
+            break;
+        }
+
         // Define a predicate for accumulator-specific lines.
         let skip_predicate = |l: &str| l.trim().starts_with(";") || l.contains(" equ ");
-        let cycle_option = extract_cycle_count(line, skip_predicate);
+        let cycle_option = extract_cycle_count(line, db, skip_predicate);
 
         if let Some(cycles) = cycle_option {
-            // For branches with multiple cycle counts, use the not-taken (first) value for basic accounting
+            // Branches count as not taken, register lists per register, ranges as their worst case
+            let base_cycles = cycles.total();
 
-            let base_cycles = if cycles.get_lookup().contains("reglist") {
-                cycles.base() + (cycles.cycles_per_reg() * cycles.get_reg_count())
-            } else {
-                cycles.base()
-            };
-
-            if (local_sum - initial_offset) + base_cycles > target {
-                let diff = target - (local_sum - initial_offset);
-                let num_nop = diff / 4; // each NOP is 4 cycles
-                for _ in 0..num_nop {
-                    let nop_line = format!("nop\t; 4 cycles\t[{}]", local_sum);
-                    chunk.push(nop_line);
-                    local_sum += 4;
-                }
+            if !scheduler.accepts(base_cycles, free(local_sum)) {
+                decisions.push(Decision {
+                    index: Some(i),
+                    cycles: base_cycles,
+                    free_before: free(local_sum),
+                    free_after: free(local_sum),
+                    verdict: Verdict::Deferred(Deferral::DoesNotFit),
+                });
+                deferred = Some(i);
                 break;
             }
-            let annotated = format_accumulated_instruction(line, &cycles, local_sum);
-            chunk.push(annotated);
+            let annotated = match options.annotation_format() {
+                AnnotationFormat::Remaining => {
+                    let after = local_sum + base_cycles;
+                    format_remaining_instruction(
+                        line,
+                        &cycles,
+                        (initial_offset + target).saturating_sub(after),
+                        scanline_end.saturating_sub(after),
+                    )
+                }
+                format => format_accumulated_instruction(line, &cycles, local_sum, format),
+            };
+            chunk.push(Instruction {
+                source: Some(i),
+                ..Instruction::timed(annotated, LineKind::User, base_cycles, local_sum)
+            });
+            decisions.push(Decision {
+                index: Some(i),
+                cycles: base_cycles,
+                free_before: free(local_sum),
+                free_after: free(local_sum + base_cycles),
+                verdict: Verdict::Accepted,
+            });
             local_sum += base_cycles;
         } else {
             i += 1;
@@ -131,25 +317,122 @@ pub fn accumulate_chunk(
 
     if (local_sum - initial_offset) < target {
         let diff = target - (local_sum - initial_offset);
-        let num_nop = diff / 4;
-        // Pre-extend the vector for the remaining NOPs
-        chunk.reserve(num_nop);
-        for _ in 0..num_nop {
-            let nop_line = format!("nop\t; 4 cycles\t[{}]", local_sum);
-            chunk.push(nop_line);
-            local_sum += 4;
+        let padded = push_padding(&mut chunk, diff, local_sum, context, options);
+        if padded > local_sum {
+            decisions.push(Decision {
+                index: deferred,
+                cycles: padded - local_sum,
+                free_before: diff,
+                free_after: free(padded),
+                verdict: Verdict::Padded,
+            });
         }
+        padding += padded - local_sum;
+        local_sum = padded;
     }
 
     if (local_sum - initial_offset) != target {
-        eprintln!(
-            "Warning: Accumulated cycles {} do not equal target {} starting at index {}.",
-            local_sum - initial_offset,
-            target,
-            start_index
+        warnings.push(
+            Diagnostic::new(
+                DiagnosticKind::CycleMismatch,
+                format!(
+                    "Accumulated cycles {} do not equal target {} starting at index {}.",
+                    local_sum - initial_offset,
+                    target,
+                    start_index
+                ),
+            )
+            .at(start_index),
         );
     }
-    (chunk, i, local_sum)
+    Accumulated {
+        chunk,
+        next_index: i,
+        offset: local_sum,
+        padding,
+        warnings,
+        decisions,
+    }
+}
+
+/// Splits `cycles` of padding into runs of the configured padding instruction and, for a
+/// remainder shorter than it, NOPs. Cycles left over after both are not padded.
+pub(crate) fn padding_runs(cycles: usize, options: &Options) -> Vec<(PaddingInstruction, usize)> {
+    let padding = options.padding_instruction();
+    let nop = PaddingInstruction::nop(options.nop_cycles());
+    let count = cycles / padding.cycles;
+    let remainder = cycles % padding.cycles;
+    let mut runs = vec![(padding, count)];
+    if runs[0].0 != nop && remainder >= nop.cycles {
+        runs.push((nop, remainder / options.nop_cycles()));
+    }
+    runs
+}
+
+/// Appends padding for up to `cycles` cycles to `chunk` in the configured padding style (see
+/// [`padding_runs`]) and returns the cycle offset after the padding. `context` is the first
+/// input line of the section, which seeds filler padding.
+pub(crate) fn push_padding(
+    chunk: &mut Vec<Instruction>,
+    cycles: usize,
+    mut local_sum: usize,
+    context: &str,
+    options: &Options,
+) -> usize {
+    for (padding, count) in padding_runs(cycles, options) {
+        match options.padding_style() {
+            PaddingStyle::Nop => {
+                // Pre-extend the vector for the remaining NOPs
+                chunk.reserve(count);
+                for _ in 0..count {
+                    let text = format!(
+                        "{}\t; {} cycles\t[{}]",
+                        padding.mnemonic, padding.cycles, local_sum
+                    );
+                    chunk.push(Instruction::timed(
+                        text,
+                        LineKind::Padding,
+                        padding.cycles,
+                        local_sum,
+                    ));
+                    local_sum += padding.cycles;
+                }
+            }
+            PaddingStyle::Dcb => {
+                if count > 0 {
+                    let text = format!(
+                        "{}\t; {} cycles\t[{}]",
+                        padding.block(count),
+                        count * padding.cycles,
+                        local_sum
+                    );
+                    chunk.push(Instruction::timed(
+                        text,
+                        LineKind::Padding,
+                        count * padding.cycles,
+                        local_sum,
+                    ));
+                    local_sum += count * padding.cycles;
+                }
+            }
+            PaddingStyle::Filler { seed } => {
+                let mut filler = Filler::new(seed, context, local_sum);
+                for (instruction, cycles) in
+                    filler.fill(count * padding.cycles, &padding, options.nop_cycles())
+                {
+                    let text = format!("{}\t; {} cycles\t[{}]", instruction, cycles, local_sum);
+                    chunk.push(Instruction::timed(
+                        text,
+                        LineKind::Padding,
+                        cycles,
+                        local_sum,
+                    ));
+                    local_sum += cycles;
+                }
+            }
+        }
+    }
+    local_sum
 }
 
 #[cfg(test)]
@@ -162,7 +445,8 @@ mod tests {
             "MOVE.W A1,A2 ; (2) cycles".to_string(),
             "ADD #2,D3 ; (4) cycles".to_string(),
         ];
-        let (chunk, next_index, accumulated) = accumulate_chunk(&lines, 0, 6, 0);
+        let (chunk, next_index, accumulated) =
+            accumulate_chunk(&lines, 0, 6, 0, &CycleDatabase::builtin());
 
         assert_eq!(chunk.len(), 2);
         assert!(chunk[0].contains("; (2) cycles"));
@@ -178,7 +462,8 @@ mod tests {
             "     ".to_string(),
             "ADD #2,D3 ; (4) cycles".to_string(),
         ];
-        let (chunk, next_index, accumulated) = accumulate_chunk(&lines, 0, 4, 0);
+        let (chunk, next_index, accumulated) =
+            accumulate_chunk(&lines, 0, 4, 0, &CycleDatabase::builtin());
 
         assert_eq!(chunk.len(), 3);
         assert_eq!(chunk[0], "; This is a comment");
@@ -194,7 +479,8 @@ mod tests {
             "MOVE.W A1,A2 ; (2) cycles".to_string(),
             "ADD #2,D3 ; (4) cycles".to_string(),
         ];
-        let (chunk, next_index, accumulated) = accumulate_chunk(&lines, 0, 14, 0);
+        let (chunk, next_index, accumulated) =
+            accumulate_chunk(&lines, 0, 14, 0, &CycleDatabase::builtin());
 
         assert!(chunk.iter().any(|line| line.contains("nop\t; 4 cycles")));
         assert_eq!(next_index, 2);
@@ -207,7 +493,8 @@ mod tests {
             "MOVE.W A1,A2 ; (2) cycles".to_string(),
             "ADD #2,D3 ; (6) cycles".to_string(),
         ];
-        let (chunk, next_index, accumulated) = accumulate_chunk(&lines, 0, 6, 0);
+        let (chunk, next_index, accumulated) =
+            accumulate_chunk(&lines, 0, 6, 0, &CycleDatabase::builtin());
 
         assert!(chunk.iter().any(|line| line.contains("MOVE.W A1,A2")));
         assert!(!chunk.iter().any(|line| line.contains("ADD #2,D3")));
@@ -215,13 +502,152 @@ mod tests {
         assert_eq!(accumulated, 6);
     }
 
+    #[test]
+    fn test_dcb_padding_style() {
+        let lines = vec!["MOVE.W A1,A2 ; (4) cycles".to_string()];
+        let options = Options::builder().padding_style(PaddingStyle::Dcb).build();
+        let (chunk, next_index, accumulated) =
+            accumulate_chunk_with(&lines, 0, 16, 0, &CycleDatabase::builtin(), &options);
+
+        assert_eq!(chunk.len(), 2);
+        assert_eq!(chunk[1], "dcb.w\t3,$4e71\t; 12 cycles\t[4]");
+        assert_eq!(next_index, 1);
+        assert_eq!(accumulated, 16);
+    }
+
+    #[test]
+    fn test_remaining_cycles_annotation() {
+        let db = CycleDatabase::builtin();
+        let lines = vec!["move.w d0,(a1)".to_string(), "move.w d0,d1".to_string()];
+        let options = Options::builder()
+            .annotation_format(AnnotationFormat::Remaining)
+            .build();
+        let slot = Slot {
+            offset: 100,
+            target: 16,
+            scanline_end: 240,
+            hot: true,
+        };
+        let accumulated = accumulate(&lines, 0, slot, &db, &options);
+        let texts: Vec<&str> = accumulated.chunk.iter().map(|i| i.text.as_str()).collect();
+        assert_eq!(
+            texts[..2],
+            [
+                "move.w d0,(a1)\t;\t(8)\t[left: 8/132]",
+                "move.w d0,d1\t;\t(4)\t[left: 4/128]"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_data_directives_take_no_cycles() {
+        let db = CycleDatabase::builtin();
+        let lines = vec!["ds.w 4".to_string(), "move.w d0,d1".to_string()];
+        let slot = Slot {
+            offset: 0,
+            target: 4,
+            scanline_end: 512,
+            hot: true,
+        };
+        let accumulated = accumulate(&lines, 0, slot, &db, &Options::default());
+
+        assert_eq!(accumulated.chunk[0], Instruction::other("ds.w 4", Some(0)));
+        assert_eq!(accumulated.chunk[1].source, Some(1));
+        assert_eq!(accumulated.next_index, 2);
+        assert_eq!(accumulated.padding, 0);
+        assert_eq!(
+            accumulated.warnings,
+            [Diagnostic::new(
+                DiagnosticKind::Other,
+                "Data directive inside a timed scanline: ds.w 4"
+            )
+            .at(0)]
+        );
+    }
+
+    #[test]
+    fn test_filler_padding_style() {
+        let db = CycleDatabase::builtin();
+        let lines = vec!["MOVE.W A1,A2 ; (4) cycles".to_string()];
+        let options = Options::builder()
+            .padding_style(PaddingStyle::Filler { seed: 7 })
+            .build();
+        let (chunk, _, accumulated) = accumulate_chunk_with(&lines, 0, 64, 0, &db, &options);
+        let (again, _, _) = accumulate_chunk_with(&lines, 0, 64, 0, &db, &options);
+
+        assert_eq!(accumulated, 64);
+        assert_eq!(chunk, again);
+        assert!(chunk[1..].iter().any(|l| !l.starts_with("nop")));
+        let padded: usize = chunk[1..]
+            .iter()
+            .map(|l| l.split("; ").nth(1).unwrap().split(' ').next().unwrap())
+            .map(|cycles| cycles.parse::<usize>().unwrap())
+            .sum();
+        assert_eq!(padded, 60);
+    }
+
+    #[test]
+    fn test_stops_before_pragma() {
+        let lines = vec![
+            "MOVE.W A1,A2 ; (4) cycles".to_string(),
+            ";@raw-scanline".to_string(),
+            "ADD #2,D3 ; (4) cycles".to_string(),
+        ];
+        let (chunk, next_index, accumulated) =
+            accumulate_chunk(&lines, 0, 12, 0, &CycleDatabase::builtin());
+
+        assert_eq!(chunk.len(), 3);
+        assert!(chunk[1].starts_with("nop"));
+        assert_eq!(next_index, 1);
+        assert_eq!(accumulated, 12);
+    }
+
     #[test]
     fn test_mismatch_warning() {
         let lines = vec!["MOVE.W A1,A2 ; (2) cycles".to_string()];
-        let (chunk, next_index, accumulated) = accumulate_chunk(&lines, 0, 10, 0);
+        let (chunk, next_index, accumulated) =
+            accumulate_chunk(&lines, 0, 10, 0, &CycleDatabase::builtin());
 
         assert!(chunk.iter().any(|line| line.contains("nop\t; 4 cycles")));
         assert_eq!(next_index, 1);
         assert_eq!(accumulated, 10);
     }
+
+    #[test]
+    fn test_records_packing_decisions() {
+        let db = CycleDatabase::builtin();
+        let lines = vec![
+            "move.w d0,d1".to_string(),
+            "lsl.w #1,d2 ;@critical".to_string(),
+        ];
+        let slot = Slot {
+            offset: 0,
+            target: 12,
+            scanline_end: 512,
+            hot: false,
+        };
+        let accumulated = accumulate(&lines, 0, slot, &db, &Options::default());
+        let decisions: Vec<_> = accumulated
+            .decisions
+            .iter()
+            .map(|d| (d.index, d.cycles, d.free_before, d.free_after, d.verdict))
+            .collect();
+        assert_eq!(
+            decisions,
+            [
+                (Some(0), 4, 12, 8, Verdict::Accepted),
+                (Some(1), 12, 8, 8, Verdict::Deferred(Deferral::NotHot)),
+                (Some(1), 8, 8, 0, Verdict::Padded),
+            ]
+        );
+
+        let slot = Slot {
+            hot: true,
+            target: 16,
+            ..slot
+        };
+        let accumulated = accumulate(&lines, 0, slot, &db, &Options::default());
+        assert_eq!(accumulated.decisions.len(), 2);
+        assert_eq!(accumulated.decisions[1].verdict, Verdict::Accepted);
+    }
 }
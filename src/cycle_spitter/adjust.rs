@@ -0,0 +1,174 @@
+// src/cycle_spitter/adjust.rs
+
+//! Project-local corrections of the cycle database.
+//!
+//! Timings measured on real hardware now and then disagree with the database, e.g. where bus
+//! contention rounds an access up. Rather than forking the database, a project can keep the
+//! corrections in an adjustment file, one rule per line:
+//!
+//! ```text
+//! # Measured on a 520 STF
+//! adjust "move.l xxx.l,xxx.l" +4
+//! adjust "divs.w dn,dn" -2   ; best case of the test pattern
+//! ```
+//!
+//! The quoted instruction is normalized like user code, so `adjust "move.l $1234,$5678" +4`
+//! names the same entry. The adjustment is added to the entry's values after every lookup - to
+//! the base of register list instructions, to all values otherwise - and never takes a value
+//! below zero. Lines starting with `#` or `;` are comments, and a later rule for the same entry
+//! replaces an earlier one.
+
+use crate::cycle_spitter::cycles::normalize_line_ext;
+use crate::cycle_spitter::database::CycleDatabase;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::error::Error;
+
+static ADJUST_RE: Lazy<Regex> = Lazy::new(|| {
+    // Matches an adjustment rule
+    // Example matches: `adjust "move.l xxx.l,xxx.l" +4`, `adjust "divs.w dn,dn" -2 ; measured`
+    // - `^adjust\s+`: The keyword
+    // - `"(?P<instruction>[^"]+)"`: The quoted instruction or normalized key
+    // - `\s+(?P<delta>[+-]\d+)`: The signed number of cycles to add
+    // - `\s*(?:[#;].*)?$`: An optional trailing comment
+    Regex::new(r#"^adjust\s+"(?P<instruction>[^"]+)"\s+(?P<delta>[+-]\d+)\s*(?:[#;].*)?$"#).unwrap()
+});
+
+/// A correction of one database entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Adjustment {
+    /// The normalized instruction.
+    pub key: String,
+    /// The cycles added to the entry's values.
+    pub delta: isize,
+}
+
+/// Parses an adjustment file (see the module documentation). Every rule must name an entry of
+/// `db`, as a rule for an unknown instruction is most likely a typo.
+///
+/// # Errors
+/// If a line is not a rule or a comment, or a rule names an instruction missing from `db`. The
+/// message names the line, counting from 1.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::adjust::parse_adjustments;
+/// use cycle_spitter::database::CycleDatabase;
+///
+/// let db = CycleDatabase::builtin();
+/// let rules = parse_adjustments("adjust \"move.l $1234,$5678\" +4", &db).unwrap();
+/// assert_eq!(rules[0].key, "move.l xxx.l,xxx.l");
+/// assert_eq!(rules[0].delta, 4);
+/// ```
+pub fn parse_adjustments(
+    content: &str,
+    db: &CycleDatabase,
+) -> Result<Vec<Adjustment>, Box<dyn Error>> {
+    let mut adjustments = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        let caps = ADJUST_RE.captures(line).ok_or_else(|| {
+            format!(
+                "line {}: expected `adjust \"<instruction>\" +<cycles>`, found '{}'",
+                index + 1,
+                line
+            )
+        })?;
+        let key = normalize_line_ext(&caps["instruction"]).0;
+        if !db.contains(&key) {
+            return Err(
+                format!("line {}: '{}' is not in the cycle database", index + 1, key).into(),
+            );
+        }
+        let delta = caps["delta"].parse().map_err(|e| {
+            format!(
+                "line {}: invalid cycles '{}': {}",
+                index + 1,
+                &caps["delta"],
+                e
+            )
+        })?;
+        adjustments.push(Adjustment { key, delta });
+    }
+    Ok(adjustments)
+}
+
+/// Parses the adjustment file `content` and applies its rules to `db`.
+///
+/// # Errors
+/// For the reasons listed at [`parse_adjustments`]; `db` is left unchanged then.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::adjust::apply_adjustments;
+/// use cycle_spitter::database::CycleDatabase;
+///
+/// let mut db = CycleDatabase::builtin();
+/// apply_adjustments("adjust \"move.l $1234,$5678\" +4", &mut db).unwrap();
+/// assert_eq!(db.adjustment("move.l xxx.l,xxx.l"), Some(4));
+/// ```
+pub fn apply_adjustments(content: &str, db: &mut CycleDatabase) -> Result<(), Box<dyn Error>> {
+    for adjustment in parse_adjustments(content, db)? {
+        db.adjust(adjustment.key, adjustment.delta);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_adjustments() {
+        let db = CycleDatabase::builtin();
+        let content = "\
+# Measured on a 520 STF
+
+adjust \"move.l xxx.l,xxx.l\" +4 ; bus contention
+adjust \"movem.l (a0)+,d0-d3\" -2
+";
+        let rules = parse_adjustments(content, &db).unwrap();
+        assert_eq!(
+            rules,
+            [
+                Adjustment {
+                    key: "move.l xxx.l,xxx.l".to_string(),
+                    delta: 4
+                },
+                Adjustment {
+                    key: "movem.l (an)+,reglist".to_string(),
+                    delta: -2
+                },
+            ]
+        );
+
+        let error = parse_adjustments("adjust move.w d0,d1 +4", &db).unwrap_err();
+        assert!(error.to_string().starts_with("line 1: expected"));
+        let error = parse_adjustments("\nadjust \"frobnicate d0\" +4", &db).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "line 2: 'frobnicate.w dn' is not in the cycle database"
+        );
+    }
+
+    #[test]
+    fn test_adjustments_apply_after_lookup() {
+        let mut db = CycleDatabase::builtin();
+        db.adjust("move.l xxx.l,xxx.l", 4);
+        db.adjust("movem.l (an)+,reglist", -2);
+        db.adjust("move.w dn,dn", -8);
+
+        assert_eq!(db.cycle_count("move.l xxx.l,xxx.l", 0).unwrap().total(), 40);
+        // Only the base of a register list instruction is adjusted.
+        assert_eq!(
+            db.cycle_count("movem.l (an)+,reglist", 4).unwrap().total(),
+            42
+        );
+        assert_eq!(db.cycle_count("move.w dn,dn", 0).unwrap().total(), 0);
+        // The stored entry is left alone.
+        assert_eq!(db.get("move.l xxx.l,xxx.l"), Some(vec![36]));
+    }
+}
@@ -0,0 +1,157 @@
+// src/cycle_spitter/annotate.rs
+
+//! Annotating a source with cycles, without splitting it into scanlines.
+//!
+//! [`annotate`] appends the cycles of every instruction to its line, as generation does for
+//! user code, and counts the cycle offset from the start of the source. It is the quick look
+//! at what a piece of code costs before it is laid out in scanlines, e.g. in an editor or the
+//! browser playground (see the `wasm` module). Block directives are left as they are, so the
+//! offsets of a `REPT` body are those of its first repetition.
+//!
+//! [`lookup_keys`] shows the step before: the key every instruction is looked up with, for
+//! finding out why one is missing from the cycle database.
+
+use crate::cycle_spitter::block::parse_directive;
+use crate::cycle_spitter::cycles::normalize_line_ext;
+use crate::cycle_spitter::data::parse_data_directive;
+use crate::cycle_spitter::database::CycleDatabase;
+use crate::cycle_spitter::helpers::{
+    extract_cycle_count, format_accumulated_instruction, strip_annotations,
+};
+use crate::cycle_spitter::options::AnnotationFormat;
+use crate::cycle_spitter::regexes::REG_NUMBER_RE;
+use crate::cycle_spitter::source::normalize_line_endings;
+
+/// Returns `content` with the cycles of every instruction appended in `format`, followed by its
+/// offset from the start. Annotations of a previous run are replaced; comments, `set`/`equ`
+/// lines, data and block directives are kept unchanged.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::annotate::annotate;
+/// use cycle_spitter::database::CycleDatabase;
+/// use cycle_spitter::options::AnnotationFormat;
+///
+/// let db = CycleDatabase::builtin();
+/// let annotated = annotate("move.w d0,d1\nmove.w d1,d2", &db, AnnotationFormat::Compact);
+/// assert_eq!(annotated, "move.w d0,d1\t;\t(4)\nmove.w d1,d2\t;\t(4)\t[4]");
+/// ```
+pub fn annotate(content: &str, db: &CycleDatabase, format: AnnotationFormat) -> String {
+    let mut offset = 0;
+    normalize_line_endings(content)
+        .lines()
+        .map(|line| {
+            let line = strip_annotations(line.trim_end());
+            if parse_directive(line).is_some() || parse_data_directive(line).is_some() {
+                return line.to_string();
+            }
+            let skip_predicate =
+                |l: &str| l.trim().is_empty() || l.trim().starts_with(';') || l.contains(" equ ");
+            match extract_cycle_count(line, db, skip_predicate) {
+                Some(_) if line.contains(" set ") => line.to_string(),
+                Some(cycles) => {
+                    let annotated = format_accumulated_instruction(line, &cycles, offset, format);
+                    offset += cycles.total();
+                    annotated
+                }
+                None => line.to_string(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The normalized form of an instruction line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LookupKey {
+    /// The line of the source, counted from 1.
+    pub line_number: usize,
+    /// The code of the line, without its comment.
+    pub code: String,
+    /// The key the cycle database is looked up with.
+    pub normalized: String,
+    /// Whether the cycle database has an entry for the key.
+    pub known: bool,
+}
+
+/// Returns the lookup key of every instruction in `content`, skipping the lines [`annotate`]
+/// leaves unchanged and those with an inline cycle override.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::annotate::lookup_keys;
+/// use cycle_spitter::database::CycleDatabase;
+///
+/// let db = CycleDatabase::builtin();
+/// let keys = lookup_keys("; effect\n\tmove.w (a0)+,d1\n\tfrobnicate d0", &db);
+/// assert_eq!(keys[0].normalized, "move.w (an)+,dn");
+/// assert!(keys[0].known);
+/// assert_eq!((keys[1].line_number, keys[1].known), (3, false));
+/// ```
+pub fn lookup_keys(content: &str, db: &CycleDatabase) -> Vec<LookupKey> {
+    normalize_line_endings(content)
+        .lines()
+        .enumerate()
+        .filter_map(|(index, line)| {
+            let line = strip_annotations(line);
+            let code = line.split(';').next().unwrap_or("").trim();
+            // Lines with an inline `(n)` override are not looked up.
+            if code.is_empty()
+                || REG_NUMBER_RE.is_match(line)
+                || code.contains(" equ ")
+                || code.contains(" set ")
+                || parse_directive(code).is_some()
+                || parse_data_directive(code).is_some()
+            {
+                return None;
+            }
+            let normalized = normalize_line_ext(code).0;
+            if normalized.is_empty() {
+                return None;
+            }
+            Some(LookupKey {
+                line_number: index + 1,
+                code: code.to_string(),
+                known: db.contains(&normalized),
+                normalized,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_annotate_skips_directives_and_replaces_annotations() {
+        let db = CycleDatabase::builtin();
+        let content =
+            "; effect\r\nrept 2\n\tmove.l d0,d1\t;\t(4)\t[12]\nendr\n\tdc.w 1\n\tnop ; (8)";
+        assert_eq!(
+            annotate(content, &db, AnnotationFormat::Full),
+            "; effect\nrept 2\n\tmove.l d0,d1\t;\t(4)\tmove.l dn,dn\nendr\n\tdc.w 1\n\tnop ; (8)\t;\t(8*ovr)\tn/a\t[4]"
+        );
+    }
+
+    #[test]
+    fn test_lookup_keys_of_instructions_only() {
+        let db = CycleDatabase::builtin();
+        // Generated annotations are stripped, inline overrides are not looked up
+        let content = "; effect\nSCREEN equ $78000\nrept 2\n.loop:\tmove.l (a0)+,d1\t;\t(12)\t[12]\n\
+                       endr\n\tdc.w 1\n\tnop ; (8)\n\tlsl.w #2,d0 ; shift\n\tfrobnicate d0";
+        let keys: Vec<(usize, String, String, bool)> = lookup_keys(content, &db)
+            .into_iter()
+            .map(|k| (k.line_number, k.code, k.normalized, k.known))
+            .collect();
+        let expected = [
+            (4, ".loop:\tmove.l (a0)+,d1", "move.l (an)+,dn", true),
+            (8, "lsl.w #2,d0", "lsl.w #xxx,dn", true),
+            (9, "frobnicate d0", "frobnicate.w dn", false),
+        ];
+        assert_eq!(
+            keys,
+            expected.map(|(n, code, key, known)| (n, code.to_string(), key.to_string(), known))
+        );
+    }
+}
@@ -0,0 +1,215 @@
+// src/cycle_spitter/baseline.rs
+
+//! Tracking the padding efficiency of a generation across runs.
+//!
+//! The [`RunStats`] of a run are stored as JSON (a *baseline*) and compared against later runs,
+//! so refactoring the input of an effect cannot silently cost scanlines or add padding. A metric
+//! regresses if it grows by more than the given threshold (in percent of the baseline value);
+//! any additional overflowing scanline is a regression regardless of the threshold.
+
+use crate::cycle_spitter::generator::ScanlineUsage;
+use crate::cycle_spitter::options::Options;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// The efficiency metrics of one run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct RunStats {
+    /// The number of scanlines generated.
+    pub scanlines: usize,
+    /// The cycles spent on padding, inside sections and at the end of scanlines.
+    pub padding_cycles: usize,
+    /// The number of scanlines exceeding their budget.
+    pub overflows: usize,
+}
+
+impl RunStats {
+    /// Computes the statistics of a generation from its usage. The budget of every scanline is
+    /// taken from `options`, following the frame layout if there is one.
+    pub fn from_usage(usage: &[ScanlineUsage], options: &Options) -> Self {
+        RunStats {
+            scanlines: usage.len(),
+            padding_cycles: usage.iter().map(|u| u.padding_cycles()).sum(),
+            overflows: usage
+                .iter()
+                .enumerate()
+                .filter(|(i, u)| u.total_cycles() > options.cycles_at(options.start_line() + i))
+                .count(),
+        }
+    }
+
+    /// Reads statistics stored with [`RunStats::save`].
+    ///
+    /// # Errors
+    /// If the file cannot be read or is not a valid baseline.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read baseline '{}': {}", path.display(), e))?;
+        serde_json::from_str(&content)
+            .map_err(|e| format!("Invalid baseline '{}': {}", path.display(), e).into())
+    }
+
+    /// Writes the statistics to `path` as JSON.
+    ///
+    /// # Errors
+    /// If the file cannot be written.
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let content = serde_json::to_string_pretty(self)?;
+        fs::write(path, content + "\n")
+            .map_err(|e| format!("Failed to write baseline '{}': {}", path.display(), e).into())
+    }
+}
+
+/// A metric that got worse than the baseline allows.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Regression {
+    /// The name of the metric.
+    pub metric: &'static str,
+    /// The value stored in the baseline.
+    pub baseline: usize,
+    /// The value of the current run.
+    pub current: usize,
+}
+
+impl fmt::Display for Regression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} regressed from {} to {}",
+            self.metric, self.baseline, self.current
+        )?;
+        if self.baseline > 0 {
+            let percent =
+                (self.current as f64 - self.baseline as f64) * 100.0 / self.baseline as f64;
+            write!(f, " (+{:.1}%)", percent)?;
+        }
+        Ok(())
+    }
+}
+
+/// Compares `current` against `baseline` and returns the metrics that regressed.
+///
+/// # Arguments
+/// - `baseline`: The stored statistics.
+/// - `current`: The statistics of the current run.
+/// - `threshold`: The growth in percent of the baseline value tolerated for scanlines and
+///   padding cycles.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::baseline::{RunStats, compare};
+///
+/// let baseline = RunStats { scanlines: 100, padding_cycles: 1000, overflows: 0 };
+/// let current = RunStats { scanlines: 100, padding_cycles: 1040, overflows: 0 };
+/// assert!(compare(&baseline, &current, 5.0).is_empty());
+/// assert_eq!(compare(&baseline, &current, 2.0)[0].metric, "padding cycles");
+/// ```
+pub fn compare(baseline: &RunStats, current: &RunStats, threshold: f64) -> Vec<Regression> {
+    let exceeds = |base: usize, now: usize| now as f64 > base as f64 * (1.0 + threshold / 100.0);
+    let metrics = [
+        (
+            "scanlines",
+            baseline.scanlines,
+            current.scanlines,
+            exceeds(baseline.scanlines, current.scanlines),
+        ),
+        (
+            "padding cycles",
+            baseline.padding_cycles,
+            current.padding_cycles,
+            exceeds(baseline.padding_cycles, current.padding_cycles),
+        ),
+        (
+            "overflows",
+            baseline.overflows,
+            current.overflows,
+            current.overflows > baseline.overflows,
+        ),
+    ];
+    metrics
+        .into_iter()
+        .filter(|(_, _, _, regressed)| *regressed)
+        .map(|(metric, baseline, current, _)| Regression {
+            metric,
+            baseline,
+            current,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cycle_spitter::generator::SectionUsage;
+
+    fn usage(user: usize, padding: usize, tail: usize) -> ScanlineUsage {
+        ScanlineUsage {
+            sections: vec![SectionUsage {
+                label: "s".to_string(),
+                template_cycles: 8,
+                user_cycles: user,
+                padding_cycles: padding,
+            }],
+            tail_padding: tail,
+        }
+    }
+
+    #[test]
+    fn test_stats_from_usage() {
+        let options = Options::builder().cycles_per_line(32).build();
+        let stats = RunStats::from_usage(&[usage(16, 4, 4), usage(28, 0, 0)], &options);
+        assert_eq!(
+            stats,
+            RunStats {
+                scanlines: 2,
+                padding_cycles: 8,
+                overflows: 1
+            }
+        );
+    }
+
+    #[test]
+    fn test_compare() {
+        let baseline = RunStats {
+            scanlines: 10,
+            padding_cycles: 0,
+            overflows: 1,
+        };
+        let same = compare(&baseline, &baseline, 0.0);
+        assert!(same.is_empty());
+
+        let worse = RunStats {
+            scanlines: 11,
+            padding_cycles: 4,
+            overflows: 2,
+        };
+        let regressions = compare(&baseline, &worse, 10.0);
+        let metrics: Vec<&str> = regressions.iter().map(|r| r.metric).collect();
+        assert_eq!(metrics, ["padding cycles", "overflows"]);
+        assert_eq!(
+            compare(&baseline, &worse, 5.0)[0].to_string(),
+            "scanlines regressed from 10 to 11 (+10.0%)"
+        );
+        assert_eq!(
+            regressions[0].to_string(),
+            "padding cycles regressed from 0 to 4"
+        );
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir().join(format!("baseline-{}.json", std::process::id()));
+        let stats = RunStats {
+            scanlines: 3,
+            padding_cycles: 40,
+            overflows: 0,
+        };
+        stats.save(&path).unwrap();
+        assert_eq!(RunStats::load(&path).unwrap(), stats);
+        fs::remove_file(&path).unwrap();
+        assert!(RunStats::load(&path).is_err());
+    }
+}
@@ -1,5 +1,11 @@
 // src/cycle_spitter/block.rs
 
+use crate::cycle_spitter::cycles::normalize_line_ext;
+use crate::cycle_spitter::database::CycleDatabase;
+use crate::cycle_spitter::literal::{leading_count, parse_literal};
+use std::error::Error;
+use std::fmt;
+
 /// Processes a block of strings to handle nested REPT (repeat) and ENDR (end repeat) directives.
 ///
 /// This function recursively processes a list of assembly-like textual instructions and expands
@@ -17,11 +23,13 @@
 ///   correct position in the parent recursion or in the remaining lines.
 ///
 /// # Behavior
-/// - Lines starting with "REPT <count>":
+/// Directives are recognized with [`parse_directive`], so they may be written in any case, with a
+/// leading dot and after a label; the label is kept as a line of its own.
+/// - "REPT <count>" directives:
 ///   - If `<count>` is a valid integer, the function recursively processes the subsequent lines
 ///     until the corresponding "ENDR" directive.
 ///   - The resulting block is repeated `<count>` times, and all repeated lines are added to the result.
-/// - Lines starting with "ENDR":
+/// - "ENDR" directives:
 ///   - Indicates the end of a REPT block and stops further processing for the current recursive call.
 /// - Any other line:
 ///   - Added directly to the result as-is.
@@ -59,33 +67,632 @@
 /// This function does not perform checks for malformed or mismatched "REPT"/"ENDR" directives,
 /// and it is the caller's responsibility to ensure valid input.
 pub fn process_block(lines: &[String], start_index: usize) -> (Vec<String>, usize) {
+    let (result, _, index) = expand(lines, start_index, 0, &ExpansionLimits::unlimited())
+        .expect("unlimited expansion cannot fail");
+    (result, index)
+}
+
+/// Limits on `REPT` expansion, so a typo like `rept 100000000` fails fast instead of
+/// exhausting memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExpansionLimits {
+    /// The maximum number of lines after expansion.
+    pub max_lines: usize,
+    /// The maximum nesting depth of `REPT` blocks.
+    pub max_depth: usize,
+    /// The maximum size of the expanded lines in bytes, counting one byte per line break.
+    pub max_bytes: usize,
+}
+
+impl ExpansionLimits {
+    /// The default maximum number of expanded lines.
+    pub const DEFAULT_MAX_LINES: usize = 1_000_000;
+    /// The default maximum nesting depth.
+    pub const DEFAULT_MAX_DEPTH: usize = 32;
+    /// The default maximum expanded size (64 MiB).
+    pub const DEFAULT_MAX_BYTES: usize = 64 << 20;
+
+    /// Limits that never trigger.
+    pub fn unlimited() -> Self {
+        ExpansionLimits {
+            max_lines: usize::MAX,
+            max_depth: usize::MAX,
+            max_bytes: usize::MAX,
+        }
+    }
+}
+
+impl Default for ExpansionLimits {
+    fn default() -> Self {
+        ExpansionLimits {
+            max_lines: Self::DEFAULT_MAX_LINES,
+            max_depth: Self::DEFAULT_MAX_DEPTH,
+            max_bytes: Self::DEFAULT_MAX_BYTES,
+        }
+    }
+}
+
+/// Expands the `REPT` blocks of `lines` like [`process_block`], but fails once the expansion
+/// exceeds `limits`. Limits are checked before a block is repeated, so oversized expansions
+/// fail without allocating them.
+///
+/// # Errors
+/// If the expansion exceeds one of the limits. The message names the offending `REPT` line
+/// (counting from 1 within `lines`).
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::block::{ExpansionLimits, expand_block};
+///
+/// let lines: Vec<String> = ["rept 100000000", "nop", "endr"].map(String::from).to_vec();
+/// let error = expand_block(&lines, &ExpansionLimits::default()).unwrap_err();
+/// assert!(error.to_string().contains("more than 1000000 lines"));
+/// ```
+pub fn expand_block(
+    lines: &[String],
+    limits: &ExpansionLimits,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    Ok(expand(lines, 0, 0, limits)?.0)
+}
+
+/// Same as [`expand_block`], but also returns the index in `lines` every expanded line came
+/// from (see [`SourceMap::remap`](crate::location::SourceMap::remap)).
+///
+/// # Errors
+/// See [`expand_block`].
+pub fn expand_block_with_origins(
+    lines: &[String],
+    limits: &ExpansionLimits,
+) -> Result<(Vec<String>, Vec<usize>), Box<dyn Error>> {
+    let (result, origins, _) = expand(lines, 0, 0, limits)?;
+    Ok((result, origins))
+}
+
+/// The expansion shared by [`process_block`] and [`expand_block`]; `depth` is the number of
+/// enclosing `REPT` blocks. Returns the expanded lines, the index each came from and the index
+/// after the block.
+fn expand(
+    lines: &[String],
+    start_index: usize,
+    depth: usize,
+    limits: &ExpansionLimits,
+) -> Result<(Vec<String>, Vec<usize>, usize), String> {
     let mut result = Vec::new();
+    let mut origins = Vec::new();
+    let mut bytes = 0;
     let mut index = start_index;
     while index < lines.len() {
         let line = &lines[index];
-        let lower = line.to_lowercase();
-        if lower.starts_with("rept") {
-            let parts: Vec<&str> = lower.split_whitespace().collect();
-            if parts.len() >= 2 {
-                if let Ok(count) = parts[1].parse::<usize>() {
-                    let (block, new_index) = process_block(lines, index + 1);
-                    for _ in 0..count {
-                        result.extend(block.iter().cloned());
+        let parsed = parse_directive(line);
+        if let Some(parsed) = parsed
+            && matches!(parsed.directive, Directive::Rept | Directive::Endr)
+            && let Some(label) = parsed.label
+        {
+            // Keep the label of a directive line in place
+            let label = format!("{}:", label);
+            bytes += label.len() + 1;
+            result.push(label);
+            origins.push(index);
+        }
+        match parsed.map(|p| (p.directive, p.argument)) {
+            Some((Directive::Rept, argument)) => {
+                let count = argument.split_whitespace().next();
+                if let Some(count) = count.and_then(parse_literal) {
+                    if depth >= limits.max_depth {
+                        return Err(format!(
+                            "'{}' at line {} nests REPT blocks deeper than {} levels",
+                            line.trim(),
+                            index + 1,
+                            limits.max_depth
+                        ));
+                    }
+                    let (block, block_origins, new_index) =
+                        expand(lines, index + 1, depth + 1, limits)?;
+                    let block_bytes: usize = block.iter().map(|l| l.len() + 1).sum();
+                    let total_lines = block
+                        .len()
+                        .checked_mul(count)
+                        .and_then(|n| n.checked_add(result.len()));
+                    if total_lines.is_none_or(|n| n > limits.max_lines) {
+                        return Err(format!(
+                            "'{}' at line {} expands to more than {} lines",
+                            line.trim(),
+                            index + 1,
+                            limits.max_lines
+                        ));
+                    }
+                    let total_bytes = block_bytes
+                        .checked_mul(count)
+                        .and_then(|n| n.checked_add(bytes));
+                    if total_bytes.is_none_or(|n| n > limits.max_bytes) {
+                        return Err(format!(
+                            "'{}' at line {} expands to more than {} bytes",
+                            line.trim(),
+                            index + 1,
+                            limits.max_bytes
+                        ));
                     }
+                    if !block.is_empty() {
+                        for _ in 0..count {
+                            result.extend(block.iter().cloned());
+                            origins.extend_from_slice(&block_origins);
+                        }
+                    }
+                    bytes += block_bytes * count;
                     index = new_index;
                     continue;
-                } else {
+                } else if count.is_some() {
+                    bytes += line.len() + 1;
                     result.push(line.clone());
+                    origins.push(index);
                 }
             }
-        } else if lower.starts_with("endr") {
-            return (result, index + 1);
+            Some((Directive::Endr, _)) => return Ok((result, origins, index + 1)),
+            _ => {
+                bytes += line.len() + 1;
+                result.push(line.clone());
+                origins.push(index);
+            }
+        }
+        index += 1;
+    }
+    if result.len() > limits.max_lines || bytes > limits.max_bytes {
+        return Err(format!(
+            "The input expands to more than {} lines or {} bytes",
+            limits.max_lines, limits.max_bytes
+        ));
+    }
+    Ok((result, origins, index))
+}
+
+/// A block-structured assembler directive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Directive {
+    Rept,
+    Endr,
+    Macro,
+    Endm,
+    /// Any conditional assembly directive (`IF`, `IFD`, `IFNE`, ...).
+    If,
+    Else,
+    /// `ENDC` or `ENDIF`.
+    Endc,
+}
+
+impl Directive {
+    /// Returns the directive opening the block this directive closes.
+    pub fn opener(self) -> Option<Directive> {
+        match self {
+            Directive::Endr => Some(Directive::Rept),
+            Directive::Endm => Some(Directive::Macro),
+            Directive::Endc => Some(Directive::If),
+            _ => None,
+        }
+    }
+
+    /// Returns the directive closing the block this directive opens.
+    pub fn closer(self) -> Option<Directive> {
+        match self {
+            Directive::Rept => Some(Directive::Endr),
+            Directive::Macro => Some(Directive::Endm),
+            Directive::If => Some(Directive::Endc),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Directive {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Directive::Rept => "REPT",
+            Directive::Endr => "ENDR",
+            Directive::Macro => "MACRO",
+            Directive::Endm => "ENDM",
+            Directive::If => "IF",
+            Directive::Else => "ELSE",
+            Directive::Endc => "ENDC",
+        })
+    }
+}
+
+/// A line holding a block-structured directive, see [`parse_directive`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DirectiveLine<'a> {
+    /// The label in front of the directive, without its colon.
+    pub label: Option<&'a str>,
+    pub directive: Directive,
+    /// The operand field after the directive, e.g. the repeat count, without comments.
+    pub argument: &'a str,
+}
+
+/// Recognizes a block-structured directive on the tokenized line: the directive is matched
+/// case-insensitively, with or without a leading dot, and may be preceded by a label with a
+/// colon or a column-0 label without one (`loop: rept 4`, `.loop REPT 4`, `.endr`).
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::block::{Directive, parse_directive};
+///
+/// let line = parse_directive(".loop:\tREPT\t4 ; unrolled").unwrap();
+/// assert_eq!(line.directive, Directive::Rept);
+/// assert_eq!(line.label, Some(".loop"));
+/// assert_eq!(line.argument, "4");
+/// assert!(parse_directive("reptile: nop").is_none());
+/// ```
+pub fn parse_directive(line: &str) -> Option<DirectiveLine<'_>> {
+    let code = line.split(';').next().unwrap_or("").trim();
+    let (first, rest) = split_token(code);
+    if let Some(directive) = directive_named(first) {
+        return Some(DirectiveLine {
+            label: None,
+            directive,
+            argument: rest,
+        });
+    }
+    let label = first.strip_suffix(':').unwrap_or(first);
+    if label.is_empty()
+        || !label
+            .chars()
+            .all(|c| c.is_alphanumeric() || "._@$".contains(c))
+    {
+        return None;
+    }
+    let (second, rest) = split_token(rest);
+    directive_named(second).map(|directive| DirectiveLine {
+        label: Some(label),
+        directive,
+        argument: rest,
+    })
+}
+
+/// Splits the first whitespace-separated token off `text`.
+fn split_token(text: &str) -> (&str, &str) {
+    let text = text.trim_start();
+    match text.find(char::is_whitespace) {
+        Some(end) => (&text[..end], text[end..].trim()),
+        None => (text, ""),
+    }
+}
+
+/// Returns the directive named by `token`, ignoring case and a leading dot.
+fn directive_named(token: &str) -> Option<Directive> {
+    let name = token.strip_prefix('.').unwrap_or(token).to_lowercase();
+    match name.as_str() {
+        "rept" => Some(Directive::Rept),
+        "endr" => Some(Directive::Endr),
+        "macro" => Some(Directive::Macro),
+        "endm" => Some(Directive::Endm),
+        "if" | "ifd" | "ifnd" | "ifdef" | "ifndef" | "ifeq" | "ifne" | "ifgt" | "ifge" | "iflt"
+        | "ifle" | "ifc" | "ifnc" => Some(Directive::If),
+        "else" | "elseif" => Some(Directive::Else),
+        "endc" | "endif" => Some(Directive::Endc),
+        _ => None,
+    }
+}
+
+/// A structural problem with the block-structured directives of an input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockProblem {
+    /// A block opened by the directive is not closed.
+    Unclosed(Directive),
+    /// The directive does not close, or continue, any open block.
+    Stray(Directive),
+    /// The repeat count of a `REPT` is missing or not a number, so the block cannot be expanded.
+    InvalidCount,
+}
+
+/// A problem found by [`validate_block_structure`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockIssue {
+    /// The line of the offending directive, counting from 1.
+    pub line: usize,
+    pub problem: BlockProblem,
+}
+
+impl fmt::Display for BlockProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlockProblem::Unclosed(directive) => write!(
+                f,
+                "{} without matching {}",
+                directive,
+                directive.closer().unwrap_or(*directive)
+            ),
+            BlockProblem::Stray(Directive::Else) => write!(f, "ELSE outside of an IF block"),
+            BlockProblem::Stray(directive) => write!(
+                f,
+                "{} without matching {}",
+                directive,
+                directive.opener().unwrap_or(*directive)
+            ),
+            BlockProblem::InvalidCount => write!(f, "REPT count is missing or not a number"),
+        }
+    }
+}
+
+impl fmt::Display for BlockIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.problem)
+    }
+}
+
+/// Checks that the `REPT`/`ENDR`, `MACRO`/`ENDM` and `IF`/`ELSE`/`ENDC` directives of `lines`
+/// are balanced and properly nested, and that every repeat count is a number.
+/// [`process_block`] tolerates mis-nesting silently, which shifts the cycle accounting of
+/// everything after it, so inputs should be validated before expansion.
+///
+/// A closing directive that skips over open blocks of another kind closes its own block and
+/// reports the skipped ones as unclosed.
+///
+/// # Returns
+/// The problems found, in line order; empty if the structure is valid.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::block::{BlockProblem, Directive, validate_block_structure};
+///
+/// let lines: Vec<String> = ["rept 2", "nop", "endr", "endr"].map(String::from).to_vec();
+/// let issues = validate_block_structure(&lines);
+/// assert_eq!(issues[0].line, 4);
+/// assert_eq!(issues[0].problem, BlockProblem::Stray(Directive::Endr));
+/// ```
+pub fn validate_block_structure(lines: &[String]) -> Vec<BlockIssue> {
+    let mut issues = Vec::new();
+    // The open blocks with the line of their opening directive
+    let mut open: Vec<(Directive, usize)> = Vec::new();
+    for (index, line) in lines.iter().enumerate() {
+        let Some(parsed) = parse_directive(line) else {
+            continue;
+        };
+        let line = index + 1;
+        let directive = parsed.directive;
+        if directive.closer().is_some() {
+            if directive == Directive::Rept && leading_count(parsed.argument).is_none() {
+                issues.push(BlockIssue {
+                    line,
+                    problem: BlockProblem::InvalidCount,
+                });
+            }
+            open.push((directive, line));
+        } else if directive == Directive::Else {
+            if !open.iter().any(|(d, _)| *d == Directive::If) {
+                issues.push(BlockIssue {
+                    line,
+                    problem: BlockProblem::Stray(directive),
+                });
+            }
+        } else if let Some(opener) = directive.opener() {
+            match open.iter().rposition(|(d, _)| *d == opener) {
+                Some(position) => {
+                    for (skipped, line) in open.split_off(position + 1) {
+                        issues.push(BlockIssue {
+                            line,
+                            problem: BlockProblem::Unclosed(skipped),
+                        });
+                    }
+                    open.pop();
+                }
+                None => issues.push(BlockIssue {
+                    line,
+                    problem: BlockProblem::Stray(directive),
+                }),
+            }
+        }
+    }
+    issues.extend(open.into_iter().map(|(directive, line)| BlockIssue {
+        line,
+        problem: BlockProblem::Unclosed(directive),
+    }));
+    issues.sort_by_key(|issue| issue.line);
+    issues
+}
+
+/// Splits lines holding several instructions into one line per instruction.
+///
+/// Some code generators emit multiple statements per line, separated by `;` (e.g.
+/// `move.w d0,(a0)+ ; move.w d1,(a0)+`) or `:`. Since `;` also starts a comment and `:` ends a
+/// label, a separator only splits the line if the text following it (up to the next
+/// separator) is an instruction known to the cycle database - and, for `:`, if the text before
+/// it is one as well. Everything after the first `;` that does not start an instruction is
+/// kept as a comment of the last statement. Separators inside quotes are ignored.
+///
+/// # Parameters
+/// - `lines`: The input lines.
+/// - `db`: The cycle database used to recognize instructions.
+///
+/// # Returns
+/// The lines with every recognized statement on a line of its own.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::block::split_statements;
+/// use cycle_spitter::database::CycleDatabase;
+///
+/// let lines = vec!["move.w d0,(a0)+ ; move.w d1,(a0)+ ; (8)".to_string()];
+/// let split = split_statements(&lines, &CycleDatabase::builtin());
+/// assert_eq!(split, ["move.w d0,(a0)+", "move.w d1,(a0)+ ; (8)"]);
+/// ```
+pub fn split_statements(lines: &[String], db: &CycleDatabase) -> Vec<String> {
+    split_statements_with_origins(lines, db).0
+}
+
+/// Same as [`split_statements`], but also returns the index in `lines` and the byte offset in
+/// that line at which every statement starts (see
+/// [`SourceMap::remap_columns`](crate::location::SourceMap::remap_columns)).
+pub fn split_statements_with_origins(
+    lines: &[String],
+    db: &CycleDatabase,
+) -> (Vec<String>, Vec<(usize, usize)>) {
+    let is_instruction = |text: &str| {
+        let text = text.trim();
+        !text.is_empty() && db.get(&normalize_line_ext(text).0).is_some()
+    };
+
+    let mut result = Vec::with_capacity(lines.len());
+    let mut origins = Vec::with_capacity(lines.len());
+    for (index, line) in lines.iter().enumerate() {
+        let pieces = separate(line);
+        let mut current = pieces[0].2.to_string();
+        let mut start = 0;
+        for &(separator, offset, piece) in &pieces[1..] {
+            let starts_statement =
+                is_instruction(piece) && (separator == ';' || is_instruction(&current));
+            if starts_statement {
+                result.push(current.trim_end().to_string());
+                origins.push((index, start));
+                start = offset + 1 + piece.len() - piece.trim_start().len();
+                current = piece.trim_start().to_string();
+            } else if separator == ';' {
+                // A comment: keep the rest of the line as it is
+                current.push_str(&line[offset..]);
+                break;
+            } else {
+                current.push(separator);
+                current.push_str(piece);
+            }
+        }
+        result.push(current);
+        origins.push((index, start));
+    }
+    (result, origins)
+}
+
+/// Joins lines continued on the next line into one logical line.
+///
+/// A line continues if its code (the part before a comment) ends with a backslash, which is
+/// removed, or with a comma, as in a register list or `dc.w` table split across lines. The
+/// comments of the joined lines are kept, in order, at the end of the logical line, so inline
+/// `(n)` cycle overrides still apply. Comment-only lines never continue, and a blank line ends
+/// a continuation.
+///
+/// # Parameters
+/// - `lines`: The input lines.
+///
+/// # Returns
+/// The logical lines.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::block::join_continuations;
+///
+/// let lines: Vec<String> = ["movem.l d0-d7/\\", "a0-a6,-(sp) ; save", "dc.w 1,2,", "3"]
+///     .iter()
+///     .map(|s| s.to_string())
+///     .collect();
+/// assert_eq!(
+///     join_continuations(&lines),
+///     ["movem.l d0-d7/a0-a6,-(sp) ; save", "dc.w 1,2,3"]
+/// );
+/// ```
+pub fn join_continuations(lines: &[String]) -> Vec<String> {
+    join_continuations_with_origins(lines).0
+}
+
+/// Same as [`join_continuations`], but also returns the index in `lines` of the first line of
+/// every logical line (see [`SourceMap::remap`](crate::location::SourceMap::remap)).
+pub fn join_continuations_with_origins(lines: &[String]) -> (Vec<String>, Vec<usize>) {
+    let mut result = Vec::with_capacity(lines.len());
+    let mut origins = Vec::with_capacity(lines.len());
+    let mut pending: Option<(String, Vec<String>)> = None;
+    let mut first = 0;
+    for (index, line) in lines.iter().enumerate() {
+        let (code, comment) = split_comment(line);
+        let is_joined = pending.is_some();
+        let (mut code, mut comments) = match pending.take() {
+            Some((joined, comments)) if line.trim().is_empty() => {
+                // A blank line ends a dangling continuation
+                result.push(with_comments(joined, &comments));
+                result.push(line.clone());
+                origins.extend([first, index]);
+                continue;
+            }
+            Some((joined, comments)) => {
+                let separator = if joined.ends_with([',', '/']) {
+                    ""
+                } else {
+                    " "
+                };
+                (format!("{}{}{}", joined, separator, code.trim()), comments)
+            }
+            None if code.trim().is_empty() => {
+                result.push(line.clone());
+                origins.push(index);
+                continue;
+            }
+            None => (code.trim_end().to_string(), Vec::new()),
+        };
+        if !comment.is_empty() {
+            comments.push(comment.to_string());
+        }
+
+        let continued = if let Some(stripped) = code.strip_suffix('\\') {
+            code = stripped.trim_end().to_string();
+            true
+        } else {
+            code.ends_with(',')
+        };
+        if !is_joined {
+            first = index;
+        }
+        if continued {
+            pending = Some((code, comments));
+        } else if is_joined {
+            result.push(with_comments(code, &comments));
+            origins.push(first);
         } else {
             result.push(line.clone());
+            origins.push(index);
         }
-        index += 1;
     }
-    (result, index)
+    if let Some((code, comments)) = pending {
+        result.push(with_comments(code, &comments));
+        origins.push(first);
+    }
+    (result, origins)
+}
+
+/// Appends `comments` to `code`, separated by spaces.
+fn with_comments(mut code: String, comments: &[String]) -> String {
+    for comment in comments {
+        code.push(' ');
+        code.push_str(comment);
+    }
+    code
+}
+
+/// Splits `line` into its code and its comment, which starts at the first `;` outside quotes.
+fn split_comment(line: &str) -> (&str, &str) {
+    match separate(line)
+        .iter()
+        .find(|(separator, _, _)| *separator == ';')
+    {
+        Some(&(_, offset, _)) => (&line[..offset], &line[offset..]),
+        None => (line, ""),
+    }
+}
+
+/// Splits `line` at every `;` and `:` outside quotes into `(separator, offset, piece)`, where
+/// `offset` is the byte offset of the separator. The first piece has separator `\0` at offset 0.
+fn separate(line: &str) -> Vec<(char, usize, &str)> {
+    let mut pieces = Vec::new();
+    let mut quote = None;
+    let mut start = 0;
+    let mut separator = ('\0', 0);
+    for (index, c) in line.char_indices() {
+        match (quote, c) {
+            (Some(q), _) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, ';' | ':') => {
+                pieces.push((separator.0, separator.1, &line[start..index]));
+                separator = (c, index);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    pieces.push((separator.0, separator.1, &line[start..]));
+    pieces
 }
 
 #[cfg(test)]
@@ -114,6 +721,19 @@ mod tests {
         assert_eq!(result, expected);
     }
 
+    #[test]
+    fn test_rept_counts_in_any_notation() {
+        for count in ["3", "$3", "%11"] {
+            let lines = vec![
+                format!("rept {}", count),
+                "nop".to_string(),
+                "endr".to_string(),
+            ];
+            let (result, _) = process_block(&lines, 0);
+            assert_eq!(result, ["nop", "nop", "nop"], "rept {}", count);
+        }
+    }
+
     #[test]
     fn test_nested_rept_blocks() {
         let lines = vec![
@@ -208,4 +828,188 @@ mod tests {
 
         assert_eq!(result, expected);
     }
+
+    #[test]
+    fn test_expansion_limits() {
+        let lines = |raw: &[&str]| raw.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let limits = ExpansionLimits {
+            max_lines: 10,
+            max_depth: 2,
+            max_bytes: 40,
+        };
+
+        let fits = lines(&["rept 2", "nop", "rept 2", "nop", "endr", "endr"]);
+        assert_eq!(expand_block(&fits, &limits).unwrap().len(), 6);
+        assert_eq!(
+            expand_block(&fits, &limits).unwrap(),
+            process_block(&fits, 0).0
+        );
+
+        let error = |raw: &[&str]| expand_block(&lines(raw), &limits).unwrap_err().to_string();
+        assert_eq!(
+            error(&["nop", "rept 100000000", "nop", "endr"]),
+            "'rept 100000000' at line 2 expands to more than 10 lines"
+        );
+        assert_eq!(
+            error(&["rept 18446744073709551615", "rept 2", "nop", "endr", "endr"]),
+            "'rept 18446744073709551615' at line 1 expands to more than 10 lines"
+        );
+        assert_eq!(
+            error(&["rept 1", "rept 1", "rept 1", "nop", "endr", "endr", "endr"]),
+            "'rept 1' at line 3 nests REPT blocks deeper than 2 levels"
+        );
+        assert_eq!(
+            error(&["rept 2", "move.l (a0)+,(a1)+ ; copy", "endr"]),
+            "'rept 2' at line 1 expands to more than 40 bytes"
+        );
+        assert_eq!(
+            error(&["nop"; 11]),
+            "The input expands to more than 10 lines or 40 bytes"
+        );
+        assert!(expand_block(&lines(&["rept 1000000000", "endr"]), &limits).is_ok());
+    }
+
+    #[test]
+    fn test_parse_directive() {
+        fn directive(line: &str) -> Option<(Option<&str>, Directive, &str)> {
+            parse_directive(line).map(|p| (p.label, p.directive, p.argument))
+        }
+        assert_eq!(directive("rept 4"), Some((None, Directive::Rept, "4")));
+        assert_eq!(directive("  .ENDR  "), Some((None, Directive::Endr, "")));
+        assert_eq!(
+            directive("loop:\tRept\t8 ; unroll"),
+            Some((Some("loop"), Directive::Rept, "8"))
+        );
+        assert_eq!(
+            directive("done endr"),
+            Some((Some("done"), Directive::Endr, ""))
+        );
+        assert_eq!(
+            directive("copy macro"),
+            Some((Some("copy"), Directive::Macro, ""))
+        );
+        assert_eq!(
+            directive("IFNE DEBUG"),
+            Some((None, Directive::If, "DEBUG"))
+        );
+        assert_eq!(directive("endif"), Some((None, Directive::Endc, "")));
+        assert_eq!(directive("reptile"), None);
+        assert_eq!(directive("move.w d0,d1 ; rept 4"), None);
+        assert_eq!(directive("move.w rept,d1"), None);
+    }
+
+    #[test]
+    fn test_process_block_with_labelled_directives() {
+        let lines = vec![
+            ".loop: REPT 2".to_string(),
+            "nop".to_string(),
+            "last .endr".to_string(),
+            "rts".to_string(),
+        ];
+        let (result, _) = process_block(&lines, 0);
+        assert_eq!(result, [".loop:", "nop", "last:", "nop", "last:", "rts"]);
+    }
+
+    #[test]
+    fn test_validate_block_structure() {
+        let lines = |raw: &[&str]| raw.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert!(
+            validate_block_structure(&lines(&[
+                "rept 2",
+                "  REPT 3",
+                "nop",
+                "ENDR",
+                "endr",
+                "ifd DEBUG",
+                "x macro",
+                "endm",
+                "else",
+                "endc",
+            ]))
+            .is_empty()
+        );
+        let issues = validate_block_structure(&lines(&[
+            "endr",
+            "rept 2",
+            "rept COUNT",
+            "nop",
+            "endr",
+            "rept",
+            "ifne DEBUG",
+            "rept 2",
+            "endc",
+            "else",
+            "endm",
+        ]));
+        assert_eq!(
+            issues.iter().map(|i| i.to_string()).collect::<Vec<_>>(),
+            [
+                "line 1: ENDR without matching REPT",
+                "line 2: REPT without matching ENDR",
+                "line 3: REPT count is missing or not a number",
+                "line 6: REPT count is missing or not a number",
+                "line 6: REPT without matching ENDR",
+                "line 8: REPT without matching ENDR",
+                "line 10: ELSE outside of an IF block",
+                "line 11: ENDM without matching MACRO",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_split_statements() {
+        let db = CycleDatabase::builtin();
+        let lines: Vec<String> = [
+            "move.w d0,(a0)+ ; move.w d1,(a0)+",
+            "move.w d0,d1: move.w d2,d3 ; copy",
+            "loop: move.w d0,d1 ; (8) ; move.w d1,d2",
+            "move.w d0,d1 ; store ; move.w d1,d2",
+            "dc.b \"a;move.w d0,d1\"",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+
+        assert_eq!(
+            split_statements(&lines, &db),
+            [
+                "move.w d0,(a0)+",
+                "move.w d1,(a0)+",
+                "move.w d0,d1",
+                "move.w d2,d3 ; copy",
+                "loop: move.w d0,d1 ; (8) ; move.w d1,d2",
+                "move.w d0,d1 ; store ; move.w d1,d2",
+                "dc.b \"a;move.w d0,d1\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_join_continuations() {
+        let lines: Vec<String> = [
+            "movem.l d0-d7/a0-a6, ; (76)",
+            "  -(sp) ; save",
+            "; not continued,",
+            "dc.w 1,\\",
+            "2 \\",
+            "",
+            "move.w d0,d1",
+            "dc.b \"a;\\\"",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+
+        assert_eq!(
+            join_continuations(&lines),
+            [
+                "movem.l d0-d7/a0-a6,-(sp) ; (76) ; save",
+                "; not continued,",
+                "dc.w 1,2",
+                "",
+                "move.w d0,d1",
+                "dc.b \"a;\\\"",
+            ]
+        );
+    }
 }
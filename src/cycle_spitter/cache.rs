@@ -0,0 +1,342 @@
+// src/cycle_spitter/cache.rs
+
+//! On-disk cache of generated scanlines for incremental runs.
+//!
+//! A scanline only depends on the active template, the input lines it consumes (plus the line
+//! following them, which decided where it ended), the cycle database and the options. Every
+//! generated scanline is stored under a hash of those inputs, so regenerating a long expansion
+//! after a small edit only recomputes the scanlines whose input changed: lookups are keyed by
+//! content, not by position, so scanlines after an edit are reused as soon as the scanline
+//! boundaries line up again.
+//!
+//! The cache as a whole is tied to a [`fingerprint`] of the tool version, the normalization
+//! version, the database (with its adjustments and trap handlers), the templates and the
+//! options; it is discarded if any of them changes. Only the scanlines used by the last run are
+//! written back, so the cache does not grow with stale entries.
+
+use crate::cycle_spitter::checksum::Fnv1a;
+use crate::cycle_spitter::cycles::NORMALIZATION_VERSION;
+use crate::cycle_spitter::database::CycleDatabase;
+use crate::cycle_spitter::exception::TRAP_VECTORS;
+use crate::cycle_spitter::generator::FilledScanline;
+use crate::cycle_spitter::options::Options;
+use crate::cycle_spitter::template::TemplateSet;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// The cache directory used when none is given.
+pub const DEFAULT_CACHE_DIR: &str = ".cyclespitter-cache";
+
+/// The file holding the cached scanlines inside the cache directory.
+const CACHE_FILE: &str = "scanlines.json";
+
+/// Computes the fingerprint of everything besides the input that affects generated scanlines.
+pub fn fingerprint(db: &CycleDatabase, templates: &TemplateSet, options: &Options) -> u64 {
+    let mut hash = Fnv1a::new();
+    hash.write(env!("CARGO_PKG_VERSION").as_bytes());
+    hash.write(&NORMALIZATION_VERSION.to_le_bytes());
+
+    let mut keys: Vec<&str> = db.keys().collect();
+    keys.sort_unstable();
+    for key in keys {
+        hash.write(format!("{}={:?}{:?}\n", key, db.entry(key), db.adjustment(key)).as_bytes());
+    }
+    for vector in 0..TRAP_VECTORS {
+        hash.write(format!("trap {}={:?}\n", vector, db.trap_handler(vector)).as_bytes());
+    }
+    hash.write(format!("{:?}", templates).as_bytes());
+    hash.write(
+        format!(
+            "{}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{}|{}|{:?}|{}|{}|{}",
+            options.cycles_per_line(),
+            options.overflow_policy(),
+            options.padding_style(),
+            options.annotation_format(),
+            options.cpu_profile(),
+            options.padding_instruction(),
+            options.packer(),
+            options.packer_window(),
+            options.margin(),
+            options.frame_layout(),
+            options.start_line(),
+            options.entry_offset(),
+            options.exit_offset()
+        )
+        .as_bytes(),
+    );
+    hash.finish()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CachedScanline {
+    /// Hash over the consumed input lines and the line following them.
+    input_hash: u64,
+    scanline: FilledScanline,
+}
+
+/// Generated scanlines from previous runs (see the module documentation).
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ScanlineCache {
+    fingerprint: u64,
+    /// Candidates keyed by the active template and the first input line of the scanline.
+    entries: HashMap<u64, Vec<CachedScanline>>,
+    /// The entries used or created by the current run; these are the ones saved.
+    #[serde(skip)]
+    used: HashMap<u64, Vec<CachedScanline>>,
+    #[serde(skip)]
+    hits: usize,
+    #[serde(skip)]
+    misses: usize,
+}
+
+impl ScanlineCache {
+    /// Creates an empty cache for the given fingerprint.
+    pub fn new(fingerprint: u64) -> Self {
+        ScanlineCache {
+            fingerprint,
+            ..Default::default()
+        }
+    }
+
+    /// Loads the cache from `dir`. Returns an empty cache if there is none yet, it cannot be
+    /// read, or it was created for a different fingerprint.
+    pub fn load(dir: &Path, fingerprint: u64) -> Self {
+        fs::read_to_string(dir.join(CACHE_FILE))
+            .ok()
+            .and_then(|content| serde_json::from_str::<ScanlineCache>(&content).ok())
+            .filter(|cache| cache.fingerprint == fingerprint)
+            .unwrap_or_else(|| ScanlineCache::new(fingerprint))
+    }
+
+    /// Writes the scanlines used by the current run to `dir`, creating it if needed.
+    pub fn save(&self, dir: &Path) -> io::Result<()> {
+        let saved = ScanlineCache {
+            fingerprint: self.fingerprint,
+            entries: self.used.clone(),
+            ..Default::default()
+        };
+        fs::create_dir_all(dir)?;
+        fs::write(dir.join(CACHE_FILE), serde_json::to_string(&saved)?)
+    }
+
+    /// The number of scanlines reused in the current run.
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+
+    /// The number of scanlines computed in the current run.
+    pub fn misses(&self) -> usize {
+        self.misses
+    }
+
+    /// Returns a cached scanline for the input at `start`, filled with the template named
+    /// `selector` (`None` for a raw scanline) into a scanline of `cycles`.
+    pub(crate) fn lookup(
+        &mut self,
+        selector: Option<&str>,
+        cycles: usize,
+        lines: &[String],
+        start: usize,
+    ) -> Option<FilledScanline> {
+        let key = entry_key(selector, cycles, &lines[start]);
+        let found = self.entries.get(&key).and_then(|candidates| {
+            candidates
+                .iter()
+                .find(|c| c.input_hash == input_hash(lines, start, c.scanline.consumed))
+                .cloned()
+        });
+        match found {
+            Some(entry) => {
+                self.hits += 1;
+                let scanline = entry.scanline.clone();
+                remember(&mut self.used, key, entry);
+                Some(scanline)
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Records a newly filled scanline for the input at `start`.
+    pub(crate) fn insert(
+        &mut self,
+        selector: Option<&str>,
+        cycles: usize,
+        lines: &[String],
+        start: usize,
+        scanline: &FilledScanline,
+    ) {
+        let key = entry_key(selector, cycles, &lines[start]);
+        let entry = CachedScanline {
+            input_hash: input_hash(lines, start, scanline.consumed),
+            scanline: scanline.clone(),
+        };
+        remember(&mut self.entries, key, entry.clone());
+        remember(&mut self.used, key, entry);
+    }
+}
+
+/// Adds `entry` to the candidates of `key` unless an entry for the same input is present.
+fn remember(map: &mut HashMap<u64, Vec<CachedScanline>>, key: u64, entry: CachedScanline) {
+    let candidates = map.entry(key).or_default();
+    if !candidates.iter().any(|c| c.input_hash == entry.input_hash) {
+        candidates.push(entry);
+    }
+}
+
+fn entry_key(selector: Option<&str>, cycles: usize, first_line: &str) -> u64 {
+    let mut hash = Fnv1a::new();
+    match selector {
+        Some(name) => {
+            hash.write(b"T");
+            hash.write(name.as_bytes());
+        }
+        None => hash.write(b"R"),
+    }
+    hash.write(&(cycles as u64).to_le_bytes());
+    hash.write(b"\n");
+    hash.write(first_line.as_bytes());
+    hash.finish()
+}
+
+/// Hashes the `consumed` lines at `start` and the line following them, if any.
+fn input_hash(lines: &[String], start: usize, consumed: usize) -> u64 {
+    let end = (start + consumed + 1).min(lines.len());
+    let mut hash = Fnv1a::new();
+    hash.write(&((end - start) as u64).to_le_bytes());
+    for line in &lines[start..end] {
+        hash.write(line.as_bytes());
+        hash.write(b"\n");
+    }
+    hash.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cycle_spitter::generator::{generate, generate_cached};
+    use crate::cycle_spitter::observer::NullObserver;
+    use crate::cycle_spitter::template::{TemplateSection, parse_template};
+
+    fn setup() -> (CycleDatabase, TemplateSet, Options) {
+        let db = CycleDatabase::builtin();
+        let template = parse_template("move.w d0,d1 ; first\ndcb.w 4,$4e71", &db).unwrap();
+        let options = Options::builder().cycles_per_line(32).build();
+        (db, TemplateSet::from(template), options)
+    }
+
+    fn input(count: usize) -> Vec<String> {
+        (0..count)
+            .map(|i| format!("move.w d{},d1 ; line {}", i % 8, i))
+            .collect()
+    }
+
+    #[test]
+    fn test_cached_generation_matches_uncached() {
+        let (db, templates, options) = setup();
+        let mut lines = input(40);
+        let mut cache = ScanlineCache::new(fingerprint(&db, &templates, &options));
+        let first = generate_cached(
+            &lines,
+            &templates,
+            &db,
+            &options,
+            &mut NullObserver,
+            &mut cache,
+        )
+        .unwrap();
+        assert_eq!(cache.hits(), 0);
+        assert_eq!(
+            first.lines,
+            generate(&lines, &templates, &db, &options).unwrap().lines
+        );
+
+        // Change one instruction; only the scanline holding it is recomputed.
+        lines[17] = "move.l d0,d1".to_string();
+        let mut cache = ScanlineCache {
+            entries: cache.used,
+            ..ScanlineCache::new(cache.fingerprint)
+        };
+        let second = generate_cached(
+            &lines,
+            &templates,
+            &db,
+            &options,
+            &mut NullObserver,
+            &mut cache,
+        )
+        .unwrap();
+        let uncached = generate(&lines, &templates, &db, &options).unwrap();
+        assert_eq!(second.lines, uncached.lines);
+        assert_eq!(second.model, uncached.model);
+        assert_eq!(cache.misses(), 1);
+        assert_eq!(cache.hits(), second.scanlines - 1);
+    }
+
+    #[test]
+    fn test_cache_roundtrip_and_fingerprint_mismatch() {
+        let (db, templates, options) = setup();
+        let dir = std::env::temp_dir().join(format!("cyclespitter-cache-{}", std::process::id()));
+        let fp = fingerprint(&db, &templates, &options);
+        let mut cache = ScanlineCache::new(fp);
+        generate_cached(
+            &input(8),
+            &templates,
+            &db,
+            &options,
+            &mut NullObserver,
+            &mut cache,
+        )
+        .unwrap();
+        cache.save(&dir).unwrap();
+
+        let loaded = ScanlineCache::load(&dir, fp);
+        assert_eq!(loaded.entries.len(), cache.used.len());
+
+        let other = Options::builder().cycles_per_line(64).build();
+        assert_ne!(fingerprint(&db, &templates, &other), fp);
+        let stale = ScanlineCache::load(&dir, fingerprint(&db, &templates, &other));
+        assert!(stale.entries.is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_trap_handlers_invalidate_the_cache() {
+        let mut db = CycleDatabase::builtin();
+        let templates = TemplateSet::from(vec![TemplateSection::code_window(256, "main")]);
+        let options = Options::builder().cycles_per_line(256).build();
+        let lines: Vec<String> = ["move.w d0,d1", "trap #1", "move.w d1,d2"]
+            .iter()
+            .map(|l| l.to_string())
+            .collect();
+        let dir = std::env::temp_dir().join(format!("cyclespitter-trap-{}", std::process::id()));
+        let run = |db: &CycleDatabase| {
+            let mut cache = ScanlineCache::load(&dir, fingerprint(db, &templates, &options));
+            let generation = generate_cached(
+                &lines,
+                &templates,
+                db,
+                &options,
+                &mut NullObserver,
+                &mut cache,
+            )
+            .unwrap();
+            cache.save(&dir).unwrap();
+            generation
+        };
+        run(&db);
+
+        db.set_trap_handler(1, 100);
+        let cached = run(&db);
+        let uncached = generate(&lines, &templates, &db, &options).unwrap();
+        assert_eq!(cached.lines, uncached.lines);
+        assert!(cached.lines.iter().any(|l| l.contains("(134")));
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,39 @@
+// src/cycle_spitter/cancellation.rs
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// A cooperative cancellation flag shared between a running generation and its owner.
+///
+/// Clones share the same flag, so a clone can be handed to another thread (e.g. an editor's
+/// UI thread) and cancelled from there. Generation checks the flag between scanlines.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::cancellation::CancellationToken;
+///
+/// let token = CancellationToken::new();
+/// let handle = token.clone();
+/// handle.cancel();
+/// assert!(token.is_cancelled());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Requests cancellation of every generation observing this token.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns `true` once [`cancel`](Self::cancel) has been called on this token or a clone.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
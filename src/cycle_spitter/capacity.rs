@@ -0,0 +1,196 @@
+// src/cycle_spitter/capacity.rs
+
+//! Checking up front whether the input fits into the frame.
+//!
+//! Generating an input that is too large for the frame takes as long as generating one that
+//! fits, and the overflow only shows at the end. [`check_capacity`] compares the cycles of
+//! every input stream with the free cycles of the scanlines it gets before anything is
+//! generated.
+//!
+//! The frame ends after `Options::max_scanlines` scanlines, or else after the last scanline of
+//! a frame layout whose last region is closed; without either, there is nothing to check. The
+//! free cycles of a scanline are the code windows of the template the frame layout selects
+//! for it, less the safety margin. With several streams (see the `interleave` module), each
+//! stream is assumed to get every n-th scanline of the frame. `;@use-template`, `;@budget`
+//! and `;@raw-scanline` pragmas are not taken into account, so the check estimates rather than
+//! proves.
+
+use crate::cycle_spitter::database::CycleDatabase;
+use crate::cycle_spitter::generator::{reserve_margin, select_template};
+use crate::cycle_spitter::options::Options;
+use crate::cycle_spitter::packer::cycles_of;
+use crate::cycle_spitter::template::TemplateSet;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+
+/// An input stream needing more cycles than the frame leaves it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Overrun {
+    /// The index of the stream, in the order the inputs were given.
+    pub stream: usize,
+    /// The cycles of the stream's user code.
+    pub user_cycles: usize,
+    /// The free cycles of the scanlines the stream gets.
+    pub free_cycles: usize,
+    /// The number of scanlines the stream gets.
+    pub scanlines: usize,
+}
+
+impl Overrun {
+    /// The cycles by which the stream exceeds its free cycles.
+    pub fn excess(&self) -> usize {
+        self.user_cycles - self.free_cycles
+    }
+}
+
+impl fmt::Display for Overrun {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the code takes {} cycles, but its {} scanlines of the frame leave {} free - {} cycles over budget",
+            self.user_cycles,
+            self.scanlines,
+            self.free_cycles,
+            self.excess()
+        )
+    }
+}
+
+/// Returns the number of scanlines of the frame from `Options::start_line` on (see the module
+/// documentation), or `None` if the frame is open-ended.
+pub fn frame_scanlines(options: &Options) -> Option<usize> {
+    options.max_scanlines().or_else(|| {
+        let last = options.frame_layout()?.regions().last()?.last?;
+        Some((last + 1).saturating_sub(options.start_line()))
+    })
+}
+
+/// Compares the cycles of every stream in `inputs` with the free cycles of its scanlines and
+/// returns the streams that cannot fit.
+///
+/// # Errors
+/// If the frame layout names an unknown template.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::capacity::check_capacity;
+/// use cycle_spitter::database::CycleDatabase;
+/// use cycle_spitter::options::Options;
+/// use cycle_spitter::template::{TemplateSection, TemplateSet};
+///
+/// let db = CycleDatabase::builtin();
+/// let templates = TemplateSet::from(vec![TemplateSection::code_window(8, "code")]);
+/// let options = Options::builder().max_scanlines(2).build();
+/// let input: Vec<String> = vec!["move.w d0,d1".to_string(); 5];
+/// let overruns = check_capacity(&[input], &templates, &db, &options).unwrap();
+/// assert_eq!(overruns[0].excess(), 4);
+/// ```
+pub fn check_capacity(
+    inputs: &[Vec<String>],
+    templates: &TemplateSet,
+    db: &CycleDatabase,
+    options: &Options,
+) -> Result<Vec<Overrun>, Box<dyn Error>> {
+    let Some(scanlines) = frame_scanlines(options) else {
+        return Ok(Vec::new());
+    };
+    let mut template = templates.initial().ok_or("No template registered")?;
+    let layout = options.frame_layout();
+    // The free cycles of every scanline of the frame
+    let mut free = Vec::with_capacity(scanlines);
+    for frame_line in options.start_line()..options.start_line() + scanlines {
+        if let Some((_, region)) = layout.and_then(|layout| layout.region_at(frame_line)) {
+            template = &region.template;
+        }
+        let sections = select_template(templates, template)?;
+        let reserved: usize = reserve_margin(sections, options).iter().sum();
+        let windows: usize = sections.iter().map(|section| section.nop_cycles).sum();
+        free.push(windows.saturating_sub(reserved));
+    }
+
+    let streams = inputs.len().max(1);
+    Ok(inputs
+        .iter()
+        .enumerate()
+        .filter_map(|(stream, lines)| {
+            let user_cycles = lines.iter().filter_map(|line| cycles_of(line, db)).sum();
+            let shares: Vec<usize> = free.iter().skip(stream).step_by(streams).copied().collect();
+            let free_cycles = shares.iter().sum();
+            (user_cycles > free_cycles).then_some(Overrun {
+                stream,
+                user_cycles,
+                free_cycles,
+                scanlines: shares.len(),
+            })
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cycle_spitter::frame::FrameLayout;
+    use crate::cycle_spitter::template::TemplateSection;
+
+    fn lines(count: usize) -> Vec<String> {
+        vec!["move.w d0,d1 ; (4)".to_string(); count]
+    }
+
+    #[test]
+    fn test_frame_scanlines() {
+        assert_eq!(frame_scanlines(&Options::default()), None);
+        let layout = FrameLayout::parse("0-99 main\n100-199 main").unwrap();
+        let options = Options::builder()
+            .frame_layout(layout.clone())
+            .start_line(50)
+            .build();
+        assert_eq!(frame_scanlines(&options), Some(150));
+        let open = FrameLayout::parse("0- main").unwrap();
+        assert_eq!(
+            frame_scanlines(&Options::builder().frame_layout(open).build()),
+            None
+        );
+        let capped = Options::builder()
+            .frame_layout(layout)
+            .max_scanlines(20)
+            .build();
+        assert_eq!(frame_scanlines(&capped), Some(20));
+    }
+
+    #[test]
+    fn test_check_capacity_per_stream() {
+        let db = CycleDatabase::builtin();
+        let mut templates = TemplateSet::new();
+        templates
+            .insert("wide", vec![TemplateSection::code_window(16, "code")])
+            .unwrap();
+        templates
+            .insert("narrow", vec![TemplateSection::code_window(8, "code")])
+            .unwrap();
+        // Scanlines 0 and 2 are wide, 1 and 3 narrow.
+        let layout = FrameLayout::parse("0 wide\n1 narrow\n2 wide\n3 narrow").unwrap();
+        let options = Options::builder().frame_layout(layout).build();
+
+        let inputs = [lines(8), lines(5)];
+        let overruns = check_capacity(&inputs, &templates, &db, &options).unwrap();
+        assert_eq!(
+            overruns,
+            [Overrun {
+                stream: 1,
+                user_cycles: 20,
+                free_cycles: 16,
+                scanlines: 2
+            }]
+        );
+        assert_eq!(
+            overruns[0].to_string(),
+            "the code takes 20 cycles, but its 2 scanlines of the frame leave 16 free - 4 cycles over budget"
+        );
+
+        // The margin is not free.
+        let margin = Options::builder().max_scanlines(4).margin(4).build();
+        let overruns = check_capacity(&[lines(13)], &templates, &db, &margin).unwrap();
+        assert_eq!(overruns[0].free_cycles, 48);
+    }
+}
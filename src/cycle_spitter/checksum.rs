@@ -0,0 +1,197 @@
+// src/cycle_spitter/checksum.rs
+
+//! Per-scanline checksums that allow detecting hand edits to generated output.
+//!
+//! When enabled, every scanline is followed by a `; Scanline checksum: <hex>` comment. The
+//! checksum covers the code of every instruction line of the scanline (comments stripped and
+//! whitespace collapsed, so re-indenting is harmless) and the scanline's total cycle count.
+//! [`verify`] recomputes the checksums of a previously generated file and reports every
+//! scanline whose code no longer matches.
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+/// Prefix of the comment carrying a scanline's checksum.
+pub const CHECKSUM_PREFIX: &str = "; Scanline checksum: ";
+
+/// Prefix of the comment carrying a scanline's total cycle count.
+pub const TOTAL_CYCLES_PREFIX: &str = "; Total cycles for scanline: ";
+
+static EQU_RE: Lazy<Regex> = Lazy::new(|| {
+    // Matches symbol definitions (`LABEL equ 12`), which do not generate code
+    Regex::new(r"(?i)^\S+\s+equ\b").unwrap()
+});
+
+/// Returns the code part of a line (everything before the first `;`), trimmed and with
+/// whitespace runs collapsed, or `None` if the line does not generate code.
+pub(crate) fn code_part(line: &str) -> Option<String> {
+    let code = line.split(';').next().unwrap_or("");
+    let collapsed = code.split_whitespace().collect::<Vec<_>>().join(" ");
+    if collapsed.is_empty() || EQU_RE.is_match(&collapsed) {
+        None
+    } else {
+        Some(collapsed)
+    }
+}
+
+/// 64-bit FNV-1a, a simple hash that is stable across platforms and compiler versions.
+pub(crate) struct Fnv1a(u64);
+
+impl Fnv1a {
+    pub(crate) fn new() -> Self {
+        Fnv1a(0xcbf2_9ce4_8422_2325)
+    }
+
+    pub(crate) fn write(&mut self, bytes: &[u8]) {
+        const PRIME: u64 = 0x0000_0100_0000_01b3;
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(PRIME);
+        }
+    }
+
+    pub(crate) fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Computes the checksum of one scanline from its lines and total cycle count.
+pub fn scanline_checksum(lines: &[String], total_cycles: usize) -> u64 {
+    let mut hash = Fnv1a::new();
+    for code in lines.iter().filter_map(|line| code_part(line)) {
+        hash.write(code.as_bytes());
+        hash.write(b"\n");
+    }
+    hash.write(total_cycles.to_string().as_bytes());
+    hash.finish()
+}
+
+/// Formats the checksum comment emitted after a scanline.
+pub fn checksum_comment(checksum: u64) -> String {
+    format!("{}{:016x}", CHECKSUM_PREFIX, checksum)
+}
+
+/// A scanline whose recorded checksum does not match its current content.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChecksumMismatch {
+    /// The 0-based index of the scanline.
+    pub scanline: usize,
+    /// The 1-based line number of the checksum comment in the verified file.
+    pub line_number: usize,
+    /// The checksum recorded in the file.
+    pub expected: String,
+    /// The checksum computed from the current content.
+    pub actual: String,
+}
+
+/// The outcome of verifying a generated file.
+#[derive(Debug, Clone, Default)]
+pub struct VerifyReport {
+    /// The number of scanlines carrying a checksum.
+    pub checked: usize,
+    /// All scanlines whose checksum does not match.
+    pub mismatches: Vec<ChecksumMismatch>,
+}
+
+impl VerifyReport {
+    /// Returns `true` if at least one checksum was found and all of them match.
+    pub fn is_ok(&self) -> bool {
+        self.checked > 0 && self.mismatches.is_empty()
+    }
+}
+
+/// Recomputes the checksum of every scanline in previously generated `content`.
+///
+/// A scanline consists of all lines since the previous checksum comment (or the start of the
+/// file) up to its `; Total cycles for scanline:` comment.
+pub fn verify(content: &str) -> VerifyReport {
+    let mut report = VerifyReport::default();
+    let mut scanline_lines: Vec<String> = Vec::new();
+    let mut total_cycles: Option<usize> = None;
+
+    for (index, line) in content.lines().enumerate() {
+        let trimmed = line.trim();
+        if let Some(total) = trimmed.strip_prefix(TOTAL_CYCLES_PREFIX) {
+            total_cycles = total.trim().parse().ok();
+        } else if let Some(expected) = trimmed.strip_prefix(CHECKSUM_PREFIX) {
+            let actual = format!(
+                "{:016x}",
+                scanline_checksum(&scanline_lines, total_cycles.unwrap_or(0))
+            );
+            if actual != expected.trim() {
+                report.mismatches.push(ChecksumMismatch {
+                    scanline: report.checked,
+                    line_number: index + 1,
+                    expected: expected.trim().to_string(),
+                    actual,
+                });
+            }
+            report.checked += 1;
+            scanline_lines.clear();
+            total_cycles = None;
+        } else {
+            scanline_lines.push(line.to_string());
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scanline(total: usize, code: &[&str]) -> String {
+        let lines: Vec<String> = code.iter().map(|s| s.to_string()).collect();
+        let checksum = scanline_checksum(&lines, total);
+        format!(
+            "{}\n{}{}\n{}\n",
+            code.join("\n"),
+            TOTAL_CYCLES_PREFIX,
+            total,
+            checksum_comment(checksum)
+        )
+    }
+
+    #[test]
+    fn test_checksum_ignores_comments_and_whitespace() {
+        let a = vec!["\tmove.w\td0,d1\t;\t(4)\t[0]".to_string()];
+        let b = vec![
+            "; a comment".to_string(),
+            "  move.w   d0,d1 ; (4) move.w dn,dn".to_string(),
+        ];
+        assert_eq!(scanline_checksum(&a, 4), scanline_checksum(&b, 4));
+        assert_ne!(scanline_checksum(&a, 4), scanline_checksum(&a, 8));
+    }
+
+    #[test]
+    fn test_verify_accepts_untouched_output() {
+        let content = format!(
+            "; header\nLINES\tequ 2\n{}{}",
+            scanline(8, &["\tmove.w\td0,d1", "\tnop"]),
+            scanline(8, &["\tmove.w\td2,d3", "\tnop"])
+        );
+        let report = verify(&content);
+        assert_eq!(report.checked, 2);
+        assert!(report.is_ok());
+    }
+
+    #[test]
+    fn test_verify_detects_hand_edits() {
+        let content = format!(
+            "{}{}",
+            scanline(8, &["\tmove.w\td0,d1", "\tnop"]),
+            scanline(8, &["\tmove.w\td2,d3", "\tnop"])
+        )
+        .replace("move.w\td2,d3", "move.l\t(a0)+,d3");
+        let report = verify(&content);
+        assert_eq!(report.checked, 2);
+        assert_eq!(report.mismatches.len(), 1);
+        assert_eq!(report.mismatches[0].scanline, 1);
+        assert_eq!(report.mismatches[0].line_number, 8);
+    }
+
+    #[test]
+    fn test_verify_without_checksums_is_not_ok() {
+        assert!(!verify("\tmove.w d0,d1\n").is_ok());
+    }
+}
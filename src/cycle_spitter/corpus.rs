@@ -0,0 +1,223 @@
+// src/cycle_spitter/corpus.rs
+
+//! Checking the tool against a corpus of real-world sources.
+//!
+//! How well the cycle database covers the 68000 is best judged on the code it is used for.
+//! [`check_corpus`] runs every source of a directory through the same steps as the command line
+//! tool - block validation, continuation joining, `REPT` expansion and generation - and reports
+//! the instructions missing from the database, the overflowing scanlines and any error.
+//!
+//! A corpus directory holds the sources as `*.s` files and the template they are generated with
+//! as `template.s`. With the `corpus` feature, `cargo test --features corpus` checks the
+//! `examples` directory and, if set, the directory named by `CYCLESPITTER_CORPUS` - real demo
+//! sources are mostly not ours to redistribute, so a larger corpus is kept outside the
+//! repository.
+
+use crate::cycle_spitter::baseline::RunStats;
+use crate::cycle_spitter::block::{
+    ExpansionLimits, expand_block, join_continuations, validate_block_structure,
+};
+use crate::cycle_spitter::database::CycleDatabase;
+use crate::cycle_spitter::generator::{find_unknown_instructions, generate_with_observer};
+use crate::cycle_spitter::observer::NullObserver;
+use crate::cycle_spitter::options::Options;
+use crate::cycle_spitter::source::{FileSource, SourceProvider};
+use crate::cycle_spitter::template::{TemplateSet, parse_template};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+/// The file of a corpus directory holding its template.
+pub const TEMPLATE_FILE: &str = "template.s";
+
+/// The outcome of checking one source.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CorpusReport {
+    /// The name of the source.
+    pub name: String,
+    /// The distinct instructions missing from the cycle database, sorted.
+    pub unknown: Vec<String>,
+    /// The statistics of the generation; all zero if it failed.
+    pub stats: RunStats,
+    /// The error that stopped reading or generating the source.
+    pub error: Option<String>,
+}
+
+impl CorpusReport {
+    /// Returns `true` if the source was generated without unknown instructions or overflows.
+    pub fn is_clean(&self) -> bool {
+        self.unknown.is_empty() && self.stats.overflows == 0 && self.error.is_none()
+    }
+}
+
+/// Checks a single source with `templates`.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::corpus::check_source;
+/// use cycle_spitter::database::CycleDatabase;
+/// use cycle_spitter::options::Options;
+/// use cycle_spitter::source::StringSource;
+/// use cycle_spitter::template::{TemplateSection, TemplateSet};
+///
+/// let db = CycleDatabase::builtin();
+/// let templates = TemplateSet::from(vec![TemplateSection::code_window(64, "code")]);
+/// let source = StringSource::new("demo", "rept 2\nmove.w d0,d1\nendr\nfrobnicate d0");
+/// let report = check_source(&source, &templates, &db, &Options::default());
+/// assert_eq!(report.unknown, ["frobnicate d0"]);
+/// assert_eq!(report.stats.scanlines, 1);
+/// ```
+pub fn check_source(
+    source: &dyn SourceProvider,
+    templates: &TemplateSet,
+    db: &CycleDatabase,
+    options: &Options,
+) -> CorpusReport {
+    let mut report = CorpusReport {
+        name: source.name(),
+        ..Default::default()
+    };
+    let lines = match read_source(source) {
+        Ok(lines) => lines,
+        Err(e) => {
+            report.error = Some(e.to_string());
+            return report;
+        }
+    };
+    report.unknown = find_unknown_instructions(&lines, db)
+        .iter()
+        .map(|line| line.trim().to_string())
+        .collect();
+    report.unknown.sort();
+    report.unknown.dedup();
+    match generate_with_observer(&lines, templates, db, options, &mut NullObserver) {
+        Ok(generation) => report.stats = RunStats::from_usage(&generation.usage, options),
+        Err(e) => report.error = Some(e.to_string()),
+    }
+    report
+}
+
+/// Reads `source` and expands it into the lines the generator works on.
+fn read_source(source: &dyn SourceProvider) -> Result<Vec<String>, Box<dyn Error>> {
+    let lines = source.lines()?;
+    if let Some(issue) = validate_block_structure(&lines).first() {
+        return Err(format!("line {}: {}", issue.line, issue.problem).into());
+    }
+    expand_block(&join_continuations(&lines), &ExpansionLimits::default())
+}
+
+/// Checks every `*.s` source of `dir` with the directory's `template.s`, in file name order.
+///
+/// # Errors
+/// If the directory or its template cannot be read.
+pub fn check_corpus(
+    dir: &Path,
+    db: &CycleDatabase,
+    options: &Options,
+) -> Result<Vec<CorpusReport>, Box<dyn Error>> {
+    let template_path = dir.join(TEMPLATE_FILE);
+    let template = fs::read_to_string(&template_path).map_err(|e| {
+        format!(
+            "Failed to read template file '{}': {}",
+            template_path.display(),
+            e
+        )
+    })?;
+    let templates = TemplateSet::from(parse_template(&template, db)?);
+
+    let mut paths: Vec<_> = fs::read_dir(dir)
+        .map_err(|e| format!("Failed to read corpus '{}': {}", dir.display(), e))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension().is_some_and(|ext| ext == "s")
+                && path.file_name().is_some_and(|name| name != TEMPLATE_FILE)
+        })
+        .collect();
+    paths.sort();
+    Ok(paths
+        .into_iter()
+        .map(|path| check_source(&FileSource::new(path), &templates, db, options))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cycle_spitter::source::StringSource;
+    use crate::cycle_spitter::template::TemplateSection;
+
+    #[test]
+    fn test_check_source_reports_overflows_and_errors() {
+        let db = CycleDatabase::builtin();
+        let templates = TemplateSet::from(vec![TemplateSection::code_window(8, "code")]);
+        let options = Options::builder().cycles_per_line(8).build();
+
+        let report = check_source(
+            &StringSource::new("clean", "move.w d0,d1\nmove.w d1,d2"),
+            &templates,
+            &db,
+            &options,
+        );
+        assert!(report.is_clean());
+
+        // The same window in a shorter scanline overflows it.
+        let short = Options::builder().cycles_per_line(4).build();
+        let report = check_source(
+            &StringSource::new("overflowing", "move.w d0,d1\nmove.w d1,d2"),
+            &templates,
+            &db,
+            &short,
+        );
+        assert_eq!(report.stats.overflows, 1);
+        assert!(!report.is_clean());
+
+        // An override larger than the window fails generation.
+        let report = check_source(
+            &StringSource::new("oversized", "divs d0,d1 ; (12)"),
+            &templates,
+            &db,
+            &options,
+        );
+        assert!(
+            report
+                .error
+                .unwrap()
+                .contains("'divs d0,d1' takes 12 cycles")
+        );
+
+        let report = check_source(
+            &StringSource::new("broken", "rept 2\nnop"),
+            &templates,
+            &db,
+            &options,
+        );
+        assert!(report.error.unwrap().starts_with("line 1: "));
+    }
+
+    /// Checks the example sources and the corpus named by `CYCLESPITTER_CORPUS`.
+    #[cfg(feature = "corpus")]
+    #[test]
+    fn test_corpus() {
+        let db = CycleDatabase::builtin();
+        let options = Options::default();
+        let mut dirs = vec![Path::new(env!("CARGO_MANIFEST_DIR")).join("examples")];
+        dirs.extend(std::env::var_os("CYCLESPITTER_CORPUS").map(Into::into));
+
+        let mut failures = Vec::new();
+        for dir in dirs {
+            for report in check_corpus(&dir, &db, &options).unwrap() {
+                if !report.is_clean() {
+                    failures.push(format!(
+                        "{}: {} unknown instruction(s) {:?}, {} overflow(s), error: {}",
+                        report.name,
+                        report.unknown.len(),
+                        report.unknown,
+                        report.stats.overflows,
+                        report.error.as_deref().unwrap_or("none")
+                    ));
+                }
+            }
+        }
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+    }
+}
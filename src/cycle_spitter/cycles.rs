@@ -16,14 +16,16 @@
 //! - **REG_SPACES**: Matches spaces and tabs.
 //! - **REG_BCC**: Matches branch conditions (`bt`, `bf`, etc.).
 //!
-//! ## Static Lookup Map
-//! - **CYCLES_MAP**: A `HashMap` populated from the `cycles.json` file that contains
-//!   instruction-to-cycle information. The JSON file is located at `db/cycles.json` within the project.
+//! ## Cycle Database
+//! Lookups are performed against a [`CycleDatabase`] passed in by the caller, so different
+//! databases can be used side by side. [`CycleDatabase::builtin`] loads the `db/cycles.json`
+//! file bundled with the project.
 //!
 //! ## Functions
-//! - `lookup_cycles`: Takes an assembly instruction line as input, normalizes it, and retrieves
-//!   the corresponding cycle count from the preloaded `CYCLES_MAP`. Throws a warning and
-//!   returns 0 in case the instruction is not found in the cycle database.
+//! - `lookup_cycles`: Takes an assembly instruction line and a database as input, normalizes the
+//!   line, and retrieves the corresponding cycle count from the database. Returns 0 in case the
+//!   instruction is not found in the cycle database; generation reports such instructions as
+//!   diagnostics (see the `diagnostics` module).
 //! - `normalize_line`: Normalizes an assembly instruction line by stripping extraneous spaces,
 //!   adjusting instruction formats, converting specific operands into standardized
 //!   placeholders for effective database lookups.
@@ -34,18 +36,46 @@
 //!
 //! ## Example
 //! ```rust
-//! use cycle_spitter::cycles::{lookup_cycles};
+//! use cycle_spitter::cycles::lookup_cycles;
+//! use cycle_spitter::database::CycleDatabase;
 //!
+//! let db = CycleDatabase::builtin();
 //! let line = " moveq #16,d0";
-//! let cycles = lookup_cycles(line);
-//! println!("Instruction: {}, Cycles: {}", line, cycles.cycles.join(", "));
+//! let cycles = lookup_cycles(line, &db);
+//! println!("Instruction: {}, Cycles: {:?}", line, cycles.get_cycles());
 //! ```
+//!
+//! ## Normalized Form Grammar
+//! The strings produced by [`normalize_line_ext`] are used as keys of the cycle database and
+//! may be used as keys by external tooling (e.g. override maps). Their shape is therefore part
+//! of the public API and guarded by [`NORMALIZATION_VERSION`]: any change to the produced
+//! output for an input that was previously accepted bumps the version and is released as a
+//! breaking change.
+//!
+//! ```text
+//! normalized := mnemonic [ " " operands ]
+//! mnemonic   := [a-z][a-z0-9]* [ "." ( "b" | "w" | "l" ) ]
+//! operands   := operand { "," operand }
+//! operand    := "dn" | "an" | "#xxx" | "xxx.w" | "xxx.l" | "reglist"
+//!             | "(an)" | "(an)+" | "-(an)" | "d(an)" | "d(an,ix)" | "d(pc)" | "d(pc,ix)"
+//!             | "sr" | "ccr" | "usp"
+//! ```
+//!
+//! - The whole string is lower case and contains exactly one space between mnemonic and operands.
+//! - Whitespace in the input is insignificant between syntactic elements: `move . w d0 , d1` and
+//!   `move.w d0,d1` normalize identically.
+//! - Unsized mnemonics receive `.w`; `lea`/`moveq` receive `.l`; `bcc.s` becomes `bcc.b`.
+//! - Comments, labels and surrounding whitespace never appear in the output.
+//!
+//! [`is_canonical`] checks whether a string conforms to this grammar.
 
 // Detailed Descriptions of Individual Components
 
 //! ### Static Variables
+//! - **REG_INDEXED**:
+//!   Matches indexed addressing patterns like `value(a3,d0.w)` or `value(pc,d0)`.
 //! - **REG_DISPLACEMENT**:
-//!   Matches displacement addressing patterns like `value(sp)` or `value(a3)`.
+//!   Matches displacement addressing patterns like `value(sp)`, `value(a3)` or `value(pc)`.
 //! - **REG_INSTRUCTION**:
 //!   Matches specific instructions `lea` or `moveq` that require `.l` suffix normalization.
 //! - **REG_IMMEDIATE**:
@@ -65,12 +95,13 @@
 //! Retrieves the number of execution cycles for a given instruction line.
 //! - **Parameters**:
 //!   - `line`: A `&str` containing the full assembly instruction line.
+//!   - `db`: The `CycleDatabase` to look the normalized instruction up in.
 //! - **Returns**:
 //!   - A `CycleCount` struct containing a `Vec<usize>` representing the cycle counts for the given instruction and a `String` representing the normalized instruction.
 //! - **Behavior**:
 //!   - Normalizes the input instruction using `normalize_line`.
-//!   - Performs a lookup in the `CYCLES_MAP`.
-//!   - If a match is not found, issues a warning on `stderr` and returns a `CycleCount` with a single zero cycle count.
+//!   - Performs a lookup in the given `CycleDatabase`.
+//!   - If a match is not found, returns a `CycleCount` with a single zero cycle count.
 //!
 //! ### `normalize_line` Function
 //! Standardizes an instruction line into a format suitable for efficient database lookup.
@@ -85,17 +116,29 @@
 //!   - Replaces certain operand patterns with placeholders (e.g., `dn`, `an`, `#xxx`, etc.).
 
 use once_cell::sync::Lazy;
-use std::collections::HashMap;
 
+use crate::cycle_spitter::database::{CycleDatabase, CycleSource};
+use crate::cycle_spitter::exception::{TRAP_KEY, trap_vector};
+use crate::cycle_spitter::literal::parse_literal;
 use crate::cycle_spitter::models::CycleCount;
 use regex::Regex;
 
+static REG_INDEXED: Lazy<Regex> = Lazy::new(|| {
+    // Matches an operand in the format: `[<displacement>](<base>,<index>[.<size>])`
+    // Example matches: `8(a0,d1.w)`, `(sp,a2.l)`, `table(pc,d0)`
+    // - `[^\s,()]*`: The optional displacement
+    // - `\((a[0-7]|sp|pc),`: The base, an address register, the stack pointer or the program counter
+    // - `([da][0-7]|sp)(\.[wl])?\)`: The index register with its optional size
+    Regex::new(r"[^\s,()]*\((a[0-7]|sp|pc),([da][0-7]|sp)(\.[wl])?\)").unwrap()
+});
+
 static REG_DISPLACEMENT: Lazy<Regex> = Lazy::new(|| {
-    // Matches an operand in the format: `<displacement>(<address_register>)`
-    // Example matches: `12(a0)`, `-4(sp)`
+    // Matches an operand in the format: `<displacement>(<base>)`
+    // Example matches: `12(a0)`, `-4(sp)`, `table(pc)`
     // - `[^\s,()]+`: Matches a series of characters that are not whitespace, commas, or parentheses
-    // - `\(a[0-7]|sp\)`: Matches an address register (`a0`-`a7`) or the stack pointer (`sp`) inside parentheses
-    Regex::new(r"([^\s,()]+)\((a[0-7]|sp)\)").unwrap()
+    // - `\(a[0-7]|sp|pc\)`: Matches an address register (`a0`-`a7`), the stack pointer (`sp`) or
+    //   the program counter (`pc`) inside parentheses
+    Regex::new(r"([^\s,()]+)\((a[0-7]|sp|pc)\)").unwrap()
 });
 
 static REG_INSTRUCTION: Lazy<Regex> = Lazy::new(|| {
@@ -106,6 +149,26 @@ static REG_INSTRUCTION: Lazy<Regex> = Lazy::new(|| {
     Regex::new(r"^(lea|moveq)$").unwrap()
 });
 
+static REG_LONG_ONLY: Lazy<Regex> = Lazy::new(|| {
+    // Matches instructions that only exist in long size besides `lea` and `moveq`
+    // Example matches: `exg`, `pea`
+    Regex::new(r"^(exg|pea)$").unwrap()
+});
+
+static REG_BYTE_ONLY: Lazy<Regex> = Lazy::new(|| {
+    // Matches instructions that only exist in byte size
+    // Example matches: `abcd`, `nbcd`, `tas`, `seq`, `st`
+    // - `(abcd|sbcd|nbcd|tas)`: BCD arithmetic and test-and-set
+    // - `s(t|f|hi|...)`: Scc, set according to condition
+    Regex::new(r"^(abcd|sbcd|nbcd|tas|s(t|f|hi|ls|cc|cs|ne|eq|vc|vs|pl|mi|ge|lt|gt|le))$").unwrap()
+});
+
+static REG_UNSIZED: Lazy<Regex> = Lazy::new(|| {
+    // Matches instructions without a size, which are looked up without a suffix
+    // Example matches: `nop`, `rts`, `trapv`, `unlk`, `jmp`
+    Regex::new(r"^(nop|rts|rte|rtr|reset|stop|trap|trapv|illegal|unlk|jmp|jsr)$").unwrap()
+});
+
 static REG_IMMEDIATE: Lazy<Regex> = Lazy::new(|| {
     // Matches immediate values prefixed with `#`
     // Example matches: `#123`, `#-45`
@@ -147,12 +210,11 @@ static REG_SPACES: Lazy<Regex> = Lazy::new(|| {
 });
 
 static REG_BCC: Lazy<Regex> = Lazy::new(|| {
-    // Matches branch instructions beginning with `b` (e.g., `bra`, `beq`) and optionally ending with `.s`
-    // Example matches: `bne`, `bra.s`
-    // - `^`: Asserts that the match occurs at the beginning of the string
-    // - `(b[A-Za-z]{2})`: Matches `b` followed by any two letters
-    // - `(\.s)?`: Optionally matches a `.s` suffix
-    Regex::new(r"^(b[A-Za-z]{2})(\.s)?").unwrap()
+    // Matches branch instructions (`bra`, `bsr` and `bcc`) with an optional size suffix
+    // Example matches: `bne`, `bra.s`, `bsr.w`; not `btst`
+    // - `^(b(ra|sr|hi|...))`: `b` followed by `ra`, `sr` or a condition code
+    // - `(\.[sbw])?$`: Optionally matches a size suffix, `.s` and `.b` meaning a short branch
+    Regex::new(r"^(b(?:ra|sr|hi|ls|cc|cs|ne|eq|vc|vs|pl|mi|ge|lt|gt|le))(\.[sbw])?$").unwrap()
 });
 
 static REG_LABEL_CHECK: Lazy<Regex> = Lazy::new(|| {
@@ -171,11 +233,50 @@ static REG_DOLLAR_CHECK: Lazy<Regex> = Lazy::new(|| {
     Regex::new(r"\$(\w+)(\.w)?([,;\n\t])?").unwrap()
 });
 
-static CYCLES_MAP: Lazy<HashMap<String, Vec<usize>>> = Lazy::new(|| {
-    let json_str = include_str!("db/cycles.json");
-    serde_json::from_str(json_str).expect("Error parsing cycles JSON")
+static REG_SIZE_SUFFIX: Lazy<Regex> = Lazy::new(|| {
+    // Matches a mnemonic whose size suffix is separated by whitespace
+    // Example matches: `move . w`, `move .l`, `add. b`
+    // - `^([a-z][a-z0-9]*)`: The mnemonic at the start of the (trimmed, lower case) line
+    // - `\s*\.\s*`: The dot, with optional whitespace on either side
+    // - `([bwls])\b`: The size letter
+    Regex::new(r"^([a-z][a-z0-9]*)\s*\.\s*([bwls])\b").unwrap()
+});
+
+static REG_OPERAND_SPACES: Lazy<Regex> = Lazy::new(|| {
+    // Matches whitespace around operand punctuation, which carries no meaning
+    // Example matches: ` , `, `( `, ` )`, ` / `, `# `
+    // - `\s*([,()+\-/#.])\s*`: A punctuation character with any surrounding whitespace
+    Regex::new(r"\s*([,()+\-/#.])\s*").unwrap()
 });
 
+static REG_CANONICAL: Lazy<Regex> = Lazy::new(|| {
+    // Matches a complete normalized instruction as described by the grammar in the module docs.
+    // - `[a-z][a-z0-9]*(\.[bwl])?`: The mnemonic with its optional size suffix
+    // - The operand group lists every placeholder the normalizer is allowed to emit
+    Regex::new(
+        r"^[a-z][a-z0-9]*(\.[bwl])?( (dn|an|#xxx|xxx\.[wl]|reglist|\(an\)\+?|-\(an\)|d\((an|pc)(,ix)?\)|sr|ccr|usp)(,(dn|an|#xxx|xxx\.[wl]|reglist|\(an\)\+?|-\(an\)|d\((an|pc)(,ix)?\)|sr|ccr|usp))*)?$",
+    )
+    .unwrap()
+});
+
+/// Version of the normalized output grammar produced by [`normalize_line_ext`].
+///
+/// Tools that persist normalized strings (e.g. as keys of override maps) should store this
+/// value alongside them and rebuild their keys when it changes.
+///
+/// - 1: Initial grammar.
+/// - 2: Unsized instructions (`nop`, `rts`, `trap`, ...) carry no size suffix; byte-only
+///   (`abcd`, `tas`, `scc`, ...) and long-only (`exg`, `pea`) instructions get their implied size.
+/// - 3: Indexed (`d(an,ix)`, `d(pc,ix)`) and PC-relative (`d(pc)`) operands, `sr`, `ccr` and
+///   `usp` are recognized and normalized keys are left unchanged; `btst`, `bset`, `bclr` and
+///   `bchg` are no longer taken for branches.
+/// - 4: Absolute addresses written in decimal or `%` binary (`1234.w`, `%100`) are normalized
+///   like `$` hexadecimal ones.
+/// - 5: Whitespace between syntactic elements is dropped: around the size suffix
+///   (`move . w` becomes `move.w`) and around operand punctuation (`d0 , d1`, `( a0 ) +`,
+///   `# 0`, `d0-d7 / a0-a6`), so spaced forms get the key of their unspaced form.
+pub const NORMALIZATION_VERSION: u32 = 5;
+
 // 1. New regex for register lists (placed with the other static regex definitions)
 static REG_REGLIST: Lazy<Regex> = Lazy::new(|| {
     // This regex matches a series of registers (d0 to d7 or a0 to a7)
@@ -207,7 +308,20 @@ fn count_registers(reg_list: &str) -> usize {
     }
     count
 }
-// 3. Extend the normalization function. We can create a new function that returns both the normalized string and reglist count.
+/// Normalizes an assembly line into its canonical lookup form.
+///
+/// Comments and leading labels are stripped, the mnemonic receives an explicit size suffix and
+/// every operand is replaced by its addressing-mode placeholder (see the grammar in the module
+/// docs). Register lists are collapsed into `reglist`; the number of registers they contained
+/// is returned alongside the normalized string.
+///
+/// # Returns
+/// A tuple of:
+/// - `String`: The normalized instruction, e.g. `movem.l reglist,-(an)`.
+/// - `usize`: The number of registers found in register lists (0 if there are none).
+///
+/// # Stability
+/// The output format is versioned by [`NORMALIZATION_VERSION`].
 pub fn normalize_line_ext(line: &str) -> (String, usize) {
     let line_without_comment = match line.find(';') {
         Some(idx) => &line[..idx],
@@ -220,30 +334,41 @@ pub fn normalize_line_ext(line: &str) -> (String, usize) {
         .to_string();
 
     let trimmed = line_without_label.trim().to_lowercase();
+    let trimmed = REG_SIZE_SUFFIX.replace(&trimmed, "$1.$2");
     let mut parts = trimmed.splitn(2, char::is_whitespace);
     let first_token = parts.next().unwrap();
-    let operand_part = parts.next().unwrap_or("");
+    let operand_part = REG_OPERAND_SPACES.replace_all(parts.next().unwrap_or("").trim(), "$1");
 
     // Process the instruction token (e.g. adding suffixes)
-    let first_token = if REG_INSTRUCTION.is_match(first_token) {
-        format!("{}.l", first_token)
-    } else if let Some(caps) = REG_BCC.captures(first_token) {
-        let trailing = caps.get(3).map_or("", |m| m.as_str());
-        if caps.get(2).is_some() {
-            format!("{}{}{}", &caps[1], ".b", trailing)
+    let first_token =
+        if REG_INSTRUCTION.is_match(first_token) || REG_LONG_ONLY.is_match(first_token) {
+            format!("{}.l", first_token)
+        } else if REG_BYTE_ONLY.is_match(first_token) {
+            format!("{}.b", first_token)
+        } else if REG_UNSIZED.is_match(first_token) {
+            first_token.to_string()
+        } else if let Some(caps) = REG_BCC.captures(first_token) {
+            if caps.get(2).is_some_and(|m| m.as_str() != ".w") {
+                format!("{}.b", &caps[1])
+            } else {
+                format!("{}.w", &caps[1])
+            }
+        } else if !first_token.contains('.') {
+            format!("{}.w", first_token)
         } else {
-            format!("{}{}{}", &caps[1], ".w", trailing)
-        }
-    } else if !first_token.contains('.') {
-        format!("{}.w", first_token)
-    } else {
-        first_token.to_string()
-    };
+            first_token.to_string()
+        };
 
     // Start processing the operands.
-    let mut operands = operand_part.to_string();
+    let mut operands = operand_part.into_owned();
 
-    // 3a. Replace displacement addressing operands.
+    // 3a. Replace indexed and displacement addressing operands.
+    operands = REG_INDEXED
+        .replace_all(&operands, |caps: &regex::Captures| {
+            let base = if &caps[1] == "pc" { "pc" } else { "an" };
+            format!("d({},ix)", base)
+        })
+        .into_owned();
     operands = REG_DISPLACEMENT
         .replace_all(&operands, |caps: &regex::Captures| {
             if &caps[1] == "-" {
@@ -278,7 +403,10 @@ pub fn normalize_line_ext(line: &str) -> (String, usize) {
             let before = caps.name("before").unwrap().as_str();
             let token = caps.name("token").unwrap().as_str();
             let suffix = caps.name("suffix").map(|m| m.as_str());
-            if token == "an" || token == "dn" || token == "d" {
+            if matches!(
+                token,
+                "an" | "dn" | "d" | "ix" | "pc" | "sr" | "ccr" | "usp" | "reglist"
+            ) {
                 caps.get(0).unwrap().as_str().to_string()
             } else if let Some(suf) = suffix {
                 if suf == ".w" {
@@ -296,7 +424,24 @@ pub fn normalize_line_ext(line: &str) -> (String, usize) {
     operands = REG_SPACES.replace_all(&operands, " ").into_owned();
     operands = operands.trim().to_owned();
 
-    // 3g. Handle '$'-prefixed variables.
+    // 3g. Replace absolute addresses written as numeric literals (`$ff8240.w`, `1234`, `%100.w`).
+    operands = operands
+        .split(',')
+        .map(|operand| {
+            let (value, size) = match operand.rsplit_once('.') {
+                Some((value, size @ ("w" | "l"))) => (value, size),
+                _ => (operand, "l"),
+            };
+            if parse_literal(value).is_some() {
+                format!("xxx.{}", size)
+            } else {
+                operand.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+
+    // 3h. Handle any other '$'-prefixed variables.
     operands = if operands.contains('$') {
         REG_DOLLAR_CHECK
             .replace_all(&operands, |caps: &regex::Captures| {
@@ -323,17 +468,28 @@ pub fn normalize_line_ext(line: &str) -> (String, usize) {
     (normalized, reg_count)
 }
 
-// 4. Update the CycleCount struct to include register count.
+/// Checks whether `normalized` conforms to the normalized form grammar documented in this module.
+///
+/// This only validates the shape of the string; it does not check whether the instruction
+/// exists in the cycle database.
+pub fn is_canonical(normalized: &str) -> bool {
+    REG_CANONICAL.is_match(normalized)
+}
 
 // 5. Update lookup_cycles to use the extended normalization.
-pub fn lookup_cycles(line: &str) -> CycleCount {
+pub fn lookup_cycles(line: &str, db: &CycleDatabase) -> CycleCount {
     let (normalized, reg_count) = normalize_line_ext(line);
 
-    if let Some(cycles) = CYCLES_MAP.get(normalized.as_str()) {
-        CycleCount::new(cycles.clone(), normalized, reg_count)
-    } else {
-        eprintln!("Warning: No cycle count found for instruction: {}", line);
-        CycleCount::new(vec![0], normalized, reg_count)
+    let cycles = db
+        .cycle_count(&normalized, reg_count)
+        .unwrap_or_else(|| CycleCount::new(vec![0], normalized.clone(), reg_count));
+    match trap_vector(line).and_then(|vector| db.trap_handler(vector)) {
+        Some(handler) if normalized == TRAP_KEY => {
+            CycleCount::new(vec![cycles.base() + handler], normalized, reg_count)
+                .with_kind(cycles.kind())
+                .with_source(CycleSource::Handler)
+        }
+        _ => cycles,
     }
 }
 
@@ -341,11 +497,11 @@ pub fn lookup_cycles(line: &str) -> CycleCount {
 mod tests {
     use super::*;
 
-    /// Test that `lookup_cycles` works with valid instructions present in the `CYCLES_MAP`.
+    /// Test that `lookup_cycles` works with valid instructions present in the builtin database.
     #[test]
     fn test_lookup_cycles_valid_instruction() {
         let line = "moveq #16,d0";
-        let cycles = lookup_cycles(line);
+        let cycles = lookup_cycles(line, &CycleDatabase::builtin());
         assert!(
             !cycles.get_cycles().is_empty(),
             "Valid instruction should return a non-empty cycle count."
@@ -356,7 +512,7 @@ mod tests {
     #[test]
     fn test_lookup_cycles_unknown_instruction() {
         let line = "unknown_op #42,d1";
-        let cycles = lookup_cycles(line);
+        let cycles = lookup_cycles(line, &CycleDatabase::builtin());
         assert_eq!(
             cycles.get_cycles(),
             vec![0],
@@ -364,6 +520,31 @@ mod tests {
         );
     }
 
+    /// Test that `lookup_cycles` only consults the database it is given.
+    #[test]
+    fn test_lookup_cycles_uses_injected_database() {
+        let db = CycleDatabase::from_json_str(r#"{ "move.w dn,dn": [6] }"#).unwrap();
+        assert_eq!(lookup_cycles("move.w d0,d1", &db).get_cycles(), vec![6]);
+        assert_eq!(lookup_cycles("moveq #1,d0", &db).get_cycles(), vec![0]);
+    }
+
+    /// Test that `lookup_cycles` reads the values as the database's kinds say.
+    #[test]
+    fn test_lookup_cycles_interprets_kinds() {
+        let db = CycleDatabase::from_json_str(
+            r#"{
+                "move.w (an),dn": { "kind": "range", "cycles": [8, 12] },
+                "movem.l (an)+,reglist": [12, 8],
+                "bne.b xxx.l": [10, 8]
+            }"#,
+        )
+        .unwrap();
+        let range = lookup_cycles("move.w (a0),d0", &db);
+        assert_eq!((range.best(), range.worst(), range.total()), (8, 12, 12));
+        assert_eq!(lookup_cycles("movem.l (a0)+,d0-d2", &db).total(), 36);
+        assert_eq!(lookup_cycles("bne.s loop", &db).total(), 10);
+    }
+
     /// Test that `lookup_cycles` handles instructions with normalized cases.
     #[test]
     fn test_lookup_cycles_normalized_instruction() {
@@ -467,6 +648,25 @@ mod tests {
         );
     }
 
+    /// Test normalization of indexed, PC-relative and special register operands.
+    #[test]
+    fn test_normalize_indexed_and_special_operands() {
+        for (line, expected) in [
+            ("move.w 8(a0,d1.w),d0", "move.w d(an,ix),dn"),
+            ("move.l (sp,a2.l),d0", "move.l d(an,ix),dn"),
+            ("lea table(pc),a0", "lea.l d(pc),an"),
+            ("move.w table(pc,d0),d1", "move.w d(pc,ix),dn"),
+            ("move.w d0,sr", "move.w dn,sr"),
+            ("andi.b #$fe,ccr", "andi.b #xxx,ccr"),
+            ("move.l usp,a0", "move.l usp,an"),
+            ("btst #1,(a0)", "btst.w #xxx,(an)"),
+            ("bra.b loop", "bra.b xxx.l"),
+            ("bsr.w sub", "bsr.w xxx.l"),
+        ] {
+            assert_eq!(normalize_line_ext(line).0, expected, "{}", line);
+        }
+    }
+
     /// Test register normalization.
     #[test]
     fn test_normalize_registers() {
@@ -497,6 +697,12 @@ mod tests {
             expected,
             "Absolute addressing should be normalized to `xxx.l`."
         );
+        assert_eq!(normalize_line_ext("move.w 1234.w,d0").0, "move.w xxx.w,dn");
+        assert_eq!(normalize_line_ext("move.l %100,a0").0, "move.l xxx.l,an");
+        assert_eq!(
+            normalize_line_ext("move.w d0,$ff8240.w").0,
+            "move.w dn,xxx.w"
+        );
     }
 
     /// Test that malformed instructions don't cause crashes.
@@ -542,6 +748,53 @@ mod tests {
         );
     }
 
+    /// Test that whitespace between syntactic elements does not change the result, using the
+    /// spacing found in old hand-written sources.
+    #[test]
+    fn test_normalize_whitespace_between_elements() {
+        let cases = [
+            ("move . w d0,d1", "move.w dn,dn"),
+            ("MOVE .W\tD0 , D1", "move.w dn,dn"),
+            ("move.w\t\td0,\td1", "move.w dn,dn"),
+            ("\tmove.l\t(a0)+ , (a1)+\t\t; copy", "move.l (an)+,(an)+"),
+            ("move.l ( a0 ) + ,d0", "move.l (an)+,dn"),
+            ("move.w d0 , - ( sp )", "move.w dn,-(an)"),
+            ("move.b  d0 , 12 ( a1 )", "move.b dn,d(an)"),
+            ("movem.l  d0-d7 / a0-a6 , -(sp)", "movem.l reglist,-(an)"),
+            ("lea\t\t$ffff8240 . w ,a0", "lea.l xxx.w,an"),
+            ("moveq # 0 , d0", "moveq.l #xxx,dn"),
+            ("loop:  add . l   d0 ,d1", "add.l dn,dn"),
+        ];
+        for (line, expected) in cases {
+            assert_eq!(normalize_line_ext(line).0, expected, "input: {:?}", line);
+        }
+        assert_eq!(normalize_line_ext("movem.l  d0-d7 / a0-a6 , -(sp)").1, 15);
+    }
+
+    /// Test that the keys of spaced forms, which changed with version 5, stay as released.
+    #[test]
+    fn test_normalization_version_pins_spaced_forms() {
+        assert_eq!(NORMALIZATION_VERSION, 5);
+        let pinned: Vec<String> = [
+            "move.w d0 , d1",
+            "move . w d0,d1",
+            "move.l ( a0 ) + , d0",
+            "moveq # 0 , d0",
+        ]
+        .iter()
+        .map(|line| normalize_line_ext(line).0)
+        .collect();
+        assert_eq!(
+            pinned,
+            [
+                "move.w dn,dn",
+                "move.w dn,dn",
+                "move.l (an)+,dn",
+                "moveq.l #xxx,dn"
+            ]
+        );
+    }
+
     /// Test branch condition with missing suffixes.
     #[test]
     fn test_branch_normalization_with_suffix() {
@@ -558,6 +811,67 @@ mod tests {
         );
     }
 
+    /// Test that rarely used instructions resolve to database entries with their implied size.
+    #[test]
+    fn test_rare_instructions_are_in_database() {
+        let db = CycleDatabase::builtin();
+        for (line, normalized) in [
+            ("tas (a0)", "tas.b (an)"),
+            ("chk d1,d0", "chk.w dn,dn"),
+            ("trapv", "trapv"),
+            ("abcd -(a0),-(a1)", "abcd.b -(an),-(an)"),
+            ("sbcd d0,d1", "sbcd.b dn,dn"),
+            ("nbcd d0", "nbcd.b dn"),
+            ("exg d0,a1", "exg.l dn,an"),
+            ("link a6,#-8", "link.w an,#xxx"),
+            ("unlk a6", "unlk an"),
+            ("trap #1", "trap #xxx"),
+            ("stop #$2700", "stop #xxx"),
+            ("seq d1", "seq.b dn"),
+            ("st $ffff820a.w", "st.b xxx.w"),
+            ("pea 4(a0)", "pea.l d(an)"),
+            ("jmp (a0)", "jmp (an)"),
+            ("nop", "nop"),
+            ("rts", "rts"),
+        ] {
+            assert_eq!(normalize_line_ext(line).0, normalized, "{}", line);
+            assert!(db.get(normalized).is_some(), "{} missing", normalized);
+        }
+    }
+
+    /// Test that normalized output conforms to the documented grammar.
+    #[test]
+    fn test_normalized_output_is_canonical() {
+        for line in [
+            "moveq #16,d0",
+            "movem.l d0-d7/a0-a6,-(sp)",
+            "lea 100(sp),a1",
+            "move.w d0,$ffff8240.w",
+            "bne.s label",
+            ".loop:\ttst.w d0 ; comment",
+        ] {
+            let normalized = normalize_line_ext(line).0;
+            assert!(
+                is_canonical(&normalized),
+                "`{}` normalized to non-canonical `{}`",
+                line,
+                normalized
+            );
+        }
+    }
+
+    /// Test that the canonical-form checker rejects raw source text.
+    #[test]
+    fn test_is_canonical_rejects_raw_lines() {
+        assert!(is_canonical("move.w dn,d(an)"));
+        assert!(is_canonical("rts"));
+        assert!(!is_canonical("move.w d0,d1"));
+        assert!(!is_canonical("MOVE.W dn,dn"));
+        assert!(!is_canonical("move.w  dn,dn"));
+        assert!(!is_canonical("move.w dn,dn ; comment"));
+        assert!(!is_canonical("label: move.w dn,dn"));
+    }
+
     /// Test fallback for unknown tokens in normalization.
     #[test]
     fn test_normalize_unknown_tokens() {
@@ -0,0 +1,383 @@
+// src/cycle_spitter/data.rs
+
+//! Data directives that reserve or include memory instead of executing.
+//!
+//! `dc.*`, `ds.*`, `dcb.*` (other than NOP blocks) and `incbin` take up space in the program but
+//! no cycles. They are excluded from cycle accounting - inside a timed scanline they are almost
+//! certainly a mistake, so the accumulator warns about them - and their byte footprint is
+//! summed up in a [`SizeReport`].
+//!
+//! Byte data of odd size leaves the following code on an odd address. Assemblers fix this by
+//! padding implicitly, or refuse to assemble; [`misaligned_code`] finds the places where an
+//! `even` directive is needed, so the padding byte is placed before any label.
+
+use crate::cycle_spitter::block::parse_directive;
+use crate::cycle_spitter::literal::parse_literal;
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+/// A recognized data directive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DataDirective {
+    /// `ds.<size> <count>`: reserved, uninitialized space.
+    Ds { bytes: Option<usize> },
+    /// `dcb.<size> <count>,<value>`: a block of repeated values.
+    Dcb { bytes: Option<usize> },
+    /// `dc.<size> <values>`: constant data.
+    Dc { bytes: Option<usize> },
+    /// `incbin <path>`: an included binary file; its size is only known once resolved.
+    Incbin { path: String },
+    /// `even`: aligns to the next even address, taking one padding byte at most.
+    Even,
+}
+
+static BLOCK_RE: Lazy<Regex> = Lazy::new(|| {
+    // Matches `ds` and `dcb` directives, optionally preceded by a label
+    // Example matches: `ds.w 10`, `buffer: ds.b $100`, `dcb.l 4,0`
+    // - `(?:[a-z_.][a-z0-9_.]*:?\s+)?`: An optional label, with or without colon
+    // - `(?P<directive>ds|dcb)\.(?P<size>[bwl])`: The directive and its element size
+    // - `(?P<count>[^,;\s]+)`: The element count
+    // - `(?:\s*,\s*(?P<value>[^;\s]+))?`: The fill value of `dcb`
+    Regex::new(
+        r"(?i)^\s*(?:[a-z_.][a-z0-9_.]*:?\s+)?(?P<directive>ds|dcb)\.(?P<size>[bwl])\s+(?P<count>[^,;\s]+)(?:\s*,\s*(?P<value>[^;\s]+))?",
+    )
+    .unwrap()
+});
+
+static INCBIN_RE: Lazy<Regex> = Lazy::new(|| {
+    // Matches `incbin` directives, optionally preceded by a label
+    // Example matches: `incbin "logo.bin"`, `font: incbin font.dat`
+    // - `(?P<path>[^"';\s]+)`: The file path, optionally quoted
+    Regex::new(r#"(?i)^\s*(?:[a-z_.][a-z0-9_.]*:?\s+)?incbin\s+["']?(?P<path>[^"';\s]+)"#).unwrap()
+});
+
+static DC_RE: Lazy<Regex> = Lazy::new(|| {
+    // Matches `dc` directives, optionally preceded by a label
+    // Example matches: `dc.b "hello",0`, `table: dc.w 1,2,3`, `dc $4e71`
+    // - `dc(?:\.(?P<size>[bwl]))?`: The directive and its element size (word by default)
+    // - `(?P<values>.*)`: The values, including any comment
+    Regex::new(r"(?i)^\s*(?:[a-z_.][a-z0-9_.]*:?\s+)?dc(?:\.(?P<size>[bwl]))?\s+(?P<values>.*)$")
+        .unwrap()
+});
+
+static EVEN_RE: Lazy<Regex> = Lazy::new(|| {
+    // Matches the `even` directive, optionally preceded by a label
+    // Example matches: `even`, `  EVEN ; align`, `.done: even`
+    Regex::new(r"(?i)^\s*(?:[a-z_.][a-z0-9_.]*:?\s+)?\.?even\s*(?:;.*)?$").unwrap()
+});
+
+/// Returns the size of the values of a `dc` directive, `None` if a value is empty. Strings are
+/// padded to whole elements.
+fn dc_bytes(values: &str, size: usize) -> Option<usize> {
+    let mut bytes = 0;
+    let mut value = String::new();
+    let mut quote = None;
+    let flush = |value: &mut String| -> Option<usize> {
+        let trimmed = value.trim();
+        let length = match trimmed.chars().next()? {
+            q @ ('"' | '\'') => trimmed.trim_matches(q).chars().count().div_ceil(size) * size,
+            _ => size,
+        };
+        value.clear();
+        Some(length)
+    };
+    for c in values.chars() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == ';' => break,
+            None if c == ',' => {
+                bytes += flush(&mut value)?;
+                continue;
+            }
+            None => {}
+        }
+        value.push(c);
+    }
+    bytes += flush(&mut value)?;
+    Some(bytes)
+}
+
+/// Returns the data directive on `line`, if any. A `dcb.w <count>,$4e71` is a block of NOPs,
+/// i.e. code, and not a data directive.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::data::{DataDirective, parse_data_directive};
+///
+/// assert_eq!(parse_data_directive("ds.l 4"), Some(DataDirective::Ds { bytes: Some(16) }));
+/// assert_eq!(parse_data_directive("dcb.w 3,$4e71"), None);
+/// ```
+pub fn parse_data_directive(line: &str) -> Option<DataDirective> {
+    if let Some(caps) = INCBIN_RE.captures(line) {
+        return Some(DataDirective::Incbin {
+            path: caps["path"].to_string(),
+        });
+    }
+    if EVEN_RE.is_match(line) {
+        return Some(DataDirective::Even);
+    }
+    if let Some(caps) = DC_RE.captures(line) {
+        let size = caps.name("size").map_or(2, |m| element_size(m.as_str()));
+        return Some(DataDirective::Dc {
+            bytes: dc_bytes(&caps["values"], size),
+        });
+    }
+    let caps = BLOCK_RE.captures(line)?;
+    let size = match caps["size"].to_lowercase().as_str() {
+        "b" => 1,
+        "w" => 2,
+        _ => 4,
+    };
+    let bytes = parse_literal(&caps["count"]).map(|count| count * size);
+    if caps["directive"].eq_ignore_ascii_case("ds") {
+        return Some(DataDirective::Ds { bytes });
+    }
+    let is_nop = size == 2
+        && caps
+            .name("value")
+            .is_some_and(|v| v.as_str().eq_ignore_ascii_case("$4e71"));
+    (!is_nop).then_some(DataDirective::Dcb { bytes })
+}
+
+/// Returns the size in bytes of the `b`, `w` or `l` element size suffix.
+fn element_size(suffix: &str) -> usize {
+    match suffix.to_lowercase().as_str() {
+        "b" => 1,
+        "w" => 2,
+        _ => 4,
+    }
+}
+
+/// Returns whether the data directive on `line` takes an odd number of bytes: `Some(true)` or
+/// `Some(false)` if known, `None` for byte data of unknown size. Word and long data are always
+/// even.
+fn odd_size(line: &str) -> Option<bool> {
+    let size = BLOCK_RE
+        .captures(line)
+        .map(|caps| caps["size"].to_string())
+        .or_else(|| {
+            DC_RE
+                .captures(line)
+                .map(|caps| caps.name("size").map_or("w", |m| m.as_str()).to_string())
+        });
+    if size.is_some_and(|size| element_size(&size) > 1) {
+        return Some(false);
+    }
+    match parse_data_directive(line)? {
+        DataDirective::Ds { bytes }
+        | DataDirective::Dcb { bytes }
+        | DataDirective::Dc { bytes } => bytes.map(|bytes| bytes % 2 == 1),
+        DataDirective::Incbin { .. } => None,
+        DataDirective::Even => Some(false),
+    }
+}
+
+/// Returns `true` for lines the assembler turns into machine code.
+fn is_code(line: &str) -> bool {
+    let trimmed = line.trim();
+    let label_only = trimmed.ends_with(':') && !trimmed.contains(char::is_whitespace);
+    !(trimmed.is_empty()
+        || trimmed.starts_with(';')
+        || label_only
+        || line.contains(" set ")
+        || line.contains(" equ ")
+        || parse_directive(line).is_some()
+        || parse_data_directive(line).is_some())
+}
+
+/// Finds code that follows byte data of odd (or unknown) size without an `even` in between.
+///
+/// # Returns
+/// The indices at which an `even` must be inserted: right after the last data directive
+/// before the code, so labels between the data and the code are aligned as well.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::data::misaligned_code;
+///
+/// let lines: Vec<String> = ["dc.b \"abc\"", "loop:", "move.w d0,d1"].map(String::from).to_vec();
+/// assert_eq!(misaligned_code(&lines), [1]);
+/// ```
+pub fn misaligned_code(lines: &[String]) -> Vec<usize> {
+    let mut positions = Vec::new();
+    // Whether the data since the last aligned point has odd size, `None` if unknown
+    let mut odd = Some(false);
+    let mut after_data = 0;
+    for (index, line) in lines.iter().enumerate() {
+        if parse_data_directive(line).is_some() {
+            if matches!(parse_data_directive(line), Some(DataDirective::Even)) {
+                odd = Some(false);
+            } else {
+                odd = match (odd, odd_size(line)) {
+                    (Some(odd), Some(size)) => Some(odd != size),
+                    _ => None,
+                };
+            }
+            after_data = index + 1;
+        } else if is_code(line) {
+            if odd != Some(false) {
+                positions.push(after_data);
+            }
+            odd = Some(false);
+        }
+    }
+    positions
+}
+
+/// One data directive found in the input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SizeEntry {
+    /// The index of the line in the input.
+    pub index: usize,
+    /// The line holding the directive.
+    pub line: String,
+    /// The footprint in bytes, `None` if it could not be determined.
+    pub bytes: Option<usize>,
+}
+
+/// The byte footprint of the data directives in the input.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SizeReport {
+    pub entries: Vec<SizeEntry>,
+}
+
+impl SizeReport {
+    /// The total footprint of all directives with a known size.
+    pub fn total_bytes(&self) -> usize {
+        self.entries.iter().filter_map(|e| e.bytes).sum()
+    }
+
+    /// The directives whose size could not be determined.
+    pub fn unknown(&self) -> impl Iterator<Item = &SizeEntry> {
+        self.entries.iter().filter(|e| e.bytes.is_none())
+    }
+}
+
+/// Collects the data directives of `lines`. The size of an `incbin` is determined by
+/// `resolve_incbin`, which receives the included path.
+///
+/// # Arguments
+/// - `lines`: The input lines.
+/// - `resolve_incbin`: Returns the size of an included file, or `None` if it is unavailable.
+///
+/// # Returns
+/// A `SizeReport` with one entry per directive, in input order.
+pub fn size_report<F>(lines: &[String], resolve_incbin: F) -> SizeReport
+where
+    F: Fn(&str) -> Option<usize>,
+{
+    let entries = lines
+        .iter()
+        .enumerate()
+        .filter_map(|(index, line)| {
+            let bytes = match parse_data_directive(line)? {
+                DataDirective::Ds { bytes }
+                | DataDirective::Dcb { bytes }
+                | DataDirective::Dc { bytes } => bytes,
+                DataDirective::Incbin { path } => resolve_incbin(&path),
+                // Alignment is not data
+                DataDirective::Even => return None,
+            };
+            Some(SizeEntry {
+                index,
+                line: line.clone(),
+                bytes,
+            })
+        })
+        .collect();
+    SizeReport { entries }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_data_directive() {
+        assert_eq!(
+            parse_data_directive("buffer: ds.b $100 ; scratch"),
+            Some(DataDirective::Ds { bytes: Some(256) })
+        );
+        assert_eq!(
+            parse_data_directive("table DS.W %101"),
+            Some(DataDirective::Ds { bytes: Some(10) })
+        );
+        assert_eq!(
+            parse_data_directive("dcb.l 4,0"),
+            Some(DataDirective::Dcb { bytes: Some(16) })
+        );
+        assert_eq!(
+            parse_data_directive("ds.w SIZE*2"),
+            Some(DataDirective::Ds { bytes: None })
+        );
+        assert_eq!(
+            parse_data_directive("incbin \"gfx/logo.bin\""),
+            Some(DataDirective::Incbin {
+                path: "gfx/logo.bin".to_string()
+            })
+        );
+        assert_eq!(parse_data_directive("dcb.w 5, $4E71"), None);
+        assert_eq!(parse_data_directive("move.w d0,d1"), None);
+        assert_eq!(parse_data_directive("; ds.w 10"), None);
+        assert_eq!(
+            parse_data_directive("msg: dc.b \"Hi, there\",0 ; text"),
+            Some(DataDirective::Dc { bytes: Some(10) })
+        );
+        assert_eq!(
+            parse_data_directive("dc.w 1,2,'abc'"),
+            Some(DataDirective::Dc { bytes: Some(8) })
+        );
+        assert_eq!(
+            parse_data_directive("DC.L table"),
+            Some(DataDirective::Dc { bytes: Some(4) })
+        );
+        assert_eq!(
+            parse_data_directive("dc.b 1,,2"),
+            Some(DataDirective::Dc { bytes: None })
+        );
+        assert_eq!(
+            parse_data_directive("  EVEN ; align"),
+            Some(DataDirective::Even)
+        );
+        assert_eq!(parse_data_directive("dcb.w 5,$4e71 ; even"), None);
+    }
+
+    #[test]
+    fn test_misaligned_code() {
+        let lines = |raw: &[&str]| raw.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        // Aligned: even sized, word data, explicit even
+        assert!(misaligned_code(&lines(&["dc.b 1,2", "ds.w COUNT", "nop"])).is_empty());
+        assert!(misaligned_code(&lines(&["dc.b 1", "even", "nop"])).is_empty());
+        assert!(misaligned_code(&lines(&["dc.b 1", "dc.b 2", "nop"])).is_empty());
+        // Odd or unknown sized byte data before code
+        assert_eq!(
+            misaligned_code(&lines(&[
+                "nop",
+                "dc.b \"abc\"",
+                "; comment",
+                "label:",
+                "move.w d0,d1",
+                "ds.b SIZE",
+                "rts",
+                "incbin data.bin",
+            ])),
+            [2, 6]
+        );
+    }
+
+    #[test]
+    fn test_size_report() {
+        let lines: Vec<String> = ["ds.w 8", "move.w d0,d1", "incbin a.bin", "incbin b.bin"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let report = size_report(&lines, |path| (path == "a.bin").then_some(100));
+
+        assert_eq!(report.entries.len(), 3);
+        assert_eq!(report.entries[1].index, 2);
+        assert_eq!(report.total_bytes(), 116);
+        assert_eq!(report.unknown().count(), 1);
+    }
+}
@@ -0,0 +1,534 @@
+// src/cycle_spitter/database.rs
+
+//! The cycle database.
+//!
+//! # File Format
+//! A database file is a versioned JSON document of typed entries:
+//!
+//! ```json
+//! {
+//!   "version": 2,
+//!   "entries": {
+//!     "move.w dn,dn": { "kind": "plain", "base": 4 },
+//!     "bne.b xxx.l": { "kind": "branch", "base": 8, "taken": 10 },
+//!     "movem.l (an)+,reglist": { "kind": "reglist", "base": 12, "per_reg": 8,
+//!                                "source": "MC68000UM table 8-13" }
+//!   }
+//! }
+//! ```
+//!
+//! Documents without a `version` are read as version 1, the flat format mapping every key to
+//! an array of cycle values (optionally tagged with its kind, `{ "kind": "range", "cycles":
+//! [8, 12] }`), and migrated on load. [`CycleDatabase::to_json_string`] writes the current
+//! version.
+
+use crate::cycle_spitter::models::CycleCount;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// The version of the database file format written by [`CycleDatabase::to_json_string`].
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// How the cycle values of a database entry are to be read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CycleKind {
+    /// A single value.
+    Plain,
+    /// `[not taken, taken]` (and further outcomes, e.g. `dbcc`'s expired counter).
+    Branch,
+    /// `[base, per register]`.
+    Reglist,
+    /// `[best, worst]`, for timings depending on the circumstances (e.g. bus contention).
+    Range,
+}
+
+impl CycleKind {
+    /// Parses a kind name (`plain`, `branch`, `reglist` or `range`, in any case).
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "plain" => Some(CycleKind::Plain),
+            "branch" => Some(CycleKind::Branch),
+            "reglist" => Some(CycleKind::Reglist),
+            "range" => Some(CycleKind::Range),
+            _ => None,
+        }
+    }
+
+    /// The kind of an untagged entry: a register list if the key holds one, a branch if there
+    /// are several values, plain otherwise.
+    pub fn infer(normalized: &str, cycles: &[usize]) -> Self {
+        if normalized.contains("reglist") {
+            CycleKind::Reglist
+        } else if cycles.len() > 1 {
+            CycleKind::Branch
+        } else {
+            CycleKind::Plain
+        }
+    }
+}
+
+/// Where a cycle count came from, marked in annotations (e.g. `(20*ovr)`) so reviewers can
+/// audit the numbers that did not come straight from the database.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CycleSource {
+    /// An entry of the cycle database, as is.
+    #[default]
+    Database,
+    /// An inline `(n)` override.
+    Override,
+    /// An entry corrected by an adjustment file (see the `adjust` module).
+    Adjusted,
+    /// The worst case of a range, by the worst-case policy of scanline accounting.
+    WorstCase,
+    /// A `trap` entry plus the cycles given for its handler (see the `exception` module).
+    Handler,
+}
+
+impl CycleSource {
+    /// The marker appended to the cycles of an annotation, `None` for database values.
+    pub fn marker(self) -> Option<&'static str> {
+        match self {
+            CycleSource::Database => None,
+            CycleSource::Override => Some("ovr"),
+            CycleSource::Adjusted => Some("adj"),
+            CycleSource::WorstCase => Some("max"),
+            CycleSource::Handler => Some("exc"),
+        }
+    }
+
+    /// Parses a marker as written by [`CycleSource::marker`].
+    pub fn from_marker(marker: &str) -> Option<Self> {
+        match marker {
+            "ovr" => Some(CycleSource::Override),
+            "adj" => Some(CycleSource::Adjusted),
+            "max" => Some(CycleSource::WorstCase),
+            "exc" => Some(CycleSource::Handler),
+            _ => None,
+        }
+    }
+}
+
+/// A typed database entry.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CycleEntry {
+    pub kind: CycleKind,
+    /// The cycles of a plain instruction, a branch not taken, a register list without
+    /// registers or the best case of a range.
+    pub base: usize,
+    /// The cycles per register of a register list.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub per_reg: Option<usize>,
+    /// The cycles of a branch taken.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub taken: Option<usize>,
+    /// The cycles of a `dbcc` whose counter expires.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expired: Option<usize>,
+    /// The worst case of a range.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub worst: Option<usize>,
+    /// Free-form remarks, e.g. on the circumstances of a range.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+    /// Where the timing comes from, e.g. a manual table or a measurement.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+}
+
+impl CycleEntry {
+    /// Creates an entry from positional cycle values (see [`CycleDatabase`]).
+    ///
+    /// # Example
+    /// ```rust
+    /// use cycle_spitter::database::{CycleEntry, CycleKind};
+    ///
+    /// let entry = CycleEntry::from_values(CycleKind::Branch, &[10, 12, 14]);
+    /// assert_eq!((entry.base, entry.taken, entry.expired), (10, Some(12), Some(14)));
+    /// assert_eq!(entry.values(), [10, 12, 14]);
+    /// ```
+    pub fn from_values(kind: CycleKind, values: &[usize]) -> Self {
+        let at = |index: usize| values.get(index).copied();
+        let (per_reg, taken, expired, worst) = match kind {
+            CycleKind::Plain => (None, None, None, None),
+            CycleKind::Branch => (None, at(1), at(2), None),
+            CycleKind::Reglist => (at(1), None, None, None),
+            CycleKind::Range => (None, None, None, at(1)),
+        };
+        CycleEntry {
+            kind,
+            base: at(0).unwrap_or(0),
+            per_reg,
+            taken,
+            expired,
+            worst,
+            notes: None,
+            source: None,
+        }
+    }
+
+    /// Returns the positional cycle values of the entry (see [`CycleDatabase`]).
+    pub fn values(&self) -> Vec<usize> {
+        let mut values = vec![self.base];
+        match self.kind {
+            CycleKind::Plain => {}
+            CycleKind::Branch => values.extend(self.taken.into_iter().chain(self.expired)),
+            CycleKind::Reglist => values.push(self.per_reg.unwrap_or(0)),
+            CycleKind::Range => values.push(self.worst.unwrap_or(self.base)),
+        }
+        values
+    }
+
+    /// Returns the problem of an entry missing a value its kind requires.
+    pub fn missing_value(&self) -> Option<&'static str> {
+        match self.kind {
+            CycleKind::Branch if self.taken.is_none() => Some("a branch needs 'taken'"),
+            CycleKind::Reglist if self.per_reg.is_none() => Some("a reglist needs 'per_reg'"),
+            CycleKind::Range if self.worst.is_none() => Some("a range needs 'worst'"),
+            _ => None,
+        }
+    }
+}
+
+/// A version 1 entry: a bare array or an array tagged with its kind.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum LegacyEntry {
+    Untagged(Vec<usize>),
+    Tagged { kind: CycleKind, cycles: Vec<usize> },
+}
+
+/// A database file of the current version.
+#[derive(Serialize, Deserialize)]
+struct DatabaseFile {
+    version: u32,
+    entries: BTreeMap<String, CycleEntry>,
+}
+
+/// A database mapping normalized instructions (see [`crate::cycles::normalize_line_ext`]) to
+/// their cycle counts.
+///
+/// The database is an ordinary value: several instances (e.g. one per CPU or per project
+/// overlay) can live side by side in one process and be shared between threads, since lookups
+/// only require `&self`.
+///
+/// # Value Layout
+/// Each entry holds one or more cycle values:
+/// - A single value for plain instructions.
+/// - `[not taken, taken]` for conditional branches.
+/// - `[base, per register]` for instructions with register lists (`movem`).
+/// - `[best, worst]` for timings that vary with the circumstances.
+///
+/// The values are read according to the entry's [`CycleKind`]; the module documentation
+/// describes the file format.
+///
+/// Adjustments (see the `adjust` module) correct entries after the lookup without changing
+/// them, and the cycles of trap handlers (see the `exception` module) are added to the
+/// `trap #n` of their vector.
+#[derive(Debug, Clone, Default)]
+pub struct CycleDatabase {
+    entries: HashMap<String, CycleEntry>,
+    adjustments: HashMap<String, isize>,
+    trap_handlers: HashMap<usize, usize>,
+}
+
+impl CycleDatabase {
+    /// Loads the database bundled with the crate (`db/cycles.json`).
+    pub fn builtin() -> Self {
+        Self::from_json_str(include_str!("db/cycles.json")).expect("Error parsing cycles JSON")
+    }
+
+    /// Parses a database file of any supported version (see the module documentation), e.g.
+    /// the flat `{ "move.w dn,dn": [4] }`.
+    ///
+    /// # Errors
+    /// If the JSON is malformed, its version is unknown or an entry lacks a value its kind
+    /// requires.
+    pub fn from_json_str(json: &str) -> Result<Self, serde_json::Error> {
+        use serde::de::Error;
+
+        let document: serde_json::Value = serde_json::from_str(json)?;
+        let entries = match document.get("version") {
+            None => {
+                let legacy: HashMap<String, LegacyEntry> = serde_json::from_value(document)?;
+                legacy
+                    .into_iter()
+                    .map(|(key, entry)| {
+                        let entry = match entry {
+                            LegacyEntry::Untagged(cycles) => {
+                                CycleEntry::from_values(CycleKind::infer(&key, &cycles), &cycles)
+                            }
+                            LegacyEntry::Tagged { kind, cycles } => {
+                                CycleEntry::from_values(kind, &cycles)
+                            }
+                        };
+                        (key, entry)
+                    })
+                    .collect()
+            }
+            Some(version) if version.as_u64() == Some(SCHEMA_VERSION as u64) => {
+                let file: DatabaseFile = serde_json::from_value(document)?;
+                if let Some((key, problem)) = file
+                    .entries
+                    .iter()
+                    .find_map(|(key, entry)| entry.missing_value().map(|p| (key, p)))
+                {
+                    return Err(serde_json::Error::custom(format!("'{}': {}", key, problem)));
+                }
+                file.entries.into_iter().collect()
+            }
+            Some(version) => {
+                return Err(serde_json::Error::custom(format!(
+                    "Unsupported cycle database version {} (expected up to {})",
+                    version, SCHEMA_VERSION
+                )));
+            }
+        };
+        Ok(CycleDatabase {
+            entries,
+            adjustments: HashMap::new(),
+            trap_handlers: HashMap::new(),
+        })
+    }
+
+    /// Renders the database as a file of the current version, with its keys sorted.
+    pub fn to_json_string(&self) -> String {
+        let file = DatabaseFile {
+            version: SCHEMA_VERSION,
+            entries: self
+                .entries
+                .iter()
+                .map(|(key, entry)| (key.clone(), entry.clone()))
+                .collect(),
+        };
+        serde_json::to_string_pretty(&file).unwrap_or_default() + "\n"
+    }
+
+    /// Adds all entries of `overlay` to this database, replacing existing entries with the same key.
+    pub fn extend(&mut self, overlay: CycleDatabase) {
+        self.entries.extend(overlay.entries);
+        self.adjustments.extend(overlay.adjustments);
+        self.trap_handlers.extend(overlay.trap_handlers);
+    }
+
+    /// Adds `delta` cycles to the values of a normalized instruction on every lookup, replacing
+    /// an earlier adjustment of the same instruction.
+    pub fn adjust(&mut self, normalized: impl Into<String>, delta: isize) {
+        self.adjustments.insert(normalized.into(), delta);
+    }
+
+    /// Returns the adjustment of a normalized instruction.
+    pub fn adjustment(&self, normalized: &str) -> Option<isize> {
+        self.adjustments.get(normalized).copied()
+    }
+
+    /// Times `trap #vector` as its exception entry plus `cycles` for the handler, which include
+    /// its `rte`.
+    pub fn set_trap_handler(&mut self, vector: usize, cycles: usize) {
+        self.trap_handlers.insert(vector, cycles);
+    }
+
+    /// Returns the cycles given for the handler of the trap `vector`.
+    pub fn trap_handler(&self, vector: usize) -> Option<usize> {
+        self.trap_handlers.get(&vector).copied()
+    }
+
+    /// Adds or replaces the entry of a normalized instruction.
+    pub fn insert(&mut self, normalized: impl Into<String>, entry: CycleEntry) {
+        self.entries.insert(normalized.into(), entry);
+    }
+
+    /// Returns the entry of a normalized instruction.
+    pub fn entry(&self, normalized: &str) -> Option<&CycleEntry> {
+        self.entries.get(normalized)
+    }
+
+    /// Returns the positional cycle values stored for a normalized instruction.
+    pub fn get(&self, normalized: &str) -> Option<Vec<usize>> {
+        self.entries.get(normalized).map(CycleEntry::values)
+    }
+
+    /// Returns how the cycle values of a normalized instruction are to be read.
+    pub fn kind(&self, normalized: &str) -> Option<CycleKind> {
+        self.entries.get(normalized).map(|entry| entry.kind)
+    }
+
+    /// Returns the cycle count of a normalized instruction with `reg_count` registers in its
+    /// register list, read according to the entry's kind and adjusted.
+    pub(crate) fn cycle_count(&self, normalized: &str, reg_count: usize) -> Option<CycleCount> {
+        self.entries.get(normalized).map(|entry| {
+            let mut values = entry.values();
+            let mut source = match entry.kind {
+                CycleKind::Range => CycleSource::WorstCase,
+                _ => CycleSource::Database,
+            };
+            if let Some(delta) = self.adjustment(normalized) {
+                source = CycleSource::Adjusted;
+                // The per-register cost of a register list is not adjusted.
+                let adjusted = if entry.kind == CycleKind::Reglist {
+                    &mut values[..1]
+                } else {
+                    &mut values[..]
+                };
+                for value in adjusted {
+                    *value = value.saturating_add_signed(delta);
+                }
+            }
+            CycleCount::new(values, normalized.to_string(), reg_count)
+                .with_kind(entry.kind)
+                .with_source(source)
+        })
+    }
+
+    /// Returns `true` if the database contains the normalized instruction.
+    pub fn contains(&self, normalized: &str) -> bool {
+        self.entries.contains_key(normalized)
+    }
+
+    /// Returns an iterator over all normalized instructions in the database.
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.entries.keys().map(String::as_str)
+    }
+
+    /// Returns the number of entries in the database.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if the database has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// Builds a database from entries keyed by normalized instruction, e.g. for a test harness that
+/// should not depend on the bundled database.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::database::{CycleDatabase, CycleEntry, CycleKind};
+///
+/// let db: CycleDatabase = [
+///     ("nop".to_string(), CycleEntry::from_values(CycleKind::Plain, &[4])),
+///     ("bra.b xxx.l".to_string(), CycleEntry::from_values(CycleKind::Branch, &[8, 12])),
+/// ]
+/// .into_iter()
+/// .collect();
+/// assert_eq!(db.get("bra.b xxx.l"), Some(vec![8, 12]));
+/// ```
+impl FromIterator<(String, CycleEntry)> for CycleDatabase {
+    fn from_iter<I: IntoIterator<Item = (String, CycleEntry)>>(entries: I) -> Self {
+        CycleDatabase {
+            entries: entries.into_iter().collect(),
+            adjustments: HashMap::new(),
+            trap_handlers: HashMap::new(),
+        }
+    }
+}
+
+impl From<HashMap<String, CycleEntry>> for CycleDatabase {
+    fn from(entries: HashMap<String, CycleEntry>) -> Self {
+        CycleDatabase {
+            entries,
+            adjustments: HashMap::new(),
+            trap_handlers: HashMap::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_database_is_populated() {
+        let db = CycleDatabase::builtin();
+        assert!(!db.is_empty());
+        assert_eq!(db.get("moveq.l #xxx,dn"), Some(vec![4]));
+    }
+
+    #[test]
+    fn test_from_json_str_fixture() {
+        let db =
+            CycleDatabase::from_json_str(r#"{ "nop": [4], "bra.b xxx.l": [10, 10] }"#).unwrap();
+        assert_eq!(db.len(), 2);
+        assert!(db.contains("nop"));
+        assert_eq!(db.get("bra.b xxx.l"), Some(vec![10, 10]));
+        assert_eq!(db.get("move.w dn,dn"), None);
+    }
+
+    #[test]
+    fn test_from_json_str_reads_kinds() {
+        let db = CycleDatabase::from_json_str(
+            r#"{
+                "nop": [4],
+                "bra.b xxx.l": [10, 10],
+                "movem.l (an),reglist": [12, 8],
+                "move.w (an),dn": { "kind": "range", "cycles": [8, 12] }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(db.kind("nop"), Some(CycleKind::Plain));
+        assert_eq!(db.kind("bra.b xxx.l"), Some(CycleKind::Branch));
+        assert_eq!(db.kind("movem.l (an),reglist"), Some(CycleKind::Reglist));
+        assert_eq!(db.kind("move.w (an),dn"), Some(CycleKind::Range));
+        assert_eq!(CycleKind::parse("RegList"), Some(CycleKind::Reglist));
+        assert_eq!(CycleKind::parse("maybe"), None);
+        assert_eq!(db.get("move.w (an),dn"), Some(vec![8, 12]));
+        assert!(
+            CycleDatabase::from_json_str(r#"{ "nop": { "kind": "maybe", "cycles": [4] } }"#)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_versioned_round_trip() {
+        let mut db = CycleDatabase::from_json_str(
+            r#"{ "nop": [4], "dbra.w dn,xxx.l": [10, 12, 14], "movem.l (an)+,reglist": [12, 8] }"#,
+        )
+        .unwrap();
+        let mut range = CycleEntry::from_values(CycleKind::Range, &[8, 12]);
+        range.source = Some("measured on a 520 STf".to_string());
+        db.insert("move.w (an),dn", range.clone());
+
+        let json = db.to_json_string();
+        assert!(json.starts_with("{\n  \"version\": 2,"));
+        let reread = CycleDatabase::from_json_str(&json).unwrap();
+        assert_eq!(reread.len(), 4);
+        assert_eq!(reread.entry("move.w (an),dn"), Some(&range));
+        assert_eq!(reread.get("dbra.w dn,xxx.l"), Some(vec![10, 12, 14]));
+        assert_eq!(
+            reread.entry("movem.l (an)+,reglist").unwrap().per_reg,
+            Some(8)
+        );
+    }
+
+    #[test]
+    fn test_versioned_entries_are_validated() {
+        let unknown = CycleDatabase::from_json_str(r#"{ "version": 3, "entries": {} }"#);
+        assert!(unknown.unwrap_err().to_string().contains("version 3"));
+        let incomplete = CycleDatabase::from_json_str(
+            r#"{ "version": 2, "entries": { "bne.b xxx.l": { "kind": "branch", "base": 8 } } }"#,
+        );
+        assert!(
+            incomplete
+                .unwrap_err()
+                .to_string()
+                .contains("'bne.b xxx.l': a branch needs 'taken'")
+        );
+    }
+
+    #[test]
+    fn test_from_json_str_rejects_invalid_input() {
+        assert!(CycleDatabase::from_json_str(r#"{ "nop": "four" }"#).is_err());
+    }
+
+    #[test]
+    fn test_extend_overrides_existing_entries() {
+        let mut db = CycleDatabase::from_json_str(r#"{ "nop": [4], "rts": [16] }"#).unwrap();
+        db.extend(CycleDatabase::from_json_str(r#"{ "nop": [8] }"#).unwrap());
+        assert_eq!(db.get("nop"), Some(vec![8]));
+        assert_eq!(db.get("rts"), Some(vec![16]));
+    }
+}
@@ -0,0 +1,147 @@
+// src/cycle_spitter/diagnostics.rs
+
+//! Non-fatal problems found during generation.
+//!
+//! The library never prints: every warning is passed to the [`Observer`] of the run as a
+//! [`Diagnostic`] of a [`DiagnosticKind`]. The command line tool prints them to standard error
+//! (see [`StderrObserver`](crate::observer::StderrObserver)); library users collect them in a
+//! [`Diagnostics`] and handle them as they see fit.
+
+use crate::cycle_spitter::location::SourceLocation;
+use crate::cycle_spitter::observer::Observer;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// What a [`Diagnostic`] is about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiagnosticKind {
+    /// An instruction missing from the cycle database, counted as 0 cycles.
+    UnknownInstruction,
+    /// A scanline exceeding its cycle budget.
+    ScanlineOverflow,
+    /// User code not adding up to the cycles of its section.
+    CycleMismatch,
+    /// Any other warning, e.g. a hardware register written by both template and user code.
+    Other,
+}
+
+/// One non-fatal problem.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Diagnostic {
+    pub kind: DiagnosticKind,
+    pub message: String,
+    /// The index of the input line the problem was found at, counting from 0 in the flattened
+    /// input (after `REPT` expansion).
+    pub index: Option<usize>,
+    /// The position of that line in the source, once located (see the `location` module).
+    pub location: Option<SourceLocation>,
+}
+
+impl Diagnostic {
+    pub fn new(kind: DiagnosticKind, message: impl Into<String>) -> Self {
+        Diagnostic {
+            kind,
+            message: message.into(),
+            index: None,
+            location: None,
+        }
+    }
+
+    /// Refers the diagnostic to the input line `index`.
+    pub fn at(mut self, index: usize) -> Self {
+        self.index = Some(index);
+        self
+    }
+}
+
+/// Prefixes the message with the source position, if located (`file:line:column: message`).
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.location {
+            Some(location) => write!(f, "{}: {}", location, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// Prefix of the comments embedding warnings in the generated output.
+pub const WARNING_COMMENT_PREFIX: &str = "; !! WARNING: ";
+
+/// Returns the comment embedding `diagnostic` in frame scanline `line` of the output.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::diagnostics::{Diagnostic, DiagnosticKind, warning_comment};
+///
+/// let diagnostic = Diagnostic::new(DiagnosticKind::ScanlineOverflow, "Scanline overflow by 8 cycles!");
+/// assert_eq!(
+///     warning_comment(34, &diagnostic),
+///     "; !! WARNING: Scanline overflow by 8 cycles! (scanline 34)"
+/// );
+/// ```
+pub fn warning_comment(line: usize, diagnostic: &Diagnostic) -> String {
+    format!(
+        "{}{} (scanline {})",
+        WARNING_COMMENT_PREFIX, diagnostic.message, line
+    )
+}
+
+/// Collects the diagnostics of a run, in the order they were raised.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::database::CycleDatabase;
+/// use cycle_spitter::diagnostics::{DiagnosticKind, Diagnostics};
+/// use cycle_spitter::generator::generate_with_observer;
+/// use cycle_spitter::options::Options;
+/// use cycle_spitter::parse_template;
+/// use cycle_spitter::template::TemplateSet;
+///
+/// let db = CycleDatabase::builtin();
+/// let template = parse_template("move.w d0,d1\ndcb.w 4,$4e71", &db).unwrap();
+/// let templates = TemplateSet::from(template);
+/// let options = Options::builder().cycles_per_line(20).build();
+/// let mut diagnostics = Diagnostics::default();
+/// let input = vec!["frobnicate d0".to_string()];
+/// generate_with_observer(&input, &templates, &db, &options, &mut diagnostics).unwrap();
+/// assert_eq!(diagnostics.of_kind(DiagnosticKind::UnknownInstruction).count(), 1);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Diagnostics {
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    pub fn push(&mut self, diagnostic: Diagnostic) {
+        self.diagnostics.push(diagnostic);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.diagnostics.iter()
+    }
+
+    /// Returns the diagnostics of `kind`.
+    pub fn of_kind(&self, kind: DiagnosticKind) -> impl Iterator<Item = &Diagnostic> {
+        self.iter()
+            .filter(move |diagnostic| diagnostic.kind == kind)
+    }
+
+    pub fn len(&self) -> usize {
+        self.diagnostics.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.diagnostics.is_empty()
+    }
+}
+
+impl Observer for Diagnostics {
+    fn on_warning(&mut self, message: &str) {
+        self.push(Diagnostic::new(DiagnosticKind::Other, message));
+    }
+
+    fn on_diagnostic(&mut self, diagnostic: &Diagnostic) {
+        self.push(diagnostic.clone());
+    }
+}
@@ -0,0 +1,321 @@
+// src/cycle_spitter/emitter.rs
+
+//! Output formats of a generation.
+//!
+//! The generated code and the information around it - header comments, symbol imports, the
+//! scanline count - are collected in a [`Document`]. An [`Emitter`] renders the document in one
+//! format; new formats implement the trait without touching the generation pipeline:
+//! - [`AsmEmitter`]: the assembler source, ready to be included.
+//! - [`JsonEmitter`]: the document as a JSON object, with the provenance of marked cycles.
+//! - [`HtmlEmitter`]: the source as a standalone HTML page with highlighted comments.
+//! - [`CsvEmitter`]: one row per line with its scanline, scanline offset and cycles.
+
+use crate::cycle_spitter::checksum::TOTAL_CYCLES_PREFIX;
+use crate::cycle_spitter::database::CycleSource;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::Serialize;
+use std::fmt::Write;
+use std::io;
+
+static OFFSET_RE: Lazy<Regex> = Lazy::new(|| {
+    // Matches the scanline offset annotation at the end of a generated line
+    // Example matches: `move.w d0,d1 ; (4) move.w dn,dn [12]` captures `12`
+    Regex::new(r"\[(\d+)\]\s*$").unwrap()
+});
+
+static CYCLES_RE: Lazy<Regex> = Lazy::new(|| {
+    // Matches the cycles of an annotation or inline override, with their provenance marker
+    // Example matches: `; (4)`, `;\t(20*ovr)\tn/a`, `; (12*max [8..12])`, `; (10/8)`
+    // - `;\s*\(`: The opening of the comment
+    // - `(?P<cycles>\d+)(?:/\d+)?`: The cycles, of a branch as not taken
+    // - `(?:\*(?P<marker>[a-z]+))?`: The optional provenance marker
+    Regex::new(r";\s*\((?P<cycles>\d+)(?:/\d+)?(?:\*(?P<marker>[a-z]+))?[\s)]").unwrap()
+});
+
+/// Everything an emitter renders.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Document {
+    /// Header lines, without comment markers.
+    pub header: Vec<String>,
+    /// The symbol receiving the scanline count.
+    pub label: String,
+    /// The number of generated scanlines.
+    pub scanlines: usize,
+    /// Directives importing external symbols.
+    pub imports: Vec<String>,
+    /// The generated lines, formatted for output.
+    pub lines: Vec<String>,
+}
+
+/// Renders a [`Document`] in one output format.
+pub trait Emitter {
+    /// Returns the rendered document.
+    fn emit(&self, document: &Document) -> String;
+
+    /// Writes the rendered document to `out`, e.g. standard output or a file.
+    ///
+    /// # Errors
+    /// If writing to `out` fails.
+    fn write_to(&self, document: &Document, out: &mut dyn io::Write) -> io::Result<()> {
+        out.write_all(self.emit(document).as_bytes())
+    }
+}
+
+/// Returns the emitter of a built-in format (`asm`, `json`, `html` or `csv`).
+pub fn emitter_for(format: &str) -> Option<Box<dyn Emitter>> {
+    match format.to_lowercase().as_str() {
+        "asm" => Some(Box::new(AsmEmitter)),
+        "json" => Some(Box::new(JsonEmitter)),
+        "html" => Some(Box::new(HtmlEmitter)),
+        "csv" => Some(Box::new(CsvEmitter)),
+        _ => None,
+    }
+}
+
+/// The separator framing the header comment.
+const HEADER_RULE: &str = "; ------------------------------------------";
+
+/// Emits assembler source: the header as comments, the imports, the scanline count as an
+/// equate and the generated lines.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::emitter::{AsmEmitter, Document, Emitter};
+///
+/// let document = Document {
+///     label: "LINES".to_string(),
+///     scanlines: 2,
+///     ..Default::default()
+/// };
+/// assert!(AsmEmitter.emit(&document).contains("LINES\tequ 2\n"));
+/// ```
+pub struct AsmEmitter;
+
+impl Emitter for AsmEmitter {
+    fn emit(&self, document: &Document) -> String {
+        let mut output = Vec::new();
+        let _ = self.write_to(document, &mut output);
+        String::from_utf8(output).unwrap_or_default()
+    }
+
+    /// Writes the lines one by one rather than rendering the whole document first.
+    fn write_to(&self, document: &Document, out: &mut dyn io::Write) -> io::Result<()> {
+        writeln!(out, "{}", HEADER_RULE)?;
+        for line in &document.header {
+            writeln!(out, "; {}", line)?;
+        }
+        writeln!(out, "{}", HEADER_RULE)?;
+        for line in &document.imports {
+            writeln!(out, "{}", line)?;
+        }
+        writeln!(out, "{}\tequ {}", document.label, document.scanlines)?;
+        for line in &document.lines {
+            writeln!(out, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+/// Cycles of a generated line that did not come straight from the cycle database.
+#[derive(Serialize)]
+struct Provenance {
+    /// The index of the line in [`Document::lines`].
+    line: usize,
+    cycles: usize,
+    source: CycleSource,
+}
+
+/// Emits the document as a pretty-printed JSON object. A `provenance` array lists the lines
+/// whose cycles came from an override, an adjustment or the worst case of a range.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::emitter::{Document, Emitter, JsonEmitter};
+///
+/// let document = Document {
+///     lines: vec!["\tnop\t;\t(20*ovr)\tn/a\t[4]".to_string()],
+///     ..Default::default()
+/// };
+/// let json = JsonEmitter.emit(&document);
+/// assert!(json.contains("\"line\": 0,\n      \"cycles\": 20,\n      \"source\": \"override\""));
+/// ```
+pub struct JsonEmitter;
+
+impl Emitter for JsonEmitter {
+    fn emit(&self, document: &Document) -> String {
+        #[derive(Serialize)]
+        struct JsonDocument<'a> {
+            #[serde(flatten)]
+            document: &'a Document,
+            provenance: Vec<Provenance>,
+        }
+
+        let provenance = document
+            .lines
+            .iter()
+            .enumerate()
+            .filter_map(|(line, text)| {
+                let caps = CYCLES_RE.captures(text)?;
+                Some(Provenance {
+                    line,
+                    cycles: caps["cycles"].parse().ok()?,
+                    source: CycleSource::from_marker(caps.name("marker")?.as_str())?,
+                })
+            })
+            .collect();
+        let json = JsonDocument {
+            document,
+            provenance,
+        };
+        serde_json::to_string_pretty(&json).unwrap_or_default() + "\n"
+    }
+}
+
+/// Escapes the HTML special characters of `text`.
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Emits the assembler source as a standalone HTML page, with comments and annotations set
+/// apart from the code.
+pub struct HtmlEmitter;
+
+impl Emitter for HtmlEmitter {
+    fn emit(&self, document: &Document) -> String {
+        let mut html = String::from(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+             <title>cycleSpitter output</title>\n<style>\n\
+             body { background: #1e1e1e; color: #d4d4d4; }\n\
+             pre { font-family: monospace; tab-size: 8; }\n\
+             .comment { color: #6a9955; }\n\
+             </style>\n</head>\n<body>\n<pre>\n",
+        );
+        for line in AsmEmitter.emit(document).lines() {
+            match line.find(';') {
+                Some(index) => {
+                    let _ = writeln!(
+                        html,
+                        "{}<span class=\"comment\">{}</span>",
+                        escape_html(&line[..index]),
+                        escape_html(&line[index..])
+                    );
+                }
+                None => {
+                    let _ = writeln!(html, "{}", escape_html(line));
+                }
+            }
+        }
+        html.push_str("</pre>\n</body>\n</html>\n");
+        html
+    }
+}
+
+/// Quotes `field` for CSV if it contains a separator, a quote or a line break.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Emits one CSV row per generated line: its scanline, its scanline offset and cycles if
+/// annotated, and the line itself.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::emitter::{CsvEmitter, Document, Emitter};
+///
+/// let document = Document {
+///     lines: vec!["\tmove.w\td0,d1\t;\t(4)\tmove.w dn,dn\t[12]".to_string()],
+///     ..Default::default()
+/// };
+/// let csv = CsvEmitter.emit(&document);
+/// assert!(csv.ends_with("0,12,4,\"\tmove.w\td0,d1\t;\t(4)\tmove.w dn,dn\t[12]\"\n"));
+/// ```
+pub struct CsvEmitter;
+
+impl Emitter for CsvEmitter {
+    fn emit(&self, document: &Document) -> String {
+        let mut csv = String::from("scanline,offset,cycles,line\n");
+        let mut scanline = 0;
+        for line in &document.lines {
+            let offset = OFFSET_RE.captures(line).map(|caps| caps[1].to_string());
+            let cycles = CYCLES_RE
+                .captures(line)
+                .map(|caps| caps["cycles"].to_string());
+            let _ = writeln!(
+                csv,
+                "{},{},{},{}",
+                scanline,
+                offset.unwrap_or_default(),
+                cycles.unwrap_or_default(),
+                csv_field(line)
+            );
+            // The cycle total closes a scanline
+            if line.trim_start().starts_with(TOTAL_CYCLES_PREFIX) {
+                scanline += 1;
+            }
+        }
+        csv
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document() -> Document {
+        Document {
+            header: vec!["This file is generated".to_string()],
+            label: "SCANLINES".to_string(),
+            scanlines: 1,
+            imports: vec!["\txref\tscreen".to_string()],
+            lines: vec![
+                "\tmove.w\td0,d1\t;\t(4)\tmove.w dn,dn\t[0]".to_string(),
+                format!("{}4", TOTAL_CYCLES_PREFIX),
+                "\tlea\tscreen,a0 ; a, \"b\" <c>".to_string(),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_asm_emitter() {
+        assert_eq!(
+            AsmEmitter.emit(&document()),
+            format!(
+                "{rule}\n; This file is generated\n{rule}\n\txref\tscreen\nSCANLINES\tequ 1\n\
+                 \tmove.w\td0,d1\t;\t(4)\tmove.w dn,dn\t[0]\n{}4\n\tlea\tscreen,a0 ; a, \"b\" <c>\n",
+                TOTAL_CYCLES_PREFIX,
+                rule = HEADER_RULE
+            )
+        );
+    }
+
+    #[test]
+    fn test_other_emitters() {
+        let json = JsonEmitter.emit(&document());
+        assert!(json.contains("\"scanlines\": 1"));
+        assert!(json.contains("\"imports\": [\n    \"\\txref\\tscreen\"\n  ]"));
+
+        let html = HtmlEmitter.emit(&document());
+        assert!(html.contains(
+            "\tlea\tscreen,a0 <span class=\"comment\">; a, &quot;b&quot; &lt;c&gt;</span>\n"
+        ));
+
+        let csv = CsvEmitter.emit(&document());
+        let rows: Vec<&str> = csv.lines().collect();
+        assert_eq!(
+            rows[1],
+            "0,0,4,\"\tmove.w\td0,d1\t;\t(4)\tmove.w dn,dn\t[0]\""
+        );
+        assert_eq!(rows[3], "1,,,\"\tlea\tscreen,a0 ; a, \"\"b\"\" <c>\"");
+
+        assert!(emitter_for("CSV").is_some());
+        assert!(emitter_for("pdf").is_none());
+    }
+}
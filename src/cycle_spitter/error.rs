@@ -0,0 +1,112 @@
+// src/cycle_spitter/error.rs
+
+//! The error type of the library entry points.
+//!
+//! [`split`](crate::split), [`generate`](crate::generator::generate) and
+//! [`CycleSpitter::run`](crate::CycleSpitter::run) fail with a [`CycleSpitterError`], so
+//! callers can tell a broken template from an unknown instruction or a scanline overflow
+//! without parsing the message. Non-fatal problems are reported as diagnostics instead (see the
+//! `diagnostics` module).
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Why generation failed.
+#[derive(Debug)]
+pub enum CycleSpitterError {
+    /// A template could not be parsed; the message names the template.
+    Template(String),
+    /// In strict mode, the input contains instructions missing from the cycle database.
+    UnknownInstruction(Vec<String>),
+    /// With `OverflowPolicy::Error`, a scanline (counting from 0) exceeds its budget by
+    /// `cycles`.
+    ScanlineOverflow { scanline: usize, cycles: usize },
+    /// A source could not be read; `what` describes it, e.g. `input file 'effect.s'`.
+    Io { what: String, source: io::Error },
+    /// Any other problem with the input, the templates or the options.
+    Invalid(String),
+}
+
+impl fmt::Display for CycleSpitterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CycleSpitterError::Template(message) | CycleSpitterError::Invalid(message) => {
+                write!(f, "{}", message)
+            }
+            CycleSpitterError::UnknownInstruction(lines) => write!(
+                f,
+                "No cycle count found for {} instruction(s): {}",
+                lines.len(),
+                lines.join(", ")
+            ),
+            CycleSpitterError::ScanlineOverflow { scanline, cycles } => {
+                write!(f, "Scanline {} overflows by {} cycles", scanline, cycles)
+            }
+            CycleSpitterError::Io { what, source } => {
+                write!(f, "Failed to read {}: {}", what, source)
+            }
+        }
+    }
+}
+
+impl Error for CycleSpitterError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CycleSpitterError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<String> for CycleSpitterError {
+    fn from(message: String) -> Self {
+        CycleSpitterError::Invalid(message)
+    }
+}
+
+impl From<&str> for CycleSpitterError {
+    fn from(message: &str) -> Self {
+        CycleSpitterError::Invalid(message.to_string())
+    }
+}
+
+/// Recovers a `CycleSpitterError` passed through a `Box<dyn Error>`; any other error becomes
+/// [`CycleSpitterError::Invalid`].
+impl From<Box<dyn Error>> for CycleSpitterError {
+    fn from(error: Box<dyn Error>) -> Self {
+        match error.downcast::<CycleSpitterError>() {
+            Ok(error) => *error,
+            Err(error) => CycleSpitterError::Invalid(error.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_boxed_errors_keep_their_variant() {
+        let boxed: Box<dyn Error> = CycleSpitterError::ScanlineOverflow {
+            scanline: 3,
+            cycles: 8,
+        }
+        .into();
+        let error = CycleSpitterError::from(boxed);
+        assert!(matches!(
+            error,
+            CycleSpitterError::ScanlineOverflow {
+                scanline: 3,
+                cycles: 8
+            }
+        ));
+        assert_eq!(error.to_string(), "Scanline 3 overflows by 8 cycles");
+
+        let boxed: Box<dyn Error> = "No template registered".into();
+        assert!(matches!(
+            CycleSpitterError::from(boxed),
+            CycleSpitterError::Invalid(_)
+        ));
+    }
+}
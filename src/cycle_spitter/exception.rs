@@ -0,0 +1,125 @@
+// src/cycle_spitter/exception.rs
+
+//! Instructions that enter an exception.
+//!
+//! `trap #n` always enters an exception, and `trapv`, `chk` and `illegal` may. The cycle
+//! database times the exception entry only (34 cycles for `trap`); the handler runs for as
+//! long as it runs. Code that deliberately traps inside a timed region (rare, but real: a
+//! handler doing a fixed amount of work can save a `jsr` in a tight loop) gives the cycles of
+//! each vector's handler, including its `rte`, with [`CycleDatabase::set_trap_handler`], and
+//! `trap #n` is then timed as entry plus handler. Either way the generator warns about every
+//! such instruction, as the latency of an exception also depends on things the tool does not
+//! see, such as a pending interrupt.
+
+use crate::cycle_spitter::cycles::{lookup_cycles, normalize_line_ext};
+use crate::cycle_spitter::database::CycleDatabase;
+use crate::cycle_spitter::literal::parse_literal;
+use std::error::Error;
+
+/// The normalized form of `trap #n`.
+pub const TRAP_KEY: &str = "trap #xxx";
+
+/// The number of `trap` vectors.
+pub const TRAP_VECTORS: usize = 16;
+
+/// Returns the vector of a `trap #n` instruction on `line`.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::exception::trap_vector;
+///
+/// assert_eq!(trap_vector("\ttrap #$e ; Xbios"), Some(14));
+/// assert_eq!(trap_vector("trap #16"), None);
+/// assert_eq!(trap_vector("trapv"), None);
+/// ```
+pub fn trap_vector(line: &str) -> Option<usize> {
+    let code = line.split(';').next().unwrap_or("").trim();
+    let (mnemonic, operand) = code.split_once(char::is_whitespace)?;
+    if !mnemonic.eq_ignore_ascii_case("trap") {
+        return None;
+    }
+    let vector = parse_literal(operand.trim().strip_prefix('#')?)?;
+    (vector < TRAP_VECTORS).then_some(vector)
+}
+
+/// Parses a trap handler given as `VECTOR=CYCLES`, e.g. `3=120`.
+///
+/// # Errors
+/// If the vector is not 0 to 15 or the cycles are not a number.
+pub fn parse_trap_handler(spec: &str) -> Result<(usize, usize), Box<dyn Error>> {
+    let invalid = || format!("Invalid trap handler '{}', expected VECTOR=CYCLES", spec);
+    let (vector, cycles) = spec.split_once('=').ok_or_else(invalid)?;
+    let vector = parse_literal(vector.trim())
+        .filter(|&vector| vector < TRAP_VECTORS)
+        .ok_or_else(invalid)?;
+    let cycles = parse_literal(cycles.trim()).ok_or_else(invalid)?;
+    Ok((vector, cycles))
+}
+
+/// Returns the warning about an instruction on `line` that enters an exception, if it does.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::database::CycleDatabase;
+/// use cycle_spitter::exception::exception_warning;
+///
+/// let mut db = CycleDatabase::builtin();
+/// assert!(exception_warning("move.w d0,d1", &db).is_none());
+/// db.set_trap_handler(3, 120);
+/// assert_eq!(
+///     exception_warning("trap #3", &db).unwrap(),
+///     "'trap #3' enters an exception, timed as 154 cycles including its handler; \
+///      exception latency varies with pending interrupts"
+/// );
+/// ```
+pub fn exception_warning(line: &str, db: &CycleDatabase) -> Option<String> {
+    let code = line.split(';').next().unwrap_or("").trim();
+    let normalized = normalize_line_ext(code).0;
+    let mnemonic = normalized.split(['.', ' ']).next().unwrap_or("");
+    if normalized == TRAP_KEY {
+        let timed = match trap_vector(code).and_then(|vector| db.trap_handler(vector)) {
+            Some(_) => "including its handler",
+            None => "for its entry only, its handler is not timed",
+        };
+        Some(format!(
+            "'{}' enters an exception, timed as {} cycles {}; exception latency varies with \
+             pending interrupts",
+            code,
+            lookup_cycles(code, db).total(),
+            timed
+        ))
+    } else if matches!(mnemonic, "trapv" | "chk" | "illegal") {
+        Some(format!(
+            "'{}' may enter an exception, whose handler is not timed",
+            code
+        ))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trap_handlers_are_timed_per_vector() {
+        let mut db = CycleDatabase::builtin();
+        let (vector, cycles) = parse_trap_handler("3=$78").unwrap();
+        db.set_trap_handler(vector, cycles);
+
+        assert_eq!(lookup_cycles("trap #3", &db).total(), 34 + 120);
+        assert_eq!(lookup_cycles("trap #4", &db).total(), 34);
+        assert!(
+            exception_warning("trap #4", &db)
+                .unwrap()
+                .contains("timed as 34 cycles for its entry only")
+        );
+        assert_eq!(
+            exception_warning("chk d1,d0", &db).unwrap(),
+            "'chk d1,d0' may enter an exception, whose handler is not timed"
+        );
+        assert!(parse_trap_handler("16=10").is_err());
+        assert!(parse_trap_handler("3").is_err());
+    }
+}
@@ -0,0 +1,235 @@
+// src/cycle_spitter/ffi.rs
+
+//! A C interface for toolchains that are not written in Rust.
+//!
+//! [`cyclespitter_split`] runs the pipeline of [`CycleSpitter`](crate::CycleSpitter) on an
+//! input and a template given as NUL-terminated strings and returns a heap-allocated
+//! [`CycleSpitterReport`] with the assembler output (as the command line tool prints it), the
+//! warnings and, if generation failed, the error. The caller releases the report with
+//! [`cyclespitter_report_free`]. Nothing is printed and no panic crosses the interface.
+//!
+//! The crate is built as a static and a dynamic library for linking. Building with the
+//! `headers` feature writes the matching C header to `include/cyclespitter.h`.
+
+use crate::cycle_spitter::diagnostics::Diagnostics;
+use crate::cycle_spitter::emitter::{AsmEmitter, Emitter};
+use crate::cycle_spitter::options::{OptionsBuilder, OverflowPolicy, Strictness};
+use crate::cycle_spitter::runner::CycleSpitterBuilder;
+use crate::cycle_spitter::source::StringSource;
+use std::error::Error;
+use std::ffi::{CStr, CString, c_char};
+use std::panic::{AssertUnwindSafe, catch_unwind};
+use std::ptr;
+
+/// Options of [`cyclespitter_split`]. Zero-initialized options, or a null pointer, select the
+/// defaults of the command line tool.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct CycleSpitterOptions {
+    /// The cycles of a scanline; 0 for the 512 cycles of a 50 Hz ST.
+    pub cycles_per_line: usize,
+    /// Fail if an instruction is missing from the cycle database instead of counting it as 0
+    /// cycles.
+    pub strict: bool,
+    /// Fail if a scanline exceeds its cycles instead of warning.
+    pub overflow_error: bool,
+    /// The symbol receiving the scanline count, or null for `SCANLINES_CONSUMED`.
+    pub label: *const c_char,
+}
+
+/// The result of [`cyclespitter_split`]. Strings are NUL-terminated and null if absent.
+#[repr(C)]
+#[derive(Debug)]
+pub struct CycleSpitterReport {
+    /// `true` if the output was generated.
+    pub ok: bool,
+    /// The number of generated scanlines.
+    pub scanlines: usize,
+    /// The generated assembler source.
+    pub output: *mut c_char,
+    /// The number of warnings in `diagnostics`.
+    pub diagnostic_count: usize,
+    /// The warnings, one per line, prefixed with the position of their input line
+    /// (`input:line:column:`) where they refer to one.
+    pub diagnostics: *mut c_char,
+    /// Why generation failed, if it did.
+    pub error: *mut c_char,
+}
+
+/// Converts `text` for C, dropping NUL characters it cannot carry.
+fn to_c_string(text: String) -> *mut c_char {
+    CString::new(text.replace('\0', ""))
+        .map(CString::into_raw)
+        .unwrap_or(ptr::null_mut())
+}
+
+/// Reads the NUL-terminated UTF-8 string `text` names.
+///
+/// # Safety
+/// `text` must be null or point to a NUL-terminated string.
+unsafe fn read_str<'a>(text: *const c_char, what: &str) -> Result<&'a str, Box<dyn Error>> {
+    if text.is_null() {
+        return Err(format!("No {} given", what).into());
+    }
+    unsafe { CStr::from_ptr(text) }
+        .to_str()
+        .map_err(|_| format!("The {} is not valid UTF-8", what).into())
+}
+
+/// Runs the splitter and fills in a report; errors before generation are returned.
+///
+/// # Safety
+/// See [`cyclespitter_split`].
+unsafe fn split_report(
+    input: *const c_char,
+    template: *const c_char,
+    options: *const CycleSpitterOptions,
+) -> Result<CycleSpitterReport, Box<dyn Error>> {
+    let input = unsafe { read_str(input, "input") }?;
+    let template = unsafe { read_str(template, "template") }?;
+    let mut builder = CycleSpitterBuilder::new()
+        .input(StringSource::new("input", input))
+        .template(StringSource::new("template", template));
+    let mut generation_options = OptionsBuilder::default();
+    if let Some(options) = unsafe { options.as_ref() } {
+        if options.cycles_per_line > 0 {
+            generation_options = generation_options.cycles_per_line(options.cycles_per_line);
+        }
+        if options.strict {
+            generation_options = generation_options.strictness(Strictness::Strict);
+        }
+        if options.overflow_error {
+            generation_options = generation_options.overflow_policy(OverflowPolicy::Error);
+        }
+        if !options.label.is_null() {
+            builder = builder.label(unsafe { read_str(options.label, "label") }?);
+        }
+    }
+    let spitter = builder.options(generation_options).build()?;
+
+    let mut diagnostics = Diagnostics::default();
+    let result = spitter.run_with_observer(&mut diagnostics);
+    let messages: Vec<String> = diagnostics.iter().map(|d| d.to_string()).collect();
+    let mut report = CycleSpitterReport {
+        ok: result.is_ok(),
+        scanlines: 0,
+        output: ptr::null_mut(),
+        diagnostic_count: messages.len(),
+        diagnostics: to_c_string(messages.join("\n")),
+        error: ptr::null_mut(),
+    };
+    match result {
+        Ok(generation) => {
+            report.scanlines = generation.scanlines;
+            report.output = to_c_string(AsmEmitter.emit(&spitter.document(&generation)));
+        }
+        Err(e) => report.error = to_c_string(e.to_string()),
+    }
+    Ok(report)
+}
+
+/// Splits the assembler source `input` into scanlines laid out by `template`, both given as
+/// file contents. Returns a report to be released with [`cyclespitter_report_free`]; it is
+/// never null.
+///
+/// # Safety
+/// `input` and `template` must point to NUL-terminated strings, and `options` must be null or
+/// point to valid options whose `label` is null or a NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cyclespitter_split(
+    input: *const c_char,
+    template: *const c_char,
+    options: *const CycleSpitterOptions,
+) -> *mut CycleSpitterReport {
+    let report = catch_unwind(AssertUnwindSafe(|| unsafe {
+        split_report(input, template, options)
+    }))
+    .unwrap_or_else(|_| Err("Internal error".into()))
+    .unwrap_or_else(|e| CycleSpitterReport {
+        ok: false,
+        scanlines: 0,
+        output: ptr::null_mut(),
+        diagnostic_count: 0,
+        diagnostics: ptr::null_mut(),
+        error: to_c_string(e.to_string()),
+    });
+    Box::into_raw(Box::new(report))
+}
+
+/// Releases a report returned by [`cyclespitter_split`], including its strings. Null is
+/// ignored.
+///
+/// # Safety
+/// `report` must be null or a report returned by [`cyclespitter_split`] that has not been
+/// released yet.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn cyclespitter_report_free(report: *mut CycleSpitterReport) {
+    if report.is_null() {
+        return;
+    }
+    let report = unsafe { Box::from_raw(report) };
+    for text in [report.output, report.diagnostics, report.error] {
+        if !text.is_null() {
+            drop(unsafe { CString::from_raw(text) });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the string of a report field.
+    fn text(field: *mut c_char) -> Option<String> {
+        (!field.is_null()).then(|| {
+            unsafe { CStr::from_ptr(field) }
+                .to_string_lossy()
+                .into_owned()
+        })
+    }
+
+    #[test]
+    fn test_split_through_the_c_interface() {
+        let input = CString::new("move.w d1,d2\nfrobnicate d0").unwrap();
+        let template = CString::new("move.w d0,d1\ndcb.w 4,$4e71").unwrap();
+        let label = CString::new("EFFECT_LINES").unwrap();
+        let options = CycleSpitterOptions {
+            cycles_per_line: 20,
+            strict: false,
+            overflow_error: false,
+            label: label.as_ptr(),
+        };
+        unsafe {
+            let report = cyclespitter_split(input.as_ptr(), template.as_ptr(), &options);
+            assert!((*report).ok);
+            assert_eq!((*report).scanlines, 1);
+            assert!(
+                text((*report).output)
+                    .unwrap()
+                    .contains("EFFECT_LINES\tequ 1\n")
+            );
+            assert_eq!((*report).diagnostic_count, 1);
+            assert!(
+                text((*report).diagnostics)
+                    .unwrap()
+                    .starts_with("input:2:1: ")
+            );
+            assert_eq!(text((*report).error), None);
+            cyclespitter_report_free(report);
+
+            let strict = CycleSpitterOptions {
+                strict: true,
+                ..options
+            };
+            let report = cyclespitter_split(input.as_ptr(), template.as_ptr(), &strict);
+            assert!(!(*report).ok);
+            assert_eq!(text((*report).output), None);
+            assert!(text((*report).error).unwrap().contains("frobnicate d0"));
+            cyclespitter_report_free(report);
+
+            let report = cyclespitter_split(ptr::null(), template.as_ptr(), ptr::null());
+            assert_eq!(text((*report).error).unwrap(), "No input given");
+            cyclespitter_report_free(report);
+        }
+    }
+}
@@ -0,0 +1,146 @@
+// src/cycle_spitter/filler.rs
+
+//! Varied filler instructions for spare cycles.
+//!
+//! With [`PaddingStyle::Filler`], padding is emitted as a mix of instructions that change
+//! neither registers, memory nor the condition codes, instead of a wall of NOPs. The choice is
+//! made by a small pseudo-random generator seeded from the user's seed, the cycle offset of the
+//! padding and the input line at which it starts. The same input and seed therefore always
+//! produce the same output, independent of caching or resuming.
+//!
+//! The padding instruction is always part of the mix, at the cycles it is configured with. The
+//! other instructions are timed for the 68000 of the Atari ST and only mixed in while a `nop`
+//! takes as long as it does there; with other NOP costs their cycles are unknown.
+//!
+//! [`PaddingStyle::Filler`]: crate::cycle_spitter::options::PaddingStyle::Filler
+
+use crate::cycle_spitter::checksum::Fnv1a;
+use crate::cycle_spitter::options::PaddingInstruction;
+
+/// Instructions without any effect besides their cycles on the 68000, as `(instruction,
+/// cycles)`. `{n}` is replaced with a random address register number.
+const FILLER_POOL: &[(&str, usize)] = &[
+    ("lea\t(a{n}),a{n}", 4),
+    ("movea.l\ta{n},a{n}", 4),
+    ("lea\t0(a{n}),a{n}", 8),
+];
+
+/// The number of address registers used by the filler; `a7` is left alone.
+const FILLER_REGISTERS: u64 = 7;
+
+/// The cycles of a `nop` on the 68000 the costs of [`FILLER_POOL`] are taken from.
+const POOL_NOP_CYCLES: usize = 4;
+
+/// A deterministic generator of filler instructions.
+pub(crate) struct Filler {
+    state: u64,
+}
+
+impl Filler {
+    /// Creates a generator for padding at cycle `offset`, starting at input line `context`.
+    pub(crate) fn new(seed: u64, context: &str, offset: usize) -> Self {
+        let mut hash = Fnv1a::new();
+        hash.write(&seed.to_le_bytes());
+        hash.write(&(offset as u64).to_le_bytes());
+        hash.write(context.as_bytes());
+        Filler {
+            state: hash.finish(),
+        }
+    }
+
+    /// Returns the next pseudo-random number (SplitMix64).
+    fn next(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns filler instructions with their cycles, adding up to `cycles` rounded down to a
+    /// multiple of the cycles of the `padding` instruction. `nop_cycles` is the cost of a `nop`
+    /// in the configured profile.
+    pub(crate) fn fill(
+        &mut self,
+        cycles: usize,
+        padding: &PaddingInstruction,
+        nop_cycles: usize,
+    ) -> Vec<(String, usize)> {
+        let mut pool = vec![(padding.mnemonic.as_str(), padding.cycles)];
+        if nop_cycles == POOL_NOP_CYCLES {
+            pool.extend(FILLER_POOL.iter().filter(|(_, c)| *c % padding.cycles == 0));
+        }
+        let mut remaining = cycles - cycles % padding.cycles;
+        let mut instructions = Vec::new();
+        while remaining > 0 {
+            let fitting: Vec<&(&str, usize)> =
+                pool.iter().filter(|(_, c)| *c <= remaining).collect();
+            let (template, cycles) = *fitting[(self.next() % fitting.len() as u64) as usize];
+            let register = (self.next() % FILLER_REGISTERS).to_string();
+            instructions.push((template.replace("{n}", &register), cycles));
+            remaining -= cycles;
+        }
+        instructions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cycle_spitter::cycles::lookup_cycles;
+    use crate::cycle_spitter::database::CycleDatabase;
+
+    #[test]
+    fn test_pool_matches_cycle_database() {
+        let db = CycleDatabase::builtin();
+        for (template, cycles) in FILLER_POOL {
+            let instruction = template.replace("{n}", "3");
+            assert_eq!(
+                lookup_cycles(&instruction, &db).total(),
+                *cycles,
+                "{}",
+                instruction
+            );
+        }
+    }
+
+    #[test]
+    fn test_fill_is_deterministic_and_exact() {
+        let nop = PaddingInstruction::nop(4);
+        let first = Filler::new(42, "move.w d0,d1", 24).fill(40, &nop, 4);
+        assert_eq!(first, Filler::new(42, "move.w d0,d1", 24).fill(40, &nop, 4));
+        assert_eq!(first.iter().map(|(_, c)| c).sum::<usize>(), 40);
+        assert!(first.iter().any(|(i, _)| i != "nop"));
+        assert!(first.iter().all(|(i, _)| !i.contains("a7")));
+
+        assert_ne!(first, Filler::new(43, "move.w d0,d1", 24).fill(40, &nop, 4));
+        assert_eq!(Filler::new(42, "", 0).fill(6, &nop, 4).len(), 1);
+
+        // Without a pool entry of a multiple of its cycles, the padding instruction is used.
+        let odd = PaddingInstruction::parse("tst.b (a7):$4a17:6").unwrap();
+        let fill = Filler::new(42, "", 0).fill(12, &odd, 4);
+        assert_eq!(
+            fill,
+            [("tst.b (a7)".to_string(), 6), ("tst.b (a7)".to_string(), 6)]
+        );
+    }
+
+    #[test]
+    fn test_fill_follows_the_nop_cost_of_the_profile() {
+        // The NOP is mixed in at its configured cost.
+        let slow = PaddingInstruction::nop(8);
+        for seed in 0..16 {
+            let fill = Filler::new(seed, "", 0).fill(64, &slow, 8);
+            assert_eq!(fill.iter().map(|(_, c)| c).sum::<usize>(), 64);
+            // The pool is timed for a 4 cycle NOP, so only NOPs are left.
+            assert!(fill.iter().all(|entry| *entry == ("nop".to_string(), 8)));
+        }
+
+        // A padding instruction other than the NOP keeps the pool of the 68000.
+        let padding = PaddingInstruction::parse("tst.w (a7):$4a57:8").unwrap();
+        let fill = Filler::new(1, "", 0).fill(160, &padding, 4);
+        assert!(fill.iter().all(|(_, c)| *c == 8));
+        assert!(fill.iter().any(|(i, _)| i.starts_with("lea\t0(")));
+        assert!(fill.iter().all(|(i, _)| i != "nop"));
+    }
+}
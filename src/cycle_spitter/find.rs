@@ -0,0 +1,101 @@
+// src/cycle_spitter/find.rs
+
+//! Locating code in generated output.
+//!
+//! Where a label or an instruction of the input ended up is written all over the generated
+//! file, but in pieces: the scanline is the number of `; Total cycles for scanline:` comments
+//! above it, the section the last `; --- ... section ---` header, the offset the last bracketed
+//! number of its annotation. [`find_in_output`] puts the pieces together for every line whose
+//! code contains the text searched for.
+
+use crate::cycle_spitter::checksum::{TOTAL_CYCLES_PREFIX, code_part};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::Serialize;
+
+static SECTION_HEADER_RE: Lazy<Regex> = Lazy::new(|| {
+    // Matches the comment opening a template section, capturing its label
+    Regex::new(r"^; --- (.+) section ---$").unwrap()
+});
+
+static OFFSET_RE: Lazy<Regex> = Lazy::new(|| {
+    // Matches the scanline offset ending an annotation, e.g. `[24]`
+    Regex::new(r"\[(\d+)\]\s*$").unwrap()
+});
+
+/// A generated line containing the text searched for.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Found {
+    /// The line of the generated file, counted from 1.
+    pub line_number: usize,
+    /// The generated scanline, counted from 0.
+    pub scanline: usize,
+    /// The label of the template section, `None` outside the sections.
+    pub section: Option<String>,
+    /// The scanline offset of the line, for lines taking cycles.
+    pub offset: Option<usize>,
+    /// The generated line, trimmed.
+    pub text: String,
+}
+
+/// Returns every line of the generated `content` whose code (the line without its comment)
+/// contains `pattern`, be it a label or the text of an instruction.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::find::find_in_output;
+///
+/// let output = "\tnop\t; 4 cycles\t[0]\n; --- left section ---\n.loop:\tmove.w d0,d1\t;\t(4)\t[4]\n\
+///               ; Total cycles for scanline: 8\n\tmove.w d0,d1\t;\t(4)\t[0]";
+/// let found = find_in_output(output, ".loop");
+/// assert_eq!(found.len(), 1);
+/// assert_eq!(
+///     (found[0].line_number, found[0].scanline, found[0].offset),
+///     (3, 0, Some(4))
+/// );
+/// assert_eq!(found[0].section.as_deref(), Some("left"));
+/// assert_eq!(find_in_output(output, "move.w d0,d1")[1].scanline, 1);
+/// ```
+pub fn find_in_output(content: &str, pattern: &str) -> Vec<Found> {
+    let pattern = pattern.split_whitespace().collect::<Vec<_>>().join(" ");
+    let mut found = Vec::new();
+    let mut scanline = 0;
+    let mut section: Option<String> = None;
+    for (index, line) in content.lines().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.starts_with(TOTAL_CYCLES_PREFIX) {
+            scanline += 1;
+            section = None;
+        } else if let Some(caps) = SECTION_HEADER_RE.captures(trimmed) {
+            section = Some(caps[1].to_string());
+        } else if code_part(line).is_some_and(|code| code.contains(&pattern)) {
+            found.push(Found {
+                line_number: index + 1,
+                scanline,
+                section: section.clone(),
+                offset: OFFSET_RE
+                    .captures(trimmed)
+                    .and_then(|caps| caps[1].parse().ok()),
+                text: trimmed.to_string(),
+            });
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_skips_comments_and_headers() {
+        let output = "; lea buffer,a0 in a comment\nSCANLINES_CONSUMED\tequ 1\n\
+                      ; --- main section ---\n\tlea  buffer,a0\t;\t(12)\tlea.l xxx.l,an\t[12]\n\
+                      ; Total cycles for scanline: 24";
+        let found = find_in_output(output, "lea   buffer");
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].line_number, 4);
+        assert_eq!(found[0].offset, Some(12));
+        assert!(find_in_output(output, "SCANLINES_CONSUMED").is_empty());
+    }
+}
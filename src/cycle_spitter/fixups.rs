@@ -0,0 +1,400 @@
+// src/cycle_spitter/fixups.rs
+
+//! Absolute addresses in generated code that have to be patched when the code is relocated.
+//!
+//! Demos often compress the generated scanline code and unpack it to an address that is only
+//! known at runtime. Every absolute long reference to a symbol - `lea table,a0`, `jmp next`,
+//! `move.l #buffer,d0`, `dc.l handler` - then has to be patched by the distance the code moved.
+//! [`find_fixups`] walks the generated lines, sizes every instruction from its addressing modes
+//! and lists the byte offsets of these references from the start of the block.
+//!
+//! Symbols defined with `equ`, `set` or `=` in the block are constants and need no fixup;
+//! numbers never do. Branches and PC-relative operands are position independent.
+//!
+//! Some references cannot be relocated at all: an absolute word (`xxx.w`) or a `dc.w` pointing
+//! at a label of the block only reaches the lowest 32 KB. These are reported as
+//! [`RelocationIssue`]s, as are lines whose size cannot be determined - offsets after them are
+//! unknown, so their fixups are reported as issues rather than listed.
+
+use crate::cycle_spitter::block::parse_directive;
+use crate::cycle_spitter::cycles::{is_canonical, normalize_line_ext};
+use crate::cycle_spitter::data::{DataDirective, parse_data_directive};
+use crate::cycle_spitter::helpers::strip_annotations;
+use crate::cycle_spitter::literal::{LITERAL_PATTERN, parse_literal};
+use crate::cycle_spitter::packer::split_operands;
+use crate::cycle_spitter::symbols::{
+    NON_SYMBOL_DIRECTIVES, code_of, split_definition, symbol_names,
+};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::collections::HashSet;
+
+static DCB_NOP_RE: Lazy<Regex> = Lazy::new(|| {
+    // Matches a block of NOPs, which the data directives leave to the code
+    // Example matches: `dcb.w 3,$4e71`, `DCB.W 12, $4E71`
+    Regex::new(&format!(
+        r"(?i)^dcb\.w\s*({})\s*,\s*\$4e71",
+        LITERAL_PATTERN
+    ))
+    .unwrap()
+});
+
+static DC_VALUES_RE: Lazy<Regex> = Lazy::new(|| {
+    // Matches word and long `dc` directives, capturing their values
+    // Example matches: `dc.l handler,0`, `DC.W next-start`
+    // - `(?P<size>[wl])`: The element size; byte data cannot hold an address
+    Regex::new(r"(?i)^dc\.(?P<size>[wl])\s+(?P<values>.*)$").unwrap()
+});
+
+static BRANCH_RE: Lazy<Regex> = Lazy::new(|| {
+    // Matches the normalized mnemonics of branches, whose target is PC-relative
+    // Example matches: `bra`, `bsr`, `bne`
+    Regex::new(r"^b(?:ra|sr|hi|ls|cc|hs|cs|lo|ne|eq|vc|vs|pl|mi|ge|lt|gt|le)$").unwrap()
+});
+
+static QUICK_RE: Lazy<Regex> = Lazy::new(|| {
+    // Matches the normalized mnemonics encoding an immediate operand in the opcode word
+    // Example matches: `moveq`, `addq`, `trap`, `lsl`, `roxr`
+    Regex::new(r"^(?:moveq|addq|subq|trap|(?:as|ls|rox|ro)[lr])$").unwrap()
+});
+
+static REGISTER_LIST_RE: Lazy<Regex> = Lazy::new(|| {
+    // Matches register lists, whose `-` and `/` are not arithmetic
+    // Example matches: `d0-d7/a0-a6`, `a4`, `d0/sp`
+    Regex::new(r"(?i)^(?:[da][0-7]|sp)(?:[-/](?:[da][0-7]|sp))*$").unwrap()
+});
+
+/// A reference to be patched with the load address of the block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fixup {
+    /// The byte offset of the 32-bit address from the start of the block.
+    pub offset: usize,
+    /// The index of the line holding the reference.
+    pub index: usize,
+    /// The referencing operand or value, e.g. `table+160`.
+    pub target: String,
+}
+
+/// A line that defeats relocation, or whose fixups cannot be located.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelocationIssue {
+    /// The index of the offending line.
+    pub index: usize,
+    /// What is wrong with it.
+    pub reason: String,
+}
+
+/// The fixups of a block, see [`find_fixups`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Fixups {
+    pub fixups: Vec<Fixup>,
+    pub issues: Vec<RelocationIssue>,
+    /// The size of the block in bytes, `None` if a line could not be sized.
+    pub bytes: Option<usize>,
+}
+
+impl Fixups {
+    /// Renders the fixups as text: one line per fixup with its offset in hex, the target and
+    /// the referencing code of `lines`.
+    pub fn listing(&self, lines: &[String]) -> String {
+        let mut listing = format!(
+            "; {} fixup(s), offsets from the start of the block\n",
+            self.fixups.len()
+        );
+        for fixup in &self.fixups {
+            let code = lines
+                .get(fixup.index)
+                .map_or("", |line| code_of(strip_annotations(line)).trim());
+            listing.push_str(&format!(
+                "${:06x}\t{}\t; {}\n",
+                fixup.offset,
+                fixup.target,
+                code.split_whitespace().collect::<Vec<_>>().join(" ")
+            ));
+        }
+        listing
+    }
+
+    /// Renders the fixups as binary: the number of fixups followed by their offsets, all as
+    /// big-endian 32-bit words, ready to be `incbin`'d next to the packed code.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(4 * (self.fixups.len() + 1));
+        bytes.extend_from_slice(&(self.fixups.len() as u32).to_be_bytes());
+        for fixup in &self.fixups {
+            bytes.extend_from_slice(&(fixup.offset as u32).to_be_bytes());
+        }
+        bytes
+    }
+}
+
+/// An operand of a sized instruction.
+struct OperandSpan {
+    /// The offset of the operand's extension words from the start of the instruction.
+    offset: usize,
+    /// The size of the extension words in bytes.
+    extension: usize,
+    /// The addressing mode, as normalized.
+    mode: String,
+    /// The operand as written.
+    raw: String,
+}
+
+/// Returns the size in bytes of the instruction `code` and its operands, or `None` if the
+/// instruction is not understood.
+fn instruction_layout(code: &str) -> Option<(usize, Vec<OperandSpan>)> {
+    let (mnemonic, raw) = code
+        .trim()
+        .split_once(char::is_whitespace)
+        .unwrap_or((code.trim(), ""));
+    let raw = split_operands(raw);
+    // The normalizer takes absolute expressions such as `table+160` apart; an address does
+    // just as well for sizing the operand.
+    let placeholders: Vec<String> = raw
+        .iter()
+        .map(|operand| {
+            let expression = !operand.contains(['(', '#'])
+                && operand.contains(['+', '-', '*', '/'])
+                && !REGISTER_LIST_RE.is_match(operand);
+            match expression {
+                true if operand.to_lowercase().ends_with(".w") => "$0.w".to_string(),
+                true => "$0".to_string(),
+                false => operand.clone(),
+            }
+        })
+        .collect();
+    let (normalized, _) = normalize_line_ext(&format!("{} {}", mnemonic, placeholders.join(",")));
+    if !is_canonical(&normalized) {
+        return None;
+    }
+    let (mnemonic, modes) = normalized.split_once(' ').unwrap_or((&normalized, ""));
+    let (base, size) = mnemonic.split_once('.').unwrap_or((mnemonic, ""));
+    if BRANCH_RE.is_match(base) {
+        return Some((if size == "b" { 2 } else { 4 }, Vec::new()));
+    }
+    if base.starts_with("db") {
+        return Some((4, Vec::new()));
+    }
+
+    let modes = split_operands(modes);
+    if raw.len() != modes.len() {
+        return None;
+    }
+    // The opcode word, followed by the register mask of `movem`
+    let mut length = if base == "movem" { 4 } else { 2 };
+    let mut operands = Vec::new();
+    for (mode, raw) in modes.into_iter().zip(raw) {
+        let extension = match mode.as_str() {
+            "#xxx" if QUICK_RE.is_match(base) => 0,
+            "#xxx" if size == "l" && !base.starts_with('b') => 4,
+            "xxx.l" => 4,
+            "#xxx" | "xxx.w" | "d(an)" | "d(pc)" | "d(an,ix)" | "d(pc,ix)" => 2,
+            _ => 0,
+        };
+        operands.push(OperandSpan {
+            offset: length,
+            extension,
+            mode,
+            raw,
+        });
+        length += extension;
+    }
+    Some((length, operands))
+}
+
+/// Lists the absolute long references of `lines` that have to be patched when the block is
+/// moved, with the problems found on the way (see the module documentation).
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::fixups::find_fixups;
+///
+/// let lines: Vec<String> = [
+///     "WIDTH equ 160",
+///     "move.w d0,d1",
+///     "lea table+WIDTH,a0",
+///     "move.l #WIDTH,d0",
+///     "jmp next",
+/// ]
+/// .map(String::from)
+/// .to_vec();
+/// let fixups = find_fixups(&lines);
+/// let offsets: Vec<_> = fixups.fixups.iter().map(|f| f.offset).collect();
+/// // `lea` follows the 2 byte move, `jmp` the 6 byte `lea` and 6 byte `move.l`
+/// assert_eq!(offsets, [4, 16]);
+/// assert_eq!(fixups.bytes, Some(20));
+/// ```
+pub fn find_fixups(lines: &[String]) -> Fixups {
+    let mut constants = HashSet::new();
+    let mut labels = HashSet::new();
+    for line in lines {
+        let definition = split_definition(code_of(line));
+        if let Some(name) = definition.name {
+            if definition.assignment {
+                constants.insert(name);
+            } else {
+                labels.insert(name);
+            }
+        }
+    }
+    let relocatable = |text: &str| symbol_names(text).any(|s| !constants.contains(s));
+    let block_label = |text: &str| {
+        symbol_names(text)
+            .find(|s| labels.contains(s))
+            .map(str::to_string)
+    };
+
+    let mut result = Fixups::default();
+    // The offset of the current line, `None` once a line could not be sized
+    let mut offset = Some(0);
+    for (index, line) in lines.iter().enumerate() {
+        let definition = split_definition(code_of(line));
+        let code = definition.rest.trim();
+        if definition.assignment || code.is_empty() {
+            continue;
+        }
+        let mut issue = |reason: String| result.issues.push(RelocationIssue { index, reason });
+
+        // Fixups of the line, as offsets from its start
+        let mut references = Vec::new();
+        let size = if let Some(directive) = parse_data_directive(code) {
+            if let Some(caps) = DC_VALUES_RE.captures(code) {
+                let long = caps["size"].eq_ignore_ascii_case("l");
+                let values = code_of(&caps["values"]).split(',');
+                for (position, value) in values.map(str::trim).enumerate() {
+                    if long && relocatable(value) {
+                        references.push((4 * position, value.to_string()));
+                    } else if let Some(label) = block_label(value).filter(|_| !long) {
+                        issue(format!(
+                            "`dc.w` holds the address of `{}` in 16 bits, which cannot be relocated",
+                            label
+                        ));
+                    }
+                }
+            }
+            match directive {
+                DataDirective::Ds { bytes }
+                | DataDirective::Dcb { bytes }
+                | DataDirective::Dc { bytes } => bytes,
+                DataDirective::Incbin { .. } => None,
+                DataDirective::Even => offset.map(|o: usize| o % 2),
+            }
+        } else if let Some(caps) = DCB_NOP_RE.captures(code) {
+            parse_literal(&caps[1]).map(|count| 2 * count)
+        } else if NON_SYMBOL_DIRECTIVES.contains(
+            &code
+                .split_whitespace()
+                .next()
+                .unwrap_or("")
+                .to_lowercase()
+                .trim_start_matches('.'),
+        ) {
+            Some(0)
+        } else if parse_directive(code).is_some() {
+            None
+        } else if let Some((length, operands)) = instruction_layout(code) {
+            for operand in operands {
+                let long_immediate = operand.mode == "#xxx" && operand.extension == 4;
+                if (operand.mode == "xxx.l" || long_immediate) && relocatable(&operand.raw) {
+                    let target = operand.raw.trim_start_matches('#').to_string();
+                    references.push((operand.offset, target));
+                } else if operand.mode == "xxx.w"
+                    && let Some(label) = block_label(&operand.raw)
+                {
+                    issue(format!(
+                        "`{}` is an absolute word address, which cannot be relocated",
+                        label
+                    ));
+                }
+            }
+            Some(length)
+        } else {
+            None
+        };
+
+        match offset {
+            Some(start) => result
+                .fixups
+                .extend(references.into_iter().map(|(at, target)| Fixup {
+                    offset: start + at,
+                    index,
+                    target,
+                })),
+            None => {
+                for (_, target) in references {
+                    issue(format!(
+                        "`{}` needs a fixup, but its offset is unknown",
+                        target
+                    ));
+                }
+            }
+        }
+        match (offset, size) {
+            (Some(start), Some(size)) => offset = Some(start + size),
+            (Some(_), None) => {
+                issue(
+                    "The size of this line is unknown; later fixups cannot be located".to_string(),
+                );
+                offset = None;
+            }
+            (None, _) => {}
+        }
+    }
+    result.bytes = offset;
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(raw: &[&str]) -> Vec<String> {
+        raw.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_find_fixups() {
+        let fixups = find_fixups(&lines(&[
+            "CHUNK equ 4",
+            "start:",
+            "\tmovem.l\td0-d7,table\t;\t(80)\tmovem.l reglist,xxx.l\t[0]",
+            "\tmove.w start,d0",
+            "\tmove.w d0,start.w",
+            "\tbra.s start",
+            "\tdbra d7,start",
+            "\tmoveq #CHUNK,d0",
+            "\tdcb.w\t3,$4e71",
+            "\tdc.l start,0,handler",
+            "\tdc.w start",
+            "\tincbin data.bin",
+            "\tjmp start",
+        ]));
+        let found: Vec<_> = fixups
+            .fixups
+            .iter()
+            .map(|f| (f.offset, f.index, f.target.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (4, 2, "table"),
+                (10, 3, "start"),
+                (32, 9, "start"),
+                (40, 9, "handler")
+            ]
+        );
+        let issues: Vec<_> = fixups.issues.iter().map(|i| i.index).collect();
+        assert_eq!(issues, [4, 10, 11, 12]);
+        assert_eq!(fixups.bytes, None);
+
+        assert_eq!(
+            fixups
+                .listing(&lines(&["", "", "\tmovem.l\td0-d7,table\t;\t(80)\t[0]"]))
+                .lines()
+                .nth(1),
+            Some("$000004\ttable\t; movem.l d0-d7,table")
+        );
+        let bytes = fixups.to_bytes();
+        assert_eq!(&bytes[..8], [0, 0, 0, 4, 0, 0, 0, 4]);
+        assert_eq!(bytes.len(), 4 * 5);
+    }
+}
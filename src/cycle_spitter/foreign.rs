@@ -0,0 +1,100 @@
+// src/cycle_spitter/foreign.rs
+
+//! Recognizing input that is not timed ST code.
+//!
+//! Fed with the wrong file - a GEM program, code for a later 680x0 or another machine - the
+//! generator would warn about every instruction it cannot time, burying the actual problem
+//! under hundreds of warnings. [`assess_input`] decides from the share of unanalyzable lines,
+//! instructions missing from the cycle database and operating system calls whose duration is
+//! unknown, whether the input as a whole looks foreign; the generator then reports one
+//! [`ForeignCode`] summary instead of the individual lines.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// The least number of unanalyzable lines for an input to be considered foreign; a handful of
+/// unknown instructions is more likely a gap in the cycle database.
+pub const MIN_UNANALYZABLE_LINES: usize = 16;
+
+/// The least share, in percent of the instruction lines, of unanalyzable lines for an input to
+/// be considered foreign.
+pub const MIN_UNANALYZABLE_PERCENT: usize = 25;
+
+/// The `trap` vectors of GEMDOS (1), AES/VDI (2), BIOS (13) and XBIOS (14).
+const SYSTEM_TRAPS: [&str; 4] = ["#1", "#2", "#13", "#14"];
+
+/// Summary of an input that does not look like timed ST code.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ForeignCode {
+    /// The instruction lines of the input.
+    pub instructions: usize,
+    /// The lines missing from the cycle database.
+    pub unknown: usize,
+    /// The operating system calls.
+    pub system_calls: usize,
+}
+
+impl ForeignCode {
+    /// The lines whose cycles cannot be known.
+    pub fn unanalyzable(&self) -> usize {
+        self.unknown + self.system_calls
+    }
+}
+
+impl fmt::Display for ForeignCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} lines unanalyzable ({} unknown instruction(s), {} system call(s)) - \
+             are you missing a cycle database overlay or processing the wrong file?",
+            self.unanalyzable(),
+            self.instructions,
+            self.unknown,
+            self.system_calls
+        )
+    }
+}
+
+/// Returns whether the instruction `line` calls the operating system (`trap #1`, `trap #14`,
+/// ...), which takes an unknown and usually large number of cycles.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::foreign::is_system_call;
+///
+/// assert!(is_system_call("\ttrap #1 ; Pterm0"));
+/// assert!(!is_system_call("trap #5"));
+/// ```
+pub fn is_system_call(line: &str) -> bool {
+    let code = line.split(';').next().unwrap_or("").to_lowercase();
+    let mut tokens = code.split_whitespace();
+    tokens.next() == Some("trap") && tokens.next().is_some_and(|v| SYSTEM_TRAPS.contains(&v))
+}
+
+/// Returns the summary of an input of `instructions` instruction lines, `unknown` of them
+/// missing from the cycle database and `system_calls` of them calling the operating system, if
+/// these add up to an input that is not timed ST code.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::foreign::assess_input;
+///
+/// assert!(assess_input(300, 4, 0).is_none());
+/// let foreign = assess_input(300, 200, 38).unwrap();
+/// assert!(foreign.to_string().starts_with("238 of 300 lines unanalyzable"));
+/// ```
+pub fn assess_input(
+    instructions: usize,
+    unknown: usize,
+    system_calls: usize,
+) -> Option<ForeignCode> {
+    let foreign = ForeignCode {
+        instructions,
+        unknown,
+        system_calls,
+    };
+    let unanalyzable = foreign.unanalyzable();
+    (unanalyzable >= MIN_UNANALYZABLE_LINES
+        && unanalyzable * 100 >= instructions * MIN_UNANALYZABLE_PERCENT)
+        .then_some(foreign)
+}
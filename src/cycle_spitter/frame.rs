@@ -0,0 +1,283 @@
+// src/cycle_spitter/frame.rs
+
+//! The layout of a frame: which template and scanline budget apply to which scanlines.
+//!
+//! A frame layout is a plain text file with one region per line:
+//!
+//! ```text
+//! ; scanlines  template  [cycles]  [irq=START+CYCLES ...]
+//! 0-199        border
+//! 200-228      bottom    508       irq=120+44
+//! 229-         border
+//! ```
+//!
+//! A region starts at its first scanline and ends at its last one (inclusive); an open range
+//! reaches to the end of the frame. Entering a region selects its template, which `;@use-template`
+//! pragmas can still override until the next region starts. The optional cycle count replaces
+//! the scanline length of the options within the region. Scanlines outside of every region keep
+//! the template in use and the default scanline length.
+//!
+//! An `irq=START+CYCLES` entry declares that an interrupt of fixed cost steals the cycles
+//! `START..START+CYCLES` of every scanline in the region. No user code is scheduled across such
+//! an IRQ window; the generator carves it out of the template's code windows.
+//!
+//! Together with `Options::start_line`, a layout allows generating a chunk of code that starts
+//! in the middle of the frame, e.g. for an effect covering only the lower third of the screen.
+
+use std::error::Error;
+
+/// A range of scanlines sharing a template and budget.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameRegion {
+    /// The first scanline of the region.
+    pub first: usize,
+    /// The last scanline of the region, `None` if the region reaches to the end of the frame.
+    pub last: Option<usize>,
+    /// The name of the template applied when the region is entered.
+    pub template: String,
+    /// The scanline length within the region, `None` for the length of the options.
+    pub cycles: Option<usize>,
+    /// The cycles stolen by interrupts in every scanline of the region, ordered by start.
+    pub irq: Vec<IrqWindow>,
+}
+
+/// A range of cycles within a scanline taken by an interrupt of fixed cost.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IrqWindow {
+    /// The scanline cycle at which the interrupt starts.
+    pub start: usize,
+    /// The cycles the interrupt takes.
+    pub cycles: usize,
+}
+
+impl IrqWindow {
+    /// The scanline cycle after the interrupt.
+    pub fn end(&self) -> usize {
+        self.start + self.cycles
+    }
+}
+
+impl FrameRegion {
+    /// Returns `true` if `scanline` lies within the region.
+    pub fn contains(&self, scanline: usize) -> bool {
+        scanline >= self.first && self.last.is_none_or(|last| scanline <= last)
+    }
+}
+
+/// The regions of a frame, ordered by scanline.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FrameLayout {
+    regions: Vec<FrameRegion>,
+}
+
+impl FrameLayout {
+    /// Parses a frame layout (see the module documentation). Empty lines and lines starting
+    /// with `;` or `#` are ignored, as is anything after a `;` on a region line.
+    ///
+    /// # Errors
+    /// - If a line is not of the form `<first>[-[<last>]] <template> [<cycles>]
+    ///   [irq=<start>+<cycles> ...]`, or its IRQ windows overlap or are out of order.
+    /// - If a region ends before it starts or overlaps the previous one.
+    ///
+    /// # Example
+    /// ```rust
+    /// use cycle_spitter::frame::FrameLayout;
+    ///
+    /// let layout = FrameLayout::parse("0-199 top\n200- bottom 508").unwrap();
+    /// assert_eq!(layout.region_at(250).unwrap().1.template, "bottom");
+    /// assert_eq!(layout.cycles_at(250), Some(508));
+    /// ```
+    pub fn parse(content: &str) -> Result<Self, Box<dyn Error>> {
+        let mut regions: Vec<FrameRegion> = Vec::new();
+        for (number, line) in content.lines().enumerate() {
+            let line = line.split(';').next().unwrap_or_default().trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let region = parse_region(line)
+                .ok_or_else(|| format!("Invalid frame layout line {}: {}", number + 1, line))?;
+            if let Some(previous) = regions.last()
+                && previous.last.is_none_or(|last| region.first <= last)
+            {
+                return Err(format!(
+                    "Frame layout line {}: region starting at scanline {} overlaps the previous one",
+                    number + 1,
+                    region.first
+                )
+                .into());
+            }
+            regions.push(region);
+        }
+        Ok(FrameLayout { regions })
+    }
+
+    /// The regions in scanline order.
+    pub fn regions(&self) -> &[FrameRegion] {
+        &self.regions
+    }
+
+    /// Returns the index and region containing `scanline`, if any.
+    pub fn region_at(&self, scanline: usize) -> Option<(usize, &FrameRegion)> {
+        self.regions
+            .iter()
+            .enumerate()
+            .find(|(_, region)| region.contains(scanline))
+    }
+
+    /// Returns the scanline length set for `scanline`, if its region sets one.
+    pub fn cycles_at(&self, scanline: usize) -> Option<usize> {
+        self.region_at(scanline)
+            .and_then(|(_, region)| region.cycles)
+    }
+
+    /// Returns the IRQ windows of `scanline`.
+    pub fn irq_windows_at(&self, scanline: usize) -> &[IrqWindow] {
+        self.region_at(scanline)
+            .map(|(_, region)| region.irq.as_slice())
+            .unwrap_or_default()
+    }
+}
+
+/// Follows the frame regions of consecutive scanlines.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RegionTracker {
+    current: Option<usize>,
+}
+
+impl RegionTracker {
+    /// Creates a tracker positioned on `scanline`, or before the first scanline if `None`.
+    pub(crate) fn at(layout: Option<&FrameLayout>, scanline: Option<usize>) -> Self {
+        let current = layout
+            .zip(scanline)
+            .and_then(|(layout, scanline)| layout.region_at(scanline))
+            .map(|(index, _)| index);
+        RegionTracker { current }
+    }
+
+    /// Moves on to `scanline` and returns the template of its region if the scanline enters a
+    /// region.
+    pub(crate) fn advance<'a>(
+        &mut self,
+        layout: Option<&'a FrameLayout>,
+        scanline: usize,
+    ) -> Option<&'a str> {
+        let (index, region) = layout?.region_at(scanline)?;
+        let entered = self.current != Some(index);
+        self.current = Some(index);
+        entered.then_some(region.template.as_str())
+    }
+}
+
+/// Parses `<first>[-[<last>]] <template> [<cycles>] [irq=<start>+<cycles> ...]`.
+fn parse_region(line: &str) -> Option<FrameRegion> {
+    let mut parts = line.split_whitespace();
+    let range = parts.next()?;
+    let template = parts.next()?.to_string();
+    let mut cycles = None;
+    let mut irq: Vec<IrqWindow> = Vec::new();
+    for part in parts {
+        if let Some(window) = part.strip_prefix("irq=") {
+            let (start, length) = window.split_once('+')?;
+            let window = IrqWindow {
+                start: start.parse().ok()?,
+                cycles: length.parse().ok()?,
+            };
+            if window.cycles == 0 || irq.last().is_some_and(|last| window.start < last.end()) {
+                return None;
+            }
+            irq.push(window);
+        } else if cycles.is_none() && irq.is_empty() {
+            cycles = Some(part.parse().ok()?);
+        } else {
+            return None;
+        }
+    }
+    let (first, last) = match range.split_once('-') {
+        Some((first, "")) => (first.parse().ok()?, None),
+        Some((first, last)) => (first.parse().ok()?, Some(last.parse().ok()?)),
+        None => {
+            let line = range.parse().ok()?;
+            (line, Some(line))
+        }
+    };
+    if last.is_some_and(|last| last < first) {
+        return None;
+    }
+    Some(FrameRegion {
+        first,
+        last,
+        template,
+        cycles,
+        irq,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_layout() {
+        let layout = FrameLayout::parse(
+            "; top border\n0-15 top\n\n# main\n16-199 main 508 ; lower\n228 last",
+        )
+        .unwrap();
+        assert_eq!(layout.regions().len(), 3);
+        assert_eq!(layout.region_at(0).unwrap().1.template, "top");
+        assert_eq!(layout.region_at(16).unwrap().0, 1);
+        assert_eq!(layout.cycles_at(199), Some(508));
+        assert_eq!(layout.cycles_at(15), None);
+        assert!(layout.region_at(200).is_none());
+        assert_eq!(layout.region_at(228).unwrap().1.template, "last");
+        assert!(layout.region_at(229).is_none());
+    }
+
+    #[test]
+    fn test_parse_irq_windows() {
+        let layout =
+            FrameLayout::parse("0-9 main irq=100+44 irq=300+20\n10- alt 508 irq=0+8").unwrap();
+        assert_eq!(
+            layout.irq_windows_at(3),
+            [
+                IrqWindow {
+                    start: 100,
+                    cycles: 44
+                },
+                IrqWindow {
+                    start: 300,
+                    cycles: 20
+                }
+            ]
+        );
+        assert_eq!(layout.cycles_at(10), Some(508));
+        assert_eq!(layout.irq_windows_at(10)[0].end(), 8);
+
+        assert!(FrameLayout::parse("0 main irq=100").is_err());
+        assert!(FrameLayout::parse("0 main irq=100+0").is_err());
+        assert!(FrameLayout::parse("0 main irq=100+44 irq=120+4").is_err());
+        assert!(FrameLayout::parse("0 main irq=100+44 508").is_err());
+    }
+
+    #[test]
+    fn test_invalid_layouts() {
+        assert!(FrameLayout::parse("0-10").is_err());
+        assert!(FrameLayout::parse("10-5 main").is_err());
+        assert!(FrameLayout::parse("0-10 main fast").is_err());
+        assert!(FrameLayout::parse("0- main\n20 other").is_err());
+        assert!(FrameLayout::parse("0-10 main\n10-20 other").is_err());
+    }
+
+    #[test]
+    fn test_region_tracker() {
+        let layout = FrameLayout::parse("2-3 a\n4- b").unwrap();
+        let mut tracker = RegionTracker::default();
+        let entered: Vec<Option<&str>> = (0..6)
+            .map(|scanline| tracker.advance(Some(&layout), scanline))
+            .collect();
+        assert_eq!(entered, [None, None, Some("a"), None, Some("b"), None]);
+
+        let mut resumed = RegionTracker::at(Some(&layout), Some(4));
+        assert_eq!(resumed.advance(Some(&layout), 5), None);
+        assert_eq!(RegionTracker::default().advance(None, 0), None);
+    }
+}
@@ -0,0 +1,2674 @@
+// src/cycle_spitter/generator.rs
+
+use crate::cycle_spitter::accumulator::{Slot, accumulate, padding_runs, push_padding};
+use crate::cycle_spitter::cache::ScanlineCache;
+use crate::cycle_spitter::capacity::frame_scanlines;
+use crate::cycle_spitter::checksum::{TOTAL_CYCLES_PREFIX, checksum_comment, scanline_checksum};
+use crate::cycle_spitter::cycles::{lookup_cycles, normalize_line_ext};
+use crate::cycle_spitter::data::{misaligned_code, parse_data_directive};
+use crate::cycle_spitter::database::CycleDatabase;
+use crate::cycle_spitter::diagnostics::{Diagnostic, DiagnosticKind, warning_comment};
+use crate::cycle_spitter::error::CycleSpitterError;
+use crate::cycle_spitter::exception::exception_warning;
+use crate::cycle_spitter::filler::Filler;
+use crate::cycle_spitter::foreign::{assess_input, is_system_call};
+use crate::cycle_spitter::frame::{IrqWindow, RegionTracker};
+use crate::cycle_spitter::hardware::{HardwareRegister, written_registers};
+use crate::cycle_spitter::helpers::{format_accumulated_instruction, strip_annotations};
+use crate::cycle_spitter::models::{Chunk, Instruction, LineKind, Scanline, render};
+use crate::cycle_spitter::observer::{Observer, StderrObserver};
+use crate::cycle_spitter::options::{
+    Options, OverflowPolicy, Packer, PaddingStyle, Strictness, Tail, Verbosity,
+};
+use crate::cycle_spitter::packer::{cycles_of, pack};
+use crate::cycle_spitter::pragma::{Pragma, is_critical, parse_budget, parse_pragma};
+use crate::cycle_spitter::regexes::REG_NUMBER_RE;
+use crate::cycle_spitter::template::{TemplateSection, TemplateSet, expand_line};
+use crate::cycle_spitter::trace::{Decision, PackingDecision};
+use crate::cycle_spitter::video::inject_video_base;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::ops::Range;
+
+/// The number of scanlines in a row that may leave the input untouched before generation is
+/// given up. A frame layout can make the scanlines after a stall wider, but a line not placed
+/// within a whole PAL frame never will be.
+const MAX_STALLED_SCANLINES: usize = 313;
+
+/// The result of splitting user code into scanlines.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Generation {
+    /// The generated lines (template code, annotated user code, padding and comments).
+    pub lines: Vec<String>,
+    /// The number of scanlines generated so far, including those of resumed runs.
+    pub scanlines: usize,
+    /// `true` if an observer stopped generation before all user code was consumed.
+    pub aborted: bool,
+    /// `true` if generation was stopped through the options' cancellation token.
+    pub cancelled: bool,
+    /// The position reached; pass it to [`resume_with_observer`] to continue an aborted or
+    /// cancelled generation.
+    pub state: ResumeState,
+    /// How the cycles of every generated scanline are spent, in scanline order.
+    pub usage: Vec<ScanlineUsage>,
+    /// The generated scanlines as data: `lines` is their rendering (see [`render`]).
+    pub model: Vec<Scanline>,
+}
+
+/// How the cycles of one template section are spent in a scanline.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SectionUsage {
+    /// The section's label.
+    pub label: String,
+    /// Cycles of the section's injection code.
+    pub template_cycles: usize,
+    /// Cycles of user code placed into the section.
+    pub user_cycles: usize,
+    /// Cycles of NOP padding inserted into the section.
+    pub padding_cycles: usize,
+}
+
+/// How the cycles of one scanline are spent.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct ScanlineUsage {
+    /// The sections in scanline order.
+    pub sections: Vec<SectionUsage>,
+    /// Cycles of the `dcb.w` block padding the scanline to its budget.
+    pub tail_padding: usize,
+}
+
+impl ScanlineUsage {
+    /// Cycles spent on user code.
+    pub fn user_cycles(&self) -> usize {
+        self.sections.iter().map(|s| s.user_cycles).sum()
+    }
+
+    /// Cycles spent on padding, inside sections and at the end of the scanline.
+    pub fn padding_cycles(&self) -> usize {
+        self.sections
+            .iter()
+            .map(|s| s.padding_cycles)
+            .sum::<usize>()
+            + self.tail_padding
+    }
+
+    /// Cycles spent on the template's injection code.
+    pub fn template_cycles(&self) -> usize {
+        self.sections.iter().map(|s| s.template_cycles).sum()
+    }
+
+    /// Total cycles accounted for in the scanline.
+    pub fn total_cycles(&self) -> usize {
+        self.template_cycles() + self.user_cycles() + self.padding_cycles()
+    }
+
+    /// Returns the comment stating how much of a scanline of `total` cycles is not padding.
+    ///
+    /// # Example
+    /// ```rust
+    /// use cycle_spitter::generator::ScanlineUsage;
+    ///
+    /// let usage = ScanlineUsage {
+    ///     tail_padding: 64,
+    ///     ..Default::default()
+    /// };
+    /// assert_eq!(
+    ///     usage.utilization_comment(512),
+    ///     "; utilization: 87% (448/512), padding 64"
+    /// );
+    /// ```
+    pub fn utilization_comment(&self, total: usize) -> String {
+        let padding = self.padding_cycles().min(total);
+        let used = total - padding;
+        format!(
+            "; utilization: {}% ({}/{}), padding {}",
+            (used * 100).checked_div(total).unwrap_or(0),
+            used,
+            total,
+            padding
+        )
+    }
+}
+
+/// The position reached by a generation.
+///
+/// Scanlines are generated independently of each other, so the index of the next unconsumed
+/// input line, the number of scanlines generated so far and the active template are all that
+/// is needed to continue. Resuming is only meaningful with the same flattened input, templates
+/// and options.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct ResumeState {
+    /// Index of the first input line not yet placed into a scanline.
+    pub next_index: usize,
+    /// Number of scanlines generated before this point.
+    pub scanlines: usize,
+    /// The template selected by the last `;@use-template` pragma, or `None` for the initial
+    /// template of the set.
+    pub template: Option<String>,
+    /// The scanline budget set by the last `;@budget` pragma, or `None` for the budget of the
+    /// options.
+    pub budget: Option<usize>,
+}
+
+impl ResumeState {
+    /// Returns `true` if all input lines have been consumed.
+    pub fn is_complete(&self, flat_lines: &[String]) -> bool {
+        self.next_index >= flat_lines.len()
+    }
+}
+
+/// Splits flattened user code into scanlines, injecting the template sections into each one.
+///
+/// # Arguments
+/// - `flat_lines`: The user code with all `REPT` blocks already expanded (see `process_block`).
+/// - `templates`: The templates available for scanlines (see `parse_template` and `TemplateSet`;
+///   a single parsed template converts with `TemplateSet::from`).
+/// - `db`: The cycle database used for instruction lookups.
+/// - `options`: Generation options (scanline length, overflow policy, padding, annotations, ...).
+///
+/// # Returns
+/// A `Generation` with the generated lines and the number of scanlines.
+///
+/// # Behavior
+/// - For every scanline, each template section's injection code is emitted, followed by as much
+///   user code as fits into the section's NOP budget (padded with NOPs if needed).
+/// - Scanlines shorter than `cycles_per_line` are padded with a `dcb.w` NOP block.
+/// - Scanlines exceeding `cycles_per_line` are handled according to the overflow policy.
+/// - The initial template of the set is applied until a `;@use-template <name>` pragma selects
+///   another one for the following scanlines.
+/// - With a frame layout (see the `frame` module), entering a region selects its template and
+///   scanline length; the first scanline is scanline `start_line` of the frame.
+/// - IRQ windows of the frame layout are kept free of user code.
+/// - The first scanline starts `entry_offset` cycles into the scanline and the last one ends
+///   `exit_offset` cycles before its end; template code outside of these bounds is left out.
+/// - With a loop seam, the block starts with its label and the last scanline ends with the
+///   jump back, early enough for the seam to be cycle-exact (see the `seam` module).
+/// - With a chunk chain, every chunk after the first starts with its label and the last
+///   scanline of every chunk but the last ends with a jump to the next one.
+/// - A `;@raw-scanline` pragma makes the next scanline a single user code window without
+///   template sections (see the `pragma` module).
+/// - A `;@budget` pragma overrides the scanline length of the options and frame layout for
+///   the following scanlines.
+/// - If checksums are enabled, each scanline ends with a checksum comment (see `checksum`).
+/// - If warning comments are enabled, the warnings raised for a scanline are comments ahead
+///   of its total cycles rather than observer events.
+/// - If a separator is set, each scanline starts with a separator comment showing its frame
+///   cycles.
+/// - Annotations left on input lines by a previous run are replaced instead of being stacked.
+/// - User code writing a hardware register that the scanline's template also writes (such as
+///   the sync mode register) raises a warning (see the `hardware` module).
+///
+/// Warnings, including instructions missing from the cycle database (counted as 0 cycles), are
+/// printed to standard error; use [`generate_with_observer`] to receive them as
+/// [`Diagnostic`]s (and other progress events) programmatically.
+///
+/// # Errors
+/// - If the template set is empty or a `;@use-template` pragma names an unknown template.
+/// - If a `;@budget` pragma gives neither a number of cycles nor `default`.
+/// - In strict mode, if any user instruction is missing from the cycle database.
+/// - With `OverflowPolicy::Error`, if a scanline exceeds the cycle budget.
+/// - If the entry or exit offset falls into template code, takes the whole scanline, or if
+///   both together exceed a block of a single scanline.
+/// - If an IRQ window overlaps template code or crosses the end of a section.
+/// - If an instruction takes more cycles than the largest code window of any template.
+/// - If the input needs more scanlines than the maximum set in the options.
+/// - If an instruction fits no section for a whole frame of scanlines, e.g. because the windows
+///   large enough for it are taken by IRQ windows or belong to templates that are never used.
+pub fn generate(
+    flat_lines: &[String],
+    templates: &TemplateSet,
+    db: &CycleDatabase,
+    options: &Options,
+) -> Result<Generation, CycleSpitterError> {
+    generate_with_observer(flat_lines, templates, db, options, &mut StderrObserver)
+}
+
+/// Same as [`generate`], but reports warnings, padding and completed scanlines to `observer`
+/// instead of standard error. Generation stops early if the observer returns
+/// `ControlFlow::Break` from [`Observer::on_scanline_complete`], or once the options'
+/// cancellation token is cancelled (checked between scanlines).
+pub fn generate_with_observer(
+    flat_lines: &[String],
+    templates: &TemplateSet,
+    db: &CycleDatabase,
+    options: &Options,
+    observer: &mut dyn Observer,
+) -> Result<Generation, CycleSpitterError> {
+    resume_with_observer(
+        flat_lines,
+        templates,
+        db,
+        options,
+        observer,
+        ResumeState::default(),
+    )
+}
+
+/// Continues a generation from `state`, as returned in [`Generation::state`] by a previous
+/// aborted or cancelled run. The returned `lines` only contain the newly generated scanlines.
+pub fn resume_with_observer(
+    flat_lines: &[String],
+    templates: &TemplateSet,
+    db: &CycleDatabase,
+    options: &Options,
+    observer: &mut dyn Observer,
+    state: ResumeState,
+) -> Result<Generation, CycleSpitterError> {
+    run(flat_lines, templates, db, options, observer, state, None)
+}
+
+/// Same as [`generate_with_observer`], but reuses scanlines found in `cache` and records every
+/// newly generated scanline in it (see the `cache` module). The output is identical to an
+/// uncached run.
+pub fn generate_cached(
+    flat_lines: &[String],
+    templates: &TemplateSet,
+    db: &CycleDatabase,
+    options: &Options,
+    observer: &mut dyn Observer,
+    cache: &mut ScanlineCache,
+) -> Result<Generation, CycleSpitterError> {
+    run(
+        flat_lines,
+        templates,
+        db,
+        options,
+        observer,
+        ResumeState::default(),
+        Some(cache),
+    )
+}
+
+fn run(
+    flat_lines: &[String],
+    templates: &TemplateSet,
+    db: &CycleDatabase,
+    options: &Options,
+    observer: &mut dyn Observer,
+    state: ResumeState,
+    mut cache: Option<&mut ScanlineCache>,
+) -> Result<Generation, CycleSpitterError> {
+    // Annotations of a previous run are replaced, not stacked.
+    let mut stripped: Vec<String> = flat_lines
+        .iter()
+        .map(|line| strip_annotations(line).to_string())
+        .collect();
+    // The index in `flat_lines` every line came from, for diagnostics.
+    let mut origins: Vec<usize> = (0..stripped.len()).collect();
+    if options.packer() != Packer::Greedy {
+        (stripped, origins) = pack(&stripped, templates, db, options)?;
+    }
+    // Code after odd-sized data would be padded by the assembler behind our back, so the
+    // padding is made explicit (and placed before any label) or refused.
+    for position in misaligned_code(&stripped).into_iter().rev() {
+        let code = stripped[position..]
+            .iter()
+            .find(|line| !line.trim().is_empty() && !line.trim().starts_with(';'))
+            .map_or("", |line| line.trim());
+        if options.strictness() == Strictness::Strict {
+            return Err(format!(
+                "Odd-sized data misaligns '{}'; add an 'even' directive",
+                code
+            )
+            .into());
+        }
+        let origin = origins.get(position).copied().unwrap_or_default();
+        observer.on_diagnostic(
+            &Diagnostic::new(
+                DiagnosticKind::Other,
+                format!(
+                    "Inserted 'even' before '{}' to align code after odd-sized data",
+                    code
+                ),
+            )
+            .at(origin),
+        );
+        stripped.insert(position, "\teven".to_string());
+        origins.insert(position, origin);
+    }
+    let flat_lines = stripped.as_slice();
+
+    let remaining = flat_lines.get(state.next_index..).unwrap_or_default();
+    if options.strictness() == Strictness::Strict {
+        let unknown = find_unknown_instructions(remaining, db);
+        if !unknown.is_empty() {
+            return Err(CycleSpitterError::UnknownInstruction(unknown));
+        }
+    } else if state.next_index == 0 {
+        // Reported once per input (and once for the templates), not on every resumed run.
+        let template = if state.scanlines == 0 {
+            template_lines(templates)
+        } else {
+            Vec::new()
+        };
+        let mut unknown_input = unknown_instruction_indices(flat_lines, db);
+        let instructions = flat_lines.iter().filter(|l| is_instruction_line(l)).count();
+        let system_calls = flat_lines.iter().filter(|l| is_system_call(l)).count();
+        let foreign = assess_input(instructions, unknown_input.len(), system_calls);
+        if let Some(foreign) = &foreign {
+            // One summary instead of a warning for every line of the wrong file
+            observer.on_diagnostic(&Diagnostic::new(
+                DiagnosticKind::UnknownInstruction,
+                foreign.to_string(),
+            ));
+            unknown_input.clear();
+        }
+        let unknown = find_unknown_instructions(&template, db)
+            .into_iter()
+            .map(|line| (line, None))
+            .chain(
+                unknown_input
+                    .into_iter()
+                    .map(|index| (flat_lines[index].clone(), Some(origins[index]))),
+            );
+        let mut reported: Vec<String> = Vec::new();
+        for (line, index) in unknown {
+            let line = line.trim().to_string();
+            if reported.contains(&line) {
+                continue;
+            }
+            let mut diagnostic = Diagnostic::new(
+                DiagnosticKind::UnknownInstruction,
+                format!("No cycle count found for instruction: {}", line),
+            );
+            diagnostic.index = index;
+            observer.on_diagnostic(&diagnostic);
+            reported.push(line);
+        }
+        if foreign.is_none() {
+            let mut warned: Vec<String> = Vec::new();
+            for (index, line) in flat_lines.iter().enumerate() {
+                if let Some(warning) = exception_warning(line, db)
+                    && !warned.contains(&warning)
+                {
+                    observer.on_diagnostic(
+                        &Diagnostic::new(DiagnosticKind::Other, warning.clone()).at(origins[index]),
+                    );
+                    warned.push(warning);
+                }
+            }
+        }
+    }
+
+    let overrides = override_lines(flat_lines, state.next_index);
+    if !overrides.is_empty() {
+        observer.on_diagnostic(&Diagnostic::new(
+            DiagnosticKind::Other,
+            format!(
+                "Cycle overrides applied to {} line(s): {}",
+                overrides.len(),
+                overrides.join(", ")
+            ),
+        ));
+    }
+
+    let mut template_name = match state.template {
+        Some(name) => name,
+        None => templates
+            .initial()
+            .ok_or("No template registered")?
+            .to_string(),
+    };
+    let mut sections = select_template(templates, &template_name)?;
+    let mut budget = state.budget;
+
+    let mut model: Vec<Scanline> = Vec::new();
+    // Lines generated ahead of the next scanline's code: labels, pragmas and separators
+    let mut lead: Vec<Instruction> = Vec::new();
+    let mut usage = Vec::new();
+    let mut current_index = state.next_index;
+    let mut scanlines = state.scanlines;
+    let mut aborted = false;
+    let mut cancelled = false;
+    // Scanlines in a row that consumed no input
+    let mut stalled = 0;
+    // The number of scanlines to generate if the frame is to be filled after the input ran
+    // out, and whether the input has been started over
+    let frame_end = match options.tail() {
+        Tail::Stop => None,
+        _ => Some(frame_scanlines(options).ok_or(
+            "Padding or repeating up to the end of the frame needs a frame that ends: set a \
+             scanline limit or close the last region of the frame layout",
+        )?),
+    };
+    let mut repeated = false;
+    // A looping block ends with the jump back instead of an epilogue, so its last scanline
+    // leaves room for the seam; until the jump is placed, the loop is open.
+    let exit_offset = match options.loop_seam() {
+        Some(seam) => seam.reserve(db, options.entry_offset())?,
+        None => options.exit_offset(),
+    };
+    let mut seam_open = options.loop_seam().is_some();
+    if let Some(seam) = options.loop_seam()
+        && scanlines == 0
+    {
+        lead.push(Instruction::other(format!("{}:", seam.label), None));
+    }
+    let layout = options.frame_layout();
+    let mut regions = RegionTracker::at(
+        layout,
+        scanlines.checked_sub(1).map(|s| options.start_line() + s),
+    );
+    // The frame cycle at which the next scanline starts
+    let mut frame_cycle: usize = (0..options.start_line() + scanlines)
+        .map(|line| options.cycles_at(line))
+        .sum();
+
+    loop {
+        let frame_left = frame_end.is_some_and(|end| scanlines < end);
+        if current_index >= flat_lines.len() {
+            if !frame_left && !seam_open {
+                break;
+            }
+            if frame_left && options.tail() == Tail::Repeat {
+                current_index = 0;
+                repeated = true;
+            }
+        } else if repeated && !frame_left {
+            break;
+        }
+        if options.cancellation().is_some_and(|t| t.is_cancelled()) {
+            cancelled = true;
+            break;
+        }
+
+        if let Some(chain) = options.chunk_chain()
+            && chain.starts_chunk(scanlines)
+        {
+            lead.push(Instruction::other(
+                format!("{}:", chain.label(scanlines / chain.every)),
+                None,
+            ));
+        }
+        let start_index = current_index;
+        let frame_line = options.start_line() + scanlines;
+        if let Some(name) = regions.advance(layout, frame_line) {
+            sections = select_template(templates, name)?;
+            template_name = name.to_string();
+        }
+
+        // Apply the pragmas preceding this scanline
+        let mut raw_scanline = false;
+        while let Some(pragma) = flat_lines.get(current_index).and_then(|l| parse_pragma(l)) {
+            match pragma {
+                Pragma::RawScanline => raw_scanline = true,
+                Pragma::UseTemplate(name) => {
+                    sections = select_template(templates, &name)?;
+                    template_name = name;
+                }
+                // Ending the previous scanline is all a split marker does.
+                Pragma::SplitHere => {}
+                Pragma::Budget(argument) => budget = parse_budget(&argument)?,
+            }
+            lead.push(Instruction::other(
+                flat_lines[current_index].clone(),
+                Some(origins[current_index]),
+            ));
+            current_index += 1;
+        }
+        let cycles_per_line = budget.unwrap_or_else(|| options.cycles_at(frame_line));
+        if current_index >= flat_lines.len() {
+            if !frame_left && !seam_open {
+                break;
+            }
+            // Start the input over in this scanline, unless it holds nothing but pragmas.
+            if options.tail() == Tail::Repeat && start_index > 0 {
+                continue;
+            }
+        }
+        if let Some(max) = options.max_scanlines()
+            && scanlines >= max
+        {
+            // Once the input is placed, only an open loop seam asks for another scanline.
+            return Err(match flat_lines.get(current_index) {
+                Some(line) => format!(
+                    "The input needs more than {} scanlines; '{}' (index {}) and the {} line(s) \
+                     after it do not fit",
+                    max,
+                    line.trim(),
+                    current_index,
+                    flat_lines.len().saturating_sub(current_index + 1)
+                ),
+                None => format!(
+                    "The input needs more than {} scanlines; the jump back to the loop label \
+                     '{}' does not fit",
+                    max,
+                    options.loop_seam().map_or("", |seam| seam.label.as_str())
+                ),
+            }
+            .into());
+        }
+        if let Some(rule) = options.separator() {
+            let rule = rule.to_string().repeat(8);
+            lead.push(Instruction::other(
+                format!(
+                    "; {} scanline {} (cycles {}..{}) {}",
+                    rule,
+                    frame_line,
+                    frame_cycle,
+                    frame_cycle + cycles_per_line,
+                    rule
+                ),
+                None,
+            ));
+        }
+        frame_cycle += cycles_per_line;
+
+        // A raw scanline is a single user code window spanning the whole budget.
+        let raw_sections;
+        let (scanline_sections, selector) = if raw_scanline {
+            raw_sections = [TemplateSection::code_window(
+                cycles_per_line,
+                "raw scanline",
+            )];
+            (&raw_sections[..], None)
+        } else {
+            (sections, Some(template_name.as_str()))
+        };
+        let updated;
+        let scanline_sections = match options.video_base() {
+            Some(update) if !raw_scanline && update.applies_to(frame_line) => {
+                updated = inject_video_base(scanline_sections, update, db)?;
+                &updated[..]
+            }
+            _ => scanline_sections,
+        };
+        let irq_windows = options
+            .frame_layout()
+            .map(|layout| layout.irq_windows_at(frame_line))
+            .unwrap_or_default();
+        let carved;
+        let scanline_sections = if irq_windows.is_empty() {
+            scanline_sections
+        } else {
+            carved = carve_irq_windows(scanline_sections, irq_windows)?;
+            &carved[..]
+        };
+        // The first scanline begins after the prologue of the enclosing code.
+        let entry = if scanlines == 0 {
+            options.entry_offset()
+        } else {
+            0
+        };
+        if entry > 0 && entry >= cycles_per_line {
+            return Err(format!(
+                "The entry offset of {} cycles leaves nothing of the {} cycles of scanline {}",
+                entry, cycles_per_line, frame_line
+            )
+            .into());
+        }
+        let entry_sections;
+        let first_sections = if entry > 0 {
+            entry_sections = clip_sections(scanline_sections, &(entry..cycles_per_line))?;
+            &entry_sections[..]
+        } else {
+            scanline_sections
+        };
+        // Clipped and carved scanlines are not worth caching, nor are those padding the frame
+        // after the input, and templates referring to the scanline index differ from scanline
+        // to scanline.
+        let cacheable = entry == 0
+            && current_index < flat_lines.len()
+            && irq_windows.is_empty()
+            && !scanline_sections.iter().any(TemplateSection::uses_line);
+        let cached = cache
+            .as_deref_mut()
+            .filter(|_| cacheable)
+            .and_then(|c| c.lookup(selector, cycles_per_line, flat_lines, current_index));
+        let mut filled = match cached {
+            Some(filled) => filled,
+            None => {
+                let filled = fill_scanline(
+                    flat_lines,
+                    current_index,
+                    first_sections,
+                    entry..cycles_per_line,
+                    db,
+                    options,
+                    frame_line,
+                )?;
+                if let Some(c) = cache.as_deref_mut().filter(|_| cacheable) {
+                    c.insert(
+                        selector,
+                        cycles_per_line,
+                        flat_lines,
+                        current_index,
+                        &filled,
+                    );
+                }
+                filled
+            }
+        };
+        // The last scanline ends where the epilogue of the enclosing code begins. Whether a
+        // scanline is the last one is only known once it has been filled, so it is filled once
+        // more with the shorter span and kept if the rest of the input still fits. When the
+        // frame is filled, its last scanline is the last one.
+        let ends_input = current_index + filled.consumed >= flat_lines.len();
+        let last_scanline = match frame_end {
+            Some(end) => scanlines + 1 >= end && (ends_input || repeated),
+            None => ends_input,
+        };
+        let mut closing_jump = None;
+        if (exit_offset > 0 || seam_open) && last_scanline {
+            if seam_open && exit_offset + entry > cycles_per_line {
+                return Err(format!(
+                    "The loop seam takes {} of the {} cycles of the last scanline",
+                    exit_offset + entry,
+                    cycles_per_line
+                )
+                .into());
+            }
+            if !seam_open && exit_offset >= cycles_per_line {
+                return Err(format!(
+                    "The exit offset of {} cycles leaves nothing of the {} cycles of scanline {}",
+                    exit_offset, cycles_per_line, frame_line
+                )
+                .into());
+            }
+            if !seam_open && exit_offset + entry > cycles_per_line {
+                return Err(format!(
+                    "The entry and exit offsets take {} of the {} cycles of the single scanline",
+                    exit_offset + entry,
+                    cycles_per_line
+                )
+                .into());
+            }
+            let exit = cycles_per_line.saturating_sub(exit_offset);
+            let span = entry..exit.max(entry);
+            let last = fill_scanline(
+                flat_lines,
+                current_index,
+                &clip_sections(scanline_sections, &span)?,
+                span,
+                db,
+                options,
+                frame_line,
+            )?;
+            if repeated || current_index + last.consumed >= flat_lines.len() {
+                filled = last;
+                if let Some(seam) = options.loop_seam() {
+                    if filled.cycles != exit {
+                        return Err(format!(
+                            "The loop seam is not cycle-exact: the last scanline runs {} cycles \
+                             before the jump back instead of {}",
+                            filled.cycles, exit
+                        )
+                        .into());
+                    }
+                    let cycles = lookup_cycles(&seam.jump(), db);
+                    closing_jump = Some((
+                        format_accumulated_instruction(
+                            &seam.jump(),
+                            &cycles,
+                            exit,
+                            options.annotation_format(),
+                        ),
+                        cycles.total(),
+                    ));
+                    seam_open = false;
+                }
+            }
+        }
+        // The last scanline of a chunk jumps to the next chunk, if another scanline follows.
+        if let Some(chain) = options.chunk_chain()
+            && closing_jump.is_none()
+            && chain.starts_chunk(scanlines + 1)
+        {
+            let next_frame_left = frame_end.is_some_and(|end| scanlines + 1 < end);
+            let follows = if current_index + filled.consumed >= flat_lines.len() {
+                next_frame_left || seam_open
+            } else {
+                !repeated || next_frame_left
+            };
+            if follows {
+                let jump = chain.jump((scanlines + 1) / chain.every);
+                let cycles = lookup_cycles(&jump, db);
+                let end = cycles_per_line.saturating_sub(cycles.total());
+                let span = entry..end.max(entry);
+                filled = fill_scanline(
+                    flat_lines,
+                    current_index,
+                    &clip_sections(scanline_sections, &span)?,
+                    span,
+                    db,
+                    options,
+                    frame_line,
+                )?;
+                closing_jump = Some((
+                    format_accumulated_instruction(
+                        &jump,
+                        &cycles,
+                        end,
+                        options.annotation_format(),
+                    ),
+                    cycles.total(),
+                ));
+            }
+        }
+
+        // An instruction that does not fit is deferred to the next section with the rest of
+        // this one padded. One that fits no section at all would be deferred forever.
+        if filled.consumed == 0
+            && let Some(line) = flat_lines.get(current_index)
+            && let Some(cycles) = cycles_of(line, db)
+        {
+            let (window, section, template) = if raw_scanline {
+                (cycles_per_line, "raw scanline", template_name.as_str())
+            } else {
+                largest_window(templates).unwrap_or((0, "", template_name.as_str()))
+            };
+            if cycles > window {
+                let instruction = line.split(';').next().unwrap_or("");
+                return Err(oversized_instruction(
+                    instruction.trim(),
+                    cycles,
+                    (window, section, template),
+                    cycles_per_line,
+                )
+                .into());
+            }
+        }
+
+        let mut warnings = Vec::new();
+        for event in &filled.events {
+            match event {
+                ScanlineEvent::Warning(diagnostic) if options.warning_comments() => {
+                    warnings.push(Instruction::other(
+                        warning_comment(frame_line, diagnostic),
+                        None,
+                    ));
+                }
+                ScanlineEvent::Warning(diagnostic) => {
+                    let mut diagnostic = diagnostic.clone();
+                    diagnostic.index = diagnostic
+                        .index
+                        .and_then(|index| origins.get(current_index + index).copied());
+                    observer.on_diagnostic(&diagnostic);
+                }
+                ScanlineEvent::Padding(cycles) => observer.on_padding_inserted(scanlines, *cycles),
+                ScanlineEvent::Decision(section, decision) => {
+                    let line = decision
+                        .index
+                        .and_then(|index| flat_lines.get(current_index + index))
+                        .map_or("", |line| line.split(';').next().unwrap_or("").trim());
+                    observer.on_packing_decision(&PackingDecision {
+                        scanline: scanlines,
+                        section: section.clone(),
+                        index: decision
+                            .index
+                            .and_then(|index| origins.get(current_index + index).copied()),
+                        location: None,
+                        line: line.to_string(),
+                        cycles: decision.cycles,
+                        free_before: decision.free_before,
+                        free_after: decision.free_after,
+                        verdict: decision.verdict,
+                    });
+                }
+            }
+        }
+        let fill_start = current_index;
+        current_index += filled.consumed;
+        if current_index > start_index || current_index >= flat_lines.len() {
+            stalled = 0;
+        } else {
+            stalled += 1;
+            if stalled > MAX_STALLED_SCANLINES {
+                return Err(format!(
+                    "No progress in {} scanlines: '{}' (index {}) fits no section of the scanlines \
+                     from frame scanline {} on (last template '{}')",
+                    stalled,
+                    flat_lines[current_index]
+                        .split(';')
+                        .next()
+                        .unwrap_or("")
+                        .trim(),
+                    current_index,
+                    frame_line + 1 - stalled,
+                    template_name
+                )
+                .into());
+            }
+        }
+        let mut chunks = vec![Chunk {
+            label: None,
+            instructions: std::mem::take(&mut lead),
+        }];
+        chunks.extend(filled.chunks);
+        // Packing may have reordered the lines, so sources are mapped back to the input.
+        for instruction in chunks[1..].iter_mut().flat_map(|c| &mut c.instructions) {
+            instruction.source = instruction
+                .source
+                .and_then(|source| origins.get(fill_start + source).copied());
+        }
+        // The jump and the closing comments end the lines after the last section.
+        if chunks.last().is_some_and(|c| c.label.is_some()) {
+            chunks.push(Chunk::default());
+        }
+        let mut closing = Vec::new();
+        let mut scanline_cycles = filled.cycles;
+        if let Some((jump, cycles)) = closing_jump {
+            closing.push(Instruction::timed(
+                jump,
+                LineKind::Jump,
+                cycles,
+                scanline_cycles + entry,
+            ));
+            scanline_cycles += cycles;
+        }
+        closing.extend(warnings);
+        closing.push(Instruction::other(
+            format!("{}{}", TOTAL_CYCLES_PREFIX, scanline_cycles),
+            None,
+        ));
+        if options.verbosity() == Verbosity::Verbose {
+            closing.push(Instruction::other(
+                filled.usage.utilization_comment(scanline_cycles),
+                None,
+            ));
+        }
+        chunks.last_mut().unwrap().instructions.extend(closing);
+        if options.checksums() {
+            let scanline = Scanline {
+                line: frame_line,
+                cycles: scanline_cycles,
+                chunks: chunks.clone(),
+            };
+            // The loop and chunk labels ahead of the scanline count as its code, as they do
+            // when the file is verified.
+            let checksum = scanline_checksum(&render(&[scanline]), scanline_cycles);
+            chunks
+                .last_mut()
+                .unwrap()
+                .instructions
+                .push(Instruction::other(checksum_comment(checksum), None));
+        }
+        if chunks[0].instructions.is_empty() {
+            chunks.remove(0);
+        }
+        model.push(Scanline {
+            line: frame_line,
+            cycles: scanline_cycles,
+            chunks,
+        });
+        usage.push(filled.usage);
+        scanlines += 1;
+
+        if observer
+            .on_scanline_complete(scanlines - 1, scanline_cycles)
+            .is_break()
+        {
+            aborted = current_index < flat_lines.len();
+            break;
+        }
+    }
+
+    // Lines generated ahead of a scanline that never came follow the last one.
+    if !lead.is_empty()
+        && let Some(last) = model.last_mut()
+    {
+        last.chunks.push(Chunk {
+            label: None,
+            instructions: lead,
+        });
+    }
+    Ok(Generation {
+        lines: render(&model),
+        model,
+        scanlines,
+        aborted,
+        cancelled,
+        state: ResumeState {
+            next_index: current_index,
+            scanlines,
+            template: Some(template_name),
+            budget,
+        },
+        usage,
+    })
+}
+
+/// An event raised while filling a scanline, replayed to the observer in order.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum ScanlineEvent {
+    Warning(Diagnostic),
+    Padding(usize),
+    /// A packing decision taken in the section of the given label.
+    Decision(String, Decision),
+}
+
+/// The template and user code of one scanline, up to (excluding) its total cycles comment.
+///
+/// The content only depends on the sections, the consumed input lines and the options, never on
+/// the scanline's position, which is what allows the `cache` module to reuse it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct FilledScanline {
+    /// The generated lines by template section, referring to input lines relative to the
+    /// scanline's first one.
+    pub chunks: Vec<Chunk>,
+    /// The number of input lines consumed.
+    pub consumed: usize,
+    /// The total cycles of the scanline after padding.
+    pub cycles: usize,
+    /// Warnings, padding and packing decisions raised while filling the scanline.
+    pub events: Vec<ScanlineEvent>,
+    /// How the scanline's cycles are spent.
+    pub usage: ScanlineUsage,
+}
+
+/// Returns the parts of `sections` that lie within the cycles `span` of the scanline. Sections
+/// outside of it are dropped and code windows crossing its bounds are shortened.
+///
+/// # Errors
+/// If a bound of `span` falls into the injection code of a section.
+pub(crate) fn clip_sections(
+    sections: &[TemplateSection],
+    span: &Range<usize>,
+) -> Result<Vec<TemplateSection>, Box<dyn Error>> {
+    let mut clipped = Vec::new();
+    let mut offset = 0;
+    for section in sections {
+        let code_cycles: usize = section.injection_code.iter().map(|(_, c)| c).sum();
+        let code = offset..offset + code_cycles;
+        let end = code.end + section.nop_cycles;
+        offset = end;
+        if end <= span.start || code.start >= span.end {
+            continue;
+        }
+        for bound in [span.start, span.end] {
+            if bound > code.start && bound < code.end {
+                return Err(format!(
+                    "Cycle {} falls into the template code of the {} section",
+                    bound, section.label
+                )
+                .into());
+            }
+        }
+        let keeps_code = span.start <= code.start;
+        let window = code.end.max(span.start)..end.min(span.end);
+        clipped.push(TemplateSection {
+            injection_code: if keeps_code {
+                section.injection_code.clone()
+            } else {
+                Vec::new()
+            },
+            nop_cycles: window.len(),
+            label: section.label.clone(),
+            hot: section.hot,
+        });
+    }
+    Ok(clipped)
+}
+
+/// Carves the IRQ `windows` of a scanline out of the code windows of `sections`. A code window
+/// holding an IRQ window is split in two; the second part starts with a comment line accounting
+/// for the stolen cycles, so no user instruction is scheduled across the interrupt. Windows after
+/// the last section fall into the tail padding and need no carving.
+///
+/// # Errors
+/// If an IRQ window overlaps the injection code of a section or crosses the end of a section.
+pub(crate) fn carve_irq_windows(
+    sections: &[TemplateSection],
+    windows: &[IrqWindow],
+) -> Result<Vec<TemplateSection>, Box<dyn Error>> {
+    let mut carved = Vec::new();
+    let mut offset = 0;
+    for section in sections {
+        let code_cycles: usize = section.injection_code.iter().map(|(_, c)| c).sum();
+        let code = offset..offset + code_cycles;
+        let end = code.end + section.nop_cycles;
+        offset = end;
+        let mut injection_code = section.injection_code.clone();
+        let mut window_start = code.end;
+        for window in windows
+            .iter()
+            .filter(|w| w.start < end && w.end() > code.start)
+        {
+            if window.start < code.end {
+                return Err(format!(
+                    "IRQ window at cycle {} overlaps the template code of the {} section",
+                    window.start, section.label
+                )
+                .into());
+            }
+            if window.end() > end {
+                return Err(format!(
+                    "IRQ window at cycle {} crosses the end of the {} section",
+                    window.start, section.label
+                )
+                .into());
+            }
+            carved.push(TemplateSection {
+                injection_code,
+                nop_cycles: window.start - window_start,
+                label: section.label.clone(),
+                hot: section.hot,
+            });
+            injection_code = vec![(
+                format!("; IRQ window ({} cycles)", window.cycles),
+                window.cycles,
+            )];
+            window_start = window.end();
+        }
+        carved.push(TemplateSection {
+            injection_code,
+            nop_cycles: end - window_start,
+            label: section.label.clone(),
+            hot: section.hot,
+        });
+    }
+    Ok(carved)
+}
+
+/// Fills the cycles `span` of one scanline (usually the whole scanline) starting at
+/// `start_index`: emits the injection code of every section, accumulates user code into the
+/// sections' NOP budgets and pads (or checks for overflow) up to the end of the span.
+/// `scanline` is the frame scanline, substituted for `LINE` in the injection code and named in
+/// error messages.
+fn fill_scanline(
+    flat_lines: &[String],
+    start_index: usize,
+    sections: &[TemplateSection],
+    span: Range<usize>,
+    db: &CycleDatabase,
+    options: &Options,
+    scanline: usize,
+) -> Result<FilledScanline, Box<dyn Error>> {
+    let cycles_per_line = span.end;
+    let padding = options.padding_instruction();
+    let mut chunks = Vec::new();
+    let mut events = Vec::new();
+    let mut usage = ScanlineUsage::default();
+    let mut current_index = start_index;
+    let mut scanline_offset = span.start;
+    let mut scanline_cycles = span.start;
+
+    let reserved = reserve_margin(sections, options);
+    for (section, reserved) in sections.iter().zip(reserved) {
+        let mut section_usage = SectionUsage {
+            label: section.label.clone(),
+            template_cycles: 0,
+            user_cycles: 0,
+            padding_cycles: 0,
+        };
+        let mut lines = Vec::new();
+        for (i, (code, cycles)) in section.injection_code.iter().enumerate() {
+            let code = expand_line(code, scanline);
+            let annotated = if i == 0 {
+                format!("{}\t[{}]", code, scanline_offset)
+            } else {
+                code
+            };
+            lines.push(Instruction::timed(
+                annotated,
+                LineKind::Template,
+                *cycles,
+                scanline_offset,
+            ));
+            scanline_offset += cycles;
+            scanline_cycles += cycles;
+            section_usage.template_cycles += cycles;
+        }
+
+        lines.push(Instruction::other(
+            format!("; --- {} section ---", section.label),
+            None,
+        ));
+
+        if section.nop_cycles > 0 && current_index < flat_lines.len() {
+            let slot = Slot {
+                offset: scanline_offset,
+                target: section.nop_cycles - reserved,
+                scanline_end: cycles_per_line,
+                hot: section.hot,
+            };
+            let accumulated = accumulate(flat_lines, current_index, slot, db, options);
+            events.extend(accumulated.warnings.into_iter().map(ScanlineEvent::Warning));
+            events.extend(
+                accumulated
+                    .decisions
+                    .into_iter()
+                    .map(|decision| ScanlineEvent::Decision(section.label.clone(), decision)),
+            );
+            if accumulated.padding > 0 {
+                events.push(ScanlineEvent::Padding(accumulated.padding));
+            }
+            section_usage.padding_cycles = accumulated.padding;
+            section_usage.user_cycles = accumulated.offset - scanline_offset - accumulated.padding;
+            scanline_offset = accumulated.offset;
+            scanline_cycles += section.nop_cycles;
+            lines.extend(accumulated.chunk);
+            if reserved > 0 {
+                lines.push(Instruction::other(
+                    format!("; Safety margin ({} cycles)", reserved),
+                    None,
+                ));
+                let padded = push_padding(
+                    &mut lines,
+                    reserved,
+                    scanline_offset,
+                    &flat_lines[current_index],
+                    options,
+                );
+                events.push(ScanlineEvent::Padding(padded - scanline_offset));
+                section_usage.padding_cycles += padded - scanline_offset;
+                scanline_offset = padded;
+            }
+            current_index = accumulated.next_index;
+        }
+        lines.push(Instruction::other(
+            format!("; Calculated cycles: {}", scanline_offset),
+            None,
+        ));
+        chunks.push(Chunk {
+            label: Some(section.label.clone()),
+            instructions: lines,
+        });
+        usage.sections.push(section_usage);
+    }
+
+    let mut tail = Vec::new();
+    if scanline_cycles < cycles_per_line {
+        let remaining = cycles_per_line - scanline_cycles;
+        let runs: Vec<_> = padding_runs(remaining, options)
+            .into_iter()
+            .filter(|(_, count)| *count > 0)
+            .collect();
+        let padded: usize = runs.iter().map(|(run, count)| count * run.cycles).sum();
+        if padded > 0 {
+            let mut offset = scanline_cycles;
+            if let PaddingStyle::Filler { seed } = options.padding_style() {
+                tail.push(Instruction::other(
+                    format!("; Pad to {} cycles ({} cycles)", cycles_per_line, remaining),
+                    None,
+                ));
+                let mut filler = Filler::new(seed, &flat_lines[start_index], scanline_cycles);
+                for (run, count) in &runs {
+                    for (instruction, cycles) in
+                        filler.fill(count * run.cycles, run, options.nop_cycles())
+                    {
+                        let text = format!("\t{}\t; {} cycles", instruction, cycles);
+                        tail.push(Instruction::timed(text, LineKind::Padding, cycles, offset));
+                        offset += cycles;
+                    }
+                }
+            } else {
+                for (index, (run, count)) in runs.iter().enumerate() {
+                    let text = if index == 0 {
+                        format!(
+                            "\t{}\t; Pad to {} cycles ({} cycles)",
+                            run.block(*count),
+                            cycles_per_line,
+                            remaining
+                        )
+                    } else {
+                        format!("\t{}", run.block(*count))
+                    };
+                    let cycles = count * run.cycles;
+                    tail.push(Instruction::timed(text, LineKind::Padding, cycles, offset));
+                    offset += cycles;
+                }
+            }
+            events.push(ScanlineEvent::Padding(padded));
+            usage.tail_padding = padded;
+        }
+        // Padding cannot fill a remainder shorter than a NOP
+        let leftover = remaining - padded;
+        if leftover > 0 {
+            events.push(ScanlineEvent::Warning(Diagnostic::new(
+                DiagnosticKind::Other,
+                format!(
+                    "Scanline {} cannot be padded exactly: {} cycles remain after the {} cycle '{}'",
+                    scanline, leftover, padding.cycles, padding.mnemonic
+                ),
+            )
+            .at(start_index)));
+        }
+        scanline_cycles = cycles_per_line - leftover;
+    } else if scanline_cycles > cycles_per_line {
+        let overflow = scanline_cycles - cycles_per_line;
+        match options.overflow_policy() {
+            OverflowPolicy::Warn => {
+                events.push(ScanlineEvent::Warning(
+                    Diagnostic::new(
+                        DiagnosticKind::ScanlineOverflow,
+                        format!("Scanline overflow by {} cycles!", overflow),
+                    )
+                    .at(start_index),
+                ));
+            }
+            OverflowPolicy::Error => {
+                return Err(CycleSpitterError::ScanlineOverflow {
+                    scanline,
+                    cycles: overflow,
+                }
+                .into());
+            }
+            OverflowPolicy::Ignore => {}
+        }
+    }
+
+    if current_index == start_index
+        && let Some(line) = flat_lines.get(current_index)
+        && is_critical(line)
+    {
+        return Err(format!(
+            "Critical instruction '{}' does not fit into a hot section of scanline {}",
+            line.trim(),
+            scanline
+        )
+        .into());
+    }
+
+    events.extend(register_conflicts(
+        sections,
+        &flat_lines[start_index..current_index],
+        start_index,
+    ));
+
+    if !tail.is_empty() {
+        chunks.push(Chunk {
+            label: None,
+            instructions: tail,
+        });
+    }
+    // Input lines are referred to relative to the scanline's first one, which keeps cached
+    // scanlines independent of their position.
+    for instruction in chunks.iter_mut().flat_map(|c| &mut c.instructions) {
+        instruction.source = instruction.source.map(|source| source - start_index);
+    }
+    for event in &mut events {
+        match event {
+            ScanlineEvent::Warning(diagnostic) => {
+                diagnostic.index = diagnostic.index.map(|index| index - start_index);
+            }
+            ScanlineEvent::Decision(_, decision) => {
+                decision.index = decision.index.map(|index| index - start_index);
+            }
+            ScanlineEvent::Padding(_) => {}
+        }
+    }
+    Ok(FilledScanline {
+        chunks,
+        consumed: current_index - start_index,
+        cycles: scanline_cycles - span.start,
+        events,
+        usage,
+    })
+}
+
+/// Splits the safety margin of `options` (rounded up to whole NOPs) over the code windows of
+/// `sections`, starting with the last one. Returns the cycles reserved in each section.
+pub(crate) fn reserve_margin(sections: &[TemplateSection], options: &Options) -> Vec<usize> {
+    let nop_cycles = options.nop_cycles();
+    let mut left = options.margin().div_ceil(nop_cycles) * nop_cycles;
+    let mut reserved = vec![0; sections.len()];
+    for (index, section) in sections.iter().enumerate().rev() {
+        reserved[index] = left.min(section.nop_cycles);
+        left -= reserved[index];
+    }
+    reserved
+}
+
+/// Returns a warning for every hardware register written by both the template `sections` and
+/// the `user` code of one scanline, which starts at input line `start_index`.
+fn register_conflicts(
+    sections: &[TemplateSection],
+    user: &[String],
+    start_index: usize,
+) -> Vec<ScanlineEvent> {
+    let template_registers: Vec<&HardwareRegister> = sections
+        .iter()
+        .flat_map(|s| &s.injection_code)
+        .flat_map(|(code, _)| written_registers(code))
+        .collect();
+    let mut conflicts: Vec<(&HardwareRegister, usize)> = Vec::new();
+    for (index, line) in user.iter().enumerate() {
+        for register in written_registers(line) {
+            if template_registers.contains(&register)
+                && !conflicts.iter().any(|(r, _)| *r == register)
+            {
+                conflicts.push((register, start_index + index));
+            }
+        }
+    }
+    conflicts
+        .into_iter()
+        .map(|(register, index)| {
+            ScanlineEvent::Warning(
+                Diagnostic::new(
+                    DiagnosticKind::Other,
+                    format!(
+                        "User code writes {} (${:06x}), which the template also writes in this scanline",
+                        register.name, register.address
+                    ),
+                )
+                .at(index),
+            )
+        })
+        .collect()
+}
+
+/// Returns the largest code window of any template in `templates`: its cycles, the label of
+/// its section and the name of its template.
+fn largest_window(templates: &TemplateSet) -> Option<(usize, &str, &str)> {
+    templates
+        .names()
+        .flat_map(|name| {
+            templates
+                .get(name)
+                .unwrap_or_default()
+                .iter()
+                .map(move |section| (section.nop_cycles, section.label.as_str(), name))
+        })
+        .max_by_key(|(cycles, _, _)| *cycles)
+}
+
+/// Describes an instruction of `cycles` cycles that exceeds the largest code window, and how to
+/// get it placed.
+fn oversized_instruction(
+    instruction: &str,
+    cycles: usize,
+    (window, section, template): (usize, &str, &str),
+    cycles_per_line: usize,
+) -> String {
+    let hint = if cycles <= cycles_per_line {
+        "split it or place it on a `;@raw-scanline`"
+    } else {
+        "split it across scanlines"
+    };
+    format!(
+        "'{}' takes {} cycles, but the largest code window is {} cycles (section '{}' of template '{}'); {}",
+        instruction, cycles, window, section, template, hint
+    )
+}
+
+/// Returns the sections of the template registered as `name`.
+pub(crate) fn select_template<'a>(
+    templates: &'a TemplateSet,
+    name: &str,
+) -> Result<&'a [TemplateSection], Box<dyn Error>> {
+    if name.is_empty() {
+        return Err("`;@use-template` requires a template name".into());
+    }
+    templates.get(name).ok_or_else(|| {
+        format!(
+            "Unknown template '{}' (registered: {})",
+            name,
+            templates.names().collect::<Vec<_>>().join(", ")
+        )
+        .into()
+    })
+}
+
+/// Returns the instruction lines from `start` on carrying an inline `(n)` cycle override,
+/// quoted with their line number, so that accidental overrides are visible.
+fn override_lines(lines: &[String], start: usize) -> Vec<String> {
+    lines
+        .iter()
+        .enumerate()
+        .skip(start)
+        .filter(|(_, line)| {
+            let code = line.split(';').next().unwrap_or("");
+            !code.trim().is_empty() && REG_NUMBER_RE.is_match(line)
+        })
+        .map(|(index, line)| format!("{} '{}'", index + 1, line.trim()))
+        .collect()
+}
+
+/// Returns the injection code of all templates in `templates`, without annotations.
+fn template_lines(templates: &TemplateSet) -> Vec<String> {
+    templates
+        .names()
+        .filter_map(|name| templates.get(name))
+        .flatten()
+        .flat_map(|section| &section.injection_code)
+        .map(|(code, _)| strip_annotations(code).to_string())
+        .collect()
+}
+
+/// Returns all instruction lines whose normalized form is not present in the cycle database.
+/// Comments, `set`/`equ` lines and lines carrying an inline `(n)` override are ignored.
+pub(crate) fn find_unknown_instructions(lines: &[String], db: &CycleDatabase) -> Vec<String> {
+    unknown_instruction_indices(lines, db)
+        .into_iter()
+        .map(|index| lines[index].clone())
+        .collect()
+}
+
+/// Returns whether `line` holds an instruction to look up, i.e. is not empty, a comment, a
+/// `set`/`equ` line, a line with an inline `(n)` override or a data directive.
+fn is_instruction_line(line: &str) -> bool {
+    let trimmed = line.trim();
+    !(trimmed.is_empty()
+        || trimmed.starts_with(';')
+        || line.contains(" set ")
+        || line.contains(" equ ")
+        || REG_NUMBER_RE.is_match(line)
+        || parse_data_directive(line).is_some())
+}
+
+/// Returns the indices of the instructions in `lines` missing from `db`.
+fn unknown_instruction_indices(lines: &[String], db: &CycleDatabase) -> Vec<usize> {
+    lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| is_instruction_line(line))
+        .filter(|(_, line)| !db.contains(&normalize_line_ext(line).0))
+        .map(|(index, _)| index)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cycle_spitter::cancellation::CancellationToken;
+    use crate::cycle_spitter::diagnostics::{Diagnostics, WARNING_COMMENT_PREFIX};
+    use crate::cycle_spitter::frame::FrameLayout;
+    use crate::cycle_spitter::options::{OptionsBuilder, PaddingInstruction};
+    use crate::cycle_spitter::seam::{ChunkChain, LoopSeam};
+    use crate::cycle_spitter::template::{parse_template, parse_template_with};
+    use std::ops::ControlFlow;
+
+    fn template(db: &CycleDatabase) -> TemplateSet {
+        TemplateSet::from(
+            parse_template(
+                r#"
+            move.w d0,d1 ; first
+            dcb.w 4,$4e71
+            move.w d1,d2 ; second
+            dcb.w 2,$4e71
+            "#,
+                db,
+            )
+            .unwrap(),
+        )
+    }
+
+    fn lines(raw: &[&str]) -> Vec<String> {
+        raw.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_generate_pads_scanlines_to_budget() {
+        let db = CycleDatabase::builtin();
+        let options = Options::builder().cycles_per_line(64).build();
+        let input = lines(&["move.w d0,d1", "move.w d2,d3", "move.w d3,d4"]);
+        let generation = generate(&input, &template(&db), &db, &options).unwrap();
+
+        assert_eq!(generation.scanlines, 1);
+        assert!(
+            generation
+                .lines
+                .iter()
+                .any(|l| l.contains("dcb.w\t10,$4e71\t; Pad to 64 cycles (40 cycles)"))
+        );
+        assert_eq!(
+            generation.lines.last().unwrap(),
+            "; Total cycles for scanline: 64"
+        );
+    }
+
+    #[test]
+    fn test_generate_splits_into_multiple_scanlines() {
+        let db = CycleDatabase::builtin();
+        let options = Options::builder().cycles_per_line(64).build();
+        let input = lines(&["move.w d0,d1"; 7]);
+        let generation = generate(&input, &template(&db), &db, &options).unwrap();
+
+        // Each scanline has 24 free cycles, i.e. six 4-cycle moves.
+        assert_eq!(generation.scanlines, 2);
+    }
+
+    #[test]
+    fn test_generate_enforces_max_scanlines() {
+        let db = CycleDatabase::builtin();
+        let input = lines(&["move.w d0,d1"; 7]);
+        let capped = |max| {
+            let options = Options::builder()
+                .cycles_per_line(64)
+                .max_scanlines(max)
+                .build();
+            generate(&input, &template(&db), &db, &options)
+        };
+        assert_eq!(capped(2).unwrap().scanlines, 2);
+        assert_eq!(
+            capped(1).unwrap_err().to_string(),
+            "The input needs more than 1 scanlines; 'move.w d0,d1' (index 6) and the 0 line(s) \
+             after it do not fit"
+        );
+    }
+
+    #[test]
+    fn test_generate_max_scanlines_reports_the_open_loop_seam() {
+        let db = CycleDatabase::builtin();
+        let template = parse_template(include_str!("../../examples/template.s"), &db).unwrap();
+        let capped = |input: &[String], max| {
+            let options = Options::builder()
+                .loop_seam(LoopSeam::new("L", 0))
+                .max_scanlines(max)
+                .build();
+            generate(input, &TemplateSet::from(template.clone()), &db, &options)
+                .unwrap_err()
+                .to_string()
+        };
+        // The input fills the first scanline, the jump back needs a second one.
+        assert_eq!(
+            capped(&lines(&["move.w d0,d1"; 110]), 1),
+            "The input needs more than 1 scanlines; the jump back to the loop label 'L' does \
+             not fit"
+        );
+        assert_eq!(
+            capped(&[], 0),
+            "The input needs more than 0 scanlines; the jump back to the loop label 'L' does \
+             not fit"
+        );
+    }
+
+    #[test]
+    fn test_generate_tail_fills_the_frame() {
+        let db = CycleDatabase::builtin();
+        let input = lines(&["move.w d0,d1"; 7]);
+        let with_tail = |tail| {
+            let options = Options::builder()
+                .cycles_per_line(64)
+                .max_scanlines(4)
+                .tail(tail)
+                .build();
+            generate(&input, &template(&db), &db, &options).unwrap()
+        };
+        let user_cycles = |generation: &Generation| -> Vec<usize> {
+            generation.usage.iter().map(|u| u.user_cycles()).collect()
+        };
+        assert_eq!(user_cycles(&with_tail(Tail::Stop)), [24, 4]);
+
+        let padded = with_tail(Tail::Pad);
+        assert_eq!(user_cycles(&padded), [24, 4, 0, 0]);
+        assert_eq!(padded.usage[3].total_cycles(), 64);
+        assert_eq!(
+            padded
+                .lines
+                .iter()
+                .filter(|l| l.contains("move.w d0,d1 ; first"))
+                .count(),
+            4
+        );
+
+        // Every repetition starts on a new scanline.
+        assert_eq!(user_cycles(&with_tail(Tail::Repeat)), [24, 4, 24, 4]);
+
+        let open = Options::builder().tail(Tail::Pad).build();
+        assert!(
+            generate(&input, &template(&db), &db, &open)
+                .unwrap_err()
+                .to_string()
+                .contains("needs a frame that ends")
+        );
+    }
+
+    #[test]
+    fn test_generate_reserves_safety_margin() {
+        let db = CycleDatabase::builtin();
+        let input = lines(&["move.w d0,d1"; 6]);
+        let options = Options::builder().cycles_per_line(64).build();
+        assert_eq!(
+            generate(&input, &template(&db), &db, &options)
+                .unwrap()
+                .scanlines,
+            1
+        );
+
+        // The 8 cycle margin takes the whole second section, leaving room for four moves.
+        let options = Options::builder().cycles_per_line(64).margin(8).build();
+        let generation = generate(&input, &template(&db), &db, &options).unwrap();
+        assert_eq!(generation.scanlines, 2);
+        assert!(
+            generation
+                .lines
+                .contains(&"; Safety margin (8 cycles)".to_string())
+        );
+        assert_eq!(generation.usage[0].sections[1].padding_cycles, 8);
+        assert_eq!(generation.usage[0].total_cycles(), 64);
+    }
+
+    #[derive(Default)]
+    struct Recorder {
+        completed: Vec<(usize, usize)>,
+        padding: usize,
+        warnings: Vec<String>,
+        stop_after: Option<usize>,
+    }
+
+    impl Observer for Recorder {
+        fn on_scanline_complete(&mut self, scanline: usize, cycles: usize) -> ControlFlow<()> {
+            self.completed.push((scanline, cycles));
+            if Some(self.completed.len()) == self.stop_after {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        }
+
+        fn on_warning(&mut self, message: &str) {
+            self.warnings.push(message.to_string());
+        }
+
+        fn on_padding_inserted(&mut self, _scanline: usize, cycles: usize) {
+            self.padding += cycles;
+        }
+    }
+
+    #[test]
+    fn test_generate_reports_events_to_observer() {
+        let db = CycleDatabase::builtin();
+        let options = Options::builder().cycles_per_line(64).build();
+        let input = lines(&["move.w d0,d1"; 7]);
+        let mut recorder = Recorder::default();
+        let generation =
+            generate_with_observer(&input, &template(&db), &db, &options, &mut recorder).unwrap();
+
+        assert!(!generation.aborted);
+        assert_eq!(recorder.completed, vec![(0, 64), (1, 64)]);
+        // Scanline 0: 32 cycle tail pad. Scanline 1: 12 cycles of section padding and, since
+        // the input is exhausted before the second section, a 40 cycle tail pad.
+        assert_eq!(recorder.padding, 84);
+        assert!(recorder.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_generate_warns_about_shared_hardware_registers() {
+        let db = CycleDatabase::builtin();
+        let options = Options::builder().cycles_per_line(64).build();
+        let templates = TemplateSet::from(
+            parse_template("move.b d7,$ffff820a.w ; sync\ndcb.w 8,$4e71", &db).unwrap(),
+        );
+        let input = lines(&["move.b d0,$ff820a", "move.b d0,$ffff8260.w"]);
+        let mut recorder = Recorder::default();
+        generate_with_observer(&input, &templates, &db, &options, &mut recorder).unwrap();
+
+        assert_eq!(
+            recorder.warnings,
+            [
+                "User code writes sync mode ($ff820a), which the template also writes in this scanline"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_generate_observer_can_abort() {
+        let db = CycleDatabase::builtin();
+        let options = Options::builder().cycles_per_line(64).build();
+        let input = lines(&["move.w d0,d1"; 20]);
+        let mut recorder = Recorder {
+            stop_after: Some(1),
+            ..Default::default()
+        };
+        let generation =
+            generate_with_observer(&input, &template(&db), &db, &options, &mut recorder).unwrap();
+
+        assert!(generation.aborted);
+        assert_eq!(generation.scanlines, 1);
+    }
+
+    #[test]
+    fn test_generate_resume_after_abort_matches_full_run() {
+        let db = CycleDatabase::builtin();
+        let options = Options::builder().cycles_per_line(64).build();
+        let input = lines(&["move.w d0,d1"; 20]);
+        let full = generate_with_observer(
+            &input,
+            &template(&db),
+            &db,
+            &options,
+            &mut Recorder::default(),
+        )
+        .unwrap();
+
+        let mut recorder = Recorder {
+            stop_after: Some(2),
+            ..Default::default()
+        };
+        let first =
+            generate_with_observer(&input, &template(&db), &db, &options, &mut recorder).unwrap();
+        assert!(first.aborted);
+        assert!(!first.state.is_complete(&input));
+
+        let rest = resume_with_observer(
+            &input,
+            &template(&db),
+            &db,
+            &options,
+            &mut Recorder::default(),
+            first.state,
+        )
+        .unwrap();
+        assert!(rest.state.is_complete(&input));
+        assert_eq!(rest.scanlines, full.scanlines);
+        assert_eq!([first.lines, rest.lines].concat(), full.lines);
+    }
+
+    #[test]
+    fn test_generate_stops_when_cancelled() {
+        let db = CycleDatabase::builtin();
+        let token = CancellationToken::new();
+        let options = Options::builder()
+            .cycles_per_line(64)
+            .cancellation(token.clone())
+            .build();
+        let input = lines(&["move.w d0,d1"; 20]);
+
+        /// Cancels the token from "outside" once the first scanline is done.
+        struct CancelAfterFirst(CancellationToken);
+        impl Observer for CancelAfterFirst {
+            fn on_scanline_complete(
+                &mut self,
+                _scanline: usize,
+                _cycles: usize,
+            ) -> ControlFlow<()> {
+                self.0.cancel();
+                ControlFlow::Continue(())
+            }
+        }
+
+        let generation = generate_with_observer(
+            &input,
+            &template(&db),
+            &db,
+            &options,
+            &mut CancelAfterFirst(token),
+        )
+        .unwrap();
+        assert!(generation.cancelled);
+        assert!(!generation.aborted);
+        assert_eq!(generation.scanlines, 1);
+        assert_eq!(generation.state.next_index, 6);
+    }
+
+    #[test]
+    fn test_generate_overflow_warning_goes_to_observer() {
+        let db = CycleDatabase::builtin();
+        let options = Options::builder().cycles_per_line(16).build();
+        let input = lines(&["move.w d0,d1"]);
+        let mut recorder = Recorder::default();
+        generate_with_observer(&input, &template(&db), &db, &options, &mut recorder).unwrap();
+
+        assert_eq!(recorder.warnings, vec!["Scanline overflow by 8 cycles!"]);
+    }
+
+    #[test]
+    fn test_generate_pads_with_configured_nop_cycles() {
+        let db = CycleDatabase::builtin();
+        let options = Options::builder().cycles_per_line(58).nop_cycles(8).build();
+        let content = "move.w d0,d1 ; first\ndcb.w 4,$4e71\nmove.w d1,d2 ; second\ndcb.w 2,$4e71";
+        let templates = TemplateSet::from(parse_template_with(content, &db, &options).unwrap());
+        let input = lines(&["move.w d0,d1"; 2]);
+        let mut recorder = Recorder::default();
+        let generation =
+            generate_with_observer(&input, &templates, &db, &options, &mut recorder).unwrap();
+
+        // 8 cycles of code and three 8 cycle NOPs fill the first window of 32 cycles.
+        assert_eq!(generation.usage[0].sections[0].padding_cycles, 24);
+        // The scanline ends after 4 + 32 + 4 cycles; two NOPs leave 2 of the 18 cycles unpadded.
+        assert_eq!(generation.usage[0].tail_padding, 16);
+        assert!(
+            generation
+                .lines
+                .contains(&format!("{}{}", TOTAL_CYCLES_PREFIX, 56))
+        );
+        assert_eq!(
+            recorder.warnings,
+            ["Scanline 0 cannot be padded exactly: 2 cycles remain after the 8 cycle 'nop'"]
+        );
+    }
+
+    #[test]
+    fn test_generate_pads_with_configured_padding_instruction() {
+        let db = CycleDatabase::builtin();
+        let options = Options::builder()
+            .cycles_per_line(32)
+            .padding_instruction(PaddingInstruction::parse("tst.w (a7):$4a57:8").unwrap())
+            .build();
+        let content = "move.w d0,d1 ; first\ndcb.w 4,$4e71";
+        let templates = TemplateSet::from(parse_template_with(content, &db, &options).unwrap());
+        let input = lines(&["move.w d0,d1"]);
+        let mut recorder = Recorder::default();
+        let generation =
+            generate_with_observer(&input, &templates, &db, &options, &mut recorder).unwrap();
+
+        // 12 cycles of the window and of the tail each take one 8 cycle `tst.w` and one NOP.
+        let padding: Vec<&str> = generation
+            .lines
+            .iter()
+            .map(|line| line.trim())
+            .filter(|line| line.starts_with("tst.w") || line.starts_with("nop"))
+            .collect();
+        assert_eq!(
+            padding,
+            ["tst.w (a7)\t; 8 cycles\t[8]", "nop\t; 4 cycles\t[16]"]
+        );
+        assert!(
+            generation
+                .lines
+                .contains(&"\tdcb.w\t1,$4a57\t; Pad to 32 cycles (12 cycles)".to_string())
+        );
+        assert!(generation.lines.contains(&"\tdcb.w\t1,$4e71".to_string()));
+        assert_eq!(generation.usage[0].tail_padding, 12);
+        assert!(recorder.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_generate_model() {
+        let db = CycleDatabase::builtin();
+        let options = Options::builder()
+            .cycles_per_line(64)
+            .separator('=')
+            .build();
+        let input = lines(&["move.w d0,d1", "; note", "move.w d1,d2", "move.l d0,d1"]);
+        let generation = generate(&input, &template(&db), &db, &options).unwrap();
+
+        assert_eq!(render(&generation.model), generation.lines);
+        let scanline = &generation.model[0];
+        let labels: Vec<Option<&str>> =
+            scanline.chunks.iter().map(|c| c.label.as_deref()).collect();
+        assert_eq!(labels, [None, Some("first"), Some("second"), None]);
+        let cycles: usize = scanline.chunks.iter().map(Chunk::cycles).sum();
+        assert_eq!((scanline.cycles, cycles), (64, 64));
+
+        let user: Vec<(Option<usize>, usize, Option<usize>)> = scanline
+            .instructions()
+            .filter(|i| i.kind == LineKind::User || i.source.is_some())
+            .map(|i| (i.source, i.cycles, i.offset))
+            .collect();
+        assert_eq!(
+            user,
+            [
+                (Some(0), 4, Some(4)),
+                (Some(1), 0, None),
+                (Some(2), 4, Some(8)),
+                (Some(3), 4, Some(12))
+            ]
+        );
+        let template_cycles: usize = scanline
+            .instructions()
+            .filter(|i| i.kind == LineKind::Template)
+            .map(|i| i.cycles)
+            .sum();
+        assert_eq!(template_cycles, generation.usage[0].template_cycles());
+    }
+
+    #[test]
+    fn test_generate_checksums_verify() {
+        let db = CycleDatabase::builtin();
+        let options = Options::builder()
+            .cycles_per_line(64)
+            .checksums(true)
+            .build();
+        let input = lines(&["move.w d0,d1"; 7]);
+        let generation = generate(&input, &template(&db), &db, &options).unwrap();
+
+        let report = crate::cycle_spitter::checksum::verify(&generation.lines.join("\n"));
+        assert_eq!(report.checked, 2);
+        assert!(report.is_ok());
+    }
+
+    #[test]
+    fn test_generate_checksums_verify_with_chunk_labels() {
+        let db = CycleDatabase::builtin();
+        let options = Options::builder()
+            .cycles_per_line(64)
+            .checksums(true)
+            .chunk_chain(ChunkChain::new(2, "chunk").unwrap())
+            .build();
+        let input = lines(&["move.w d0,d1"; 40]);
+        let generation = generate(&input, &template(&db), &db, &options).unwrap();
+        let content = generation.lines.join("\n");
+        assert!(content.contains("chunk_1:"));
+
+        let report = crate::cycle_spitter::checksum::verify(&content);
+        assert!(report.checked > 2);
+        assert!(report.is_ok());
+    }
+
+    #[test]
+    fn test_generate_checksums_verify_with_loop_label() {
+        let db = CycleDatabase::builtin();
+        let options = Options::builder()
+            .cycles_per_line(64)
+            .checksums(true)
+            .loop_seam(LoopSeam::new("L", 0))
+            .build();
+        let input = lines(&["move.w d0,d1"; 7]);
+        let generation = generate(&input, &template(&db), &db, &options).unwrap();
+        let content = generation.lines.join("\n");
+        assert!(content.contains("L:"));
+
+        let report = crate::cycle_spitter::checksum::verify(&content);
+        assert_eq!(report.checked, 2);
+        assert!(report.is_ok());
+    }
+
+    #[test]
+    fn test_generate_raw_scanline_pragma() {
+        let db = CycleDatabase::builtin();
+        let options = Options::builder().cycles_per_line(64).build();
+        let input = lines(&["move.w d0,d1", ";@raw-scanline", "move.w d2,d3"]);
+        let generation = generate(&input, &template(&db), &db, &options).unwrap();
+
+        assert_eq!(generation.scanlines, 2);
+        let raw_start = generation
+            .lines
+            .iter()
+            .position(|l| l == ";@raw-scanline")
+            .unwrap();
+        let raw = &generation.lines[raw_start..];
+        // No template code in the raw scanline, just user code and padding.
+        assert!(!raw.iter().any(|l| l.contains("move.w d0,d1")));
+        assert_eq!(raw[1], "; --- raw scanline section ---");
+        assert!(raw[2].starts_with("move.w d2,d3"));
+        assert_eq!(raw.iter().filter(|l| l.starts_with("nop")).count(), 15);
+        assert_eq!(raw.last().unwrap(), "; Total cycles for scanline: 64");
+    }
+
+    #[test]
+    fn test_generate_budget_pragma() {
+        let db = CycleDatabase::builtin();
+        let options = Options::builder().cycles_per_line(64).build();
+        let input = lines(&[
+            "move.w d0,d1",
+            ";@budget 40",
+            "move.w d2,d3",
+            ";@BUDGET default",
+            "move.w d3,d4",
+        ]);
+        let generation = generate(&input, &template(&db), &db, &options).unwrap();
+        let totals: Vec<&str> = generation
+            .lines
+            .iter()
+            .filter_map(|l| l.strip_prefix(TOTAL_CYCLES_PREFIX))
+            .collect();
+        assert_eq!(totals, ["64", "40", "64"]);
+        assert_eq!(generation.state.budget, None);
+
+        let input = lines(&[";@budget fast", "move.w d0,d1"]);
+        let err = generate(&input, &template(&db), &db, &options).unwrap_err();
+        assert!(err.to_string().contains("found 'fast'"));
+    }
+
+    #[test]
+    fn test_generate_overflow_policy_error() {
+        let db = CycleDatabase::builtin();
+        let options = Options::builder()
+            .cycles_per_line(16)
+            .overflow_policy(OverflowPolicy::Error)
+            .build();
+        let input = lines(&["move.w d0,d1"]);
+        assert!(generate(&input, &template(&db), &db, &options).is_err());
+    }
+
+    #[test]
+    fn test_generate_rejects_instructions_larger_than_any_window() {
+        let db = CycleDatabase::builtin();
+        let options = Options::builder().cycles_per_line(64).build();
+        let input = lines(&["move.w d0,d1", "divs d0,d1 ; (20)"]);
+        let err = generate(&input, &template(&db), &db, &options).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "'divs d0,d1' takes 20 cycles, but the largest code window is 16 cycles \
+             (section 'first' of template 'default'); split it or place it on a `;@raw-scanline`"
+        );
+
+        // A raw scanline is large enough
+        let input = lines(&["move.w d0,d1", ";@raw-scanline", "divs d0,d1 ; (20)"]);
+        let generation = generate(&input, &template(&db), &db, &options).unwrap();
+        assert_eq!(generation.scanlines, 2);
+    }
+
+    #[test]
+    fn test_generate_strict_rejects_unknown_instructions() {
+        let db = CycleDatabase::builtin();
+        let input = lines(&["move.w d0,d1", "frobnicate d0", "frobnicate d1 ; (4)"]);
+        let strict = Options::builder()
+            .cycles_per_line(64)
+            .strictness(Strictness::Strict)
+            .build();
+        let err = generate(&input, &template(&db), &db, &strict).unwrap_err();
+        assert!(err.to_string().contains("frobnicate d0"));
+        assert!(!err.to_string().contains("frobnicate d1"));
+        assert!(matches!(err, CycleSpitterError::UnknownInstruction(lines) if lines.len() == 1));
+    }
+
+    #[test]
+    fn test_generate_reports_diagnostics_by_kind() {
+        let db = CycleDatabase::builtin();
+        let options = Options::builder().cycles_per_line(16).build();
+        let input = lines(&["frobnicate d0", "frobnicate d0", "move.w d0,d1"]);
+        let mut diagnostics = Diagnostics::default();
+        generate_with_observer(&input, &template(&db), &db, &options, &mut diagnostics).unwrap();
+
+        let unknown: Vec<&Diagnostic> = diagnostics
+            .of_kind(DiagnosticKind::UnknownInstruction)
+            .collect();
+        assert_eq!(unknown.len(), 1);
+        assert_eq!(
+            unknown[0].message,
+            "No cycle count found for instruction: frobnicate d0"
+        );
+        assert_eq!(
+            diagnostics
+                .of_kind(DiagnosticKind::ScanlineOverflow)
+                .count(),
+            1
+        );
+
+        let strict = Options::builder()
+            .cycles_per_line(16)
+            .overflow_policy(OverflowPolicy::Error)
+            .build();
+        let err = generate(&input, &template(&db), &db, &strict).unwrap_err();
+        assert!(matches!(
+            err,
+            CycleSpitterError::ScanlineOverflow { scanline: 0, .. }
+        ));
+    }
+
+    #[test]
+    fn test_generate_summarizes_foreign_input() {
+        let db = CycleDatabase::builtin();
+        let options = Options::builder().cycles_per_line(512).build();
+        let mut raw = vec!["move.w d0,d1"; 20];
+        raw.extend(["bfextu d0{0:8},d1", "extb.l d0", "pea msg(pc)", "trap #1"].repeat(6));
+        let templates = TemplateSet::from(parse_template("nop\ndcb.w 100,$4e71", &db).unwrap());
+        let mut diagnostics = Diagnostics::default();
+        generate_with_observer(&lines(&raw), &templates, &db, &options, &mut diagnostics).unwrap();
+
+        let unknown: Vec<&Diagnostic> = diagnostics
+            .of_kind(DiagnosticKind::UnknownInstruction)
+            .collect();
+        assert_eq!(unknown.len(), 1);
+        assert_eq!(
+            unknown[0].message,
+            "18 of 44 lines unanalyzable (12 unknown instruction(s), 6 system call(s)) - are you \
+             missing a cycle database overlay or processing the wrong file?"
+        );
+    }
+
+    #[test]
+    fn test_generate_warns_once_about_each_exception() {
+        let mut db = CycleDatabase::builtin();
+        db.set_trap_handler(3, 120);
+        let options = Options::builder().cycles_per_line(512).build();
+        let raw = ["trap #3", "move.w d0,d1", "trap #3", "trapv"];
+        let templates = TemplateSet::from(parse_template("nop\ndcb.w 100,$4e71", &db).unwrap());
+        let mut diagnostics = Diagnostics::default();
+        let generation =
+            generate_with_observer(&lines(&raw), &templates, &db, &options, &mut diagnostics)
+                .unwrap();
+
+        let warnings: Vec<&str> = diagnostics
+            .of_kind(DiagnosticKind::Other)
+            .map(|d| d.message.as_str())
+            .collect();
+        assert_eq!(warnings.len(), 2, "{:?}", warnings);
+        assert!(warnings[0].contains("timed as 154 cycles including its handler"));
+        assert!(warnings[1].starts_with("'trapv' may enter an exception"));
+        assert_eq!(generation.usage[0].user_cycles(), 154 * 2 + 4 + 4);
+    }
+
+    #[test]
+    fn test_generation_round_trips_through_json() {
+        let db = CycleDatabase::builtin();
+        let options = Options::builder().cycles_per_line(32).build();
+        let input = lines(&["move.w d0,d1", "move.l d0,d1", "move.w d1,d2"]);
+        let generation = generate(&input, &template(&db), &db, &options).unwrap();
+
+        let json = serde_json::to_string(&generation).unwrap();
+        let read: Generation = serde_json::from_str(&json).unwrap();
+        assert_eq!(read.lines, generation.lines);
+        assert_eq!(read.model, generation.model);
+        assert_eq!(read.usage, generation.usage);
+        assert_eq!(read.state, generation.state);
+    }
+
+    #[test]
+    fn test_generate_embeds_warning_comments() {
+        let db = CycleDatabase::builtin();
+        let options = Options::builder()
+            .cycles_per_line(16)
+            .warning_comments(true)
+            .build();
+        let input = lines(&["frobnicate d0", "move.w d0,d1"]);
+        let mut diagnostics = Diagnostics::default();
+        let generation =
+            generate_with_observer(&input, &template(&db), &db, &options, &mut diagnostics)
+                .unwrap();
+
+        // Scanline warnings end up in the output, others are still reported.
+        assert_eq!(
+            diagnostics
+                .of_kind(DiagnosticKind::ScanlineOverflow)
+                .count(),
+            0
+        );
+        assert_eq!(
+            diagnostics
+                .of_kind(DiagnosticKind::UnknownInstruction)
+                .count(),
+            1
+        );
+        let warning = generation
+            .lines
+            .iter()
+            .position(|l| l.starts_with(WARNING_COMMENT_PREFIX))
+            .unwrap();
+        assert_eq!(
+            generation.lines[warning],
+            "; !! WARNING: Scanline overflow by 8 cycles! (scanline 0)"
+        );
+        assert!(generation.lines[warning + 1].starts_with(TOTAL_CYCLES_PREFIX));
+    }
+
+    #[test]
+    fn test_generate_lists_applied_overrides() {
+        let db = CycleDatabase::builtin();
+        let options = Options::builder().cycles_per_line(64).build();
+        let input = lines(&[
+            "move.w d0,d1 ; see table (3)",
+            "move.w d1,d2 ; (8) Measured on hardware",
+            "; (12)",
+            "move.w d2,d3 ; copy ; (6)",
+        ]);
+        let mut recorder = Recorder::default();
+        let generation =
+            generate_with_observer(&input, &template(&db), &db, &options, &mut recorder).unwrap();
+
+        assert_eq!(
+            recorder.warnings[0],
+            "Cycle overrides applied to 1 line(s): 2 'move.w d1,d2 ; (8) Measured on hardware'"
+        );
+        // Neither a number inside the first comment nor one opening a later comment is an
+        // override.
+        for looked_up in ["move.w d0,d1 ; see table (3)", "move.w d2,d3 ; copy ; (6)"] {
+            assert!(
+                generation
+                    .lines
+                    .iter()
+                    .any(|line| line.starts_with(&format!("{}\t;\t(4)", looked_up))),
+                "{}",
+                looked_up
+            );
+        }
+    }
+
+    #[test]
+    fn test_generate_separators() {
+        let db = CycleDatabase::builtin();
+        let input = lines(&["move.w d0,d1"; 12]);
+        let options = Options::builder()
+            .cycles_per_line(32)
+            .start_line(17)
+            .separator('=')
+            .build();
+        let generation = generate(&input, &template(&db), &db, &options).unwrap();
+        let separators: Vec<&String> = generation
+            .lines
+            .iter()
+            .filter(|l| l.contains("scanline") && l.contains("========"))
+            .collect();
+        assert_eq!(separators.len(), generation.scanlines);
+        assert_eq!(
+            separators[0],
+            "; ======== scanline 17 (cycles 544..576) ========"
+        );
+        assert_eq!(
+            separators[1],
+            "; ======== scanline 18 (cycles 576..608) ========"
+        );
+    }
+
+    #[test]
+    fn test_generate_expands_line_in_template() {
+        let db = CycleDatabase::builtin();
+        let templates = TemplateSet::from(
+            parse_template("move.w #LINE*2,d0 ; video base\ndcb.w 4,$4e71\n", &db).unwrap(),
+        );
+        let input = lines(&["move.w d0,d1"; 8]);
+        let options = Options::builder().cycles_per_line(32).build();
+        let expanded = |generation: Generation| -> Vec<String> {
+            generation
+                .lines
+                .into_iter()
+                .filter(|l| l.contains(",d0"))
+                .map(|l| l.split(';').next().unwrap().trim().to_string())
+                .collect()
+        };
+
+        let generation = generate(&input, &templates, &db, &options).unwrap();
+        // The template still takes its 8 cycles, leaving 4 instructions per scanline
+        assert_eq!(generation.scanlines, 2);
+        assert_eq!(expanded(generation), ["move.w #0*2,d0", "move.w #1*2,d0"]);
+
+        // Identical scanlines must not be served from the cache
+        let mut cache = ScanlineCache::new(0);
+        let cached = generate_cached(
+            &input,
+            &templates,
+            &db,
+            &options,
+            &mut Recorder::default(),
+            &mut cache,
+        )
+        .unwrap();
+        assert_eq!(expanded(cached)[1], "move.w #1*2,d0");
+        assert_eq!(cache.hits(), 0);
+    }
+
+    #[test]
+    fn test_generate_aligns_code_after_odd_sized_data() {
+        let db = CycleDatabase::builtin();
+        let input = lines(&["dc.b \"odd\"", "loop:", "move.w d0,d1"]);
+        let options = Options::builder().cycles_per_line(64).build();
+        let mut recorder = Recorder::default();
+        let output = generate_with_observer(&input, &template(&db), &db, &options, &mut recorder)
+            .unwrap()
+            .lines;
+        let even = output.iter().position(|l| l.trim() == "even").unwrap();
+        assert!(output[even - 1].starts_with("dc.b"));
+        assert!(output[even + 1].starts_with("loop:"));
+        assert_eq!(
+            recorder.warnings[0],
+            "Inserted 'even' before 'loop:' to align code after odd-sized data"
+        );
+
+        let strict = Options::builder()
+            .cycles_per_line(64)
+            .strictness(Strictness::Strict)
+            .build();
+        let err = generate(&input, &template(&db), &db, &strict).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Odd-sized data misaligns 'loop:'; add an 'even' directive"
+        );
+    }
+
+    fn two_templates(db: &CycleDatabase) -> TemplateSet {
+        let mut templates = TemplateSet::new();
+        templates
+            .insert(
+                "main",
+                parse_template("move.w d0,d1 ; main\ndcb.w 4,$4e71", db).unwrap(),
+            )
+            .unwrap();
+        templates
+            .insert(
+                "alt",
+                parse_template("move.l d0,d1 ; alt\ndcb.w 1,$4e71", db).unwrap(),
+            )
+            .unwrap();
+        templates
+    }
+
+    #[test]
+    fn test_generate_use_template_pragma_switches_template() {
+        let db = CycleDatabase::builtin();
+        let options = Options::builder().cycles_per_line(32).build();
+        let input = lines(&[
+            "move.w d2,d3",
+            ";@use-template alt",
+            "move.w d2,d3",
+            "move.w d2,d3",
+        ]);
+        let generation = generate(&input, &two_templates(&db), &db, &options).unwrap();
+
+        let sections: Vec<&String> = generation
+            .lines
+            .iter()
+            .filter(|l| l.starts_with("; ---"))
+            .collect();
+        assert_eq!(
+            sections,
+            vec![
+                "; --- main section ---",
+                "; --- alt section ---",
+                "; --- alt section ---"
+            ]
+        );
+        assert_eq!(generation.state.template.as_deref(), Some("alt"));
+    }
+
+    #[test]
+    fn test_generate_gives_up_when_no_progress_is_possible() {
+        let db = CycleDatabase::builtin();
+        let options = Options::builder().cycles_per_line(64).build();
+        // Only the unused main template has room for eight cycles.
+        let input = lines(&[";@use-template alt", "divs d0,d1 ; (8)"]);
+        let err = generate(&input, &two_templates(&db), &db, &options).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "No progress in {} scanlines: 'divs d0,d1' (index 1) fits no section of \
+                 the scanlines from frame scanline 1 on (last template 'alt')",
+                MAX_STALLED_SCANLINES + 1
+            )
+        );
+    }
+
+    #[test]
+    fn test_generate_use_template_pragma_rejects_unknown_names() {
+        let db = CycleDatabase::builtin();
+        let options = Options::builder().cycles_per_line(32).build();
+        let input = lines(&[";@use-template nope", "move.w d2,d3"]);
+        let err = generate(&input, &two_templates(&db), &db, &options).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Unknown template 'nope' (registered: main, alt)"
+        );
+
+        let input = lines(&[";@use-template", "move.w d2,d3"]);
+        assert!(generate(&input, &two_templates(&db), &db, &options).is_err());
+    }
+
+    #[test]
+    fn test_generate_follows_frame_layout_from_start_line() {
+        let db = CycleDatabase::builtin();
+        let options = Options::builder()
+            .cycles_per_line(32)
+            .frame_layout(FrameLayout::parse("0-9 main\n10- alt 36").unwrap())
+            .start_line(9)
+            .build();
+        let input = lines(&[
+            "move.w d2,d3",
+            ";@split-here",
+            "move.w d2,d3",
+            ";@split-here",
+            "move.w d2,d3",
+        ]);
+        let generation = generate(&input, &two_templates(&db), &db, &options).unwrap();
+
+        let sections: Vec<&String> = generation
+            .lines
+            .iter()
+            .filter(|l| l.starts_with("; ---"))
+            .collect();
+        assert_eq!(
+            sections,
+            vec![
+                "; --- main section ---",
+                "; --- alt section ---",
+                "; --- alt section ---"
+            ]
+        );
+        let totals: Vec<&str> = generation
+            .lines
+            .iter()
+            .filter_map(|l| l.strip_prefix(TOTAL_CYCLES_PREFIX))
+            .collect();
+        assert_eq!(totals, ["32", "36", "36"]);
+    }
+
+    #[test]
+    fn test_generate_honors_entry_and_exit_offsets() {
+        let db = CycleDatabase::builtin();
+        let options = Options::builder()
+            .cycles_per_line(32)
+            .entry_offset(8)
+            .exit_offset(16)
+            .build();
+        let input = lines(&["move.w d2,d3"; 5]);
+        let generation = generate(&input, &two_templates(&db), &db, &options).unwrap();
+
+        // Scanline 1 starts inside the code window, scanline 2 ends inside it.
+        assert_eq!(generation.scanlines, 2);
+        let template_code = generation
+            .lines
+            .iter()
+            .filter(|l| l.starts_with("move.w d0,d1"))
+            .count();
+        assert_eq!(template_code, 1);
+        assert!(generation.lines.iter().any(|l| l.ends_with("[8]")));
+        let totals: Vec<&str> = generation
+            .lines
+            .iter()
+            .filter_map(|l| l.strip_prefix(TOTAL_CYCLES_PREFIX))
+            .collect();
+        assert_eq!(totals, ["24", "16"]);
+        assert_eq!(generation.usage[0].user_cycles(), 12);
+        assert_eq!(generation.usage[1].user_cycles(), 8);
+
+        // A single scanline is both the first and the last one.
+        let input = lines(&["move.w d2,d3"]);
+        let generation = generate(&input, &two_templates(&db), &db, &options).unwrap();
+        assert!(
+            generation
+                .lines
+                .contains(&format!("{}8", TOTAL_CYCLES_PREFIX))
+        );
+    }
+
+    #[test]
+    fn test_generate_rejects_offsets_inside_template_code() {
+        let db = CycleDatabase::builtin();
+        let options = Options::builder()
+            .cycles_per_line(32)
+            .entry_offset(2)
+            .build();
+        let input = lines(&["move.w d2,d3"]);
+        let err = generate(&input, &two_templates(&db), &db, &options).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Cycle 2 falls into the template code of the main section"
+        );
+    }
+
+    #[test]
+    fn test_generate_rejects_offsets_beyond_the_scanline() {
+        let db = CycleDatabase::builtin();
+        let input = lines(&["move.w d2,d3"]);
+        let generate_with = |builder: OptionsBuilder| {
+            let options = builder.cycles_per_line(32).build();
+            generate(&input, &two_templates(&db), &db, &options)
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(
+            generate_with(Options::builder().entry_offset(32)),
+            "The entry offset of 32 cycles leaves nothing of the 32 cycles of scanline 0"
+        );
+        assert_eq!(
+            generate_with(Options::builder().exit_offset(40)),
+            "The exit offset of 40 cycles leaves nothing of the 32 cycles of scanline 0"
+        );
+        assert_eq!(
+            generate_with(Options::builder().entry_offset(16).exit_offset(20)),
+            "The entry and exit offsets take 36 of the 32 cycles of the single scanline"
+        );
+        // The scanline length of the frame layout bounds the offsets.
+        assert_eq!(
+            generate_with(
+                Options::builder()
+                    .frame_layout(FrameLayout::parse("0- main 16").unwrap())
+                    .entry_offset(16)
+            ),
+            "The entry offset of 16 cycles leaves nothing of the 16 cycles of scanline 0"
+        );
+    }
+
+    #[test]
+    fn test_generate_keeps_irq_windows_free() {
+        let db = CycleDatabase::builtin();
+        let options = Options::builder()
+            .cycles_per_line(32)
+            .frame_layout(FrameLayout::parse("0- main irq=8+8").unwrap())
+            .build();
+        let input = lines(&["move.w d2,d3"; 3]);
+        let generation = generate(&input, &two_templates(&db), &db, &options).unwrap();
+
+        // The 16 free cycles of the template are split into 4 before and 4 after the IRQ.
+        assert_eq!(generation.scanlines, 2);
+        let first: Vec<&String> = generation.lines.iter().take(6).collect();
+        assert!(first[0].starts_with("move.w d0,d1 ; main") && first[0].ends_with("[0]"));
+        assert!(first[2].ends_with("[4]"));
+        assert_eq!(first[4], "; IRQ window (8 cycles)\t[8]");
+        assert_eq!(generation.usage[0].template_cycles(), 12);
+        assert_eq!(generation.usage[0].user_cycles(), 8);
+
+        let options = Options::builder()
+            .cycles_per_line(32)
+            .frame_layout(FrameLayout::parse("0- main irq=2+8").unwrap())
+            .build();
+        let err = generate(&input, &two_templates(&db), &db, &options).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "IRQ window at cycle 2 overlaps the template code of the main section"
+        );
+    }
+
+    #[test]
+    fn test_generate_resume_keeps_selected_template() {
+        let db = CycleDatabase::builtin();
+        let options = Options::builder().cycles_per_line(32).build();
+        let input = lines(&[";@use-template alt", "move.w d2,d3", "move.w d2,d3"]);
+        let mut recorder = Recorder {
+            stop_after: Some(1),
+            ..Default::default()
+        };
+        let first =
+            generate_with_observer(&input, &two_templates(&db), &db, &options, &mut recorder)
+                .unwrap();
+        let rest = resume_with_observer(
+            &input,
+            &two_templates(&db),
+            &db,
+            &options,
+            &mut Recorder::default(),
+            first.state,
+        )
+        .unwrap();
+        assert!(rest.lines.contains(&"; --- alt section ---".to_string()));
+    }
+
+    #[test]
+    fn test_generate_reports_section_usage() {
+        let db = CycleDatabase::builtin();
+        let options = Options::builder().cycles_per_line(64).build();
+        let input = lines(&["move.w d0,d1"; 7]);
+        let generation = generate(&input, &template(&db), &db, &options).unwrap();
+
+        assert_eq!(generation.usage.len(), 2);
+        let first = &generation.usage[0];
+        assert_eq!(first.sections[0].label, "first");
+        assert_eq!(first.sections[0].template_cycles, 4);
+        assert_eq!(first.sections[0].user_cycles, 16);
+        assert_eq!(first.sections[1].user_cycles, 8);
+        assert_eq!(first.tail_padding, 32);
+        assert!(generation.usage.iter().all(|u| u.total_cycles() == 64));
+    }
+
+    #[test]
+    fn test_generate_verbose_states_utilization() {
+        let db = CycleDatabase::builtin();
+        let options = Options::builder()
+            .cycles_per_line(64)
+            .verbosity(Verbosity::Verbose)
+            .build();
+        let input = lines(&["move.w d0,d1"; 7]);
+        let generation = generate(&input, &template(&db), &db, &options).unwrap();
+        let total = generation
+            .lines
+            .iter()
+            .position(|l| l.starts_with(TOTAL_CYCLES_PREFIX))
+            .unwrap();
+        assert_eq!(
+            generation.lines[total + 1],
+            "; utilization: 50% (32/64), padding 32"
+        );
+    }
+
+    #[test]
+    fn test_generate_replaces_previous_annotations() {
+        let db = CycleDatabase::builtin();
+        let options = Options::builder().cycles_per_line(64).build();
+        let input = lines(&["move.w d2,d3"; 3]);
+        let first = generate(&input, &template(&db), &db, &options).unwrap();
+
+        // Feed the annotated user code back in.
+        let annotated: Vec<String> = first
+            .lines
+            .iter()
+            .filter(|l| l.starts_with("move.w d2,d3"))
+            .cloned()
+            .collect();
+        assert_eq!(annotated.len(), 3);
+        let second = generate(&annotated, &template(&db), &db, &options).unwrap();
+        assert_eq!(second.lines, first.lines);
+    }
+
+    #[test]
+    fn test_generate_without_template() {
+        let db = CycleDatabase::builtin();
+        let options = Options::builder().cycles_per_line(64).build();
+        let templates = TemplateSet::from(vec![TemplateSection::code_window(64, "user code")]);
+        let input = lines(&["move.w d0,d1"; 20]);
+        let generation = generate(&input, &templates, &db, &options).unwrap();
+
+        // 16 four-cycle moves per scanline, no injected code.
+        assert_eq!(generation.scanlines, 2);
+        assert_eq!(generation.usage[0].user_cycles(), 64);
+        assert_eq!(generation.usage[0].template_cycles(), 0);
+        assert_eq!(generation.usage[1].user_cycles(), 16);
+    }
+
+    #[test]
+    fn test_generate_split_here_pragma_ends_scanline() {
+        let db = CycleDatabase::builtin();
+        let options = Options::builder().cycles_per_line(64).build();
+        let input = lines(&[
+            "move.w d0,d1",
+            ";@split-here",
+            "move.w d2,d3",
+            "move.w d3,d4",
+        ]);
+        let generation = generate(&input, &template(&db), &db, &options).unwrap();
+
+        assert_eq!(generation.scanlines, 2);
+        assert_eq!(generation.usage[0].user_cycles(), 4);
+        assert_eq!(generation.usage[1].user_cycles(), 8);
+        let split = generation
+            .lines
+            .iter()
+            .position(|l| l == ";@split-here")
+            .unwrap();
+        assert!(generation.lines[split - 1].starts_with(TOTAL_CYCLES_PREFIX));
+    }
+
+    #[test]
+    fn test_generate_places_critical_instructions_in_hot_sections() {
+        let db = CycleDatabase::builtin();
+        let templates = TemplateSet::from(
+            parse_template(
+                "move.w d0,d1 ; left\ndcb.w 2,$4e71\nmove.w d0,d1 ; hbl\ndcb.w 2,$4e71 ;@hot",
+                &db,
+            )
+            .unwrap(),
+        );
+        let options = Options::builder().cycles_per_line(24).build();
+        let input = lines(&["move.w d2,d3", "move.w d4,d5 ;@critical", "move.w d2,d3"]);
+        let generation = generate(&input, &templates, &db, &options).unwrap();
+        assert_eq!(generation.scanlines, 1);
+        let position = |prefix: &str| {
+            generation
+                .lines
+                .iter()
+                .position(|l| l.starts_with(prefix))
+                .unwrap()
+        };
+        assert!(position("move.w d4,d5") > position("; --- hbl section ---"));
+        assert!(position("move.w d2,d3") < position("; --- hbl section ---"));
+
+        let cold = TemplateSet::from(parse_template("move.w d0,d1\ndcb.w 4,$4e71", &db).unwrap());
+        let error = generate(&input, &cold, &db, &options).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Critical instruction 'move.w d4,d5 ;@critical' does not fit into a hot section of scanline 1"
+        );
+    }
+}
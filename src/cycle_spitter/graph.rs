@@ -0,0 +1,272 @@
+// src/cycle_spitter/graph.rs
+
+//! Charts of how the cycles of every scanline are spent across the frame.
+//!
+//! Two formats are available:
+//! - [`utilization_svg`] draws one horizontal bar per scanline, top to bottom like the display,
+//!   with a segment per section for its template code, user code and padding, and a final
+//!   segment for the padding that fills the scanline. Load imbalances show up as columns of
+//!   padding or as bars crossing the budget marker.
+//! - [`utilization_gnuplot`] writes the same numbers as a whitespace-separated data table for
+//!   gnuplot or any other plotting tool.
+//! - [`utilization_heatmap`] prints one character per scanline for a quick look in the terminal.
+
+use crate::cycle_spitter::generator::ScanlineUsage;
+use std::fmt::Write;
+
+/// Fill colors of the chart segments.
+const TEMPLATE_COLOR: &str = "#9e9e9e";
+const USER_COLOR: &str = "#43a047";
+const PADDING_COLOR: &str = "#fb8c00";
+const TAIL_COLOR: &str = "#ffcc80";
+
+/// Height of one scanline bar in pixels.
+const ROW_HEIGHT: usize = 3;
+/// Space left of the bars for the scanline ruler.
+const LEFT_MARGIN: usize = 48;
+/// Space above the bars for the legend.
+const TOP_MARGIN: usize = 40;
+
+/// Renders the utilization of `usage` as an SVG document. One pixel corresponds to one cycle;
+/// the budget of `cycles_per_line` is marked with a vertical line.
+pub fn utilization_svg(usage: &[ScanlineUsage], cycles_per_line: usize) -> String {
+    let widest = usage
+        .iter()
+        .map(|u| u.total_cycles())
+        .max()
+        .unwrap_or(0)
+        .max(cycles_per_line);
+    let width = LEFT_MARGIN + widest + 16;
+    let height = TOP_MARGIN + usage.len() * ROW_HEIGHT + 16;
+
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" font-family="monospace" font-size="10">"#,
+        width, height
+    );
+    let _ = writeln!(
+        svg,
+        r#"<rect width="{}" height="{}" fill="white"/>"#,
+        width, height
+    );
+
+    // Legend
+    let legend = [
+        (TEMPLATE_COLOR, "template"),
+        (USER_COLOR, "user code"),
+        (PADDING_COLOR, "section padding"),
+        (TAIL_COLOR, "scanline padding"),
+    ];
+    for (i, (color, name)) in legend.iter().enumerate() {
+        let x = LEFT_MARGIN + i * 120;
+        let _ = writeln!(
+            svg,
+            r#"<rect x="{}" y="8" width="10" height="10" fill="{}"/><text x="{}" y="17">{}</text>"#,
+            x,
+            color,
+            x + 14,
+            name
+        );
+    }
+
+    for (scanline, line_usage) in usage.iter().enumerate() {
+        let y = TOP_MARGIN + scanline * ROW_HEIGHT;
+        let mut x = LEFT_MARGIN;
+        let mut segment = |svg: &mut String, cycles: usize, color: &str, title: String| {
+            if cycles > 0 {
+                let _ = writeln!(
+                    svg,
+                    r#"<rect x="{}" y="{}" width="{}" height="{}" fill="{}"><title>{}</title></rect>"#,
+                    x,
+                    y,
+                    cycles,
+                    ROW_HEIGHT,
+                    color,
+                    escape(&title)
+                );
+                x += cycles;
+            }
+        };
+        for section in &line_usage.sections {
+            segment(
+                &mut svg,
+                section.template_cycles,
+                TEMPLATE_COLOR,
+                format!("scanline {}: {} template", scanline, section.label),
+            );
+            segment(
+                &mut svg,
+                section.user_cycles,
+                USER_COLOR,
+                format!(
+                    "scanline {}: {} user code ({} cycles)",
+                    scanline, section.label, section.user_cycles
+                ),
+            );
+            segment(
+                &mut svg,
+                section.padding_cycles,
+                PADDING_COLOR,
+                format!(
+                    "scanline {}: {} padding ({} cycles)",
+                    scanline, section.label, section.padding_cycles
+                ),
+            );
+        }
+        segment(
+            &mut svg,
+            line_usage.tail_padding,
+            TAIL_COLOR,
+            format!(
+                "scanline {}: scanline padding ({} cycles)",
+                scanline, line_usage.tail_padding
+            ),
+        );
+
+        if scanline % 10 == 0 {
+            let _ = writeln!(
+                svg,
+                r#"<text x="{}" y="{}" text-anchor="end">{}</text>"#,
+                LEFT_MARGIN - 4,
+                y + ROW_HEIGHT,
+                scanline
+            );
+        }
+    }
+
+    // Budget marker
+    let _ = writeln!(
+        svg,
+        r#"<line x1="{x}" y1="{}" x2="{x}" y2="{}" stroke="red" stroke-width="1"/>"#,
+        TOP_MARGIN - 4,
+        TOP_MARGIN + usage.len() * ROW_HEIGHT + 4,
+        x = LEFT_MARGIN + cycles_per_line
+    );
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// Renders the utilization of `usage` as a gnuplot data table with one row per section and
+/// scanline, plus one `<tail>` row per scanline for the padding filling the scanline.
+pub fn utilization_gnuplot(usage: &[ScanlineUsage]) -> String {
+    let mut data = String::new();
+    data.push_str("# scanline\tsection\ttemplate\tuser\tpadding\tlabel\n");
+    for (scanline, line_usage) in usage.iter().enumerate() {
+        for (index, section) in line_usage.sections.iter().enumerate() {
+            let _ = writeln!(
+                data,
+                "{}\t{}\t{}\t{}\t{}\t\"{}\"",
+                scanline,
+                index,
+                section.template_cycles,
+                section.user_cycles,
+                section.padding_cycles,
+                section.label.replace('"', "'")
+            );
+        }
+        let _ = writeln!(
+            data,
+            "{}\t{}\t0\t0\t{}\t\"<tail>\"",
+            scanline,
+            line_usage.sections.len(),
+            line_usage.tail_padding
+        );
+    }
+    data
+}
+
+/// Scanlines per row of the terminal heatmap.
+const HEATMAP_WIDTH: usize = 32;
+
+/// Renders a compact heatmap with one character per scanline, [`HEATMAP_WIDTH`] scanlines per
+/// row. The character shows how much of `cycles_per_line` is used by template and user code
+/// (`░` below 25%, `▒` below 50%, `▓` below 75%, `█` above), `!` marks overflowing scanlines.
+/// With `color`, the characters are additionally colored with ANSI escapes from red (mostly
+/// padding) to green (fully used), overflows in magenta.
+pub fn utilization_heatmap(usage: &[ScanlineUsage], cycles_per_line: usize, color: bool) -> String {
+    let mut map = String::new();
+    for (row, chunk) in usage.chunks(HEATMAP_WIDTH).enumerate() {
+        let _ = write!(map, "{:>5} ", row * HEATMAP_WIDTH);
+        for line_usage in chunk {
+            let used = line_usage.template_cycles() + line_usage.user_cycles();
+            let (symbol, ansi) = if line_usage.total_cycles() > cycles_per_line {
+                ('!', "35")
+            } else {
+                match used * 100 / cycles_per_line.max(1) {
+                    0..25 => ('░', "31"),
+                    25..50 => ('▒', "33"),
+                    50..75 => ('▓', "32"),
+                    _ => ('█', "92"),
+                }
+            };
+            if color {
+                let _ = write!(map, "\x1b[{}m{}\x1b[0m", ansi, symbol);
+            } else {
+                map.push(symbol);
+            }
+        }
+        map.push('\n');
+    }
+    map.push_str("      ░ <25%  ▒ <50%  ▓ <75%  █ >=75%  ! overflow\n");
+    map
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cycle_spitter::generator::SectionUsage;
+
+    fn usage() -> Vec<ScanlineUsage> {
+        vec![ScanlineUsage {
+            sections: vec![SectionUsage {
+                label: "left <border>".to_string(),
+                template_cycles: 8,
+                user_cycles: 20,
+                padding_cycles: 4,
+            }],
+            tail_padding: 32,
+        }]
+    }
+
+    #[test]
+    fn test_utilization_svg() {
+        let svg = utilization_svg(&usage(), 64);
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.trim_end().ends_with("</svg>"));
+        assert!(svg.contains(&format!(r#"width="20" height="3" fill="{}""#, USER_COLOR)));
+        assert!(svg.contains("left &lt;border&gt; user code (20 cycles)"));
+        assert!(svg.contains(&format!(r#"x1="{}""#, LEFT_MARGIN + 64)));
+    }
+
+    #[test]
+    fn test_utilization_heatmap() {
+        let mut usage = vec![usage()[0].clone(); 40];
+        usage[1].tail_padding = 0;
+        usage[1].sections[0].user_cycles = 100;
+        let map = utilization_heatmap(&usage, 64, false);
+        let rows: Vec<&str> = map.lines().collect();
+        assert_eq!(rows.len(), 3);
+        assert!(rows[0].starts_with("    0 ▒!▒"));
+        assert_eq!(rows[0].chars().count(), 6 + 32);
+        assert!(rows[1].starts_with("   32 ▒▒▒▒▒▒▒▒"));
+
+        let colored = utilization_heatmap(&usage[..1], 64, true);
+        assert!(colored.contains("\x1b[33m▒\x1b[0m"));
+    }
+
+    #[test]
+    fn test_utilization_gnuplot() {
+        let data = utilization_gnuplot(&usage());
+        let rows: Vec<&str> = data.lines().collect();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[1], "0\t0\t8\t20\t4\t\"left <border>\"");
+        assert_eq!(rows[2], "0\t1\t0\t0\t32\t\"<tail>\"");
+    }
+}
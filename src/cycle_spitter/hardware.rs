@@ -0,0 +1,188 @@
+// src/cycle_spitter/hardware.rs
+
+//! Map of the Atari ST/STE hardware registers relevant to raster code.
+//!
+//! Used to detect writes to the same register by template and user code within one scanline;
+//! switching sync or shift mode twice usually destroys the overscan. Only writes to absolute
+//! addresses are recognized - writes through an address register cannot be resolved
+//! statically.
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+/// A memory mapped hardware register.
+#[derive(Debug, PartialEq, Eq)]
+pub struct HardwareRegister {
+    /// The 24-bit address of the register's first byte.
+    pub address: u32,
+    /// The number of bytes the register occupies.
+    pub size: u32,
+    /// A human-readable name.
+    pub name: &'static str,
+}
+
+macro_rules! register {
+    ($address:expr, $size:expr, $name:expr) => {
+        HardwareRegister {
+            address: $address,
+            size: $size,
+            name: $name,
+        }
+    };
+}
+
+/// The known hardware registers, ordered by address.
+pub static HARDWARE_REGISTERS: &[HardwareRegister] = &[
+    register!(0xff8201, 1, "video base high"),
+    register!(0xff8203, 1, "video base mid"),
+    register!(0xff8205, 1, "video counter high"),
+    register!(0xff8207, 1, "video counter mid"),
+    register!(0xff8209, 1, "video counter low"),
+    register!(0xff820a, 1, "sync mode"),
+    register!(0xff820d, 1, "video base low (STE)"),
+    register!(0xff820f, 1, "line offset (STE)"),
+    register!(0xff8240, 32, "palette"),
+    register!(0xff8260, 1, "shift mode"),
+    register!(0xff8265, 1, "horizontal scroll (STE)"),
+    register!(0xff8800, 1, "YM2149 register select"),
+    register!(0xff8802, 1, "YM2149 data"),
+    register!(0xfffa01, 47, "MFP 68901"),
+];
+
+/// Mnemonics that only read their last operand.
+const READ_ONLY: &[&str] = &["tst", "cmp", "cmpi", "cmpa", "btst", "pea", "jmp", "jsr"];
+
+static ABSOLUTE_DESTINATION_RE: Lazy<Regex> = Lazy::new(|| {
+    // Matches an instruction whose last operand is an absolute address
+    // Example matches: `move.b d7,$ffff820a.w`, `clr.w $ff8240`, `.l: sf $820a.w ; comment`
+    // - `(?:\.*[a-z_][a-z0-9_]*:\s*)?`: An optional leading label
+    // - `(?P<mnemonic>[a-z]+)(?:\.(?P<size>[bwl]))?`: The mnemonic and its size suffix
+    // - `(?:[^;]*,)?`: The source operands, if any
+    // - `\$(?P<address>[0-9a-f]+)(?:\.(?P<width>[wl]))?`: The destination address and its width
+    Regex::new(
+        r"(?i)^\s*(?:\.*[a-z_][a-z0-9_]*:\s*)?(?P<mnemonic>[a-z]+)(?:\.(?P<size>[bwl]))?\s+(?:[^;]*,)?\s*\$(?P<address>[0-9a-f]+)(?:\.(?P<width>[wl]))?\s*(?:;.*)?$",
+    )
+    .unwrap()
+});
+
+/// Returns the hardware registers written by `line`, if it writes to an absolute address.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::hardware::written_registers;
+///
+/// let written = written_registers("move.w d7,$ffff820a.w");
+/// assert_eq!(written[0].name, "sync mode");
+/// assert!(written_registers("tst.b $fffa01").is_empty());
+/// ```
+pub fn written_registers(line: &str) -> Vec<&'static HardwareRegister> {
+    let Some(caps) = ABSOLUTE_DESTINATION_RE.captures(line) else {
+        return Vec::new();
+    };
+    if READ_ONLY.contains(&caps["mnemonic"].to_lowercase().as_str()) {
+        return Vec::new();
+    }
+    let Ok(value) = u32::from_str_radix(&caps["address"], 16) else {
+        return Vec::new();
+    };
+    // Short absolute addresses are sign-extended
+    let address = match caps.name("width").map(|m| m.as_str().to_lowercase()) {
+        Some(width) if width == "w" && value <= 0xffff => value as u16 as i16 as i32 as u32,
+        _ => value,
+    } & 0xffffff;
+    let size = match caps.name("size").map(|m| m.as_str().to_lowercase()) {
+        Some(size) if size == "b" => 1,
+        Some(size) if size == "l" => 4,
+        _ => 2,
+    };
+    HARDWARE_REGISTERS
+        .iter()
+        .filter(|r| address < r.address + r.size && r.address < address + size)
+        .collect()
+}
+
+/// The register `--safe` redirects sync and shift mode writes to by default: the last palette
+/// entry, which sits in the Shifter like the mode registers and so costs the same bus cycles.
+pub const SAFE_DUMMY_ADDRESS: u32 = 0xff825e;
+
+/// Redirects a write to the sync mode or shift mode register to the 24-bit address `dummy`,
+/// keeping the instruction and its addressing mode and thus its timing. `dummy` must lie in the
+/// I/O area (`$ff8000` and above) to be reachable with short absolute addressing. Returns `None`
+/// for lines that switch neither mode.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::hardware::neutralize_sync_writes;
+///
+/// assert_eq!(
+///     neutralize_sync_writes("move.b\td7,$ffff820a.w ; open", 0xff825e).unwrap(),
+///     "move.b\td7,$ffff825e.w ; open"
+/// );
+/// assert!(neutralize_sync_writes("move.w d0,$ffff8240.w", 0xff825e).is_none());
+/// ```
+pub fn neutralize_sync_writes(line: &str, dummy: u32) -> Option<String> {
+    let switches_mode = written_registers(line)
+        .iter()
+        .any(|r| r.name == "sync mode" || r.name == "shift mode");
+    if !switches_mode {
+        return None;
+    }
+    let caps = ABSOLUTE_DESTINATION_RE.captures(line)?;
+    let address = caps.name("address")?;
+    // Keep short addresses short and write long ones so that they cannot be shortened
+    let replacement = match caps.name("width").map(|m| m.as_str().to_lowercase()) {
+        Some(width) if width == "w" => format!("ffff{:04x}", dummy & 0xffff),
+        _ => format!("{:06x}", dummy & 0xffffff),
+    };
+    Some(format!(
+        "{}{}{}",
+        &line[..address.start()],
+        replacement,
+        &line[address.end()..]
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(line: &str) -> Vec<&'static str> {
+        written_registers(line).iter().map(|r| r.name).collect()
+    }
+
+    #[test]
+    fn test_written_registers() {
+        assert_eq!(names("move.b d7,$ffff820a.w"), ["sync mode"]);
+        assert_eq!(names("MOVE.B D7,$820A.W ; open"), ["sync mode"]);
+        assert_eq!(names("move.b #2,$ff8260"), ["shift mode"]);
+        assert_eq!(names(".open: clr.b $ffff8260.w"), ["shift mode"]);
+        assert_eq!(names("move.l d0,$ffff8240.w"), ["palette"]);
+        assert_eq!(
+            names("move.l d0,$ffff8208.w"),
+            ["video counter low", "sync mode"]
+        );
+    }
+
+    #[test]
+    fn test_reads_and_other_addresses_are_ignored() {
+        assert!(names("move.b $ffff820a.w,d0").is_empty());
+        assert!(names("tst.b $fffffa01.w").is_empty());
+        assert!(names("move.w d0,(a0)").is_empty());
+        assert!(names("move.w d0,$1234.w").is_empty());
+        assert!(names("move.w d0,d1 ; $ffff820a").is_empty());
+    }
+
+    #[test]
+    fn test_neutralize_sync_writes() {
+        assert_eq!(
+            neutralize_sync_writes("move.w d7,$ffff8260.w", SAFE_DUMMY_ADDRESS).unwrap(),
+            "move.w d7,$ffff825e.w"
+        );
+        assert_eq!(
+            neutralize_sync_writes("clr.b $ffff820a", SAFE_DUMMY_ADDRESS).unwrap(),
+            "clr.b $ff825e"
+        );
+        assert!(neutralize_sync_writes("move.b $ffff820a.w,d0", SAFE_DUMMY_ADDRESS).is_none());
+        assert!(neutralize_sync_writes("move.w d7,$ffff8240.w", SAFE_DUMMY_ADDRESS).is_none());
+    }
+}
@@ -0,0 +1,284 @@
+// src/cycle_spitter/hatari.rs
+
+//! Proposing a frame layout from a Hatari video trace.
+//!
+//! Hatari logs every write to the sync mode and shift mode registers when run with
+//! `--trace video_sync,video_res`:
+//!
+//! ```text
+//! sync=0x00 video_cyc_w=17784 line_cyc_w=376 @ nHBL=34/video_hbl_w=34 pc=1a462 instr_cyc=12
+//! shifter=0x02 video_cyc_w=17852 line_cyc_w=444 @ nHBL=34/video_hbl_w=34 pc=1a47a instr_cyc=12
+//! ```
+//!
+//! [`parse_trace`] reads these writes; other log lines are ignored. Hatari reports the cycle of
+//! the register write, the last bus access of the switching instruction, so the instruction
+//! starts `instr_cyc - 4` cycles earlier. Only the first frame of a trace is read.
+//!
+//! [`propose_frame`] groups consecutive scanlines with the same switches into regions and names
+//! every distinct set of switches, which `synthesis::synthesize_template` turns into a template.
+//! A scanline's budget is 508 cycles if it starts at 60 Hz and 512 cycles otherwise. The result
+//! is a starting point: lines without switches are left out, and the positions are only as
+//! exact as the emulation.
+
+use crate::cycle_spitter::synthesis::{Switch, SwitchPosition};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::error::Error;
+
+/// The cycles of a 50 Hz scanline.
+const CYCLES_50HZ: usize = 512;
+/// The cycles of a 60 Hz scanline.
+const CYCLES_60HZ: usize = 508;
+
+static WRITE_RE: Lazy<Regex> = Lazy::new(|| {
+    // Matches a sync or shift mode register write of a Hatari video trace
+    // Example match: `sync=0x00 video_cyc_w=17784 line_cyc_w=376 @ nHBL=34/video_hbl_w=34 pc=1a462 instr_cyc=12`
+    // - `(?P<register>sync|shifter)=0x(?P<value>[0-9a-f]+)`: The register and the value written
+    // - `line_cyc_w=(?P<cycle>\d+)`: The scanline cycle of the write
+    // - `nHBL=(?P<scanline>\d+)`: The scanline
+    // - `instr_cyc=(?P<instruction>\d+)`: The cycles of the writing instruction, if logged
+    Regex::new(
+        r"(?i)\b(?P<register>sync|shifter)=0x(?P<value>[0-9a-f]+)\b.*?\bline_cyc_w=(?P<cycle>\d+).*?\bnHBL=(?P<scanline>\d+)(?:.*?\binstr_cyc=(?P<instruction>\d+))?",
+    )
+    .unwrap()
+});
+
+/// A register write found in a trace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceWrite {
+    /// The scanline (HBL) of the write.
+    pub scanline: usize,
+    /// The switch and the scanline cycle at which its instruction starts.
+    pub position: SwitchPosition,
+}
+
+/// Reads the sync and shift mode writes of the first frame of a Hatari trace (see the module
+/// documentation).
+///
+/// # Errors
+/// If the trace holds no such writes.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::hatari::parse_trace;
+/// use cycle_spitter::synthesis::Switch;
+///
+/// let trace = "sync=0x00 video_cyc_w=17784 line_cyc_w=384 @ nHBL=34/video_hbl_w=34 pc=1a462 instr_cyc=12";
+/// let writes = parse_trace(trace).unwrap();
+/// assert_eq!(writes[0].scanline, 34);
+/// assert_eq!(writes[0].position.switch, Switch::Hz60);
+/// assert_eq!(writes[0].position.cycle, 376);
+/// ```
+pub fn parse_trace(content: &str) -> Result<Vec<TraceWrite>, Box<dyn Error>> {
+    let mut writes: Vec<TraceWrite> = Vec::new();
+    for caps in content.lines().filter_map(|line| WRITE_RE.captures(line)) {
+        let value = u8::from_str_radix(&caps["value"], 16)?;
+        let switch = match (caps["register"].to_lowercase().as_str(), value & 3) {
+            ("sync", 2) => Switch::Hz50,
+            ("sync", _) => Switch::Hz60,
+            (_, 2) => Switch::HighRes,
+            _ => Switch::LowRes,
+        };
+        let scanline: usize = caps["scanline"].parse()?;
+        // A new frame starts once the scanlines count down again
+        if writes.last().is_some_and(|last| scanline < last.scanline) {
+            break;
+        }
+        let instruction: usize = caps
+            .name("instruction")
+            .map_or(Ok(4), |m| m.as_str().parse())?;
+        let cycle: usize = caps["cycle"].parse()?;
+        writes.push(TraceWrite {
+            scanline,
+            position: SwitchPosition {
+                switch,
+                cycle: cycle.saturating_sub(instruction.saturating_sub(4)),
+            },
+        });
+    }
+    if writes.is_empty() {
+        return Err("No sync or shift mode writes found (trace with video_sync,video_res)".into());
+    }
+    Ok(writes)
+}
+
+/// A range of scanlines with the same switches.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProposedRegion {
+    pub first: usize,
+    pub last: usize,
+    /// The index of the region's switches in [`FrameProposal::templates`].
+    pub template: usize,
+    pub cycles: usize,
+}
+
+/// A frame layout proposed from a trace.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FrameProposal {
+    /// The distinct switch sets, in order of first appearance, sorted by cycle.
+    pub templates: Vec<Vec<SwitchPosition>>,
+    /// The regions, ordered by scanline.
+    pub regions: Vec<ProposedRegion>,
+}
+
+impl FrameProposal {
+    /// The name of the template at `index`.
+    pub fn template_name(index: usize) -> String {
+        format!("overscan{}", index + 1)
+    }
+
+    /// Renders the proposal as a frame layout, followed by the switch spec of every template
+    /// as a comment.
+    pub fn to_layout(&self) -> String {
+        let mut layout = String::from("; Frame layout proposed from a Hatari trace\n");
+        layout.push_str("; scanlines  template  cycles\n");
+        for region in &self.regions {
+            layout.push_str(&format!(
+                "{:<12} {:<10} {}\n",
+                format!("{}-{}", region.first, region.last),
+                Self::template_name(region.template),
+                region.cycles
+            ));
+        }
+        for (index, switches) in self.templates.iter().enumerate() {
+            let spec: Vec<String> = switches
+                .iter()
+                .map(|s| format!("{} at {}", s.switch, s.cycle))
+                .collect();
+            layout.push_str(&format!(
+                ";\n; {}: {}\n",
+                Self::template_name(index),
+                spec.join(", ")
+            ));
+        }
+        layout
+    }
+}
+
+/// Groups the writes of a trace into regions of scanlines with the same switches and budget.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::hatari::{parse_trace, propose_frame};
+///
+/// let trace = "\
+/// sync=0x00 line_cyc_w=384 @ nHBL=34 instr_cyc=12
+/// sync=0x02 line_cyc_w=396 @ nHBL=34 instr_cyc=12
+/// sync=0x00 line_cyc_w=384 @ nHBL=35 instr_cyc=12
+/// sync=0x02 line_cyc_w=396 @ nHBL=35 instr_cyc=12";
+/// let proposal = propose_frame(&parse_trace(trace).unwrap());
+/// assert_eq!(proposal.templates.len(), 1);
+/// assert_eq!((proposal.regions[0].first, proposal.regions[0].last), (34, 35));
+/// ```
+pub fn propose_frame(writes: &[TraceWrite]) -> FrameProposal {
+    let mut proposal = FrameProposal::default();
+    // The sync mode in effect, starting at 50 Hz
+    let mut sync = Switch::Hz50;
+    for line in writes.chunk_by(|a, b| a.scanline == b.scanline) {
+        let scanline = line[0].scanline;
+        let cycles = if sync == Switch::Hz60 {
+            CYCLES_60HZ
+        } else {
+            CYCLES_50HZ
+        };
+        let mut switches: Vec<SwitchPosition> = line.iter().map(|w| w.position).collect();
+        switches.sort_by_key(|s| s.cycle);
+        if let Some(last) = switches
+            .iter()
+            .rev()
+            .find(|s| matches!(s.switch, Switch::Hz50 | Switch::Hz60))
+        {
+            sync = last.switch;
+        }
+
+        let template = match proposal.templates.iter().position(|t| *t == switches) {
+            Some(index) => index,
+            None => {
+                proposal.templates.push(switches);
+                proposal.templates.len() - 1
+            }
+        };
+        match proposal.regions.last_mut() {
+            Some(region)
+                if region.last + 1 == scanline
+                    && region.template == template
+                    && region.cycles == cycles =>
+            {
+                region.last = scanline;
+            }
+            _ => proposal.regions.push(ProposedRegion {
+                first: scanline,
+                last: scanline,
+                template,
+                cycles,
+            }),
+        }
+    }
+    proposal
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cycle_spitter::frame::FrameLayout;
+
+    const TRACE: &str = "\
+HBL 33 video_cyc=16896
+sync=0x00 video_cyc_w=17792 line_cyc_w=384 @ nHBL=34/video_hbl_w=34 pc=1a462 instr_cyc=12
+sync=0x02 video_cyc_w=17804 line_cyc_w=396 @ nHBL=34/video_hbl_w=34 pc=1a466 instr_cyc=12
+shifter=0x02 video_cyc_w=17860 line_cyc_w=452 @ nHBL=34/video_hbl_w=34 pc=1a46a instr_cyc=12
+shifter=0x00 video_cyc_w=17872 line_cyc_w=464 @ nHBL=34/video_hbl_w=34 pc=1a46e instr_cyc=12
+sync=0x00 video_cyc_w=18304 line_cyc_w=384 @ nHBL=35/video_hbl_w=35 pc=1a462 instr_cyc=12
+sync=0x02 video_cyc_w=18316 line_cyc_w=396 @ nHBL=35/video_hbl_w=35 pc=1a466 instr_cyc=12
+shifter=0x02 video_cyc_w=18372 line_cyc_w=452 @ nHBL=35/video_hbl_w=35 pc=1a46a instr_cyc=12
+shifter=0x00 video_cyc_w=18384 line_cyc_w=464 @ nHBL=35/video_hbl_w=35 pc=1a46e instr_cyc=12
+sync=0x00 video_cyc_w=19200 line_cyc_w=400 @ nHBL=37/video_hbl_w=37 pc=1a500 instr_cyc=12
+sync=0x00 video_cyc_w=17792 line_cyc_w=384 @ nHBL=34/video_hbl_w=34 pc=1a462 instr_cyc=12
+";
+
+    #[test]
+    fn test_parse_trace() {
+        let writes = parse_trace(TRACE).unwrap();
+        // The second frame is ignored
+        assert_eq!(writes.len(), 9);
+        assert_eq!(
+            writes[2].position,
+            SwitchPosition {
+                switch: Switch::HighRes,
+                cycle: 444
+            }
+        );
+        assert!(parse_trace("HBL 33 video_cyc=16896").is_err());
+    }
+
+    #[test]
+    fn test_propose_frame() {
+        let proposal = propose_frame(&parse_trace(TRACE).unwrap());
+        assert_eq!(proposal.templates.len(), 2);
+        assert_eq!(
+            proposal.regions,
+            [
+                ProposedRegion {
+                    first: 34,
+                    last: 35,
+                    template: 0,
+                    cycles: 512
+                },
+                ProposedRegion {
+                    first: 37,
+                    last: 37,
+                    template: 1,
+                    cycles: 512
+                },
+            ]
+        );
+        let layout = proposal.to_layout();
+        assert!(
+            layout
+                .contains("; overscan1: 60hz at 376, 50hz at 388, hi-res at 444, lo-res at 456\n")
+        );
+        let parsed = FrameLayout::parse(&layout).unwrap();
+        assert_eq!(parsed.regions()[1].template, "overscan2");
+        assert_eq!(parsed.cycles_at(36), None);
+    }
+}
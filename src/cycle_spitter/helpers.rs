@@ -1,8 +1,26 @@
 // src/cycle_spitter/helpers.rs
 
-use crate::cycle_spitter::cycles::lookup_cycles;
+use crate::cycle_spitter::cycles::{is_canonical, lookup_cycles};
+use crate::cycle_spitter::database::{CycleDatabase, CycleKind, CycleSource};
 use crate::cycle_spitter::models::CycleCount;
+use crate::cycle_spitter::options::AnnotationFormat;
 use crate::cycle_spitter::regexes::REG_NUMBER_RE;
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+static ANNOTATION_RE: Lazy<Regex> = Lazy::new(|| {
+    // Matches an annotation appended by `format_accumulated_instruction` at the end of a line
+    // Example matches: `\t;\t(8)\tmove.w dn,(an)\t[24]`, ` ; (4) [12]`, `\t;\t(10/8)\tbne.b`
+    // - `\s*;\s*`: The comment separator (tabs when written by this tool)
+    // - `\((?:...)\)`: The cycles: plain, `not-taken/taken`, the register list breakdown or a
+    //   range, each with an optional provenance marker such as `*ovr`
+    // - `(?:\s+(?P<lookup>n/a|[a-z]\S*(?: \S+)?))?`: The optional normalized lookup key
+    // - `(?:\s+\[(?P<offset>...)\])?`: The optional scanline offset or remaining cycles
+    Regex::new(
+        r"\s*;\s*\((?:\d+(?:/\d+)?(?:\*[a-z]+)?|\d+(?:\*[a-z]+)? -> \[base \(\d+\) \+ \(reg count \(\d+\) \* reg \(\d+\)\)\]|\d+(?:\*[a-z]+)? \[\d+\.\.\d+\])\)(?:\s+(?P<lookup>n/a|[a-z]\S*(?: \S+)?))?(?:\s+\[(?P<offset>\d+|left: \d+/\d+)\])?\s*$",
+    )
+    .unwrap()
+});
 
 /// Extracts the cycle count from a line of code. It first attempts to match a numeric value
 /// using REG_NUMBER_RE. If that fails, it applies the provided `should_skip` predicate. If the
@@ -11,54 +29,104 @@ use crate::cycle_spitter::regexes::REG_NUMBER_RE;
 ///
 /// # Arguments
 /// - `line`: The line to extract cycle information from.
+/// - `db`: The cycle database used for lookups.
 /// - `should_skip`: A predicate function that returns `true` if the line should be skipped.
 ///
 /// # Returns
 /// An `Option<CycleCount>` if a cycle count was extracted, or `None` if the line meets a skip condition.
-pub fn extract_cycle_count<F>(line: &str, should_skip: F) -> Option<CycleCount>
+pub fn extract_cycle_count<F>(line: &str, db: &CycleDatabase, should_skip: F) -> Option<CycleCount>
 where
     F: Fn(&str) -> bool,
 {
     if let Some(cap) = REG_NUMBER_RE.captures(line) {
-        Some(CycleCount::new(
-            vec![
-                cap.get(1)
-                    .map(|m| m.as_str().parse::<usize>().unwrap_or(0))
-                    .unwrap_or(0),
-            ],
-            String::from("n/a"),
-            0,
-        ))
+        Some(
+            CycleCount::new(
+                vec![
+                    cap.get(1)
+                        .map(|m| m.as_str().parse::<usize>().unwrap_or(0))
+                        .unwrap_or(0),
+                ],
+                String::from("n/a"),
+                0,
+            )
+            .with_source(CycleSource::Override),
+        )
     } else if should_skip(line) {
         None
     } else {
-        Some(lookup_cycles(line))
+        Some(lookup_cycles(line, db))
+    }
+}
+
+/// Removes annotations added by a previous run (see [`format_accumulated_instruction`]) from
+/// the end of `line`, so feeding generated output back in does not stack annotations.
+///
+/// A trailing `; (n)` comment is only considered an annotation if it carries a normalized
+/// lookup key or a `[offset]`. A bare `; (8)` is an inline cycle override and is kept, which
+/// also keeps a compact annotation at offset 0 - re-reading it as an override is harmless.
+pub(crate) fn strip_annotations(line: &str) -> &str {
+    let mut stripped = line;
+    while let Some(caps) = ANNOTATION_RE.captures(stripped) {
+        let is_annotation = caps.name("offset").is_some()
+            || caps
+                .name("lookup")
+                .is_some_and(|m| m.as_str() == "n/a" || is_canonical(m.as_str()));
+        if !is_annotation {
+            break;
+        }
+        stripped = &stripped[..caps.get(0).unwrap().start()];
+    }
+    stripped
+}
+
+/// Formats the cycles of an annotation: plain, `not-taken/taken` for branches or the register
+/// list breakdown.
+fn format_cycles(cycle_count: &CycleCount) -> String {
+    // Cycles that did not come straight from the database are marked, e.g. `20*ovr`
+    let marker = cycle_count
+        .source()
+        .marker()
+        .map_or(String::new(), |marker| format!("*{}", marker));
+    match cycle_count.kind() {
+        // Format as "not-taken/taken" for branches
+        CycleKind::Branch if cycle_count.get_cycles().len() > 1 => format!(
+            "{}/{}{}",
+            cycle_count.base(),
+            cycle_count.extra_if_taken(),
+            marker
+        ),
+        CycleKind::Reglist if cycle_count.get_cycles().len() > 1 => format!(
+            "{}{} -> [base ({}) + (reg count ({}) * reg ({}))]",
+            cycle_count.total(),
+            marker,
+            cycle_count.base(),
+            cycle_count.get_reg_count(),
+            cycle_count.cycles_per_reg()
+        ),
+        CycleKind::Range => format!(
+            "{}{} [{}..{}]",
+            cycle_count.total(),
+            marker,
+            cycle_count.best(),
+            cycle_count.worst()
+        ),
+        _ => format!("{}{}", cycle_count.base(), marker),
     }
 }
 
 /// Formats an instruction line for the accumulator module, including a given offset.
+/// With [`AnnotationFormat::Compact`] the normalized lookup key is omitted;
+/// [`AnnotationFormat::Remaining`] is formatted by [`format_remaining_instruction`], which
+/// needs the section and scanline ends, and falls back to the compact format here.
 pub fn format_accumulated_instruction(
     line: &str,
     cycle_count: &CycleCount,
     offset: usize,
+    format: AnnotationFormat,
 ) -> String {
-    // Pre-calculate capacity to avoid reallocations
-    let cycles_str = if cycle_count.get_cycles().len() > 1
-        && !cycle_count.get_lookup().contains("reglist")
-    {
-        format!("{}/{}", cycle_count.base(), cycle_count.extra_if_taken()) // Format as "not-taken/taken" for branches
-    } else if cycle_count.get_cycles().len() > 1 && cycle_count.get_lookup().contains("reglist") {
-        format!(
-            "{} -> [base ({}) + (reg count ({}) * reg ({}))]",
-            cycle_count.base() + (cycle_count.get_reg_count() * cycle_count.cycles_per_reg()),
-            cycle_count.base(),
-            cycle_count.get_reg_count(),
-            cycle_count.cycles_per_reg()
-        )
-    } else {
-        cycle_count.base().to_string()
-    };
+    let cycles_str = format_cycles(cycle_count);
 
+    // Pre-calculate capacity to avoid reallocations
     let mut result = String::with_capacity(
         line.len()
             + cycle_count.get_lookup().len()
@@ -70,8 +138,11 @@ pub fn format_accumulated_instruction(
     result.push_str(line);
     result.push_str("\t;\t(");
     result.push_str(&cycles_str);
-    result.push_str(")\t");
-    result.push_str(&cycle_count.get_lookup());
+    result.push(')');
+    if format == AnnotationFormat::Full {
+        result.push('\t');
+        result.push_str(&cycle_count.get_lookup());
+    }
     if offset > 0 {
         result.push_str("\t[");
         result.push_str(&offset.to_string());
@@ -79,3 +150,121 @@ pub fn format_accumulated_instruction(
     }
     result
 }
+
+/// Formats an instruction line with the cycles left in its section and scanline after it, e.g.
+/// `move.w d0,(a1)\t;\t(8)\t[left: 36/140]`.
+pub fn format_remaining_instruction(
+    line: &str,
+    cycle_count: &CycleCount,
+    section_left: usize,
+    scanline_left: usize,
+) -> String {
+    format!(
+        "{}\t;\t({})\t[left: {}/{}]",
+        line,
+        format_cycles(cycle_count),
+        section_left,
+        scanline_left
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cycle_spitter::database::CycleEntry;
+
+    #[test]
+    fn test_strip_annotations_roundtrip() {
+        let db = CycleDatabase::builtin();
+        for line in ["move.w d0,(a1)", "movem.l d0-d7/a1-a3,-(sp)", "bne.s loop"] {
+            let cycles = extract_cycle_count(line, &db, |_| false).unwrap();
+            for format in [AnnotationFormat::Full, AnnotationFormat::Compact] {
+                for offset in [0, 24] {
+                    if format == AnnotationFormat::Compact && offset == 0 {
+                        // Indistinguishable from an inline override, see `strip_annotations`.
+                        continue;
+                    }
+                    let annotated = format_accumulated_instruction(line, &cycles, offset, format);
+                    assert_eq!(strip_annotations(&annotated), line, "{:?}", annotated);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_only_the_first_comment_overrides_cycles() {
+        let db = CycleDatabase::builtin();
+        let cycles = |line| extract_cycle_count(line, &db, |_| false).unwrap();
+        assert_eq!(cycles("move.w d0,d1 ; (12) ; note").total(), 12);
+        assert_eq!(
+            cycles("move.w d0,d1 ; (12) ; note").source(),
+            CycleSource::Override
+        );
+        for line in ["move.w d0,d1 ; note ; (12)", "move.w d0,d1 ; note (12)"] {
+            assert_eq!(cycles(line).total(), 4, "{}", line);
+            assert_ne!(cycles(line).source(), CycleSource::Override, "{}", line);
+        }
+    }
+
+    #[test]
+    fn test_format_marks_provenance() {
+        let mut db = CycleDatabase::builtin();
+        db.insert(
+            "move.w (an),dn",
+            CycleEntry::from_values(CycleKind::Range, &[8, 12]),
+        );
+        db.adjust("move.w dn,dn", 2);
+        let annotate = |line: &str| {
+            let cycles = extract_cycle_count(line, &db, |_| false).unwrap();
+            format_accumulated_instruction(line, &cycles, 4, AnnotationFormat::Compact)
+        };
+        assert_eq!(annotate("nop ; (20)"), "nop ; (20)\t;\t(20*ovr)\t[4]");
+        assert_eq!(annotate("move.w d0,d1"), "move.w d0,d1\t;\t(6*adj)\t[4]");
+        assert_eq!(
+            annotate("move.w (a0),d1"),
+            "move.w (a0),d1\t;\t(12*max [8..12])\t[4]"
+        );
+        assert_eq!(annotate("move.w d0,(a1)"), "move.w d0,(a1)\t;\t(8)\t[4]");
+        for line in ["move.w d0,d1", "move.w (a0),d1"] {
+            assert_eq!(strip_annotations(&annotate(line)), line);
+        }
+    }
+
+    #[test]
+    fn test_format_remaining_instruction() {
+        let db = CycleDatabase::builtin();
+        let cycles = extract_cycle_count("move.w d0,(a1)", &db, |_| false).unwrap();
+        let annotated = format_remaining_instruction("move.w d0,(a1)", &cycles, 36, 140);
+        assert_eq!(annotated, "move.w d0,(a1)\t;\t(8)\t[left: 36/140]");
+        assert_eq!(strip_annotations(&annotated), "move.w d0,(a1)");
+    }
+
+    #[test]
+    fn test_strip_annotations_removes_stacked_and_respaced_annotations() {
+        assert_eq!(
+            strip_annotations("move.w d0,d1\t;\t(4)\tmove.w dn,dn\t[24]\t;\t(4)\tn/a\t[28]"),
+            "move.w d0,d1"
+        );
+        assert_eq!(
+            strip_annotations("move.w d0,(a1)  ; (8) move.w dn,(an) [24]"),
+            "move.w d0,(a1)"
+        );
+        assert_eq!(
+            strip_annotations("move.w d0,d1 ; (8)\t;\t(8)\tn/a\t[16]"),
+            "move.w d0,d1 ; (8)"
+        );
+    }
+
+    #[test]
+    fn test_strip_annotations_keeps_user_overrides_and_comments() {
+        assert_eq!(
+            strip_annotations("move.w d0,d1 ; (8)"),
+            "move.w d0,d1 ; (8)"
+        );
+        assert_eq!(
+            strip_annotations("move.l (a0)+,d0 ; (12) fast path"),
+            "move.l (a0)+,d0 ; (12) fast path"
+        );
+        assert_eq!(strip_annotations("move.w d0,d1"), "move.w d0,d1");
+    }
+}
@@ -0,0 +1,122 @@
+// src/cycle_spitter/includes.rs
+
+//! Scanline-granular include files.
+//!
+//! Large demo builds include the generated code rather than pasting it. [`split_includes`]
+//! cuts a document into one file per scanline (or group of scanlines), named after the first
+//! scanline it holds (`scanline_000.i`, `scanline_001.i`, ...), and a master include
+//! ([`MASTER_INCLUDE`]) with the header, the scanline count and an `include` directive per
+//! file. An edit of the input then only changes the files of the scanlines it affects, so
+//! build tools tracking file changes only reassemble those.
+
+use crate::cycle_spitter::emitter::{AsmEmitter, Document, Emitter};
+use crate::cycle_spitter::models::Scanline;
+use std::error::Error;
+
+/// The name of the master include.
+pub const MASTER_INCLUDE: &str = "scanlines.i";
+
+/// Returns the name of the include file starting with `scanline` (counting from 0).
+pub fn include_name(scanline: usize) -> String {
+    format!("scanline_{:03}.i", scanline)
+}
+
+/// Splits `document` into include files of `group` scanlines each, followed by the master
+/// include. Returns the file names and contents. The lines of `document` must be the rendered
+/// lines of `model`, one per instruction.
+///
+/// # Errors
+/// If `group` is zero or the lines of `document` do not match `model`.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::emitter::Document;
+/// use cycle_spitter::includes::split_includes;
+/// use cycle_spitter::models::{Chunk, Instruction, Scanline};
+///
+/// let scanline = |text: &str| Scanline {
+///     line: 0,
+///     cycles: 4,
+///     chunks: vec![Chunk {
+///         label: None,
+///         instructions: vec![Instruction::other(text, None)],
+///     }],
+/// };
+/// let document = Document {
+///     label: "LINES".to_string(),
+///     scanlines: 2,
+///     lines: vec!["; first".to_string(), "; second".to_string()],
+///     ..Default::default()
+/// };
+/// let files = split_includes(&document, &[scanline("; first"), scanline("; second")], 1).unwrap();
+/// assert_eq!(files[1], ("scanline_001.i".to_string(), "; second\n".to_string()));
+/// assert_eq!(files[2].0, "scanlines.i");
+/// assert!(files[2].1.ends_with("\tinclude\t\"scanline_000.i\"\n\tinclude\t\"scanline_001.i\"\n"));
+/// ```
+pub fn split_includes(
+    document: &Document,
+    model: &[Scanline],
+    group: usize,
+) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    if group == 0 {
+        return Err("An include file needs at least one scanline".into());
+    }
+    let counts: Vec<usize> = model.iter().map(|s| s.instructions().count()).collect();
+    if counts.iter().sum::<usize>() != document.lines.len() {
+        return Err("The generated lines do not match the generated scanlines".into());
+    }
+
+    let mut files = Vec::new();
+    let mut lines = document.lines.iter();
+    for (index, counts) in counts.chunks(group).enumerate() {
+        let mut content = String::new();
+        for line in lines.by_ref().take(counts.iter().sum()) {
+            content.push_str(line);
+            content.push('\n');
+        }
+        files.push((include_name(index * group), content));
+    }
+    let master = Document {
+        lines: files
+            .iter()
+            .map(|(name, _)| format!("\tinclude\t\"{}\"", name))
+            .collect(),
+        ..document.clone()
+    };
+    files.push((MASTER_INCLUDE.to_string(), AsmEmitter.emit(&master)));
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cycle_spitter::models::{Chunk, Instruction};
+
+    fn scanline(lines: &[&str]) -> Scanline {
+        Scanline {
+            line: 0,
+            cycles: 4,
+            chunks: vec![Chunk {
+                label: None,
+                instructions: lines.iter().map(|l| Instruction::other(*l, None)).collect(),
+            }],
+        }
+    }
+
+    #[test]
+    fn test_split_includes_groups_scanlines() {
+        let model = vec![scanline(&["a", "b"]), scanline(&["c"]), scanline(&["d"])];
+        let document = Document {
+            lines: ["a", "b", "c", "d"].iter().map(|l| l.to_string()).collect(),
+            ..Default::default()
+        };
+        let files = split_includes(&document, &model, 2).unwrap();
+        let names: Vec<&str> = files.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["scanline_000.i", "scanline_002.i", "scanlines.i"]);
+        assert_eq!(files[0].1, "a\nb\nc\n");
+        assert_eq!(files[1].1, "d\n");
+
+        assert!(split_includes(&document, &model[..2], 1).is_err());
+        assert!(split_includes(&document, &model, 0).is_err());
+    }
+}
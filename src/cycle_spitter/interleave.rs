@@ -0,0 +1,256 @@
+// src/cycle_spitter/interleave.rs
+
+//! Round-robin generation from several inputs, one scanline at a time.
+//!
+//! Interlaced and "flickerline" effects run different code on alternating scanlines. Instead of
+//! merging the code by hand, each effect is kept in its own input and the scanlines are taken
+//! from the inputs in turn: with two inputs, the 1st, 3rd, 5th... scanline comes from the first
+//! input and the 2nd, 4th, 6th... from the second. Every input keeps its own position and
+//! `;@use-template` selection; scanlines are numbered across all inputs, so frame layouts and
+//! the entry offset apply to the interleaved result.
+//!
+//! Once an input is exhausted, the remaining scanlines come from the other inputs.
+
+use crate::cycle_spitter::database::CycleDatabase;
+use crate::cycle_spitter::diagnostics::Diagnostic;
+use crate::cycle_spitter::generator::{ResumeState, ScanlineUsage, resume_with_observer};
+use crate::cycle_spitter::models::Scanline;
+use crate::cycle_spitter::observer::Observer;
+use crate::cycle_spitter::options::Options;
+use crate::cycle_spitter::template::TemplateSet;
+use crate::cycle_spitter::trace::PackingDecision;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::ops::ControlFlow;
+
+/// The result of interleaving several inputs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Interleaving {
+    /// The generated lines of all inputs, in scanline order.
+    pub lines: Vec<String>,
+    /// The total number of scanlines generated.
+    pub scanlines: usize,
+    /// `true` if an observer stopped generation before all inputs were consumed.
+    pub aborted: bool,
+    /// `true` if generation was stopped through the options' cancellation token.
+    pub cancelled: bool,
+    /// How the cycles of every generated scanline are spent, in scanline order.
+    pub usage: Vec<ScanlineUsage>,
+    /// The generated scanlines as data, in scanline order. Input line indices refer to the
+    /// input each scanline was generated from.
+    pub model: Vec<Scanline>,
+    /// What was consumed from each input, in the order the inputs were given.
+    pub streams: Vec<StreamConsumption>,
+}
+
+/// What an interleaved generation consumed from one input.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct StreamConsumption {
+    /// The number of input lines consumed.
+    pub lines: usize,
+    /// The number of scanlines generated from the input.
+    pub scanlines: usize,
+}
+
+/// Forwards all events and stops generation after every scanline.
+struct SingleScanline<'a> {
+    inner: &'a mut dyn Observer,
+    stopped: bool,
+}
+
+impl Observer for SingleScanline<'_> {
+    fn on_scanline_complete(&mut self, scanline: usize, cycles: usize) -> ControlFlow<()> {
+        self.stopped = self.inner.on_scanline_complete(scanline, cycles).is_break();
+        ControlFlow::Break(())
+    }
+
+    fn on_warning(&mut self, message: &str) {
+        self.inner.on_warning(message);
+    }
+
+    fn on_diagnostic(&mut self, diagnostic: &Diagnostic) {
+        self.inner.on_diagnostic(diagnostic);
+    }
+
+    fn on_padding_inserted(&mut self, scanline: usize, cycles: usize) {
+        self.inner.on_padding_inserted(scanline, cycles);
+    }
+
+    fn on_packing_decision(&mut self, decision: &PackingDecision) {
+        self.inner.on_packing_decision(decision);
+    }
+}
+
+/// Generates scanlines from `inputs` in turn (see the module documentation).
+///
+/// # Arguments
+/// - `inputs`: The flattened user code of each input (see `process_block`).
+/// - `templates`, `db`, `options`: As for `generate`.
+/// - `observer`: Receives the events of all inputs; scanline numbers count across all inputs.
+///
+/// # Returns
+/// An `Interleaving` with the generated lines and the consumption of every input.
+///
+/// # Errors
+/// The errors of `generate` for any of the inputs.
+pub fn generate_interleaved(
+    inputs: &[Vec<String>],
+    templates: &TemplateSet,
+    db: &CycleDatabase,
+    options: &Options,
+    observer: &mut dyn Observer,
+) -> Result<Interleaving, Box<dyn Error>> {
+    let mut states = vec![ResumeState::default(); inputs.len()];
+    let mut streams = vec![StreamConsumption::default(); inputs.len()];
+    let mut result = Interleaving {
+        lines: Vec::new(),
+        scanlines: 0,
+        aborted: false,
+        cancelled: false,
+        usage: Vec::new(),
+        model: Vec::new(),
+        streams: Vec::new(),
+    };
+    let mut exhausted = vec![false; inputs.len()];
+
+    'outer: while exhausted.iter().any(|done| !done) {
+        for (index, input) in inputs.iter().enumerate() {
+            if exhausted[index] {
+                continue;
+            }
+            if states[index].is_complete(input) {
+                exhausted[index] = true;
+                continue;
+            }
+            // Scanlines are numbered across all inputs.
+            let state = ResumeState {
+                scanlines: result.scanlines,
+                ..states[index].clone()
+            };
+            let mut single = SingleScanline {
+                inner: observer,
+                stopped: false,
+            };
+            let generation =
+                resume_with_observer(input, templates, db, options, &mut single, state)?;
+            result.lines.extend(generation.lines);
+            result.usage.extend(generation.usage);
+            result.model.extend(generation.model);
+            streams[index].scanlines += generation.scanlines - result.scanlines;
+            streams[index].lines = generation.state.next_index;
+            result.scanlines = generation.scanlines;
+            states[index] = generation.state;
+            if generation.cancelled {
+                result.cancelled = true;
+                break 'outer;
+            }
+            if single.stopped {
+                result.aborted = inputs
+                    .iter()
+                    .zip(&states)
+                    .any(|(input, state)| !state.is_complete(input));
+                break 'outer;
+            }
+        }
+    }
+
+    result.streams = streams;
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cycle_spitter::generator::generate;
+    use crate::cycle_spitter::observer::NullObserver;
+    use crate::cycle_spitter::template::parse_template;
+
+    fn lines(raw: &[&str]) -> Vec<String> {
+        raw.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_interleave_alternates_inputs() {
+        let db = CycleDatabase::builtin();
+        let templates =
+            TemplateSet::from(parse_template("move.w d2,d3 ; window\ndcb.w 2,$4e71", &db).unwrap());
+        let options = Options::builder().cycles_per_line(16).build();
+        let a = lines(&[
+            "move.w d0,d1",
+            "move.w d0,d1",
+            "move.w d0,d1",
+            "move.w d0,d1",
+        ]);
+        let b = lines(&["move.l d0,d1"; 5]);
+
+        let result = generate_interleaved(
+            &[a.clone(), b.clone()],
+            &templates,
+            &db,
+            &options,
+            &mut NullObserver,
+        )
+        .unwrap();
+
+        // Two moves fit into a scanline: 2 scanlines from a, 3 from b.
+        let user: Vec<&str> = result
+            .lines
+            .iter()
+            .filter(|l| l.contains("d0,d1"))
+            .map(|l| &l[..6])
+            .collect();
+        assert_eq!(
+            user,
+            [
+                "move.w", "move.w", "move.l", "move.l", "move.w", "move.w", "move.l", "move.l",
+                "move.l"
+            ]
+        );
+        assert_eq!(result.scanlines, 5);
+        assert_eq!(
+            result.streams,
+            [
+                StreamConsumption {
+                    lines: 4,
+                    scanlines: 2
+                },
+                StreamConsumption {
+                    lines: 5,
+                    scanlines: 3
+                }
+            ]
+        );
+        let single = generate(&a, &templates, &db, &options).unwrap();
+        assert_eq!(single.scanlines, 2);
+    }
+
+    #[test]
+    fn test_interleave_observer_can_abort() {
+        struct StopAfterTwo;
+        impl Observer for StopAfterTwo {
+            fn on_scanline_complete(&mut self, scanline: usize, _: usize) -> ControlFlow<()> {
+                if scanline == 1 {
+                    ControlFlow::Break(())
+                } else {
+                    ControlFlow::Continue(())
+                }
+            }
+        }
+        let db = CycleDatabase::builtin();
+        let templates =
+            TemplateSet::from(parse_template("move.w d2,d3 ; window\ndcb.w 2,$4e71", &db).unwrap());
+        let options = Options::builder().cycles_per_line(16).build();
+        let input = lines(&["move.w d0,d1"; 4]);
+
+        let result = generate_interleaved(
+            &[input.clone(), input],
+            &templates,
+            &db,
+            &options,
+            &mut StopAfterTwo,
+        )
+        .unwrap();
+        assert_eq!(result.scanlines, 2);
+        assert!(result.aborted);
+    }
+}
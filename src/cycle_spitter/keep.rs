@@ -0,0 +1,326 @@
+// src/cycle_spitter/keep.rs
+
+//! Hand-tuned regions preserved across regenerations.
+//!
+//! A region of a previously generated file enclosed in `;@keep-begin` / `;@keep-end` comments
+//! is copied verbatim into the regenerated output, replacing the scanlines at the same
+//! position. This allows tuning a few scanlines by hand while still regenerating the rest.
+//!
+//! Regions must enclose whole scanlines: they start right after a scanline boundary and
+//! contain one or more `; Total cycles for scanline:` comments. The input consumed by the
+//! replaced scanlines is unchanged, so the hand-tuned code is expected to be an equivalent
+//! rearrangement of what was generated. The cycles of every kept scanline are recounted and
+//! must still fit into the scanline budget.
+
+use crate::cycle_spitter::checksum::{CHECKSUM_PREFIX, TOTAL_CYCLES_PREFIX, code_part};
+use crate::cycle_spitter::database::CycleDatabase;
+use crate::cycle_spitter::helpers::extract_cycle_count;
+use crate::cycle_spitter::literal::{LITERAL_PATTERN, parse_literal};
+use crate::cycle_spitter::options::Options;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::error::Error;
+
+/// Marker comment opening a kept region.
+pub const KEEP_BEGIN: &str = ";@keep-begin";
+
+/// Marker comment closing a kept region.
+pub const KEEP_END: &str = ";@keep-end";
+
+static DCB_PADDING_RE: Lazy<Regex> = Lazy::new(|| {
+    // Matches a block of single-word instructions: `dcb.w <count>,$<opcode>`
+    // Example matches: `dcb.w 3,$4e71` (NOPs), `dcb.w 2, $4A57` (`tst.w (a7)`)
+    Regex::new(&format!(
+        r"(?i)dcb\.w\s*({})\s*,\s*\$([0-9a-f]{{1,4}})\b",
+        LITERAL_PATTERN
+    ))
+    .unwrap()
+});
+
+static LABEL_ONLY_RE: Lazy<Regex> = Lazy::new(|| {
+    // Matches a line holding nothing but a label, e.g. `.loop:`
+    Regex::new(r"^\.*[a-zA-Z_][a-zA-Z0-9_]*:$").unwrap()
+});
+
+/// A region of a previous output to preserve.
+#[derive(Debug, Clone)]
+pub struct KeptRegion {
+    /// The index of the first scanline replaced by this region.
+    pub first_scanline: usize,
+    /// The recounted cycles of each scanline in the region.
+    pub scanline_cycles: Vec<usize>,
+    /// The lines of the region including its markers, exactly as found in the file.
+    pub lines: Vec<String>,
+}
+
+impl KeptRegion {
+    /// The number of scanlines replaced by this region.
+    pub fn scanlines(&self) -> usize {
+        self.scanline_cycles.len()
+    }
+}
+
+/// Returns the cycles consumed by one line of generated output.
+fn line_cycles(line: &str, db: &CycleDatabase, options: &Options) -> usize {
+    let nop_cycles = options.nop_cycles();
+    let padding = options.padding_instruction();
+    let Some(code) = code_part(line) else {
+        return 0;
+    };
+    if let Some(caps) = DCB_PADDING_RE.captures(&code) {
+        let count = parse_literal(&caps[1]).unwrap_or(0);
+        match u16::from_str_radix(&caps[2], 16) {
+            Ok(0x4e71) => return count * nop_cycles,
+            Ok(opcode) if opcode == padding.opcode => return count * padding.cycles,
+            _ => {}
+        }
+    }
+    let instruction = code.rsplit(": ").next().unwrap_or(&code);
+    if instruction.eq_ignore_ascii_case("nop") {
+        return nop_cycles;
+    }
+    let mnemonic = padding.mnemonic.to_lowercase();
+    if instruction
+        .to_lowercase()
+        .split_whitespace()
+        .eq(mnemonic.split_whitespace())
+    {
+        return padding.cycles;
+    }
+    if LABEL_ONLY_RE.is_match(&code) || code.contains(" set ") {
+        return 0;
+    }
+    extract_cycle_count(line, db, |l| {
+        l.trim().starts_with(";") || l.contains(" equ ")
+    })
+    .map(|cycles| cycles.total())
+    .unwrap_or(0)
+}
+
+/// Extracts all kept regions from previously generated `content`.
+///
+/// # Errors
+/// - If markers are unbalanced or nested.
+/// - If a region does not start at a scanline boundary or does not end with a complete scanline.
+/// - If a kept scanline no longer fits into `options.cycles_per_line()`.
+pub fn parse_kept_regions(
+    content: &str,
+    db: &CycleDatabase,
+    options: &Options,
+) -> Result<Vec<KeptRegion>, Box<dyn Error>> {
+    let mut regions = Vec::new();
+    let mut current: Option<KeptRegion> = None;
+    let mut scanline = 0;
+    let mut cycles = 0;
+    let mut code_since_boundary = false;
+
+    for (index, line) in content.lines().enumerate() {
+        let line_number = index + 1;
+        let trimmed = line.trim();
+
+        if trimmed.starts_with(KEEP_BEGIN) {
+            if current.is_some() {
+                return Err(format!("line {}: nested {}", line_number, KEEP_BEGIN).into());
+            }
+            if code_since_boundary {
+                return Err(format!(
+                    "line {}: {} must be placed at a scanline boundary",
+                    line_number, KEEP_BEGIN
+                )
+                .into());
+            }
+            current = Some(KeptRegion {
+                first_scanline: scanline,
+                scanline_cycles: Vec::new(),
+                lines: vec![line.to_string()],
+            });
+            continue;
+        }
+
+        if trimmed.starts_with(KEEP_END) {
+            let Some(mut region) = current.take() else {
+                return Err(
+                    format!("line {}: {} without {}", line_number, KEEP_END, KEEP_BEGIN).into(),
+                );
+            };
+            if region.scanline_cycles.is_empty() || code_since_boundary {
+                return Err(format!(
+                    "line {}: kept region must end with a complete scanline",
+                    line_number
+                )
+                .into());
+            }
+            region.lines.push(line.to_string());
+            regions.push(region);
+            continue;
+        }
+
+        if let Some(region) = current.as_mut() {
+            region.lines.push(line.to_string());
+        }
+
+        if trimmed.starts_with(TOTAL_CYCLES_PREFIX) {
+            if let Some(region) = current.as_mut() {
+                if cycles > options.cycles_per_line() {
+                    return Err(format!(
+                        "line {}: kept scanline {} uses {} cycles, exceeding the budget of {}",
+                        line_number,
+                        scanline,
+                        cycles,
+                        options.cycles_per_line()
+                    )
+                    .into());
+                }
+                region.scanline_cycles.push(cycles);
+            }
+            scanline += 1;
+            cycles = 0;
+            code_since_boundary = false;
+        } else if !trimmed.starts_with(CHECKSUM_PREFIX) && code_part(line).is_some() {
+            cycles += line_cycles(line, db, options);
+            code_since_boundary = true;
+        }
+    }
+
+    if current.is_some() {
+        return Err(format!("{} without {}", KEEP_BEGIN, KEEP_END).into());
+    }
+    Ok(regions)
+}
+
+/// Replaces the scanlines covered by `regions` in the generated `lines`.
+///
+/// A scanline of `lines` ends with its `; Total cycles for scanline:` comment, followed by its
+/// checksum comment if present.
+///
+/// # Errors
+/// If a region refers to scanlines that are not present in `lines`.
+pub fn apply_kept_regions(
+    lines: Vec<String>,
+    regions: &[KeptRegion],
+) -> Result<Vec<String>, Box<dyn Error>> {
+    // Split the generated lines into scanlines.
+    let mut scanlines: Vec<Vec<String>> = vec![Vec::new()];
+    let mut iter = lines.into_iter().peekable();
+    while let Some(line) = iter.next() {
+        let is_end = line.trim().starts_with(TOTAL_CYCLES_PREFIX);
+        scanlines.last_mut().unwrap().push(line);
+        if is_end {
+            if let Some(next) = iter.next_if(|l| l.trim().starts_with(CHECKSUM_PREFIX)) {
+                scanlines.last_mut().unwrap().push(next);
+            }
+            scanlines.push(Vec::new());
+        }
+    }
+    // Anything after the last scanline (usually nothing) is kept at the end.
+    let trailer = scanlines.pop().unwrap_or_default();
+
+    for region in regions {
+        let end = region.first_scanline + region.scanlines();
+        if end > scanlines.len() {
+            return Err(format!(
+                "kept region for scanlines {}..{} does not exist in the regenerated output ({} scanlines)",
+                region.first_scanline,
+                end,
+                scanlines.len()
+            )
+            .into());
+        }
+    }
+
+    let mut result = Vec::new();
+    let mut index = 0;
+    while index < scanlines.len() {
+        if let Some(region) = regions.iter().find(|r| r.first_scanline == index) {
+            result.extend(region.lines.iter().cloned());
+            index += region.scanlines();
+        } else {
+            result.append(&mut scanlines[index]);
+            index += 1;
+        }
+    }
+    result.extend(trailer);
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options() -> Options {
+        Options::builder().cycles_per_line(16).build()
+    }
+
+    fn lines(raw: &str) -> Vec<String> {
+        raw.lines().map(|s| s.to_string()).collect()
+    }
+
+    const PREVIOUS: &str = "; header
+LINES\tequ 3
+\tmove.w\td0,d1\t;\t(4)
+\tdcb.w\t3,$4e71
+; Total cycles for scanline: 16
+;@keep-begin
+\tmove.w\t(a0),d1\t; hand tuned
+\tnop
+\tnop
+; Total cycles for scanline: 16
+;@keep-end
+\tmove.w\td2,d3
+\tdcb.w\t3,$4e71
+; Total cycles for scanline: 16
+";
+
+    #[test]
+    fn test_parse_kept_regions() {
+        let regions = parse_kept_regions(PREVIOUS, &CycleDatabase::builtin(), &options()).unwrap();
+        assert_eq!(regions.len(), 1);
+        assert_eq!(regions[0].first_scanline, 1);
+        assert_eq!(regions[0].scanline_cycles, vec![16]);
+        assert_eq!(regions[0].lines.len(), 6);
+    }
+
+    #[test]
+    fn test_apply_kept_regions_replaces_scanlines_verbatim() {
+        let regions = parse_kept_regions(PREVIOUS, &CycleDatabase::builtin(), &options()).unwrap();
+        let regenerated = lines(
+            "move.w d0,d1
+; Total cycles for scanline: 16
+move.w d4,d5
+; Total cycles for scanline: 16
+; Scanline checksum: 0123456789abcdef
+move.w d2,d3
+; Total cycles for scanline: 16",
+        );
+        let result = apply_kept_regions(regenerated, &regions).unwrap();
+        assert_eq!(result[2], ";@keep-begin");
+        assert_eq!(result[3], "\tmove.w\t(a0),d1\t; hand tuned");
+        assert_eq!(result[7], ";@keep-end");
+        assert_eq!(result[8], "move.w d2,d3");
+        assert!(!result.iter().any(|l| l.contains("d4,d5")));
+        assert!(!result.iter().any(|l| l.contains("checksum")));
+    }
+
+    #[test]
+    fn test_kept_scanline_exceeding_budget_is_rejected() {
+        let content = PREVIOUS.replace("\tnop\n\tnop\n", "\tnop\n\tnop\n\tnop\n");
+        let err = parse_kept_regions(&content, &CycleDatabase::builtin(), &options()).unwrap_err();
+        assert!(err.to_string().contains("uses 20 cycles"));
+    }
+
+    #[test]
+    fn test_unbalanced_markers_are_rejected() {
+        let db = CycleDatabase::builtin();
+        assert!(parse_kept_regions(";@keep-end\n", &db, &options()).is_err());
+        assert!(parse_kept_regions(";@keep-begin\n", &db, &options()).is_err());
+        let mid_scanline = "\tnop\n;@keep-begin\n; Total cycles for scanline: 4\n;@keep-end\n";
+        assert!(parse_kept_regions(mid_scanline, &db, &options()).is_err());
+    }
+
+    #[test]
+    fn test_region_beyond_regenerated_output_is_rejected() {
+        let regions = parse_kept_regions(PREVIOUS, &CycleDatabase::builtin(), &options()).unwrap();
+        let regenerated = lines("nop\n; Total cycles for scanline: 16");
+        assert!(apply_kept_regions(regenerated, &regions).is_err());
+    }
+}
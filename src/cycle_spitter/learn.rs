@@ -0,0 +1,175 @@
+// src/cycle_spitter/learn.rs
+
+//! Learning cycle overrides from hand-annotated sources.
+//!
+//! Projects that were timed by hand carry their corrections as `(n)` comments. Instead of
+//! repeating them in every new file, [`learn_overrides`] collects them from a reference file:
+//! every override that differs from the cycle database is recorded for the normalized form of
+//! its instruction, so it applies to all instructions of that shape. If the reference disagrees
+//! with itself about a normalized form, or the count depends on a register list, the overrides
+//! are recorded for the exact instruction text instead.
+
+use crate::cycle_spitter::cycles::normalize_line_ext;
+use crate::cycle_spitter::database::CycleDatabase;
+use crate::cycle_spitter::helpers::strip_annotations;
+use crate::cycle_spitter::regexes::REG_NUMBER_RE;
+use std::collections::BTreeMap;
+
+/// The overrides learned from a reference file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LearnedOverrides {
+    /// Cycle counts by normalized instruction.
+    pub normalized: BTreeMap<String, usize>,
+    /// Cycle counts by instruction text, for instructions whose normalized form is ambiguous.
+    pub lines: BTreeMap<String, usize>,
+    /// The number of overrides that matched the database and were left out.
+    pub redundant: usize,
+}
+
+impl LearnedOverrides {
+    /// Returns `true` if nothing was learned.
+    pub fn is_empty(&self) -> bool {
+        self.normalized.is_empty() && self.lines.is_empty()
+    }
+
+    /// Renders the overrides as TOML with a `[normalized]` and a `[lines]` table. `source` names
+    /// the reference file in the header comment.
+    pub fn to_toml(&self, source: &str) -> String {
+        let mut toml = format!("# Cycle overrides learned from {}\n", source);
+        for (table, entries) in [("normalized", &self.normalized), ("lines", &self.lines)] {
+            toml.push_str(&format!("\n[{}]\n", table));
+            for (key, cycles) in entries {
+                toml.push_str(&format!("{} = {}\n", toml_string(key), cycles));
+            }
+        }
+        toml
+    }
+}
+
+/// Quotes `value` as a TOML basic string.
+fn toml_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04X}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Collects the `(n)` overrides of `lines` that differ from `db` (see the module
+/// documentation). Annotations of generated output are ignored.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::database::CycleDatabase;
+/// use cycle_spitter::learn::learn_overrides;
+///
+/// let lines = vec![
+///     "move.w d7,$ffff820a.w ; (16)".to_string(),
+///     "move.w d0,d1 ; (4)".to_string(),
+/// ];
+/// let learned = learn_overrides(&lines, &CycleDatabase::builtin());
+/// assert_eq!(learned.normalized["move.w dn,xxx.w"], 16);
+/// assert_eq!(learned.redundant, 1);
+/// ```
+pub fn learn_overrides(lines: &[String], db: &CycleDatabase) -> LearnedOverrides {
+    // Every override by normalized form, with the instruction text it was found on
+    let mut found: BTreeMap<String, Vec<(String, usize)>> = BTreeMap::new();
+    let mut learned = LearnedOverrides::default();
+    for line in lines {
+        let line = strip_annotations(line);
+        let Some(caps) = REG_NUMBER_RE.captures(line) else {
+            continue;
+        };
+        let code = line.split(';').next().unwrap_or("").trim();
+        if code.is_empty() {
+            continue;
+        }
+        let cycles: usize = caps[1].parse().unwrap_or(0);
+        let (normalized, reg_count) = normalize_line_ext(code);
+        let known = db
+            .cycle_count(&normalized, reg_count)
+            .map(|cycles| cycles.total());
+        if known == Some(cycles) {
+            learned.redundant += 1;
+        } else if reg_count > 0 {
+            learned.lines.insert(code.to_string(), cycles);
+        } else {
+            found
+                .entry(normalized)
+                .or_default()
+                .push((code.to_string(), cycles));
+        }
+    }
+
+    for (normalized, overrides) in found {
+        if overrides
+            .iter()
+            .all(|(_, cycles)| *cycles == overrides[0].1)
+        {
+            learned.normalized.insert(normalized, overrides[0].1);
+        } else {
+            learned.lines.extend(overrides);
+        }
+    }
+    learned
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(raw: &[&str]) -> Vec<String> {
+        raw.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_learn_overrides() {
+        let db = CycleDatabase::builtin();
+        let learned = learn_overrides(
+            &lines(&[
+                "move.b d7,$ffff8260.w ; (16) shifter wait",
+                "move.b d6,$ffff8260.w ; (16)",
+                "add.w d0,(a0) ; (8)",
+                "add.w d1,(a1) ; (16)",
+                "movem.l d0-d3,-(sp) ; (44)",
+                "move.w d0,d1 ; (4)",
+                "move.w d0,d1\t;\t(4)\tmove.w dn,dn\t[8]",
+                "; (20) a comment",
+                "move.w d0,d2",
+            ]),
+            &db,
+        );
+        assert_eq!(
+            learned.normalized.into_iter().collect::<Vec<_>>(),
+            [("move.b dn,xxx.w".to_string(), 16)]
+        );
+        assert_eq!(
+            learned.lines.into_iter().collect::<Vec<_>>(),
+            [
+                ("add.w d0,(a0)".to_string(), 8),
+                ("add.w d1,(a1)".to_string(), 16),
+                ("movem.l d0-d3,-(sp)".to_string(), 44),
+            ]
+        );
+        assert_eq!(learned.redundant, 1);
+    }
+
+    #[test]
+    fn test_to_toml() {
+        let mut learned = LearnedOverrides::default();
+        learned.normalized.insert("move.b dn,xxx.w".to_string(), 16);
+        learned.lines.insert("move.w\td0,\"x\"".to_string(), 8);
+        assert_eq!(
+            learned.to_toml("ref.s"),
+            "# Cycle overrides learned from ref.s\n\n[normalized]\n\"move.b dn,xxx.w\" = 16\n\n[lines]\n\"move.w\\td0,\\\"x\\\"\" = 8\n"
+        );
+    }
+}
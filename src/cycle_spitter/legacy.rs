@@ -0,0 +1,182 @@
+// src/cycle_spitter/legacy.rs
+
+//! Importing the cycle comments of legacy sources as overrides.
+//!
+//! Sources timed by hand often note the cycles of an instruction in a comment of their own
+//! style. [`import_legacy_cycles`] recognizes the common ones and rewrites them into `(n)`
+//! overrides:
+//!
+//! | Style              | Example                        |
+//! |--------------------|--------------------------------|
+//! | Bare number        | `move.w d0,(a0) ;12`           |
+//! | Number with unit   | `move.w d0,(a0) ; 12 cycles`, `; 12c`, `; 12 cyc` |
+//! | Labelled number    | `move.w d0,(a0) ; cycles: 12`, `; cyc=12` |
+//!
+//! Only the first comment of an instruction line is considered, and lines that already carry an
+//! override are left alone. Where the comment disagrees with the cycle database, the comment
+//! wins - it is an override after all - but the disagreement is reported, as legacy comments
+//! are as often outdated as they are right.
+
+use crate::cycle_spitter::cycles::normalize_line_ext;
+use crate::cycle_spitter::database::CycleDatabase;
+use crate::cycle_spitter::regexes::REG_NUMBER_RE;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::fmt;
+
+static LEGACY_RE: Lazy<Regex> = Lazy::new(|| {
+    // Matches a legacy cycle comment at the start of a line's comment
+    // Example matches: `;12`, `; 12 cycles`, `; 12c fast path`, `; cycles: 12`, `;cyc=12`
+    // - `^;\s*`: The comment separator
+    // - `(?:cycles?|cyc)\s*[:=]\s*(?P<labelled>\d+)`: A labelled number
+    // - `(?P<number>\d+)(?:\s*(?:cycles?|cyc|c)\b|\s*$)`: A number followed by a unit or nothing
+    Regex::new(
+        r"(?i)^;\s*(?:(?:cycles?|cyc)\s*[:=]\s*(?P<labelled>\d+)\b|(?P<number>\d+)(?:\s*(?:cycles?|cyc|c)\b|\s*$))",
+    )
+    .unwrap()
+});
+
+/// A legacy cycle comment that disagrees with the cycle database.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Disagreement {
+    /// The 1-based line number.
+    pub line: usize,
+    /// The instruction, without its comment.
+    pub instruction: String,
+    /// The cycles given by the comment.
+    pub annotated: usize,
+    /// The cycles of the database.
+    pub database: usize,
+}
+
+impl fmt::Display for Disagreement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "'{}' is annotated with {} cycles, the database says {}",
+            self.instruction, self.annotated, self.database
+        )
+    }
+}
+
+/// The result of [`import_legacy_cycles`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LegacyImport {
+    /// The input with legacy comments rewritten into overrides.
+    pub lines: Vec<String>,
+    /// The number of comments rewritten.
+    pub imported: usize,
+    /// The rewritten comments that disagree with the database.
+    pub disagreements: Vec<Disagreement>,
+}
+
+/// Rewrites the legacy cycle comments of `lines` into `(n)` overrides (see the module
+/// documentation) and compares them with `db`.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::database::CycleDatabase;
+/// use cycle_spitter::legacy::import_legacy_cycles;
+///
+/// let lines = vec!["move.w d0,d1 ; 4 cycles".to_string(), "nop ;8".to_string()];
+/// let import = import_legacy_cycles(&lines, &CycleDatabase::builtin());
+/// assert_eq!(import.lines, ["move.w d0,d1 ; (4)", "nop ; (8)"]);
+/// assert_eq!(import.disagreements[0].database, 4);
+/// ```
+pub fn import_legacy_cycles(lines: &[String], db: &CycleDatabase) -> LegacyImport {
+    let mut import = LegacyImport::default();
+    for (index, line) in lines.iter().enumerate() {
+        let rewritten = line.split_once(';').and_then(|(code, comment)| {
+            let code = code.trim_end();
+            if code.trim().is_empty() || REG_NUMBER_RE.is_match(line) {
+                return None;
+            }
+            let comment = format!(";{}", comment);
+            let caps = LEGACY_RE.captures(&comment)?;
+            let cycles = caps
+                .name("labelled")
+                .or_else(|| caps.name("number"))?
+                .as_str();
+            let rest = comment[caps.get(0)?.end()..].trim();
+            Some((
+                code.to_string(),
+                cycles.parse::<usize>().ok()?,
+                rest.to_string(),
+            ))
+        });
+        let Some((code, cycles, rest)) = rewritten else {
+            import.lines.push(line.clone());
+            continue;
+        };
+
+        let (normalized, reg_count) = normalize_line_ext(&code);
+        if let Some(count) = db.cycle_count(&normalized, reg_count) {
+            let database = count.total();
+            if database != cycles {
+                import.disagreements.push(Disagreement {
+                    line: index + 1,
+                    instruction: code.trim().to_string(),
+                    annotated: cycles,
+                    database,
+                });
+            }
+        }
+        import.imported += 1;
+        import.lines.push(if rest.is_empty() {
+            format!("{} ; ({})", code, cycles)
+        } else {
+            format!("{} ; ({}) {}", code, cycles, rest)
+        });
+    }
+    import
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_import_legacy_cycles() {
+        let lines: Vec<String> = [
+            "move.w d0,(a0) ;12",
+            "move.w d0,(a1) ; 8 cycles fast path",
+            "move.w d0,(a2)\t; 8c",
+            "move.w d0,(a3) ; cycles: 8",
+            "move.w d0,(a4) ;CYC=8",
+            "move.w d0,(a5) ; (8) already overridden",
+            "move.w d0,(a6) ; 2 planes",
+            "move.w d0,(a7) ; 12 bytes",
+            "; 12 cycles for the whole loop",
+            "frobnicate d0 ; 6",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        let import = import_legacy_cycles(&lines, &CycleDatabase::builtin());
+        assert_eq!(
+            import.lines,
+            [
+                "move.w d0,(a0) ; (12)",
+                "move.w d0,(a1) ; (8) fast path",
+                "move.w d0,(a2) ; (8)",
+                "move.w d0,(a3) ; (8)",
+                "move.w d0,(a4) ; (8)",
+                "move.w d0,(a5) ; (8) already overridden",
+                "move.w d0,(a6) ; 2 planes",
+                "move.w d0,(a7) ; 12 bytes",
+                "; 12 cycles for the whole loop",
+                "frobnicate d0 ; (6)",
+            ]
+        );
+        assert_eq!(import.imported, 6);
+        assert_eq!(
+            import
+                .disagreements
+                .iter()
+                .map(|d| d.to_string())
+                .collect::<Vec<_>>(),
+            ["'move.w d0,(a0)' is annotated with 12 cycles, the database says 8"]
+        );
+        assert_eq!(import.disagreements[0].line, 1);
+    }
+}
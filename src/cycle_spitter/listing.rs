@@ -0,0 +1,147 @@
+// src/cycle_spitter/listing.rs
+
+//! The input annotated with where generation placed it.
+//!
+//! The generated file shows the scanlines and what ended up in them; reviewing why a line was
+//! deferred or split off means searching for it there. A [`listing`] is the inverted view: every
+//! line of the flattened input (`REPT` blocks expanded, so each repetition is listed) followed,
+//! indented, by the scanline and section it was placed in and the cycles it takes there. Lines
+//! the generator drops (empty lines, annotations of a previous run) have no placement.
+
+use crate::cycle_spitter::models::Scanline;
+use crate::cycle_spitter::space::TAIL_LABEL;
+use std::ops::Range;
+
+/// The name of the lines generated ahead of a scanline's first section, e.g. its pragmas.
+pub const LEAD_LABEL: &str = "lead";
+
+/// Where an input line was placed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Placement {
+    /// The generated scanline, counted from 0.
+    pub scanline: usize,
+    /// The scanline of the frame.
+    pub line: usize,
+    /// The label of the template section, or [`LEAD_LABEL`] / [`TAIL_LABEL`] for lines before
+    /// the first and after the last section.
+    pub section: String,
+    /// The scanline offsets of the cycles the line takes, `None` for lines taking none.
+    pub cycles: Option<Range<usize>>,
+}
+
+/// Collects the placements of the `len` lines of the flattened input generated into `model`.
+/// A line placed several times, as when the input is repeated to fill the frame, has several.
+pub fn placements(model: &[Scanline], len: usize) -> Vec<Vec<Placement>> {
+    let mut placements = vec![Vec::new(); len];
+    for (scanline, generated) in model.iter().enumerate() {
+        for (index, chunk) in generated.chunks.iter().enumerate() {
+            let section = match &chunk.label {
+                Some(label) => label.as_str(),
+                None if index == 0 => LEAD_LABEL,
+                None => TAIL_LABEL,
+            };
+            for instruction in &chunk.instructions {
+                let Some(source) = instruction.source.filter(|&source| source < len) else {
+                    continue;
+                };
+                placements[source].push(Placement {
+                    scanline,
+                    line: generated.line,
+                    section: section.to_string(),
+                    cycles: instruction
+                        .offset
+                        .map(|start| start..start + instruction.cycles),
+                });
+            }
+        }
+    }
+    placements
+}
+
+/// Renders the listing of the flattened `input` generated into `model`.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::listing::listing;
+/// use cycle_spitter::models::{Chunk, Instruction, LineKind, Scanline};
+///
+/// let input = vec!["\tmove.w d0,d1".to_string(), "; done".to_string()];
+/// let scanline = Scanline {
+///     line: 34,
+///     cycles: 8,
+///     chunks: vec![Chunk {
+///         label: Some("left".to_string()),
+///         instructions: vec![
+///             Instruction::timed("\tnop", LineKind::Template, 4, 0),
+///             Instruction {
+///                 source: Some(0),
+///                 ..Instruction::timed("\tmove.w d0,d1", LineKind::User, 4, 4)
+///             },
+///         ],
+///     }],
+/// };
+/// assert_eq!(
+///     listing(&input, &[scanline]),
+///     "\tmove.w d0,d1\n\t\tscanline 0 (line 34), left, cycles 4..8\n; done\n"
+/// );
+/// ```
+pub fn listing(input: &[String], model: &[Scanline]) -> String {
+    let mut listing = String::new();
+    for (line, placements) in input.iter().zip(placements(model, input.len())) {
+        listing.push_str(line.trim_end());
+        listing.push('\n');
+        for placement in placements {
+            listing.push_str(&format!(
+                "\t\tscanline {} (line {}), {}",
+                placement.scanline, placement.line, placement.section
+            ));
+            if let Some(cycles) = placement.cycles {
+                listing.push_str(&format!(", cycles {}..{}", cycles.start, cycles.end));
+            }
+            listing.push('\n');
+        }
+    }
+    listing
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cycle_spitter::database::CycleDatabase;
+    use crate::cycle_spitter::generator::generate;
+    use crate::cycle_spitter::options::Options;
+    use crate::cycle_spitter::template::{TemplateSet, parse_template};
+
+    #[test]
+    fn test_listing_follows_lines_into_their_sections() {
+        let db = CycleDatabase::builtin();
+        let templates = TemplateSet::from(
+            parse_template("nop ; left\ndcb.w 3,$4e71\nnop ; right\ndcb.w 3,$4e71", &db).unwrap(),
+        );
+        let options = Options::builder().cycles_per_line(32).build();
+        let input: Vec<String> = ["; effect", "move.l d0,d1", "lsl.w #1,d2", "move.w d2,d3"]
+            .iter()
+            .map(|l| l.to_string())
+            .collect();
+        let generation = generate(&input, &templates, &db, &options).unwrap();
+
+        let listed = placements(&generation.model, input.len());
+        let sections: Vec<Vec<(usize, &str)>> = listed
+            .iter()
+            .map(|p| p.iter().map(|p| (p.scanline, p.section.as_str())).collect())
+            .collect();
+        assert_eq!(
+            sections,
+            [
+                vec![(0, "left")],
+                vec![(0, "left")],
+                vec![(0, "right")],
+                vec![(1, "left")]
+            ]
+        );
+        assert_eq!(listed[1][0].cycles, Some(4..8));
+        assert!(listing(&input, &generation.model).starts_with(
+            "; effect\n\t\tscanline 0 (line 0), left\nmove.l d0,d1\n\t\tscanline 0 (line 0), left, cycles 4..8\n"
+        ));
+    }
+}
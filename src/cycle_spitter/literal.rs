@@ -0,0 +1,66 @@
+// src/cycle_spitter/literal.rs
+
+//! Numeric literals as the assemblers of the ST write them.
+//!
+//! A number is written in decimal (`16`), `$` hexadecimal (`$10`) or `%` binary (`%10000`).
+//! Counts (`rept`, `dcb`, `ds`) and absolute addresses in operands accept all three notations;
+//! they are all read with [`parse_literal`], and regexes matching them embed
+//! [`LITERAL_PATTERN`], so a notation accepted in one place is accepted everywhere.
+
+/// Regex pattern (without groups) matching a numeric literal in any notation.
+pub const LITERAL_PATTERN: &str = r"(?:\$[0-9a-fA-F]+|%[01]+|[0-9]+)";
+
+/// Parses a numeric literal in decimal, `$` hexadecimal or `%` binary notation. Anything else,
+/// e.g. a symbol or an expression, is `None`.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::literal::parse_literal;
+///
+/// assert_eq!(parse_literal("16"), Some(16));
+/// assert_eq!(parse_literal("$10"), Some(16));
+/// assert_eq!(parse_literal("%10000"), Some(16));
+/// assert_eq!(parse_literal("COUNT"), None);
+/// assert_eq!(parse_literal("4*4"), None);
+/// ```
+pub fn parse_literal(text: &str) -> Option<usize> {
+    let (digits, radix) = if let Some(hex) = text.strip_prefix('$') {
+        (hex, 16)
+    } else if let Some(binary) = text.strip_prefix('%') {
+        (binary, 2)
+    } else {
+        (text, 10)
+    };
+    // `from_str_radix` would accept a sign
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        return None;
+    }
+    usize::from_str_radix(digits, radix).ok()
+}
+
+/// Returns the count at the start of a directive argument such as `$10` in `rept $10`.
+pub fn leading_count(argument: &str) -> Option<usize> {
+    argument.split_whitespace().next().and_then(parse_literal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::Regex;
+
+    #[test]
+    fn test_pattern_and_parser_agree() {
+        let pattern = Regex::new(&format!("^{}$", LITERAL_PATTERN)).unwrap();
+        for text in [
+            "0", "512", "$ff", "$FF", "%101", "$", "%", "%12", "$g", "-4", "+4", "x",
+        ] {
+            assert_eq!(
+                pattern.is_match(text),
+                parse_literal(text).is_some(),
+                "{}",
+                text
+            );
+        }
+        assert_eq!(leading_count("%11 ; three"), Some(3));
+    }
+}
@@ -0,0 +1,177 @@
+// src/cycle_spitter/location.rs
+
+//! Where the lines of a flattened input came from.
+//!
+//! Generation works on flattened input: continuation lines are joined, statements split and
+//! `REPT` blocks expanded, so line 40 of the flattened input may be line 12 of the file.
+//! Diagnostics refer to flattened lines by index; a [`SourceMap`] built alongside the
+//! flattening (see the `_with_origins` functions of the `block` module) turns such an index
+//! into the file, line and column an editor can jump to. A [`Locator`] does so for every
+//! diagnostic on its way to an observer.
+
+use crate::cycle_spitter::diagnostics::Diagnostic;
+use crate::cycle_spitter::observer::Observer;
+use crate::cycle_spitter::trace::PackingDecision;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::ops::ControlFlow;
+
+/// A position in a source, counting lines and columns from 1.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceLocation {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
+
+/// The source position of every line of a flattened input.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::block::{ExpansionLimits, expand_block_with_origins};
+/// use cycle_spitter::location::SourceMap;
+///
+/// let content = "rept 2\n  move.w d0,d1\nendr";
+/// let lines: Vec<String> = content.lines().map(|l| l.trim().to_string()).collect();
+/// let (flat, origins) = expand_block_with_origins(&lines, &ExpansionLimits::default()).unwrap();
+/// let map = SourceMap::new("effect.s", content).remap(&origins);
+/// assert_eq!(flat.len(), 2);
+/// assert_eq!(map.locate(1).unwrap().to_string(), "effect.s:2:3");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    file: String,
+    /// The 0-based line and column of every flattened line.
+    entries: Vec<(usize, usize)>,
+}
+
+impl SourceMap {
+    /// Maps every line of `content`, as read from `file`, to itself. The column is that of the
+    /// first character after the indentation, as the lines are trimmed when read.
+    pub fn new(file: impl Into<String>, content: &str) -> Self {
+        SourceMap {
+            file: file.into(),
+            entries: content
+                .lines()
+                .enumerate()
+                .map(|(line, text)| (line, text.len() - text.trim_start().len()))
+                .collect(),
+        }
+    }
+
+    /// Returns the map of lines derived from the mapped ones, where `origins` holds the index of
+    /// the mapped line every derived line came from.
+    pub fn remap(&self, origins: &[usize]) -> Self {
+        SourceMap {
+            file: self.file.clone(),
+            entries: origins
+                .iter()
+                .map(|&origin| self.entries.get(origin).copied().unwrap_or_default())
+                .collect(),
+        }
+    }
+
+    /// Same as [`SourceMap::remap`] for lines starting within the mapped ones: `origins` holds
+    /// the index of the mapped line and the byte offset in it at which every derived line starts.
+    pub fn remap_columns(&self, origins: &[(usize, usize)]) -> Self {
+        SourceMap {
+            file: self.file.clone(),
+            entries: origins
+                .iter()
+                .map(|&(origin, offset)| {
+                    let (line, column) = self.entries.get(origin).copied().unwrap_or_default();
+                    (line, column + offset)
+                })
+                .collect(),
+        }
+    }
+
+    /// Returns the source position of the flattened line `index`.
+    pub fn locate(&self, index: usize) -> Option<SourceLocation> {
+        self.entries
+            .get(index)
+            .map(|&(line, column)| SourceLocation {
+                file: self.file.clone(),
+                line: line + 1,
+                column: column + 1,
+            })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// Forwards all events to `inner`, locating diagnostics that refer to an input line with `map`.
+pub struct Locator<'a> {
+    pub map: &'a SourceMap,
+    pub inner: &'a mut dyn Observer,
+}
+
+impl Observer for Locator<'_> {
+    fn on_scanline_complete(&mut self, scanline: usize, cycles: usize) -> ControlFlow<()> {
+        self.inner.on_scanline_complete(scanline, cycles)
+    }
+
+    fn on_warning(&mut self, message: &str) {
+        self.inner.on_warning(message);
+    }
+
+    fn on_diagnostic(&mut self, diagnostic: &Diagnostic) {
+        let mut diagnostic = diagnostic.clone();
+        if diagnostic.location.is_none() {
+            diagnostic.location = diagnostic.index.and_then(|index| self.map.locate(index));
+        }
+        self.inner.on_diagnostic(&diagnostic);
+    }
+
+    fn on_padding_inserted(&mut self, scanline: usize, cycles: usize) {
+        self.inner.on_padding_inserted(scanline, cycles);
+    }
+
+    fn on_packing_decision(&mut self, decision: &PackingDecision) {
+        let mut decision = decision.clone();
+        if decision.location.is_none() {
+            decision.location = decision.index.and_then(|index| self.map.locate(index));
+        }
+        self.inner.on_packing_decision(&decision);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cycle_spitter::block::{
+        join_continuations_with_origins, split_statements_with_origins,
+    };
+    use crate::cycle_spitter::database::CycleDatabase;
+
+    #[test]
+    fn test_map_follows_joined_and_split_lines() {
+        let content = "\tmovem.l d0-d3,\n\t\ta0-a1,-(sp)\n\tmove.w d0,(a0)+ ; move.w d1,(a0)+";
+        let lines: Vec<String> = content.lines().map(|l| l.trim().to_string()).collect();
+        let map = SourceMap::new("effect.s", content);
+
+        let (joined, origins) = join_continuations_with_origins(&lines);
+        let map = map.remap(&origins);
+        assert_eq!(joined.len(), 2);
+        let (split, origins) = split_statements_with_origins(&joined, &CycleDatabase::builtin());
+        let map = map.remap_columns(&origins);
+
+        assert_eq!(split.len(), 3);
+        assert_eq!(map.locate(0).unwrap().to_string(), "effect.s:1:2");
+        assert_eq!(map.locate(1).unwrap().to_string(), "effect.s:3:2");
+        assert_eq!(map.locate(2).unwrap().to_string(), "effect.s:3:20");
+        assert_eq!(map.locate(3), None);
+    }
+}
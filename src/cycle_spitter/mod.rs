@@ -1,9 +1,71 @@
 // src/cycle_spitter/mod.rs
 pub mod accumulator;
+pub mod adjust;
+pub mod annotate;
+pub mod baseline;
 pub mod block;
+pub mod cache;
+pub mod cancellation;
+pub mod capacity;
+pub mod checksum;
+pub mod corpus;
+pub mod cycles;
+pub mod data;
+pub mod database;
+pub mod diagnostics;
+pub mod emitter;
+pub mod error;
+pub mod exception;
+pub mod ffi;
+mod filler;
+pub mod find;
+pub mod fixups;
+pub mod foreign;
+pub mod frame;
+pub mod generator;
+pub mod graph;
+pub mod hardware;
+pub mod hatari;
+pub mod includes;
+pub mod interleave;
+pub mod keep;
+pub mod learn;
+pub mod legacy;
+pub mod listing;
+pub mod literal;
+pub mod location;
+pub mod models;
+pub mod observer;
+pub mod options;
+pub mod output;
+pub mod packer;
+pub mod padding;
+pub mod pragma;
 pub mod regexes;
+pub mod runner;
+pub mod seam;
+pub mod selftest;
+pub mod source;
+pub mod space;
+mod split;
+pub mod style;
+pub mod symbols;
+pub mod synthesis;
 pub mod template;
+pub mod timeline;
+pub mod trace;
+pub mod video;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+pub mod whatif;
 
-mod cycles;
 mod helpers;
-mod models;
+
+pub use accumulator::accumulate_chunk;
+pub use block::process_block;
+pub use cycles::{NORMALIZATION_VERSION, is_canonical, normalize_line_ext};
+pub use diagnostics::{Diagnostic, DiagnosticKind, Diagnostics};
+pub use error::CycleSpitterError;
+pub use runner::{CycleSpitter, CycleSpitterBuilder};
+pub use split::split;
+pub use template::parse_template;
@@ -1,19 +1,60 @@
-#[derive(Debug, Clone)]
+// src/cycle_spitter/models.rs
+
+//! Data carried through the pipeline.
+//!
+//! Besides the [`CycleCount`] of a lookup, generation produces a structured result: every
+//! [`Scanline`] is made of [`Chunk`]s - one per template section, plus the lines around them -
+//! holding the generated [`Instruction`]s with their cycles, scanline offsets and the input
+//! lines they came from. The assembler text is one rendering of it ([`render`]); consumers can
+//! post-process the data instead of parsing the text. Like the other results of the crate
+//! (the `Generation`, its usage and resume state, timelines and diagnostics), the models
+//! implement serde's `Serialize` and `Deserialize`.
+
+use crate::cycle_spitter::database::{CycleKind, CycleSource};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct CycleCount {
     cycles: Vec<usize>,
     lookup: String,
     reg_count: usize,
+    kind: CycleKind,
+    source: CycleSource,
 }
 
 impl CycleCount {
+    /// Creates a cycle count whose kind is inferred from the lookup key and the number of values.
     pub fn new(cycles: Vec<usize>, lookup: String, reg_count: usize) -> Self {
+        let kind = CycleKind::infer(&lookup, &cycles);
         CycleCount {
             cycles,
             lookup,
             reg_count,
+            kind,
+            source: CycleSource::Database,
         }
     }
 
+    /// Sets the kind, e.g. as stated by the database.
+    pub fn with_kind(mut self, kind: CycleKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    pub fn kind(&self) -> CycleKind {
+        self.kind
+    }
+
+    /// Sets where the cycles came from.
+    pub fn with_source(mut self, source: CycleSource) -> Self {
+        self.source = source;
+        self
+    }
+
+    pub fn source(&self) -> CycleSource {
+        self.source
+    }
+
     pub fn base(&self) -> usize {
         self.cycles.first().cloned().unwrap_or(0)
     }
@@ -34,6 +75,26 @@ impl CycleCount {
         self.cycles.clone()
     }
 
+    /// The best case of a range.
+    pub fn best(&self) -> usize {
+        self.cycles.iter().copied().min().unwrap_or(0)
+    }
+
+    /// The worst case of a range.
+    pub fn worst(&self) -> usize {
+        self.cycles.iter().copied().max().unwrap_or(0)
+    }
+
+    /// The cycles used for scanline accounting: the base value, plus the per-register cost
+    /// for register list instructions. Branches count as not taken, ranges as their worst case.
+    pub fn total(&self) -> usize {
+        match self.kind {
+            CycleKind::Plain | CycleKind::Branch => self.base(),
+            CycleKind::Reglist => self.base() + self.cycles_per_reg() * self.reg_count,
+            CycleKind::Range => self.worst(),
+        }
+    }
+
     pub fn extra_if_taken(&self) -> usize {
         self.cycles.get(1).cloned().unwrap_or(0)
     }
@@ -46,3 +107,101 @@ impl CycleCount {
         }
     }
 }
+
+/// What a generated line holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LineKind {
+    /// Injection code of the template.
+    Template,
+    /// An instruction of the input.
+    User,
+    /// Padding filling a section or the scanline.
+    Padding,
+    /// A jump generated to chain output chunks or to close a loop.
+    Jump,
+    /// A line taking no cycles: comments, labels, pragmas, data and assignments.
+    Other,
+}
+
+/// One generated line.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Instruction {
+    /// The generated text, including its annotation.
+    pub text: String,
+    pub kind: LineKind,
+    /// The cycles the line takes in the scanline.
+    pub cycles: usize,
+    /// The scanline offset at which the line starts, for lines taking cycles.
+    pub offset: Option<usize>,
+    /// The index of the input line it was generated from, counting from 0 in the flattened
+    /// input (after `REPT` expansion).
+    pub source: Option<usize>,
+}
+
+impl Instruction {
+    /// A line taking no cycles, e.g. a comment.
+    pub fn other(text: impl Into<String>, source: Option<usize>) -> Self {
+        Instruction {
+            text: text.into(),
+            kind: LineKind::Other,
+            cycles: 0,
+            offset: None,
+            source,
+        }
+    }
+
+    /// A line taking `cycles` at the scanline `offset`.
+    pub fn timed(text: impl Into<String>, kind: LineKind, cycles: usize, offset: usize) -> Self {
+        Instruction {
+            text: text.into(),
+            kind,
+            cycles,
+            offset: Some(offset),
+            source: None,
+        }
+    }
+}
+
+/// A run of consecutive lines of a scanline: a template section or the lines around the
+/// sections.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Chunk {
+    /// The label of the template section, `None` for the lines before the first and after the
+    /// last section (e.g. pragmas, tail padding and the cycle total).
+    pub label: Option<String>,
+    pub instructions: Vec<Instruction>,
+}
+
+impl Chunk {
+    /// The cycles of the chunk's lines.
+    pub fn cycles(&self) -> usize {
+        self.instructions.iter().map(|i| i.cycles).sum()
+    }
+}
+
+/// One generated scanline.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Scanline {
+    /// The scanline of the frame (counting from the configured start line).
+    pub line: usize,
+    /// The total cycles of the scanline.
+    pub cycles: usize,
+    pub chunks: Vec<Chunk>,
+}
+
+impl Scanline {
+    /// Returns the generated lines of the scanline in order.
+    pub fn instructions(&self) -> impl Iterator<Item = &Instruction> {
+        self.chunks.iter().flat_map(|chunk| &chunk.instructions)
+    }
+}
+
+/// Renders `scanlines` as the text of the generated assembler source.
+pub fn render(scanlines: &[Scanline]) -> Vec<String> {
+    scanlines
+        .iter()
+        .flat_map(Scanline::instructions)
+        .map(|instruction| instruction.text.clone())
+        .collect()
+}
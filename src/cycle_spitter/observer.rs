@@ -0,0 +1,77 @@
+// src/cycle_spitter/observer.rs
+
+use crate::cycle_spitter::diagnostics::Diagnostic;
+use crate::cycle_spitter::trace::PackingDecision;
+use std::ops::ControlFlow;
+
+/// Receives events while scanlines are being generated.
+///
+/// Implement this trait to drive progress bars, collect metrics or abort generation early.
+/// All methods have empty default implementations, so only the events of interest need to be
+/// handled.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::observer::Observer;
+/// use std::ops::ControlFlow;
+///
+/// /// Stops generation after a fixed number of scanlines.
+/// struct Limit(usize);
+///
+/// impl Observer for Limit {
+///     fn on_scanline_complete(&mut self, scanline: usize, _cycles: usize) -> ControlFlow<()> {
+///         if scanline + 1 >= self.0 {
+///             ControlFlow::Break(())
+///         } else {
+///             ControlFlow::Continue(())
+///         }
+///     }
+/// }
+/// ```
+pub trait Observer {
+    /// Called after scanline `scanline` (0-based) has been generated with a total of `cycles`.
+    /// Returning `ControlFlow::Break` stops generation after this scanline.
+    fn on_scanline_complete(&mut self, _scanline: usize, _cycles: usize) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// Called for every non-fatal problem, e.g. a scanline overflow.
+    fn on_warning(&mut self, _message: &str) {}
+
+    /// Called for every non-fatal problem along with its kind. Passes the message on to
+    /// [`Observer::on_warning`] unless implemented.
+    fn on_diagnostic(&mut self, diagnostic: &Diagnostic) {
+        self.on_warning(&diagnostic.message);
+    }
+
+    /// Called whenever `cycles` of NOP padding are inserted into scanline `scanline`.
+    fn on_padding_inserted(&mut self, _scanline: usize, _cycles: usize) {}
+
+    /// Called for every line accepted into or deferred from a section's code window, and for
+    /// the padding of what is left of it (see the `trace` module).
+    fn on_packing_decision(&mut self, _decision: &PackingDecision) {}
+}
+
+/// An observer that ignores all events.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NullObserver;
+
+impl Observer for NullObserver {}
+
+/// An observer that prints warnings to standard error, as the command line tool does.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StderrObserver;
+
+impl Observer for StderrObserver {
+    fn on_warning(&mut self, message: &str) {
+        eprintln!("Warning: {}", message);
+    }
+
+    /// Located diagnostics start with their source position, so editors can jump to it.
+    fn on_diagnostic(&mut self, diagnostic: &Diagnostic) {
+        match &diagnostic.location {
+            Some(location) => eprintln!("{}: Warning: {}", location, diagnostic.message),
+            None => self.on_warning(&diagnostic.message),
+        }
+    }
+}
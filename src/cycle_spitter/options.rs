@@ -0,0 +1,659 @@
+// src/cycle_spitter/options.rs
+
+use crate::cycle_spitter::cancellation::CancellationToken;
+use crate::cycle_spitter::frame::FrameLayout;
+use crate::cycle_spitter::packer::{
+    FirstFitScheduler, OptimalScheduler, Scheduler, StrictOrderScheduler,
+};
+use crate::cycle_spitter::seam::{ChunkChain, LoopSeam};
+use crate::cycle_spitter::video::VideoBaseUpdate;
+use std::error::Error;
+
+/// What to do when the template and user code of a scanline exceed the cycle budget.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+    /// Report the overflow as a warning and continue.
+    #[default]
+    Warn,
+    /// Abort generation with an error.
+    Error,
+    /// Continue silently.
+    Ignore,
+}
+
+/// How padding inside a template section is emitted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PaddingStyle {
+    /// One annotated `nop` per padding slot.
+    #[default]
+    Nop,
+    /// A single `dcb.w <count>,$4e71` per padding run.
+    Dcb,
+    /// A reproducible mix of instructions without side effects, chosen by a generator seeded
+    /// with `seed` (see the `filler` module). Avoids the bus pattern of long NOP runs.
+    Filler { seed: u64 },
+}
+
+/// The single-word instruction repeated to pad spare cycles.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PaddingInstruction {
+    /// The instruction as written to the output, e.g. `nop` or `tst.w (a7)`.
+    pub mnemonic: String,
+    /// The encoding of the instruction, written by `dcb.w` padding blocks.
+    pub opcode: u16,
+    /// The cycles of one instruction.
+    pub cycles: usize,
+}
+
+impl PaddingInstruction {
+    /// A `nop` of `cycles` cycles.
+    pub fn nop(cycles: usize) -> Self {
+        PaddingInstruction {
+            mnemonic: "nop".to_string(),
+            opcode: 0x4e71,
+            cycles,
+        }
+    }
+
+    /// Parses `<instruction>:<opcode>:<cycles>`, with the opcode in `$` hexadecimal, e.g.
+    /// `tst.w (a7):$4a57:8`.
+    ///
+    /// # Errors
+    /// If a part is missing or invalid, or the cycles are 0.
+    ///
+    /// # Example
+    /// ```rust
+    /// use cycle_spitter::options::PaddingInstruction;
+    ///
+    /// let padding = PaddingInstruction::parse("tst.w (a7):$4a57:8").unwrap();
+    /// assert_eq!(padding.block(3), "dcb.w\t3,$4a57");
+    /// assert!(PaddingInstruction::parse("tst.w (a7):8").is_err());
+    /// ```
+    pub fn parse(spec: &str) -> Result<Self, Box<dyn Error>> {
+        let invalid = || {
+            format!(
+                "Invalid padding instruction '{}', expected <instruction>:<opcode>:<cycles> \
+                 such as 'tst.w (a7):$4a57:8'",
+                spec
+            )
+        };
+        let mut parts = spec.rsplitn(3, ':');
+        let (Some(cycles), Some(opcode), Some(mnemonic)) =
+            (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid().into());
+        };
+        let opcode = opcode
+            .trim()
+            .strip_prefix('$')
+            .and_then(|hex| u16::from_str_radix(hex, 16).ok())
+            .ok_or_else(invalid)?;
+        let cycles = cycles
+            .trim()
+            .parse::<usize>()
+            .ok()
+            .filter(|&cycles| cycles > 0)
+            .ok_or_else(invalid)?;
+        if mnemonic.trim().is_empty() {
+            return Err(invalid().into());
+        }
+        Ok(PaddingInstruction {
+            mnemonic: mnemonic.trim().to_string(),
+            opcode,
+            cycles,
+        })
+    }
+
+    /// Returns the directive emitting `count` instructions in one block.
+    pub fn block(&self, count: usize) -> String {
+        format!("dcb.w\t{},${:04x}", count, self.opcode)
+    }
+}
+
+/// How user instructions are annotated in the generated output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AnnotationFormat {
+    /// Cycles, normalized lookup key and scanline offset, e.g. `; (8) move.w dn,(an) [24]`.
+    #[default]
+    Full,
+    /// Cycles and scanline offset only, e.g. `; (8) [24]`.
+    Compact,
+    /// Cycles and the cycles left in the section and the scanline after the instruction, e.g.
+    /// `; (8) [left: 36/140]`.
+    Remaining,
+}
+
+/// How much the generated output comments on itself beyond the instruction annotations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Verbosity {
+    /// The total cycles of every scanline.
+    #[default]
+    Normal,
+    /// In addition, the utilization of every scanline, e.g.
+    /// `; utilization: 87% (448/512), padding 64`.
+    Verbose,
+}
+
+/// The target machine the timings are generated for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CpuProfile {
+    /// 8 MHz 68000 in an Atari ST at 50 Hz (512 cycles per scanline).
+    #[default]
+    AtariSt,
+}
+
+impl CpuProfile {
+    /// The number of cycles in one scanline on this machine.
+    pub fn cycles_per_line(&self) -> usize {
+        match self {
+            CpuProfile::AtariSt => 512,
+        }
+    }
+
+    /// The number of cycles consumed by one `nop`.
+    pub fn nop_cycles(&self) -> usize {
+        match self {
+            CpuProfile::AtariSt => 4,
+        }
+    }
+}
+
+/// How unknown instructions are treated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Strictness {
+    /// Warn and count unknown instructions as 0 cycles.
+    #[default]
+    Lenient,
+    /// Refuse to generate output if any instruction is missing from the cycle database.
+    Strict,
+}
+
+/// How user code is packed into the free cycles of a section: which [`Scheduler`] makes the
+/// placement decisions (see the `packer` module).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Packer {
+    /// Take instructions in source order until the next one does not fit.
+    #[default]
+    Greedy,
+    /// Like `Greedy`, but fill the gap left before an instruction that does not fit with the
+    /// first independent instructions of a bounded lookahead window that fit.
+    FirstFit,
+    /// Like `Greedy`, but fill the gap left before an instruction that does not fit with the
+    /// combination of independent instructions of a bounded lookahead window that fills it
+    /// most tightly.
+    Optimal,
+}
+
+impl Packer {
+    /// The scheduler making the placement decisions of the packer.
+    pub fn scheduler(self) -> &'static dyn Scheduler {
+        match self {
+            Packer::Greedy => &StrictOrderScheduler,
+            Packer::FirstFit => &FirstFitScheduler,
+            Packer::Optimal => &OptimalScheduler,
+        }
+    }
+}
+
+/// What happens when the input runs out before the frame ends.
+///
+/// The frame ends after `max_scanlines` scanlines, or else after the last scanline of a frame
+/// layout whose last region is closed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Tail {
+    /// End with the scanline that consumes the last line of the input.
+    #[default]
+    Stop,
+    /// Fill the remaining scanlines of the frame with their template and padding.
+    Pad,
+    /// Continue with the input from the beginning until the frame ends, for effects that loop.
+    Repeat,
+}
+
+/// Options controlling scanline generation.
+///
+/// Options are created through [`Options::builder`], so new settings can be added without
+/// breaking existing callers:
+///
+/// ```rust
+/// use cycle_spitter::options::{Options, OverflowPolicy};
+///
+/// let options = Options::builder()
+///     .cycles_per_line(508)
+///     .overflow_policy(OverflowPolicy::Error)
+///     .build();
+/// assert_eq!(options.cycles_per_line(), 508);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Options {
+    cycles_per_line: usize,
+    overflow_policy: OverflowPolicy,
+    padding_style: PaddingStyle,
+    annotation_format: AnnotationFormat,
+    verbosity: Verbosity,
+    cpu_profile: CpuProfile,
+    nop_cycles: Option<usize>,
+    padding_instruction: Option<PaddingInstruction>,
+    strictness: Strictness,
+    checksums: bool,
+    warning_comments: bool,
+    cancellation: Option<CancellationToken>,
+    packer: Packer,
+    packer_window: usize,
+    margin: usize,
+    frame_layout: Option<FrameLayout>,
+    start_line: usize,
+    entry_offset: usize,
+    exit_offset: usize,
+    separator: Option<char>,
+    video_base: Option<VideoBaseUpdate>,
+    max_scanlines: Option<usize>,
+    tail: Tail,
+    loop_seam: Option<LoopSeam>,
+    chunk_chain: Option<ChunkChain>,
+}
+
+impl Options {
+    /// Returns a builder initialized with the default options.
+    pub fn builder() -> OptionsBuilder {
+        OptionsBuilder::default()
+    }
+
+    pub fn cycles_per_line(&self) -> usize {
+        self.cycles_per_line
+    }
+
+    pub fn overflow_policy(&self) -> OverflowPolicy {
+        self.overflow_policy
+    }
+
+    pub fn padding_style(&self) -> PaddingStyle {
+        self.padding_style
+    }
+
+    /// The cycles of one padding NOP: the override set with `OptionsBuilder::nop_cycles`, or
+    /// the CPU profile's.
+    pub fn nop_cycles(&self) -> usize {
+        self.nop_cycles
+            .unwrap_or_else(|| self.cpu_profile.nop_cycles())
+    }
+
+    /// The instruction padding spare cycles: the one set with
+    /// `OptionsBuilder::padding_instruction`, or a `nop` of [`Options::nop_cycles`].
+    pub fn padding_instruction(&self) -> PaddingInstruction {
+        self.padding_instruction
+            .clone()
+            .unwrap_or_else(|| PaddingInstruction::nop(self.nop_cycles()))
+    }
+
+    pub fn annotation_format(&self) -> AnnotationFormat {
+        self.annotation_format
+    }
+
+    pub fn verbosity(&self) -> Verbosity {
+        self.verbosity
+    }
+
+    pub fn cpu_profile(&self) -> CpuProfile {
+        self.cpu_profile
+    }
+
+    pub fn strictness(&self) -> Strictness {
+        self.strictness
+    }
+
+    pub fn checksums(&self) -> bool {
+        self.checksums
+    }
+
+    pub fn warning_comments(&self) -> bool {
+        self.warning_comments
+    }
+
+    pub fn cancellation(&self) -> Option<&CancellationToken> {
+        self.cancellation.as_ref()
+    }
+
+    pub fn packer(&self) -> Packer {
+        self.packer
+    }
+
+    pub fn packer_window(&self) -> usize {
+        self.packer_window
+    }
+
+    pub fn margin(&self) -> usize {
+        self.margin
+    }
+
+    pub fn frame_layout(&self) -> Option<&FrameLayout> {
+        self.frame_layout.as_ref()
+    }
+
+    pub fn start_line(&self) -> usize {
+        self.start_line
+    }
+
+    pub fn entry_offset(&self) -> usize {
+        self.entry_offset
+    }
+
+    pub fn exit_offset(&self) -> usize {
+        self.exit_offset
+    }
+
+    pub fn separator(&self) -> Option<char> {
+        self.separator
+    }
+
+    pub fn video_base(&self) -> Option<&VideoBaseUpdate> {
+        self.video_base.as_ref()
+    }
+
+    pub fn max_scanlines(&self) -> Option<usize> {
+        self.max_scanlines
+    }
+
+    pub fn tail(&self) -> Tail {
+        self.tail
+    }
+
+    pub fn loop_seam(&self) -> Option<&LoopSeam> {
+        self.loop_seam.as_ref()
+    }
+
+    pub fn chunk_chain(&self) -> Option<&ChunkChain> {
+        self.chunk_chain.as_ref()
+    }
+
+    /// The scanline length at `scanline` of the frame: the length set by the frame layout's
+    /// region, or `cycles_per_line`.
+    pub fn cycles_at(&self, scanline: usize) -> usize {
+        self.frame_layout
+            .as_ref()
+            .and_then(|layout| layout.cycles_at(scanline))
+            .unwrap_or(self.cycles_per_line)
+    }
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options::builder().build()
+    }
+}
+
+/// Builder for [`Options`].
+///
+/// Settings that are not given fall back to their defaults; `cycles_per_line` defaults to the
+/// scanline length of the selected [`CpuProfile`], `packer_window` to
+/// [`DEFAULT_PACKER_WINDOW`].
+#[derive(Debug, Clone, Default)]
+pub struct OptionsBuilder {
+    cycles_per_line: Option<usize>,
+    overflow_policy: OverflowPolicy,
+    padding_style: PaddingStyle,
+    annotation_format: AnnotationFormat,
+    verbosity: Verbosity,
+    cpu_profile: CpuProfile,
+    nop_cycles: Option<usize>,
+    padding_instruction: Option<PaddingInstruction>,
+    strictness: Strictness,
+    checksums: bool,
+    warning_comments: bool,
+    cancellation: Option<CancellationToken>,
+    packer: Packer,
+    packer_window: Option<usize>,
+    margin: usize,
+    frame_layout: Option<FrameLayout>,
+    start_line: usize,
+    entry_offset: usize,
+    exit_offset: usize,
+    separator: Option<char>,
+    video_base: Option<VideoBaseUpdate>,
+    max_scanlines: Option<usize>,
+    tail: Tail,
+    loop_seam: Option<LoopSeam>,
+    chunk_chain: Option<ChunkChain>,
+}
+
+/// The number of instructions a reordering packer looks ahead by default.
+pub const DEFAULT_PACKER_WINDOW: usize = 8;
+
+impl OptionsBuilder {
+    pub fn cycles_per_line(mut self, cycles: usize) -> Self {
+        self.cycles_per_line = Some(cycles);
+        self
+    }
+
+    pub fn overflow_policy(mut self, policy: OverflowPolicy) -> Self {
+        self.overflow_policy = policy;
+        self
+    }
+
+    pub fn padding_style(mut self, style: PaddingStyle) -> Self {
+        self.padding_style = style;
+        self
+    }
+
+    pub fn annotation_format(mut self, format: AnnotationFormat) -> Self {
+        self.annotation_format = format;
+        self
+    }
+
+    /// Overrides the cycles of one padding NOP, for targets where it does not take the CPU
+    /// profile's 4 cycles.
+    pub fn nop_cycles(mut self, cycles: usize) -> Self {
+        self.nop_cycles = Some(cycles.max(1));
+        self
+    }
+
+    /// Pads with `instruction` instead of `nop`. Template windows are still delimited by
+    /// `dcb.w <count>,$4e71` blocks.
+    pub fn padding_instruction(mut self, instruction: PaddingInstruction) -> Self {
+        self.padding_instruction = Some(instruction);
+        self
+    }
+
+    /// Sets how much the output comments on itself.
+    pub fn verbosity(mut self, verbosity: Verbosity) -> Self {
+        self.verbosity = verbosity;
+        self
+    }
+
+    pub fn cpu_profile(mut self, profile: CpuProfile) -> Self {
+        self.cpu_profile = profile;
+        self
+    }
+
+    pub fn strictness(mut self, strictness: Strictness) -> Self {
+        self.strictness = strictness;
+        self
+    }
+
+    /// Appends a checksum comment to every scanline so hand edits can be detected later.
+    pub fn checksums(mut self, enabled: bool) -> Self {
+        self.checksums = enabled;
+        self
+    }
+
+    /// Embeds the warnings raised for a scanline as comments at its end (see
+    /// `diagnostics::warning_comment`) instead of reporting them to the observer.
+    pub fn warning_comments(mut self, enabled: bool) -> Self {
+        self.warning_comments = enabled;
+        self
+    }
+
+    /// Sets a token that is checked between scanlines; once cancelled, generation stops and
+    /// returns what has been generated so far together with a resumable state.
+    pub fn cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    pub fn packer(mut self, packer: Packer) -> Self {
+        self.packer = packer;
+        self
+    }
+
+    /// Sets how many instructions after a blocking one a reordering packer considers.
+    pub fn packer_window(mut self, window: usize) -> Self {
+        self.packer_window = Some(window);
+        self
+    }
+
+    /// Reserves `cycles` of every scanline as a safety margin: user code is packed as if the
+    /// scanline were that much shorter and the margin is padded, so debug code can be added
+    /// later without re-flowing the scanlines.
+    pub fn margin(mut self, cycles: usize) -> Self {
+        self.margin = cycles;
+        self
+    }
+
+    /// Sets the layout choosing templates and scanline lengths by frame scanline (see the
+    /// `frame` module).
+    pub fn frame_layout(mut self, layout: FrameLayout) -> Self {
+        self.frame_layout = Some(layout);
+        self
+    }
+
+    /// Lays out the generated code as if its first scanline were scanline `line` of the frame.
+    pub fn start_line(mut self, line: usize) -> Self {
+        self.start_line = line;
+        self
+    }
+
+    /// Starts the first scanline `cycles` into the scanline, after the prologue of the code the
+    /// generated block is embedded in. Template code before that point is left out. Generation
+    /// fails if the offset takes the whole first scanline.
+    pub fn entry_offset(mut self, cycles: usize) -> Self {
+        self.entry_offset = cycles;
+        self
+    }
+
+    /// Ends the last scanline `cycles` before the end of the scanline, leaving room for the
+    /// epilogue of the code the generated block is embedded in. Generation fails if the offset
+    /// takes the whole last scanline or, in a block of a single scanline, leaves no room after
+    /// the entry offset.
+    pub fn exit_offset(mut self, cycles: usize) -> Self {
+        self.exit_offset = cycles;
+        self
+    }
+
+    /// Starts every scanline with a separator comment drawn with `rule`, showing the scanline
+    /// and its cycle range within the frame, e.g. `; ======== scanline 17 (cycles 8704..9216)
+    /// ========`.
+    pub fn separator(mut self, rule: char) -> Self {
+        self.separator = Some(rule);
+        self
+    }
+
+    /// Injects the video counter writes of `update` into the scanlines it applies to (see the
+    /// `video` module).
+    pub fn video_base(mut self, update: VideoBaseUpdate) -> Self {
+        self.video_base = Some(update);
+        self
+    }
+
+    /// Fails generation once the input needs more than `scanlines` scanlines, e.g. more than
+    /// the frame can display.
+    pub fn max_scanlines(mut self, scanlines: usize) -> Self {
+        self.max_scanlines = Some(scanlines);
+        self
+    }
+
+    /// Sets what happens when the input runs out before the frame ends.
+    pub fn tail(mut self, tail: Tail) -> Self {
+        self.tail = tail;
+        self
+    }
+
+    /// Makes the generated block loop every frame through `seam`, which replaces the exit
+    /// offset (see the `seam` module).
+    pub fn loop_seam(mut self, seam: LoopSeam) -> Self {
+        self.loop_seam = Some(seam);
+        self
+    }
+
+    /// Cuts the generated block into chunks chained by jumps (see the `seam` module).
+    pub fn chunk_chain(mut self, chain: ChunkChain) -> Self {
+        self.chunk_chain = Some(chain);
+        self
+    }
+
+    pub fn build(self) -> Options {
+        Options {
+            cycles_per_line: self
+                .cycles_per_line
+                .unwrap_or_else(|| self.cpu_profile.cycles_per_line()),
+            overflow_policy: self.overflow_policy,
+            padding_style: self.padding_style,
+            annotation_format: self.annotation_format,
+            verbosity: self.verbosity,
+            cpu_profile: self.cpu_profile,
+            nop_cycles: self.nop_cycles,
+            padding_instruction: self.padding_instruction,
+            strictness: self.strictness,
+            checksums: self.checksums,
+            warning_comments: self.warning_comments,
+            cancellation: self.cancellation,
+            packer: self.packer,
+            packer_window: self.packer_window.unwrap_or(DEFAULT_PACKER_WINDOW),
+            margin: self.margin,
+            frame_layout: self.frame_layout,
+            start_line: self.start_line,
+            entry_offset: self.entry_offset,
+            exit_offset: self.exit_offset,
+            separator: self.separator,
+            video_base: self.video_base,
+            max_scanlines: self.max_scanlines,
+            tail: self.tail,
+            loop_seam: self.loop_seam,
+            chunk_chain: self.chunk_chain,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_defaults_follow_cpu_profile() {
+        let options = Options::default();
+        assert_eq!(options.cycles_per_line(), 512);
+        assert_eq!(options.overflow_policy(), OverflowPolicy::Warn);
+        assert_eq!(options.padding_style(), PaddingStyle::Nop);
+        assert_eq!(options.annotation_format(), AnnotationFormat::Full);
+        assert_eq!(options.strictness(), Strictness::Lenient);
+        assert_eq!(options.packer(), Packer::Greedy);
+        assert_eq!(options.packer_window(), DEFAULT_PACKER_WINDOW);
+        assert_eq!(options.margin(), 0);
+        assert_eq!(options.frame_layout(), None);
+        assert_eq!(options.start_line(), 0);
+        assert_eq!(options.entry_offset(), 0);
+        assert_eq!(options.exit_offset(), 0);
+        assert_eq!(options.max_scanlines(), None);
+        assert_eq!(options.tail(), Tail::Stop);
+        assert_eq!(options.loop_seam(), None);
+        assert_eq!(options.chunk_chain(), None);
+        assert_eq!(options.verbosity(), Verbosity::Normal);
+        assert_eq!(options.nop_cycles(), 4);
+        assert_eq!(options.padding_instruction(), PaddingInstruction::nop(4));
+    }
+
+    #[test]
+    fn test_builder_overrides() {
+        let options = Options::builder()
+            .cycles_per_line(508)
+            .overflow_policy(OverflowPolicy::Error)
+            .padding_style(PaddingStyle::Dcb)
+            .annotation_format(AnnotationFormat::Compact)
+            .strictness(Strictness::Strict)
+            .build();
+        assert_eq!(options.cycles_per_line(), 508);
+        assert_eq!(options.overflow_policy(), OverflowPolicy::Error);
+        assert_eq!(options.padding_style(), PaddingStyle::Dcb);
+        assert_eq!(options.annotation_format(), AnnotationFormat::Compact);
+        assert_eq!(options.strictness(), Strictness::Strict);
+    }
+}
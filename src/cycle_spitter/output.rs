@@ -0,0 +1,82 @@
+// src/cycle_spitter/output.rs
+
+//! Writing generated files without ever leaving them half-written.
+//!
+//! A generated include file is usually part of a build. If the generation is interrupted while
+//! the file is being written, the build would pick up a truncated file and fail in confusing
+//! ways, or worse, assemble. [`write_atomically`] writes to a temporary file next to the
+//! target and renames it over the target only once everything has been written and flushed,
+//! so the target holds either the previous or the new content.
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Returns the temporary file `path` is written to before being renamed: a hidden file in the
+/// same directory, as a rename only replaces a file atomically within one file system.
+fn temporary_path(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{}.{}.tmp", name, std::process::id()))
+}
+
+/// Creates or replaces the file at `path` with what `write` writes, atomically: if `write` or
+/// any step of writing fails, the file at `path` is left untouched.
+///
+/// # Errors
+/// If the temporary file cannot be created, written, flushed or renamed, or if `write` fails.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::output::write_atomically;
+/// use std::io::Write;
+///
+/// let path = std::env::temp_dir().join("cycle_spitter_doctest_output.s");
+/// write_atomically(&path, |out| writeln!(out, "\tnop")).unwrap();
+/// assert_eq!(std::fs::read_to_string(&path).unwrap(), "\tnop\n");
+/// # std::fs::remove_file(&path).unwrap();
+/// ```
+pub fn write_atomically(
+    path: &Path,
+    write: impl FnOnce(&mut dyn Write) -> io::Result<()>,
+) -> io::Result<()> {
+    let temporary = temporary_path(path);
+    let result = File::create(&temporary).and_then(|file| {
+        let mut out = BufWriter::new(file);
+        write(&mut out)?;
+        let file = out.into_inner().map_err(|e| e.into_error())?;
+        file.sync_all()?;
+        fs::rename(&temporary, path)
+    });
+    if result.is_err() {
+        let _ = fs::remove_file(&temporary);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_failed_write_keeps_previous_content() {
+        let dir = std::env::temp_dir().join(format!("cycle_spitter_output_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("scanlines.s");
+        fs::write(&path, "previous\n").unwrap();
+
+        let result = write_atomically(&path, |out| {
+            writeln!(out, "partial")?;
+            Err(io::Error::other("interrupted"))
+        });
+        assert!(result.is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "previous\n");
+        assert!(!temporary_path(&path).exists());
+
+        write_atomically(&path, |out| writeln!(out, "new")).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
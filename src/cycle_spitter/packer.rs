@@ -0,0 +1,787 @@
+// src/cycle_spitter/packer.rs
+
+//! Packing strategies behind the [`Scheduler`] trait.
+//!
+//! The [`Scheduler`] of the selected [`Packer`] makes the placement decisions: whether an
+//! instruction is placed into what is left of a section or deferred to a later one
+//! ([`Scheduler::accepts`], asked by the accumulator and by [`pack`]), and which instructions
+//! fill the gap left before a deferred one ([`Scheduler::fill`]). A reordering packer plans
+//! the sections in advance and, whenever a gap would be padded, collects the instructions of
+//! the next `packer_window` that may be moved in front of the blocking one for its scheduler to
+//! pick from:
+//! - [`StrictOrderScheduler`] picks none, keeping the source order ([`Packer::Greedy`], the
+//!   default).
+//! - [`FirstFitScheduler`] picks the first ones that fit ([`Packer::FirstFit`]).
+//! - [`OptimalScheduler`] picks the combination that fills the gap most tightly, a small
+//!   subset-sum problem ([`Packer::Optimal`]).
+//!
+//! The chosen instructions are moved in front of the blocking one, and generation then runs on
+//! the reordered input - so resuming and caching see the same input on every run. New
+//! strategies implement [`Scheduler`] and can be compared on the same input with [`pack`].
+//!
+//! An instruction is only moved if this cannot change what the program computes:
+//! - It must be a plain data instruction (moves, arithmetic, logic, shifts, `lea`, ...) that
+//!   does not write memory.
+//! - It must not write a register used by an instruction it moves across, or read a register
+//!   written by one, and must not read memory if one of them writes memory.
+//! - It must not be the last instruction before a branch or another condition code reader.
+//! - If it sets the condition codes, they must be overwritten before the next line that may
+//!   read them: `lea`, `movea`, `adda`, `suba`, `exg` and `movem` leave them alone, so a
+//!   flag-setter followed by those still feeds a branch after them. At the end of the input the
+//!   condition codes count as read, as the code following the block may test them.
+//! - The lookahead stops at labels, branches, pragmas, `set`/`equ` lines, directives and
+//!   instructions marked `;@critical`, which are never moved either.
+//!
+//! Moving instructions changes when they execute within the scanline. Memory writes keep their
+//! order, but may shift in time; pin timing critical code with `;@split-here` or a label.
+//!
+//! [`Packer`]: crate::cycle_spitter::options::Packer
+//! [`Packer::Greedy`]: crate::cycle_spitter::options::Packer::Greedy
+//! [`Packer::FirstFit`]: crate::cycle_spitter::options::Packer::FirstFit
+//! [`Packer::Optimal`]: crate::cycle_spitter::options::Packer::Optimal
+
+use crate::cycle_spitter::cycles::normalize_line_ext;
+use crate::cycle_spitter::database::CycleDatabase;
+use crate::cycle_spitter::frame::RegionTracker;
+use crate::cycle_spitter::generator::{
+    carve_irq_windows, clip_sections, reserve_margin, select_template,
+};
+use crate::cycle_spitter::options::Options;
+use crate::cycle_spitter::pragma::{Pragma, is_critical, parse_budget, parse_pragma};
+use crate::cycle_spitter::regexes::REG_NUMBER_RE;
+use crate::cycle_spitter::template::{TemplateSection, TemplateSet};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::error::Error;
+
+/// Mnemonics whose only effects are on their operands and the condition codes.
+const MOVABLE: &[&str] = &[
+    "move", "movea", "moveq", "movem", "lea", "add", "adda", "addi", "addq", "sub", "suba", "subi",
+    "subq", "and", "andi", "or", "ori", "eor", "eori", "not", "neg", "clr", "tst", "cmp", "cmpa",
+    "cmpi", "ext", "swap", "exg", "lsl", "lsr", "asl", "asr", "rol", "ror", "mulu", "muls", "divu",
+    "divs", "btst", "bset", "bclr", "bchg",
+];
+
+/// Movable mnemonics that do not write their last operand.
+const READ_ONLY: &[&str] = &["tst", "cmp", "cmpa", "cmpi", "btst"];
+
+/// Movable mnemonics that leave the condition codes unchanged.
+const KEEPS_FLAGS: &[&str] = &["lea", "movea", "adda", "suba", "exg", "movem"];
+
+/// Movable mnemonics that leave the condition codes unchanged when writing an address register.
+const KEEPS_FLAGS_TO_AN: &[&str] = &["move", "add", "addq", "sub", "subq"];
+
+static LABEL_RE: Lazy<Regex> = Lazy::new(|| {
+    // Matches a line starting with a label, e.g. `.loop:` or `  start: move.w d0,d1`
+    Regex::new(r"^\s*\.*[a-zA-Z_][a-zA-Z0-9_]*:").unwrap()
+});
+
+static REGISTER_RE: Lazy<Regex> = Lazy::new(|| {
+    // Matches the registers used inside an addressing mode, e.g. `a0` and `d1` in `4(a0,d1.w)`
+    Regex::new(r"\b([da][0-7]|sp)\b").unwrap()
+});
+
+/// The registers, memory and condition codes an instruction reads and writes. Registers are
+/// bit masks with `d0`-`d7` in bits 0-7 and `a0`-`a7` in bits 8-15. No movable instruction
+/// reads the condition codes; the lines that may read them are barriers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Effects {
+    reads: u16,
+    writes: u16,
+    mem_read: bool,
+    mem_write: bool,
+    sets_flags: bool,
+}
+
+impl Effects {
+    /// Returns `true` if the two instructions give the same result in either order.
+    fn commutes_with(&self, other: &Effects) -> bool {
+        self.writes & (other.reads | other.writes) == 0
+            && other.writes & self.reads == 0
+            && !(self.mem_write && (other.mem_read || other.mem_write))
+            && !(other.mem_write && self.mem_read)
+    }
+}
+
+enum Operand {
+    Immediate,
+    Registers(u16),
+    Memory { registers: u16, updated: u16 },
+}
+
+fn register_bit(name: &str) -> Option<u16> {
+    let index = match name.as_bytes() {
+        [b'd', n @ b'0'..=b'7'] => n - b'0',
+        [b'a', n @ b'0'..=b'7'] => 8 + n - b'0',
+        b"sp" => 15,
+        _ => return None,
+    };
+    Some(1 << index)
+}
+
+/// Parses a single register or a register list such as `d0-d7/a0`.
+fn register_list(operand: &str) -> Option<u16> {
+    let mut mask = 0;
+    for part in operand.split('/') {
+        match part.split_once('-') {
+            Some((first, last)) => {
+                let (first, last) = (register_bit(first)?, register_bit(last)?);
+                if first > last {
+                    return None;
+                }
+                let mut bit = first;
+                while bit <= last {
+                    mask |= bit;
+                    bit <<= 1;
+                }
+            }
+            None => mask |= register_bit(part)?,
+        }
+    }
+    Some(mask)
+}
+
+/// Classifies an operand (whitespace already removed). Returns `None` for the status and
+/// user stack registers, which make the instruction a barrier.
+fn parse_operand(operand: &str) -> Option<Operand> {
+    if matches!(operand, "sr" | "ccr" | "usp") {
+        return None;
+    }
+    if operand.starts_with('#') {
+        return Some(Operand::Immediate);
+    }
+    if let Some(mask) = register_list(operand) {
+        return Some(Operand::Registers(mask));
+    }
+    let registers = REGISTER_RE
+        .find_iter(operand)
+        .filter_map(|m| register_bit(m.as_str()))
+        .fold(0, |mask, bit| mask | bit);
+    let updated = if operand.starts_with("-(") || operand.ends_with(")+") {
+        registers
+    } else {
+        0
+    };
+    Some(Operand::Memory { registers, updated })
+}
+
+/// Splits an operand field at the commas that are not inside parentheses.
+pub(crate) fn split_operands(field: &str) -> Vec<String> {
+    let mut operands = vec![String::new()];
+    let mut depth = 0;
+    for c in field.chars().filter(|c| !c.is_whitespace()) {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                operands.push(String::new());
+                continue;
+            }
+            _ => {}
+        }
+        operands.last_mut().unwrap().push(c);
+    }
+    operands.retain(|o| !o.is_empty());
+    operands
+}
+
+/// Returns the effects of a movable instruction, or `None` if the line must stay in place
+/// and nothing may be moved across it.
+fn effects(line: &str) -> Option<Effects> {
+    if parse_pragma(line).is_some()
+        || is_critical(line)
+        || LABEL_RE.is_match(line)
+        || line.contains(" set ")
+        || line.contains(" equ ")
+    {
+        return None;
+    }
+    let code = line.split(';').next().unwrap_or("").trim().to_lowercase();
+    let (mnemonic, field) = code.split_once(char::is_whitespace).unwrap_or((&code, ""));
+    let base = mnemonic.split('.').next().unwrap_or("");
+    if !MOVABLE.contains(&base) {
+        return None;
+    }
+
+    let operands = split_operands(field);
+    let writes_destination = !READ_ONLY.contains(&base);
+    let mut effects = Effects::default();
+    for (index, operand) in operands.iter().enumerate() {
+        let is_destination = index + 1 == operands.len();
+        match parse_operand(operand)? {
+            Operand::Immediate => {}
+            Operand::Registers(mask) => {
+                effects.reads |= mask;
+                if is_destination && writes_destination {
+                    effects.writes |= mask;
+                }
+            }
+            Operand::Memory { registers, updated } => {
+                effects.reads |= registers;
+                effects.writes |= updated;
+                effects.mem_read = true;
+                if is_destination && writes_destination {
+                    effects.mem_write = true;
+                }
+            }
+        }
+    }
+    if base == "exg" {
+        effects.writes |= effects.reads;
+    }
+    let to_address_register = operands
+        .last()
+        .and_then(|operand| register_bit(operand))
+        .is_some_and(|bit| bit >= 1 << 8);
+    effects.sets_flags = !(KEEPS_FLAGS.contains(&base)
+        || (to_address_register && KEEPS_FLAGS_TO_AN.contains(&base)));
+    Some(effects)
+}
+
+/// Returns `true` if the condition codes set by `lines[index]` may still be read: no later
+/// instruction sets them before a line that is not movable (a branch, say) or the end of the
+/// input.
+fn flags_live_after(lines: &[String], index: usize) -> bool {
+    for line in lines[index + 1..].iter().filter(|l| !is_transparent(l)) {
+        match effects(line) {
+            Some(effects) if effects.sets_flags => return false,
+            Some(_) => {}
+            None => return true,
+        }
+    }
+    true
+}
+
+/// Returns `true` for lines the accumulator passes through without effect on the code.
+fn is_transparent(line: &str) -> bool {
+    let trimmed = line.trim();
+    (trimmed.is_empty() || trimmed.starts_with(';')) && parse_pragma(line).is_none()
+}
+
+/// Makes the placement decisions of a packer.
+pub trait Scheduler {
+    /// Returns whether an instruction of `cycles` is placed into a section with `free` cycles
+    /// left, rather than deferred to a later section. By default it is placed if it fits.
+    fn accepts(&self, cycles: usize, free: usize) -> bool {
+        cycles <= free
+    }
+
+    /// Returns the indices, in ascending order, of the `candidates` to move in front of an
+    /// instruction that does not fit the `gap` of free cycles left in a section. Candidates
+    /// are given by their cycles, in source order; the chosen ones must add up to at most `gap`.
+    fn fill(&self, gap: usize, candidates: &[usize]) -> Vec<usize>;
+}
+
+/// Keeps the source order: the gap is padded.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::packer::{Scheduler, StrictOrderScheduler};
+///
+/// assert!(StrictOrderScheduler.accepts(8, 8));
+/// assert!(!StrictOrderScheduler.accepts(12, 8));
+/// assert!(StrictOrderScheduler.fill(8, &[4, 8]).is_empty());
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StrictOrderScheduler;
+
+impl Scheduler for StrictOrderScheduler {
+    fn fill(&self, _gap: usize, _candidates: &[usize]) -> Vec<usize> {
+        Vec::new()
+    }
+}
+
+/// Takes the candidates in source order as long as they fit.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::packer::{FirstFitScheduler, Scheduler};
+///
+/// assert_eq!(FirstFitScheduler.fill(8, &[4, 8, 4]), [0, 2]);
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FirstFitScheduler;
+
+impl Scheduler for FirstFitScheduler {
+    fn fill(&self, gap: usize, candidates: &[usize]) -> Vec<usize> {
+        let mut left = gap;
+        let mut chosen = Vec::new();
+        for (candidate, &cycles) in candidates.iter().enumerate() {
+            if cycles <= left {
+                left -= cycles;
+                chosen.push(candidate);
+            }
+        }
+        chosen
+    }
+}
+
+/// Takes the combination of candidates that fills the gap most tightly, the earliest one of
+/// those that fill it equally.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::packer::{OptimalScheduler, Scheduler};
+///
+/// assert_eq!(OptimalScheduler.fill(8, &[4, 8, 4]), [1]);
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OptimalScheduler;
+
+impl Scheduler for OptimalScheduler {
+    fn fill(&self, gap: usize, candidates: &[usize]) -> Vec<usize> {
+        // best[s] holds the earliest found combination of candidates totalling s.
+        let mut best: Vec<Option<Vec<usize>>> = vec![None; gap + 1];
+        best[0] = Some(Vec::new());
+        for (candidate, &cycles) in candidates.iter().enumerate() {
+            if cycles == 0 || cycles > gap {
+                continue;
+            }
+            for sum in (cycles..=gap).rev() {
+                if best[sum].is_none()
+                    && let Some(previous) = &best[sum - cycles]
+                {
+                    let mut combination = previous.clone();
+                    combination.push(candidate);
+                    best[sum] = Some(combination);
+                }
+            }
+        }
+        best.into_iter().rev().flatten().next().unwrap_or_default()
+    }
+}
+
+/// Returns the cycles the accumulator counts for `line`, or `None` for lines it consumes
+/// without counting (comments, `set` and `equ` lines). Mirrors `accumulate`, without warnings.
+pub(crate) fn cycles_of(line: &str, db: &CycleDatabase) -> Option<usize> {
+    let trimmed = line.trim();
+    if trimmed.is_empty() || trimmed.starts_with(';') || line.contains(" set ") {
+        return None;
+    }
+    if let Some(caps) = REG_NUMBER_RE.captures(line) {
+        return Some(caps[1].parse().unwrap_or(0));
+    }
+    if line.contains(" equ ") {
+        return None;
+    }
+    let (normalized, reg_count) = normalize_line_ext(line);
+    Some(
+        db.cycle_count(&normalized, reg_count)
+            .map(|cycles| cycles.total())
+            .unwrap_or(0),
+    )
+}
+
+/// Reorders `lines` as the scheduler of the packer in `options` decides to fill the gaps
+/// accumulation would pad, following the same scanline and section layout as the generator
+/// (see the module documentation). Returns the reordered lines and the index in `lines` each
+/// of them came from.
+///
+/// # Errors
+/// If a template selected by the input or the frame layout does not exist.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::database::CycleDatabase;
+/// use cycle_spitter::options::{Options, Packer};
+/// use cycle_spitter::packer::pack;
+/// use cycle_spitter::template::{TemplateSection, TemplateSet};
+///
+/// let lines: Vec<String> = ["move.w d0,d1 ; (8)", "move.w d1,d2 ; (12)", "move.w d3,d4 ; (4)",
+///     "move.w d5,d6 ; (8)", "move.w d7,d0 ; (4)"].iter().map(|l| l.to_string()).collect();
+/// let templates = TemplateSet::from(vec![TemplateSection::code_window(16, "user code")]);
+/// let db = CycleDatabase::builtin();
+/// let packed = |packer| {
+///     let options = Options::builder().packer(packer).build();
+///     pack(&lines, &templates, &db, &options).unwrap().1
+/// };
+/// // A gap of 8 cycles before the 12 cycle move
+/// assert_eq!(packed(Packer::FirstFit), [0, 2, 1, 3, 4]);
+/// assert_eq!(packed(Packer::Optimal), [0, 3, 1, 2, 4]);
+/// ```
+pub fn pack(
+    lines: &[String],
+    templates: &TemplateSet,
+    db: &CycleDatabase,
+    options: &Options,
+) -> Result<(Vec<String>, Vec<usize>), Box<dyn Error>> {
+    let scheduler = options.packer().scheduler();
+    let mut lines = lines.to_vec();
+    let mut origins: Vec<usize> = (0..lines.len()).collect();
+    let initial = templates.initial().ok_or("No template registered")?;
+    let mut sections = select_template(templates, initial)?;
+    let mut index = 0;
+    let layout = options.frame_layout();
+    let mut regions = RegionTracker::default();
+    let mut frame_line = options.start_line();
+    let mut budget = None;
+
+    while index < lines.len() {
+        if let Some(name) = regions.advance(layout, frame_line) {
+            sections = select_template(templates, name)?;
+        }
+        let mut raw_scanline = false;
+        while let Some(pragma) = lines.get(index).and_then(|l| parse_pragma(l)) {
+            match pragma {
+                Pragma::RawScanline => raw_scanline = true,
+                Pragma::UseTemplate(name) => sections = select_template(templates, &name)?,
+                Pragma::SplitHere => {}
+                Pragma::Budget(argument) => budget = parse_budget(&argument)?,
+            }
+            index += 1;
+        }
+        let cycles_per_line = budget.unwrap_or_else(|| options.cycles_at(frame_line));
+
+        let scanline_start = index;
+        let raw_sections;
+        let scanline_sections = if raw_scanline {
+            raw_sections = [TemplateSection::code_window(
+                cycles_per_line,
+                "raw scanline",
+            )];
+            &raw_sections[..]
+        } else {
+            sections
+        };
+        let irq_windows = layout
+            .map(|layout| layout.irq_windows_at(frame_line))
+            .unwrap_or_default();
+        let carved;
+        let scanline_sections = if irq_windows.is_empty() {
+            scanline_sections
+        } else {
+            carved = carve_irq_windows(scanline_sections, irq_windows)?;
+            &carved[..]
+        };
+        let entry_sections;
+        let scanline_sections = if frame_line == options.start_line() && options.entry_offset() > 0
+        {
+            let span = options.entry_offset()..cycles_per_line;
+            entry_sections = clip_sections(scanline_sections, &span)?;
+            &entry_sections[..]
+        } else {
+            scanline_sections
+        };
+        let reserved = reserve_margin(scanline_sections, options);
+        for (section, reserved) in scanline_sections.iter().zip(reserved) {
+            let budget = section.nop_cycles - reserved;
+            if budget > 0 && index < lines.len() {
+                index = pack_section(
+                    &mut lines,
+                    &mut origins,
+                    (index, budget, section.hot),
+                    db,
+                    options,
+                    scheduler,
+                );
+            }
+        }
+        if index == scanline_start {
+            // Nothing fits into this scanline; leave the rest to the generator.
+            break;
+        }
+        frame_line += 1;
+    }
+    Ok((lines, origins))
+}
+
+/// Plans one section of `budget` cycles starting at `start` and returns the index after its
+/// last line. Like the accumulator, a section that is not `hot` ends before a critical
+/// instruction.
+fn pack_section(
+    lines: &mut Vec<String>,
+    origins: &mut Vec<usize>,
+    (start, budget, hot): (usize, usize, bool),
+    db: &CycleDatabase,
+    options: &Options,
+    scheduler: &dyn Scheduler,
+) -> usize {
+    let mut used = 0;
+    let mut index = start;
+    while index < lines.len() && used < budget {
+        if parse_pragma(&lines[index]).is_some() || (!hot && is_critical(&lines[index])) {
+            break;
+        }
+        match cycles_of(&lines[index], db) {
+            Some(cycles) if !scheduler.accepts(cycles, budget - used) => {
+                let gap = budget - used;
+                return index + hoist(lines, origins, (index, gap), db, options, scheduler);
+            }
+            Some(cycles) => used += cycles,
+            None => {}
+        }
+        index += 1;
+    }
+    index
+}
+
+/// Moves the instructions after `blocker` that `scheduler` chooses to fill `gap` in front of
+/// it, along with their `origins`, and returns the number of moved lines.
+fn hoist(
+    lines: &mut Vec<String>,
+    origins: &mut Vec<usize>,
+    (blocker, gap): (usize, usize),
+    db: &CycleDatabase,
+    options: &Options,
+    scheduler: &dyn Scheduler,
+) -> usize {
+    let Some(blocker_effects) = effects(&lines[blocker]) else {
+        return 0;
+    };
+    let mut crossed = vec![blocker_effects];
+    let mut candidates: Vec<(usize, usize)> = Vec::new();
+    let mut seen = 0;
+    let mut index = blocker + 1;
+    while index < lines.len() && seen < options.packer_window() {
+        if is_transparent(&lines[index]) {
+            index += 1;
+            continue;
+        }
+        let Some(line_effects) = effects(&lines[index]) else {
+            break;
+        };
+        seen += 1;
+        let cycles = cycles_of(&lines[index], db).unwrap_or(0);
+        let before_barrier = lines[index + 1..]
+            .iter()
+            .find(|l| !is_transparent(l))
+            .is_some_and(|l| effects(l).is_none());
+        if !line_effects.mem_write
+            && cycles > 0
+            && cycles <= gap
+            && !before_barrier
+            && !(line_effects.sets_flags && flags_live_after(lines, index))
+            && crossed.iter().all(|c| c.commutes_with(&line_effects))
+        {
+            candidates.push((index, cycles));
+        }
+        crossed.push(line_effects);
+        index += 1;
+    }
+
+    let cycles: Vec<usize> = candidates.iter().map(|&(_, cycles)| cycles).collect();
+    let mut chosen = scheduler.fill(gap, &cycles);
+    // Schedulers developed outside this module may break the contract; the gap must hold.
+    chosen.sort_unstable();
+    chosen.dedup();
+    chosen.retain(|&candidate| candidate < candidates.len());
+    let mut left = gap;
+    chosen.retain(|&candidate| {
+        let fits = cycles[candidate] <= left;
+        left -= cycles[candidate].min(left);
+        fits
+    });
+
+    let mut moved: Vec<(String, usize)> = chosen
+        .iter()
+        .rev()
+        .map(|&candidate| {
+            let index = candidates[candidate].0;
+            (lines.remove(index), origins.remove(index))
+        })
+        .collect();
+    moved.reverse();
+    let count = moved.len();
+    for (offset, (line, origin)) in moved.into_iter().enumerate() {
+        lines.insert(blocker + offset, line);
+        origins.insert(blocker + offset, origin);
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cycle_spitter::generator::generate;
+    use crate::cycle_spitter::options::Packer;
+
+    fn lines(raw: &[&str]) -> Vec<String> {
+        raw.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn window(cycles: usize) -> TemplateSet {
+        TemplateSet::from(vec![TemplateSection::code_window(cycles, "user code")])
+    }
+
+    fn options(packer: Packer) -> Options {
+        Options::builder()
+            .cycles_per_line(16)
+            .packer(packer)
+            .build()
+    }
+
+    #[test]
+    fn test_effects() {
+        let a = effects("move.w d0,d1").unwrap();
+        assert_eq!(a.reads, 0b11);
+        assert_eq!(a.writes, 0b10);
+        assert!(!a.commutes_with(&effects("add.w d1,d2").unwrap()));
+        assert!(a.commutes_with(&effects("move.w d3,d4").unwrap()));
+
+        let store = effects("move.w d3,(a0)+").unwrap();
+        assert!(store.mem_write);
+        assert_eq!(store.writes, 1 << 8);
+        assert!(!store.commutes_with(&effects("move.w 4(a1),d5").unwrap()));
+        assert!(effects("movem.l d0-d7/a0,-(sp)").unwrap().reads & 0x1ff == 0x1ff);
+
+        assert!(effects(".loop: move.w d0,d1").is_none());
+        assert!(effects("bne.s loop").is_none());
+        assert!(effects("move.w d0,sr").is_none());
+        assert!(effects("addx.w d0,d1").is_none());
+        assert!(effects(";@split-here").is_none());
+
+        assert!(effects("tst.w d6").unwrap().sets_flags);
+        assert!(effects("addq.w #1,d0").unwrap().sets_flags);
+        for keeps in [
+            "lea 4(a0),a1",
+            "move.l d2,a2",
+            "addq.l #2,a0",
+            "exg d0,d1",
+            "movem.l (sp)+,d0-d1",
+        ] {
+            assert!(!effects(keeps).unwrap().sets_flags, "{}", keeps);
+        }
+    }
+
+    #[test]
+    fn test_pack_fills_gap_with_independent_instruction() {
+        let db = CycleDatabase::builtin();
+        let input = lines(&[
+            "move.w d0,d1 ; (8)",
+            "move.w d1,d2 ; (12)",
+            "move.w d3,d4 ; (8)",
+            "move.w d5,d6 ; (4)",
+        ]);
+        let (packed, origins) = pack(&input, &window(16), &db, &options(Packer::Optimal)).unwrap();
+        assert_eq!(
+            packed,
+            lines(&[
+                "move.w d0,d1 ; (8)",
+                "move.w d3,d4 ; (8)",
+                "move.w d1,d2 ; (12)",
+                "move.w d5,d6 ; (4)",
+            ])
+        );
+        assert_eq!(origins, [0, 2, 1, 3]);
+
+        let greedy = generate(&input, &window(16), &db, &options(Packer::Greedy)).unwrap();
+        let optimal = generate(&input, &window(16), &db, &options(Packer::Optimal)).unwrap();
+        assert_eq!(greedy.scanlines, 3);
+        assert_eq!(optimal.scanlines, 2);
+        assert!(optimal.usage.iter().all(|u| u.padding_cycles() == 0));
+    }
+
+    #[test]
+    fn test_schedulers_order_a_dependency_graph() {
+        let db = CycleDatabase::builtin();
+        // A gap of 8 cycles is left before line 1. Line 3 depends on line 2 and line 5 is the
+        // last one, whose flags the code after the block may test, so 2 and 4 may fill it.
+        let input = lines(&[
+            "move.w d0,d1 ; (8)",
+            "move.w d1,d2 ; (12)",
+            "move.w d3,d4 ; (4)",
+            "move.w d4,d5 ; (4)",
+            "move.w d6,d7 ; (8)",
+            "move.w d0,d3 ; (4)",
+        ]);
+        let origins = |packer| pack(&input, &window(16), &db, &options(packer)).unwrap().1;
+        assert_eq!(origins(Packer::Greedy), [0, 1, 2, 3, 4, 5]);
+        assert_eq!(origins(Packer::FirstFit), [0, 2, 1, 3, 4, 5]);
+        assert_eq!(origins(Packer::Optimal), [0, 4, 1, 2, 3, 5]);
+    }
+
+    #[test]
+    fn test_greedy_keeps_the_source_order() {
+        let db = CycleDatabase::builtin();
+        let input = lines(&[
+            "move.w d0,d1 ; (8)",
+            "move.w d1,d2 ; (12)",
+            "move.w d3,d4 ; (8)",
+            "move.w d5,d6 ; (4)",
+        ]);
+        let options = options(Packer::Greedy);
+        assert_eq!(pack(&input, &window(16), &db, &options).unwrap().0, input);
+
+        // The lines are placed in source order, each gap padded.
+        let generation = generate(&input, &window(16), &db, &options).unwrap();
+        let placed: Vec<&str> = generation
+            .lines
+            .iter()
+            .filter(|l| l.starts_with("move.w"))
+            .map(|l| &l[..12])
+            .collect();
+        assert_eq!(
+            placed,
+            [
+                "move.w d0,d1",
+                "move.w d1,d2",
+                "move.w d3,d4",
+                "move.w d5,d6"
+            ]
+        );
+        assert_eq!(generation.usage[0].padding_cycles(), 8);
+        assert_eq!(generation.usage[1].padding_cycles(), 4);
+    }
+
+    #[test]
+    fn test_pack_respects_dependencies_and_barriers() {
+        let db = CycleDatabase::builtin();
+        let unchanged = [
+            // Reads d2, which the blocking instruction writes
+            &[
+                "move.w d0,d1 ; (8)",
+                "move.w d1,d2 ; (12)",
+                "move.w d2,d4 ; (8)",
+            ][..],
+            // Writes memory
+            &[
+                "move.w d0,d1 ; (8)",
+                "move.w d1,d2 ; (12)",
+                "move.w d3,(a0) ; (8)",
+            ][..],
+            // Behind a label
+            &[
+                "move.w d0,d1 ; (8)",
+                "move.w d1,d2 ; (12)",
+                "loop:",
+                "move.w d3,d4 ; (8)",
+            ][..],
+            // Sets the flags for a branch
+            &[
+                "move.w d0,d1 ; (8)",
+                "move.w d1,d2 ; (12)",
+                "tst.w d3 ; (8)",
+                "bne.s loop",
+            ][..],
+            // Sets the flags for a branch behind an instruction leaving them alone
+            &[
+                "move.w d0,d1 ; (8)",
+                "move.w d1,d2 ; (12)",
+                "tst.w d3 ; (8)",
+                "move.l d2,a2 ; (4)",
+                "beq.s skip",
+            ][..],
+            // Sets the flags the code after the block may test
+            &[
+                "move.w d0,d1 ; (8)",
+                "move.w d1,d2 ; (12)",
+                "tst.w d3 ; (8)",
+            ][..],
+            // Critical
+            &[
+                "move.w d0,d1 ; (8)",
+                "move.w d1,d2 ; (12)",
+                "move.w d3,d4 ; (8) ;@critical",
+            ][..],
+        ];
+        for input in unchanged {
+            let input = lines(input);
+            for packer in [Packer::FirstFit, Packer::Optimal] {
+                assert_eq!(
+                    pack(&input, &window(16), &db, &options(packer)).unwrap().0,
+                    input
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,141 @@
+// src/cycle_spitter/padding.rs
+
+//! Finding long runs of padding.
+//!
+//! A scanline padded for long stretches usually means the input was cut up badly, e.g. an
+//! instruction too large for the section it was meant for got deferred and left its window
+//! empty. [`long_padding_runs`] finds the scanlines with a run of consecutive padding
+//! exceeding a limit, so such problems show up before the frame is timed on hardware. Padding
+//! lines are consecutive if each starts where the previous one ends, whether or not a section
+//! boundary lies between them.
+
+use crate::cycle_spitter::models::{LineKind, Scanline};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// The longest run of padding of a scanline.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PaddingRun {
+    /// The generated scanline, counted from 0.
+    pub scanline: usize,
+    /// The scanline of the frame.
+    pub line: usize,
+    /// The scanline offset at which the run starts.
+    pub start: usize,
+    pub cycles: usize,
+}
+
+impl fmt::Display for PaddingRun {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "scanline {} (frame scanline {}) pads {} consecutive cycles from cycle {}",
+            self.scanline, self.line, self.cycles, self.start
+        )
+    }
+}
+
+/// Returns the longest run of padding of every scanline in `model` whose run exceeds `max`
+/// cycles.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::models::{Chunk, Instruction, LineKind, Scanline};
+/// use cycle_spitter::padding::long_padding_runs;
+///
+/// let scanline = Scanline {
+///     line: 34,
+///     cycles: 16,
+///     chunks: vec![Chunk {
+///         label: None,
+///         instructions: vec![
+///             Instruction::timed("\tmove.w d0,d1", LineKind::User, 4, 0),
+///             Instruction::timed("\tnop", LineKind::Padding, 4, 4),
+///             Instruction::timed("\tdcb.w 2,$4e71", LineKind::Padding, 8, 8),
+///         ],
+///     }],
+/// };
+/// let runs = long_padding_runs(&[scanline], 8);
+/// assert_eq!((runs[0].start, runs[0].cycles), (4, 12));
+/// ```
+pub fn long_padding_runs(model: &[Scanline], max: usize) -> Vec<PaddingRun> {
+    let mut runs = Vec::new();
+    for (scanline, line) in model.iter().enumerate() {
+        let mut longest: Option<(usize, usize)> = None;
+        let mut current: Option<(usize, usize)> = None;
+        for instruction in line.instructions() {
+            let Some(offset) = instruction.offset else {
+                continue;
+            };
+            if instruction.kind != LineKind::Padding {
+                current = None;
+                continue;
+            }
+            current = match current {
+                Some((start, end)) if end == offset => Some((start, end + instruction.cycles)),
+                _ => Some((offset, offset + instruction.cycles)),
+            };
+            if let Some((start, end)) = current
+                && longest.is_none_or(|(s, e)| end - start > e - s)
+            {
+                longest = Some((start, end));
+            }
+        }
+        if let Some((start, end)) = longest
+            && end - start > max
+        {
+            runs.push(PaddingRun {
+                scanline,
+                line: line.line,
+                start,
+                cycles: end - start,
+            });
+        }
+    }
+    runs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cycle_spitter::models::{Chunk, Instruction};
+
+    #[test]
+    fn test_runs_span_sections_but_not_code() {
+        let scanline = Scanline {
+            line: 0,
+            cycles: 32,
+            chunks: vec![
+                Chunk {
+                    label: Some("left".to_string()),
+                    instructions: vec![
+                        Instruction::timed("\tnop", LineKind::Padding, 4, 0),
+                        Instruction::timed("\tmove.w d0,d1", LineKind::Template, 4, 4),
+                        Instruction::timed("\tnop", LineKind::Padding, 4, 8),
+                        Instruction::other("; Calculated cycles: 12", None),
+                    ],
+                },
+                Chunk {
+                    label: None,
+                    instructions: vec![Instruction::timed(
+                        "\tdcb.w 5,$4e71",
+                        LineKind::Padding,
+                        20,
+                        12,
+                    )],
+                },
+            ],
+        };
+        let runs = long_padding_runs(std::slice::from_ref(&scanline), 16);
+        assert_eq!(
+            runs,
+            [PaddingRun {
+                scanline: 0,
+                line: 0,
+                start: 8,
+                cycles: 24
+            }]
+        );
+        assert!(long_padding_runs(&[scanline], 24).is_empty());
+    }
+}
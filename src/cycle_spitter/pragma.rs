@@ -0,0 +1,155 @@
+// src/cycle_spitter/pragma.rs
+
+//! Generator directives embedded in the input as `;@` comments.
+//!
+//! Pragmas are ordinary assembler comments, so the input stays assemblable. Scanline-level
+//! pragmas end the scanline being filled (the remaining sections are padded) and take effect
+//! from the next scanline on.
+//!
+//! | Pragma            | Effect                                                              |
+//! |-------------------|---------------------------------------------------------------------|
+//! | `;@raw-scanline`  | The next scanline contains only user code and NOP padding, without |
+//! |                   | any template sections.                                              |
+//! | `;@use-template`  | `;@use-template <name>` applies the template registered as `<name>` |
+//! |                   | to all following scanlines.                                         |
+//! | `;@split-here`    | Only ends the current scanline, keeping the code after it together  |
+//! |                   | in the next one.                                                    |
+//! | `;@budget`        | `;@budget <cycles>` sets the cycle budget of all following          |
+//! |                   | scanlines, e.g. while blitter hog mode takes the bus; `;@budget     |
+//! |                   | default` returns to the budget of the options and frame layout.     |
+//!
+//! Markers are `;@` comments attached to a single line instead of standing on their own:
+//!
+//! | Marker        | Effect                                                                  |
+//! |---------------|-------------------------------------------------------------------------|
+//! | `;@critical`  | On an input instruction: the instruction is only placed in hot template |
+//! |               | sections.                                                               |
+//! | `;@hot`       | In a template: marks the code window of the section as hot, typically   |
+//! |               | the horizontal blank.                                                   |
+
+use std::error::Error;
+
+/// Prefix shared by all pragma comments.
+pub const PRAGMA_PREFIX: &str = ";@";
+
+/// A directive recognized in the input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Pragma {
+    /// Emit the next scanline without template injection.
+    RawScanline,
+    /// Switch to the named template from the next scanline onward. The name is empty if the
+    /// pragma was given without one.
+    UseTemplate(String),
+    /// End the current scanline here, even if more code would fit.
+    SplitHere,
+    /// Change the cycle budget from the next scanline onward. The argument is kept as given;
+    /// see [`parse_budget`].
+    Budget(String),
+}
+
+/// Parses a pragma comment. Returns `None` for ordinary lines and unknown pragmas, which are
+/// treated as regular comments.
+pub fn parse_pragma(line: &str) -> Option<Pragma> {
+    let directive = line.trim().strip_prefix(PRAGMA_PREFIX)?;
+    let mut parts = directive.split_whitespace();
+    match parts.next()?.to_lowercase().as_str() {
+        "raw-scanline" => Some(Pragma::RawScanline),
+        "split-here" => Some(Pragma::SplitHere),
+        "budget" => Some(Pragma::Budget(parts.next().unwrap_or_default().to_string())),
+        "use-template" => Some(Pragma::UseTemplate(
+            parts.next().unwrap_or_default().to_string(),
+        )),
+        _ => None,
+    }
+}
+
+/// Parses the argument of a `;@budget` pragma: a number of cycles, or `None` for `default`.
+///
+/// # Errors
+/// If the argument is neither a positive number nor `default`.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::pragma::parse_budget;
+///
+/// assert_eq!(parse_budget("384").unwrap(), Some(384));
+/// assert_eq!(parse_budget("default").unwrap(), None);
+/// assert!(parse_budget("fast").is_err());
+/// ```
+pub fn parse_budget(argument: &str) -> Result<Option<usize>, Box<dyn Error>> {
+    if argument.eq_ignore_ascii_case("default") {
+        return Ok(None);
+    }
+    match argument.parse::<usize>() {
+        Ok(cycles) if cycles > 0 => Ok(Some(cycles)),
+        _ => Err(format!(
+            "`;@budget` requires a number of cycles or `default`, found '{}'",
+            argument
+        )
+        .into()),
+    }
+}
+
+/// Marks an input instruction that must only be placed in a hot template section.
+pub const CRITICAL_MARKER: &str = "critical";
+
+/// Marks the code window of a template section as hot.
+pub const HOT_MARKER: &str = "hot";
+
+/// Returns `true` if `line` carries the `;@<marker>` comment, e.g. `move.b d0,(a0) ;@critical`.
+/// Markers are matched case-insensitively, like pragmas.
+pub fn has_marker(line: &str, marker: &str) -> bool {
+    line.match_indices(PRAGMA_PREFIX).any(|(index, _)| {
+        line[index + PRAGMA_PREFIX.len()..]
+            .split(|c: char| c.is_whitespace() || c == ';')
+            .next()
+            .is_some_and(|word| word.eq_ignore_ascii_case(marker))
+    })
+}
+
+/// Returns `true` for an instruction marked `;@critical`. Comment lines are never critical.
+pub fn is_critical(line: &str) -> bool {
+    !line.trim().starts_with(';') && has_marker(line, CRITICAL_MARKER)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_pragma() {
+        assert_eq!(parse_pragma(";@raw-scanline"), Some(Pragma::RawScanline));
+        assert_eq!(
+            parse_pragma("  ;@RAW-SCANLINE  "),
+            Some(Pragma::RawScanline)
+        );
+        assert_eq!(parse_pragma("; @raw-scanline"), None);
+        assert_eq!(
+            parse_pragma(";@use-template Border  ; open the border"),
+            Some(Pragma::UseTemplate("Border".to_string()))
+        );
+        assert_eq!(
+            parse_pragma(";@use-template"),
+            Some(Pragma::UseTemplate(String::new()))
+        );
+        assert_eq!(parse_pragma(";@split-here"), Some(Pragma::SplitHere));
+        assert_eq!(
+            parse_pragma(";@budget 384 ; blitter hog mode"),
+            Some(Pragma::Budget("384".to_string()))
+        );
+        assert_eq!(parse_pragma(";@unknown"), None);
+        assert_eq!(parse_pragma("move.w d0,d1"), None);
+    }
+
+    #[test]
+    fn test_markers() {
+        assert!(is_critical("move.b d0,(a0) ;@critical"));
+        assert!(is_critical("move.b d0,(a0) ; palette ;@CRITICAL"));
+        assert!(!is_critical("move.b d0,(a0) ;@critical-ish"));
+        assert!(!is_critical(";@critical"));
+        assert!(!is_critical("move.b d0,(a0)"));
+        assert!(has_marker("dcb.w 11,$4e71 ;@hot", HOT_MARKER));
+        assert!(has_marker(";@hot;hbl", HOT_MARKER));
+        assert!(!has_marker("dcb.w 11,$4e71 ; @hot", HOT_MARKER));
+    }
+}
@@ -1,8 +1,11 @@
 use once_cell::sync::Lazy;
 use regex::Regex;
 
+// Matches an inline cycle override: a `(n)` opening the first comment of a line, e.g.
+// `move.w d0,d1 ; (8)`. A number elsewhere in a comment, as in `; see table (3)` or
+// `; copy ; (6)`, is not an override.
 pub static REG_NUMBER_RE: Lazy<Regex> =
-    Lazy::new(|| Regex::new(r"(?:^|\s)\(\s*(\d+)\s*\)").unwrap());
+    Lazy::new(|| Regex::new(r"^[^;]*;\s*\(\s*(\d+)\s*\)(?:\s|;|$)").unwrap());
 
 pub static REG_LABEL_RE: Lazy<Regex> =
     Lazy::new(|| Regex::new(r"^(\s|\.*[a-zA-Z_][a-zA-Z0-9_]*:)(.*)$").unwrap());
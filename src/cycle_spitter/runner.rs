@@ -0,0 +1,545 @@
+// src/cycle_spitter/runner.rs
+
+//! Embedding the splitter in other Rust tools.
+//!
+//! A [`CycleSpitterBuilder`] collects what the command line otherwise provides - the input
+//! and template sources, the generation options, the label of the scanline count and the cycle
+//! database - and builds a [`CycleSpitter`] that runs the pipeline as often as needed, e.g.
+//! whenever an editor buffer changes. Templates can also be given as a ready-made
+//! [`TemplateSet`], so generation pipelines building them in code skip the text round-trip.
+//!
+//! Runs are incremental: the flattened input and the parsed templates of the previous run are
+//! kept in a [`StageCache`] and reused as long as the text of their source is unchanged, so
+//! tuning a template does not expand the input again and editing the input does not parse the
+//! templates again. [`CycleSpitter::set_options`] swaps the generation options (e.g. the frame
+//! layout) of a built splitter, keeping the flattened input and, unless the cost of a NOP
+//! changes, the parsed templates. The `--watch` mode of the command line keeps the same caches
+//! between its runs.
+
+use crate::cycle_spitter::block::{
+    ExpansionLimits, expand_block_with_origins, join_continuations_with_origins,
+    validate_block_structure,
+};
+use crate::cycle_spitter::database::CycleDatabase;
+use crate::cycle_spitter::emitter::Document;
+use crate::cycle_spitter::error::CycleSpitterError;
+use crate::cycle_spitter::generator::{Generation, generate_with_observer};
+use crate::cycle_spitter::location::{Locator, SourceMap};
+use crate::cycle_spitter::observer::{Observer, StderrObserver};
+use crate::cycle_spitter::options::{Options, OptionsBuilder, PaddingInstruction, PaddingStyle};
+use crate::cycle_spitter::source::{SourceProvider, locate_lines};
+use crate::cycle_spitter::template::{TemplateSet, parse_template_with};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::error::Error;
+
+/// The label receiving the scanline count unless configured otherwise.
+pub const DEFAULT_LABEL: &str = "SCANLINES_CONSUMED";
+
+/// Configures a [`CycleSpitter`].
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::source::StringSource;
+/// use cycle_spitter::{CycleSpitter, CycleSpitterBuilder};
+///
+/// let spitter: CycleSpitter = CycleSpitterBuilder::new()
+///     .input(StringSource::new("effect.s", "move.w d1,d2"))
+///     .template(StringSource::new("template.s", "move.w d0,d1\ndcb.w 4,$4e71"))
+///     .cycles_per_line(20)
+///     .label("EFFECT_LINES")
+///     .build()
+///     .unwrap();
+/// let generation = spitter.run().unwrap();
+/// assert_eq!(generation.scanlines, 1);
+/// assert_eq!(spitter.document(&generation).label, "EFFECT_LINES");
+/// ```
+pub struct CycleSpitterBuilder {
+    input: Option<Box<dyn SourceProvider>>,
+    template: Option<Templates>,
+    options: OptionsBuilder,
+    label: String,
+    db: Option<CycleDatabase>,
+    limits: ExpansionLimits,
+}
+
+impl Default for CycleSpitterBuilder {
+    fn default() -> Self {
+        CycleSpitterBuilder {
+            input: None,
+            template: None,
+            options: OptionsBuilder::default(),
+            label: DEFAULT_LABEL.to_string(),
+            db: None,
+            limits: ExpansionLimits::default(),
+        }
+    }
+}
+
+impl CycleSpitterBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The assembly source to split.
+    pub fn input(mut self, source: impl SourceProvider + 'static) -> Self {
+        self.input = Some(Box::new(source));
+        self
+    }
+
+    /// The template laying out each scanline.
+    pub fn template(mut self, source: impl SourceProvider + 'static) -> Self {
+        self.template = Some(Templates::Source(Box::new(source)));
+        self
+    }
+
+    /// Lays out the scanlines with `templates` instead of a template source.
+    ///
+    /// # Example
+    /// ```rust
+    /// use cycle_spitter::CycleSpitterBuilder;
+    /// use cycle_spitter::source::StringSource;
+    /// use cycle_spitter::template::{TemplateSection, TemplateSet};
+    ///
+    /// let section = TemplateSection::new("left", vec![("move.w d0,d1".to_string(), 4)], 16);
+    /// let spitter = CycleSpitterBuilder::new()
+    ///     .input(StringSource::new("effect.s", "move.w d1,d2"))
+    ///     .templates(TemplateSet::from(vec![section]))
+    ///     .cycles_per_line(20)
+    ///     .build()
+    ///     .unwrap();
+    /// assert_eq!(spitter.run().unwrap().scanlines, 1);
+    /// ```
+    pub fn templates(mut self, templates: TemplateSet) -> Self {
+        self.template = Some(Templates::Set(templates));
+        self
+    }
+
+    /// Replaces all generation options, including those set by the methods below.
+    pub fn options(mut self, options: OptionsBuilder) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn cycles_per_line(mut self, cycles: usize) -> Self {
+        self.options = self.options.cycles_per_line(cycles);
+        self
+    }
+
+    pub fn padding_style(mut self, style: PaddingStyle) -> Self {
+        self.options = self.options.padding_style(style);
+        self
+    }
+
+    pub fn padding_instruction(mut self, instruction: PaddingInstruction) -> Self {
+        self.options = self.options.padding_instruction(instruction);
+        self
+    }
+
+    /// The symbol receiving the scanline count in the output (default [`DEFAULT_LABEL`]).
+    pub fn label(mut self, label: impl Into<String>) -> Self {
+        self.label = label.into();
+        self
+    }
+
+    /// The cycle database (default [`CycleDatabase::builtin`]).
+    pub fn database(mut self, db: CycleDatabase) -> Self {
+        self.db = Some(db);
+        self
+    }
+
+    /// Limits on `REPT` expansion of the input (default [`ExpansionLimits::default`]).
+    pub fn expansion_limits(mut self, limits: ExpansionLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// # Errors
+    /// If no input or no template was given.
+    pub fn build(self) -> Result<CycleSpitter, Box<dyn Error>> {
+        Ok(CycleSpitter {
+            input: self.input.ok_or("No input source given")?,
+            template: self.template.ok_or("No template given")?,
+            options: self.options.build(),
+            label: self.label,
+            db: self.db.unwrap_or_else(CycleDatabase::builtin),
+            limits: self.limits,
+            stages: RefCell::default(),
+        })
+    }
+}
+
+/// Where the templates of a [`CycleSpitter`] come from.
+enum Templates {
+    /// A template source, read and parsed on every run.
+    Source(Box<dyn SourceProvider>),
+    Set(TemplateSet),
+}
+
+/// The results of a pipeline stage, one per source, each kept with the source text it was made
+/// of, so that a run over an unchanged source skips the stage.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::runner::StageCache;
+///
+/// let mut cache = StageCache::default();
+/// let mut runs = 0;
+/// for text in ["move.w d0,d1", "move.w d0,d1", "move.w d1,d2"] {
+///     let lines = cache
+///         .get_or_run("effect.s", text.to_string(), |text| {
+///             runs += 1;
+///             Ok::<_, String>(vec![text.to_string()])
+///         })
+///         .unwrap();
+///     assert_eq!(lines[0], text);
+/// }
+/// assert_eq!(runs, 2);
+/// ```
+pub struct StageCache<T> {
+    entries: HashMap<String, (String, T)>,
+}
+
+impl<T> Default for StageCache<T> {
+    fn default() -> Self {
+        StageCache {
+            entries: HashMap::new(),
+        }
+    }
+}
+
+impl<T> StageCache<T> {
+    /// Returns the result of the stage for the source `name` read as `text`, running `stage` on
+    /// `text` unless the kept result was made of the same text.
+    ///
+    /// # Errors
+    /// The error of `stage`; the previous result of `name` is dropped then.
+    pub fn get_or_run<E>(
+        &mut self,
+        name: &str,
+        text: String,
+        stage: impl FnOnce(&str) -> Result<T, E>,
+    ) -> Result<&T, E> {
+        if self
+            .entries
+            .get(name)
+            .is_none_or(|(made_of, _)| *made_of != text)
+        {
+            self.entries.remove(name);
+            let result = stage(&text)?;
+            self.entries.insert(name.to_string(), (text, result));
+        }
+        Ok(&self.entries[name].1)
+    }
+
+    /// Drops all results, e.g. when a setting they depend on changed.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+/// The results of the stages of the previous run.
+#[derive(Default)]
+struct Stages {
+    /// The flattened input lines and their positions in the input.
+    input: StageCache<(Vec<String>, SourceMap)>,
+    /// The templates parsed from a template source.
+    templates: StageCache<TemplateSet>,
+}
+
+/// Runs the splitter as configured by a [`CycleSpitterBuilder`]. The sources are read on
+/// every run; stages whose source did not change since the previous run are not repeated.
+pub struct CycleSpitter {
+    input: Box<dyn SourceProvider>,
+    template: Templates,
+    options: Options,
+    label: String,
+    db: CycleDatabase,
+    limits: ExpansionLimits,
+    stages: RefCell<Stages>,
+}
+
+impl CycleSpitter {
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    /// Replaces the generation options for the following runs. The flattened input is kept,
+    /// and so are the parsed templates unless the cost of a NOP changes, which their timing
+    /// depends on.
+    ///
+    /// # Example
+    /// ```rust
+    /// use cycle_spitter::CycleSpitterBuilder;
+    /// use cycle_spitter::options::OptionsBuilder;
+    /// use cycle_spitter::source::StringSource;
+    ///
+    /// let mut spitter = CycleSpitterBuilder::new()
+    ///     .input(StringSource::new("effect.s", "move.w d1,d2\nmove.w d2,d3"))
+    ///     .template(StringSource::new("template.s", "move.w d0,d1\ndcb.w 1,$4e71"))
+    ///     .cycles_per_line(8)
+    ///     .build()
+    ///     .unwrap();
+    /// assert_eq!(spitter.run().unwrap().scanlines, 2);
+    /// spitter.set_options(OptionsBuilder::default().cycles_per_line(12));
+    /// assert_eq!(spitter.options().cycles_per_line(), 12);
+    /// ```
+    pub fn set_options(&mut self, options: OptionsBuilder) {
+        let options = options.build();
+        if options.nop_cycles() != self.options.nop_cycles() {
+            self.stages.get_mut().templates.clear();
+        }
+        self.options = options;
+    }
+
+    /// Reads the sources and generates the scanlines, printing warnings to standard error.
+    ///
+    /// # Errors
+    /// See [`CycleSpitter::run_with_observer`].
+    pub fn run(&self) -> Result<Generation, CycleSpitterError> {
+        self.run_with_observer(&mut StderrObserver)
+    }
+
+    /// Same as [`CycleSpitter::run`], but reports to `observer` (e.g. a
+    /// [`Diagnostics`](crate::diagnostics::Diagnostics)) instead of standard error. Diagnostics
+    /// referring to an input line carry its position in the input source.
+    ///
+    /// # Errors
+    /// - If a source cannot be read ([`CycleSpitterError::Io`] for files and standard input).
+    /// - If the input has unbalanced block directives or expands beyond the limits.
+    /// - If the template cannot be parsed ([`CycleSpitterError::Template`]).
+    /// - For the reasons listed at [`generate`](crate::generator::generate).
+    pub fn run_with_observer(
+        &self,
+        observer: &mut dyn Observer,
+    ) -> Result<Generation, CycleSpitterError> {
+        let mut stages = self.stages.borrow_mut();
+        let Stages { input, templates } = &mut *stages;
+        let name = self.input.name();
+        let (flat_lines, map) = input.get_or_run(&name, self.input.read()?, |input| {
+            let (lines, map) = locate_lines(&name, input);
+            flatten(&name, &lines, &map, &self.limits)
+        })?;
+        let templates = match &self.template {
+            Templates::Source(source) => {
+                let name = source.name();
+                templates.get_or_run(&name, source.read()?, |template| {
+                    parse_template_with(template, &self.db, &self.options)
+                        .map(TemplateSet::from)
+                        .map_err(|e| CycleSpitterError::Template(format!("{}: {}", name, e)))
+                })?
+            }
+            Templates::Set(templates) => templates,
+        };
+        generate_with_observer(
+            flat_lines,
+            templates,
+            &self.db,
+            &self.options,
+            &mut Locator {
+                map,
+                inner: observer,
+            },
+        )
+    }
+
+    /// Wraps `generation` into a [`Document`] for one of the emitters.
+    pub fn document(&self, generation: &Generation) -> Document {
+        let template = match &self.template {
+            Templates::Source(source) => source.name(),
+            Templates::Set(templates) => templates.names().collect::<Vec<_>>().join(", "),
+        };
+        Document {
+            header: vec![
+                "This file is generated using".to_string(),
+                "cycleSpitter (c) 2025 - slippy / vectronix".to_string(),
+                format!("Total scanlines created: {}", generation.scanlines),
+                format!("Template used: {}", template),
+            ],
+            label: self.label.clone(),
+            scanlines: generation.scanlines,
+            imports: Vec::new(),
+            lines: generation.lines.clone(),
+        }
+    }
+}
+
+/// Flattens the trimmed `lines` of the input `name` for generation: joins continuation lines
+/// and expands `REPT` blocks within `limits`. Returns the flattened lines and `map` (locating
+/// `lines`) carried over to them.
+///
+/// # Errors
+/// If the block directives are unbalanced or the expansion exceeds `limits`.
+pub(crate) fn flatten(
+    name: &str,
+    lines: &[String],
+    map: &SourceMap,
+    limits: &ExpansionLimits,
+) -> Result<(Vec<String>, SourceMap), Box<dyn Error>> {
+    if let Some(issue) = validate_block_structure(lines).first() {
+        return Err(format!("{}:{}: {}", name, issue.line, issue.problem).into());
+    }
+    let (joined, origins) = join_continuations_with_origins(lines);
+    let map = map.remap(&origins);
+    let (flat_lines, origins) =
+        expand_block_with_origins(&joined, limits).map_err(|e| format!("{}: {}", name, e))?;
+    Ok((flat_lines, map.remap(&origins)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cycle_spitter::diagnostics::{DiagnosticKind, Diagnostics};
+    use crate::cycle_spitter::frame::FrameLayout;
+    use crate::cycle_spitter::source::StringSource;
+
+    #[test]
+    fn test_build_requires_sources() {
+        let err = CycleSpitterBuilder::new().build().err().unwrap();
+        assert_eq!(err.to_string(), "No input source given");
+        let err = CycleSpitterBuilder::new()
+            .input(StringSource::new("input", "nop"))
+            .build()
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "No template given");
+    }
+
+    #[test]
+    fn test_run_with_in_memory_templates_and_database() {
+        use crate::cycle_spitter::database::{CycleEntry, CycleKind};
+        use crate::cycle_spitter::template::TemplateSection;
+
+        let db: CycleDatabase = [(
+            "move.w dn,dn".to_string(),
+            CycleEntry::from_values(CycleKind::Plain, &[8]),
+        )]
+        .into_iter()
+        .collect();
+        let mut templates = TemplateSet::new();
+        let left = TemplateSection::new("left", vec![("move.w d0,d1".to_string(), 4)], 12);
+        templates.insert("scroller", vec![left]).unwrap();
+        let spitter = CycleSpitterBuilder::new()
+            .input(StringSource::new("effect.s", "move.w d1,d2\nmove.w d2,d3"))
+            .templates(templates)
+            .database(db)
+            .cycles_per_line(16)
+            .build()
+            .unwrap();
+        let generation = spitter.run().unwrap();
+        // The database makes the moves 8 cycles, so only one fits the 12 cycle window.
+        assert_eq!(generation.scanlines, 2);
+        assert_eq!(
+            spitter.document(&generation).header[3],
+            "Template used: scroller"
+        );
+    }
+
+    #[test]
+    fn test_run_reuses_unchanged_stages() {
+        use std::rc::Rc;
+
+        /// A template being edited between runs.
+        struct Edited(Rc<RefCell<String>>);
+
+        impl SourceProvider for Edited {
+            fn name(&self) -> String {
+                "template.s".to_string()
+            }
+
+            fn read(&self) -> Result<String, Box<dyn Error>> {
+                Ok(self.0.borrow().clone())
+            }
+        }
+
+        let template = Rc::new(RefCell::new("move.w d0,d1\ndcb.w 1,$4e71".to_string()));
+        let mut spitter = CycleSpitterBuilder::new()
+            .input(StringSource::new(
+                "effect.s",
+                "rept 2\n\tmove.w d1,d2\nendr",
+            ))
+            .template(Edited(Rc::clone(&template)))
+            .cycles_per_line(8)
+            .build()
+            .unwrap();
+        assert_eq!(spitter.run().unwrap().scanlines, 2);
+        let flattened = |spitter: &CycleSpitter| {
+            spitter.stages.borrow().input.entries["effect.s"]
+                .1
+                .0
+                .as_ptr()
+        };
+        let parsed = |spitter: &CycleSpitter| {
+            let stages = spitter.stages.borrow();
+            let templates = &stages.templates.entries["template.s"].1;
+            templates.get(TemplateSet::DEFAULT_NAME).unwrap().as_ptr()
+        };
+        let before = flattened(&spitter);
+
+        // A template-only change parses the template again, but does not flatten the input.
+        *template.borrow_mut() = "move.w d0,d1\ndcb.w 2,$4e71".to_string();
+        spitter.set_options(OptionsBuilder::default().cycles_per_line(12));
+        assert_eq!(spitter.run().unwrap().scanlines, 1);
+        assert_eq!(flattened(&spitter), before);
+
+        // A layout-only change neither flattens the input nor parses the template again.
+        let sections = parsed(&spitter);
+        spitter.set_options(
+            OptionsBuilder::default()
+                .cycles_per_line(12)
+                .frame_layout(FrameLayout::parse("0- default").unwrap()),
+        );
+        assert_eq!(spitter.run().unwrap().scanlines, 1);
+        assert_eq!(flattened(&spitter), before);
+        assert_eq!(parsed(&spitter), sections);
+
+        // A different NOP cost changes the timing of the template, which is parsed again.
+        spitter.set_options(OptionsBuilder::default().cycles_per_line(12).nop_cycles(8));
+        spitter.run().unwrap();
+        let stages = spitter.stages.borrow();
+        let templates = &stages.templates.entries["template.s"].1;
+        assert_eq!(
+            templates.get(TemplateSet::DEFAULT_NAME).unwrap()[0].nop_cycles,
+            16
+        );
+    }
+
+    #[test]
+    fn test_run_names_the_failing_source() {
+        let spitter = CycleSpitterBuilder::new()
+            .input(StringSource::new("effect.s", "rept 2\nnop"))
+            .template(StringSource::new("template.s", "dcb.w 4,$4e71"))
+            .build()
+            .unwrap();
+        let err = spitter.run().unwrap_err();
+        assert_eq!(err.to_string(), "effect.s:1: REPT without matching ENDR");
+    }
+
+    #[test]
+    fn test_run_locates_diagnostics() {
+        let spitter = CycleSpitterBuilder::new()
+            .input(StringSource::new(
+                "effect.s",
+                "rept 2\n\tmove.w d0,d1\nendr\n\tfrobnicate d0",
+            ))
+            .template(StringSource::new(
+                "template.s",
+                "move.w d0,d1\ndcb.w 4,$4e71",
+            ))
+            .cycles_per_line(20)
+            .build()
+            .unwrap();
+        let mut diagnostics = Diagnostics::default();
+        spitter.run_with_observer(&mut diagnostics).unwrap();
+
+        let unknown = diagnostics
+            .of_kind(DiagnosticKind::UnknownInstruction)
+            .next()
+            .unwrap();
+        assert_eq!(unknown.index, Some(2));
+        assert_eq!(
+            unknown.to_string(),
+            "effect.s:4:2: No cycle count found for instruction: frobnicate d0"
+        );
+    }
+}
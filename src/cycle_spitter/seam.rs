@@ -0,0 +1,229 @@
+// src/cycle_spitter/seam.rs
+
+//! Jumps joining generated code: blocks that loop every frame and chunks that chain into each
+//! other.
+//!
+//! # Loop seams
+//!
+//! An effect running the generated block once per frame jumps back to the start of the block
+//! at the end of the last scanline, usually after some code of fixed length between the frames,
+//! such as a VBL wait padded to a known cycle count. The loop only stays in step with the beam
+//! if the seam is cycle-exact: the code of the last scanline, the jump back and the code between
+//! the frames must take exactly one scanline, counted up to the entry offset of the first
+//! scanline where the jump lands.
+//!
+//! With a [`LoopSeam`] in the options, the generator puts the label at the start of the block,
+//! ends the last scanline early enough for the jump back and the wait, pads it up to that point
+//! and appends the jump. If the code of the last scanline does not fit before the seam, it is
+//! followed by one more scanline holding just the template, the padding and the jump. The exit
+//! offset is not used, as a looping block has no epilogue.
+//!
+//! # Chunk chains
+//!
+//! A long block may have to be split into pieces that are assembled or loaded separately, so
+//! the pieces no longer follow each other in memory. With a [`ChunkChain`] in the options, the
+//! generator cuts the block every `every` scanlines: the last scanline of a chunk ends with a
+//! jump to the label starting the next chunk, and its code is padded up to the jump so the
+//! scanline keeps its length.
+
+use crate::cycle_spitter::cycles::lookup_cycles;
+use crate::cycle_spitter::database::CycleDatabase;
+use std::error::Error;
+
+/// The seam between the last and the first scanline of a looping block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoopSeam {
+    /// The label placed at the start of the block and jumped to.
+    pub label: String,
+    /// The cycles of the code run between the jump back and the first scanline.
+    pub wait: usize,
+}
+
+impl LoopSeam {
+    /// Creates a seam jumping back to `label` after `wait` cycles of code between the frames.
+    pub fn new(label: &str, wait: usize) -> Self {
+        LoopSeam {
+            label: label.to_string(),
+            wait,
+        }
+    }
+
+    /// The instruction jumping back to the start of the block.
+    pub fn jump(&self) -> String {
+        format!("jmp\t{}", self.label)
+    }
+
+    /// Returns the cycles the last scanline leaves free after its code: those of the jump back
+    /// and the wait, less the `entry` offset of the first scanline they run into.
+    ///
+    /// # Errors
+    /// If the jump back and the wait are shorter than the entry offset, as the seam would then
+    /// have to stretch the last scanline.
+    ///
+    /// # Example
+    /// ```rust
+    /// use cycle_spitter::database::CycleDatabase;
+    /// use cycle_spitter::seam::LoopSeam;
+    ///
+    /// let db = CycleDatabase::builtin();
+    /// // A 12 cycle `jmp xxx.l` and 100 cycles of waiting, of which 40 run in the first scanline
+    /// assert_eq!(LoopSeam::new("loop", 100).reserve(&db, 40).unwrap(), 72);
+    /// assert!(LoopSeam::new("loop", 0).reserve(&db, 40).is_err());
+    /// ```
+    pub fn reserve(&self, db: &CycleDatabase, entry: usize) -> Result<usize, Box<dyn Error>> {
+        let seam = self.jump_cycles(db) + self.wait;
+        seam.checked_sub(entry).ok_or_else(|| {
+            format!(
+                "The loop seam takes {} cycles, fewer than the entry offset of {}; lengthen the \
+                 wait by {} cycles",
+                seam,
+                entry,
+                entry - seam
+            )
+            .into()
+        })
+    }
+
+    /// The cycles of the jump back.
+    pub fn jump_cycles(&self, db: &CycleDatabase) -> usize {
+        lookup_cycles(&self.jump(), db).total()
+    }
+}
+
+/// The cuts of a block into chunks of a fixed number of scanlines.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkChain {
+    /// The number of scanlines of every chunk.
+    pub every: usize,
+    /// The prefix of the chunk labels; chunk `k` starts at `<prefix>_<k>`.
+    pub prefix: String,
+}
+
+impl ChunkChain {
+    /// Creates a chain cutting the block every `every` scanlines, labelling the chunks with
+    /// `prefix`.
+    ///
+    /// # Errors
+    /// If `every` is 0.
+    pub fn new(every: usize, prefix: &str) -> Result<Self, Box<dyn Error>> {
+        if every == 0 {
+            return Err("A chunk needs at least one scanline".into());
+        }
+        Ok(ChunkChain {
+            every,
+            prefix: prefix.to_string(),
+        })
+    }
+
+    /// The label of chunk `chunk`.
+    pub fn label(&self, chunk: usize) -> String {
+        format!("{}_{}", self.prefix, chunk)
+    }
+
+    /// The instruction jumping to chunk `chunk`.
+    pub fn jump(&self, chunk: usize) -> String {
+        format!("jmp\t{}", self.label(chunk))
+    }
+
+    /// Returns `true` if `scanline` (counted from the start of the block) starts a chunk other
+    /// than the first.
+    pub fn starts_chunk(&self, scanline: usize) -> bool {
+        scanline > 0 && scanline.is_multiple_of(self.every)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cycle_spitter::checksum::TOTAL_CYCLES_PREFIX;
+    use crate::cycle_spitter::generator::generate;
+    use crate::cycle_spitter::options::Options;
+    use crate::cycle_spitter::template::{TemplateSection, TemplateSet};
+
+    #[test]
+    fn test_generate_chains_chunks() {
+        let db = CycleDatabase::builtin();
+        let templates = TemplateSet::from(vec![TemplateSection::code_window(64, "code")]);
+        let input = vec!["move.w d0,d1".to_string(); 40];
+        let options = Options::builder()
+            .cycles_per_line(64)
+            .chunk_chain(ChunkChain::new(2, "part").unwrap())
+            .build();
+        let generation = generate(&input, &templates, &db, &options).unwrap();
+
+        // Scanline 1 ends the first chunk with a jump, leaving room for 13 moves. The last
+        // scanline ends the block and needs none.
+        assert_eq!(generation.scanlines, 3);
+        let user: Vec<_> = generation.usage.iter().map(|u| u.user_cycles()).collect();
+        assert_eq!(user, [64, 52, 44]);
+        let jumps: Vec<_> = generation
+            .lines
+            .iter()
+            .filter(|l| l.starts_with("jmp"))
+            .collect();
+        assert_eq!(jumps.len(), 1);
+        assert!(jumps[0].starts_with("jmp\tpart_1\t;\t(12)"));
+        let label = generation
+            .lines
+            .iter()
+            .position(|l| l == "part_1:")
+            .unwrap();
+        let jump = generation
+            .lines
+            .iter()
+            .position(|l| l.starts_with("jmp"))
+            .unwrap();
+        assert_eq!(
+            generation.lines[jump + 1],
+            format!("{}{}", TOTAL_CYCLES_PREFIX, 64)
+        );
+        assert!(jump < label);
+        assert!(ChunkChain::new(0, "part").is_err());
+    }
+
+    #[test]
+    fn test_generate_closes_the_loop() {
+        let db = CycleDatabase::builtin();
+        let templates = TemplateSet::from(vec![TemplateSection::code_window(64, "code")]);
+        let input = vec!["move.w d0,d1".to_string(); 20];
+        let options = Options::builder()
+            .cycles_per_line(64)
+            .entry_offset(8)
+            .loop_seam(LoopSeam::new("frame", 20))
+            .build();
+        let generation = generate(&input, &templates, &db, &options).unwrap();
+        assert_eq!(generation.lines[0], "frame:");
+
+        // Scanline 0 holds 14 moves after the entry offset. The last scanline ends 12 + 20 - 8
+        // cycles early with the jump back, so its 6 moves are padded up to cycle 40.
+        assert_eq!(generation.scanlines, 2);
+        let total = generation
+            .lines
+            .iter()
+            .rposition(|l| l.starts_with(TOTAL_CYCLES_PREFIX))
+            .unwrap();
+        assert!(generation.lines[total - 1].starts_with("jmp\tframe\t;\t(12)"));
+        assert_eq!(
+            generation.lines[total],
+            format!("{}{}", TOTAL_CYCLES_PREFIX, 40 + 12)
+        );
+        assert_eq!(generation.usage[1].padding_cycles(), 40 - 24);
+
+        // Code that does not fit before the seam gets a scanline of its own.
+        let input = vec!["move.w d0,d1".to_string(); 25];
+        let generation = generate(&input, &templates, &db, &options).unwrap();
+        assert_eq!(generation.scanlines, 3);
+        assert_eq!(generation.usage[2].user_cycles(), 0);
+
+        // A seam longer than the last scanline cannot be closed.
+        let long = Options::builder()
+            .cycles_per_line(64)
+            .loop_seam(LoopSeam::new("frame", 60))
+            .build();
+        let error = generate(&input, &templates, &db, &long).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The loop seam takes 72 of the 64 cycles of the last scanline"
+        );
+    }
+}
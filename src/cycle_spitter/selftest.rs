@@ -0,0 +1,239 @@
+// src/cycle_spitter/selftest.rs
+
+//! Consistency check between the normalizer and the keys of a cycle database.
+//!
+//! Lookups only succeed for keys the normalizer can produce. A key that does not normalize to
+//! itself is dead weight at best - written for an older normalization grammar or with a typo -
+//! and hides a missing entry at worst, so every key is round-tripped through
+//! [`normalize_line_ext`].
+
+use crate::cycle_spitter::cycles::{is_canonical, normalize_line_ext};
+use crate::cycle_spitter::database::CycleDatabase;
+use std::fmt;
+
+/// Why a database key fails the self-test.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyProblem {
+    /// The key does not conform to the normalized form grammar, so no input can reach it.
+    Unreachable,
+    /// The key normalizes to a different key. `known` tells whether that key is in the
+    /// database, too.
+    NotIdempotent { normalized: String, known: bool },
+}
+
+/// A database key failing the self-test.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyIssue {
+    pub key: String,
+    pub problem: KeyProblem,
+}
+
+impl fmt::Display for KeyIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.problem {
+            KeyProblem::Unreachable => {
+                write!(
+                    f,
+                    "'{}' is not in normalized form and unreachable",
+                    self.key
+                )
+            }
+            KeyProblem::NotIdempotent { normalized, known } => write!(
+                f,
+                "'{}' normalizes to '{}'{}",
+                self.key,
+                normalized,
+                if *known {
+                    " (a separate entry)"
+                } else {
+                    " (not in the database)"
+                }
+            ),
+        }
+    }
+}
+
+/// The result of [`self_test`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SelfTestReport {
+    /// The number of keys checked.
+    pub checked: usize,
+    /// The failing keys, sorted by key.
+    pub issues: Vec<KeyIssue>,
+}
+
+impl SelfTestReport {
+    /// Returns `true` if every key normalizes to itself.
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+/// Round-trips every key of `db` through the normalizer.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::database::CycleDatabase;
+/// use cycle_spitter::selftest::self_test;
+///
+/// let db = CycleDatabase::from_json_str(r#"{ "nop": [4], "MOVE.W D0,D1": [4] }"#).unwrap();
+/// let report = self_test(&db);
+/// assert_eq!(report.checked, 2);
+/// assert_eq!(report.issues[0].key, "MOVE.W D0,D1");
+/// ```
+pub fn self_test(db: &CycleDatabase) -> SelfTestReport {
+    let mut keys: Vec<&str> = db.keys().collect();
+    keys.sort_unstable();
+    let issues = keys.iter().filter_map(|key| check_key(key, db)).collect();
+    SelfTestReport {
+        checked: keys.len(),
+        issues,
+    }
+}
+
+/// Round-trips a single key through the normalizer, returning its issue if it does not
+/// normalize to itself. `db` tells whether the key it normalizes to is known.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::database::CycleDatabase;
+/// use cycle_spitter::selftest::check_key;
+///
+/// let db = CycleDatabase::default();
+/// assert!(check_key("add.w dn,dn", &db).is_none());
+/// assert!(check_key("add.w d0,d1", &db).is_some());
+/// ```
+pub fn check_key(key: &str, db: &CycleDatabase) -> Option<KeyIssue> {
+    let problem = if !is_canonical(key) {
+        KeyProblem::Unreachable
+    } else {
+        let (normalized, _) = normalize_line_ext(key);
+        if normalized == key {
+            return None;
+        }
+        KeyProblem::NotIdempotent {
+            known: db.contains(&normalized),
+            normalized,
+        }
+    };
+    Some(KeyIssue {
+        key: key.to_string(),
+        problem,
+    })
+}
+
+/// Concrete operands standing in for the placeholders of normalized keys, longest first so a
+/// placeholder is never replaced inside another one.
+const EXAMPLE_OPERANDS: [(&str, &str); 13] = [
+    ("d(an,ix)", "8(a1,d2.w)"),
+    ("d(pc,ix)", "8(pc,d2.w)"),
+    ("d(pc)", "8(pc)"),
+    ("d(an)", "8(a1)"),
+    ("-(an)", "-(a1)"),
+    ("(an)+", "(a1)+"),
+    ("(an)", "(a1)"),
+    ("#xxx", "#1"),
+    ("xxx.w", "$8240.w"),
+    ("xxx.l", "label"),
+    ("reglist", "d0-d3/a0"),
+    ("dn", "d0"),
+    ("an", "a1"),
+];
+
+/// Synthesizes a concrete instruction that normalizes to `key`, reversing the normalization:
+/// placeholders become registers, addresses and immediates, and short branches (`bne.b`) are
+/// written with the `.s` suffix assemblers expect.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::selftest::example_instruction;
+///
+/// assert_eq!(example_instruction("add.w d(an,ix),dn"), "add.w 8(a1,d2.w),d0");
+/// assert_eq!(example_instruction("bne.b xxx.l"), "bne.s label");
+/// ```
+pub fn example_instruction(key: &str) -> String {
+    let (mnemonic, operands) = key.split_once(' ').unwrap_or((key, ""));
+    let mnemonic = match mnemonic.strip_suffix(".b") {
+        Some(branch) if branch.len() == 3 && branch.starts_with('b') => format!("{}.s", branch),
+        _ => mnemonic.to_string(),
+    };
+    let operands = EXAMPLE_OPERANDS
+        .iter()
+        .fold(operands.to_string(), |operands, (placeholder, example)| {
+            operands.replace(placeholder, example)
+        });
+    if operands.is_empty() {
+        mnemonic
+    } else {
+        format!("{} {}", mnemonic, operands)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cycle_spitter::cycles::lookup_cycles;
+
+    #[test]
+    fn test_builtin_database_common_keys_pass() {
+        let db = CycleDatabase::builtin();
+        let report = self_test(&db);
+        assert_eq!(report.checked, db.len());
+        for key in [
+            "move.w dn,dn",
+            "move.l (an)+,(an)+",
+            "lea.l d(an),an",
+            "nop",
+        ] {
+            assert!(
+                !report.issues.iter().any(|issue| issue.key == key),
+                "{}",
+                key
+            );
+        }
+    }
+
+    /// Every entry of the bundled database must be reachable from real source: a concrete
+    /// instruction synthesized from its key has to be looked up as that key.
+    #[test]
+    fn test_every_database_entry_is_reachable_from_an_example() {
+        let db = CycleDatabase::builtin();
+        let mut unreachable = Vec::new();
+        for key in db.keys() {
+            let example = example_instruction(key);
+            let cycles = lookup_cycles(&example, &db);
+            if cycles.get_lookup() != key {
+                unreachable.push(format!(
+                    "'{}' (as '{}') looks up '{}'",
+                    key,
+                    example,
+                    cycles.get_lookup()
+                ));
+            }
+        }
+        unreachable.sort();
+        assert!(unreachable.is_empty(), "{}", unreachable.join("\n"));
+    }
+
+    #[test]
+    fn test_reports_problems() {
+        let db = CycleDatabase::from_json_str(
+            r#"{ "move.w dn,dn": [4], "move.w d0,dn": [4], "move.w d0,d1": [4], "frob dn": [4] }"#,
+        )
+        .unwrap();
+        let report = self_test(&db);
+        assert_eq!(report.checked, 4);
+        assert_eq!(
+            report
+                .issues
+                .iter()
+                .map(|i| i.to_string())
+                .collect::<Vec<_>>(),
+            [
+                "'frob dn' normalizes to 'frob.w dn' (not in the database)",
+                "'move.w d0,d1' is not in normalized form and unreachable",
+                "'move.w d0,dn' is not in normalized form and unreachable",
+            ]
+        );
+    }
+}
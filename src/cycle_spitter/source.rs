@@ -0,0 +1,254 @@
+// src/cycle_spitter/source.rs
+
+//! Where the input comes from.
+//!
+//! The generator works on lines; a [`SourceProvider`] acquires them. Besides files
+//! ([`FileSource`]) and standard input ([`StdinSource`]), editor plugins and other library
+//! users can pass in-memory buffers ([`StringSource`]) without going through temporary files,
+//! and [`ProjectSource`] joins several sources into one program, in order.
+
+use crate::cycle_spitter::error::CycleSpitterError;
+use crate::cycle_spitter::location::SourceMap;
+use std::borrow::Cow;
+use std::error::Error;
+use std::io::Read;
+use std::path::PathBuf;
+
+/// Converts the line endings of `content` to `\n`: Windows (`\r\n`) as well as lone `\r`, as
+/// left by classic Mac editors or by files whose endings were converted twice (`\r\r\n`).
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::source::normalize_line_endings;
+///
+/// assert_eq!(normalize_line_endings("nop\r\nrts\r\r\nbra.s loop\r"), "nop\nrts\n\nbra.s loop\n");
+/// ```
+pub fn normalize_line_endings(content: &str) -> Cow<'_, str> {
+    if content.contains('\r') {
+        Cow::Owned(content.replace("\r\n", "\n").replace('\r', "\n"))
+    } else {
+        Cow::Borrowed(content)
+    }
+}
+
+/// Converts the separators of `path` to those of the platform, so paths written on Windows
+/// (`gfx\logo.bin`) also work elsewhere. Windows accepts both separators anyway.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::source::native_path;
+///
+/// let path = native_path("gfx\\logo.bin");
+/// assert_eq!(path.file_name().unwrap(), "logo.bin");
+/// ```
+pub fn native_path(path: &str) -> PathBuf {
+    if cfg!(windows) {
+        PathBuf::from(path)
+    } else {
+        PathBuf::from(path.replace('\\', "/"))
+    }
+}
+
+/// A source of assembly input.
+pub trait SourceProvider {
+    /// The name of the source in messages, e.g. its path.
+    fn name(&self) -> String;
+
+    /// Reads the whole source.
+    ///
+    /// # Errors
+    /// If the source cannot be read.
+    fn read(&self) -> Result<String, Box<dyn Error>>;
+
+    /// Reads the source as trimmed lines, whatever its line endings.
+    ///
+    /// # Errors
+    /// If the source cannot be read.
+    fn lines(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        Ok(normalize_line_endings(&self.read()?)
+            .lines()
+            .map(|line| line.trim().to_string())
+            .collect())
+    }
+
+    /// Same as [`SourceProvider::lines`], but also returns the map locating the lines in the
+    /// source.
+    ///
+    /// # Errors
+    /// If the source cannot be read.
+    fn located_lines(&self) -> Result<(Vec<String>, SourceMap), Box<dyn Error>> {
+        Ok(locate_lines(&self.name(), &self.read()?))
+    }
+}
+
+/// Splits the text `read` from the source `name` into trimmed lines and their positions.
+pub(crate) fn locate_lines(name: &str, read: &str) -> (Vec<String>, SourceMap) {
+    let content = normalize_line_endings(read);
+    let lines = content
+        .lines()
+        .map(|line| line.trim().to_string())
+        .collect();
+    (lines, SourceMap::new(name, &content))
+}
+
+/// A source file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileSource {
+    pub path: PathBuf,
+}
+
+impl FileSource {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        FileSource { path: path.into() }
+    }
+}
+
+impl SourceProvider for FileSource {
+    fn name(&self) -> String {
+        self.path.display().to_string()
+    }
+
+    fn read(&self) -> Result<String, Box<dyn Error>> {
+        std::fs::read_to_string(&self.path).map_err(|e| {
+            CycleSpitterError::Io {
+                what: format!("input file '{}'", self.path.display()),
+                source: e,
+            }
+            .into()
+        })
+    }
+}
+
+/// Standard input, read until its end.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StdinSource;
+
+impl SourceProvider for StdinSource {
+    fn name(&self) -> String {
+        "<stdin>".to_string()
+    }
+
+    fn read(&self) -> Result<String, Box<dyn Error>> {
+        let mut content = String::new();
+        std::io::stdin()
+            .read_to_string(&mut content)
+            .map_err(|e| CycleSpitterError::Io {
+                what: "standard input".to_string(),
+                source: e,
+            })?;
+        Ok(content)
+    }
+}
+
+/// An in-memory buffer.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::source::{SourceProvider, StringSource};
+///
+/// let source = StringSource::new("buffer", "  move.w d0,d1  \n\tnop");
+/// assert_eq!(source.lines().unwrap(), ["move.w d0,d1", "nop"]);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StringSource {
+    pub name: String,
+    pub content: String,
+}
+
+impl StringSource {
+    pub fn new(name: impl Into<String>, content: impl Into<String>) -> Self {
+        StringSource {
+            name: name.into(),
+            content: content.into(),
+        }
+    }
+}
+
+impl SourceProvider for StringSource {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn read(&self) -> Result<String, Box<dyn Error>> {
+        Ok(self.content.clone())
+    }
+}
+
+/// Several sources forming one program, read one after the other. Line numbers in messages
+/// count through all parts.
+#[derive(Default)]
+pub struct ProjectSource {
+    parts: Vec<Box<dyn SourceProvider>>,
+}
+
+impl ProjectSource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a source to the project.
+    pub fn with(mut self, part: impl SourceProvider + 'static) -> Self {
+        self.parts.push(Box::new(part));
+        self
+    }
+}
+
+impl SourceProvider for ProjectSource {
+    fn name(&self) -> String {
+        self.parts
+            .iter()
+            .map(|part| part.name())
+            .collect::<Vec<_>>()
+            .join("+")
+    }
+
+    fn read(&self) -> Result<String, Box<dyn Error>> {
+        let mut content = String::new();
+        for part in &self.parts {
+            content.push_str(&part.read()?);
+            if !content.is_empty() && !content.ends_with('\n') {
+                content.push('\n');
+            }
+        }
+        Ok(content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_project_source() {
+        let project = ProjectSource::new()
+            .with(StringSource::new("init", "moveq #0,d0"))
+            .with(StringSource::new("loop", "add.w d0,d1\nnop\n"));
+        assert_eq!(project.name(), "init+loop");
+        assert_eq!(
+            project.lines().unwrap(),
+            ["moveq #0,d0", "add.w d0,d1", "nop"]
+        );
+    }
+
+    #[test]
+    fn test_lines_accept_any_line_ending() {
+        let source = StringSource::new("effect.s", "moveq #0,d0\r\n  nop \r\rrts");
+        let (lines, map) = source.located_lines().unwrap();
+        assert_eq!(lines, ["moveq #0,d0", "nop", "", "rts"]);
+        assert_eq!(map.locate(1).unwrap().to_string(), "effect.s:2:3");
+        assert_eq!(map.locate(3).unwrap().to_string(), "effect.s:4:1");
+    }
+
+    #[test]
+    fn test_file_source_errors_name_the_path() {
+        let source = FileSource::new("/nonexistent/input.s");
+        assert_eq!(source.name(), "/nonexistent/input.s");
+        assert!(
+            source
+                .read()
+                .unwrap_err()
+                .to_string()
+                .starts_with("Failed to read input file '/nonexistent/input.s'")
+        );
+    }
+}
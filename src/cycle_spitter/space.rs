@@ -0,0 +1,179 @@
+// src/cycle_spitter/space.rs
+
+//! The free cycles of every scanline section, for code generated at runtime.
+//!
+//! Runtime code generators poke instructions into the padding of a generated buffer. They need
+//! to know where every section starts and how many of its cycles are padding: the padding of a
+//! section follows its template and user code, and the padding at the end of the scanline forms
+//! a final `tail` section. Cycles count from the first generated instruction of the scanline.
+//!
+//! [`free_space`] collects these numbers from the usage of a generation; they can be exported
+//! as JSON ([`free_space_json`]) or as assembler equates ([`free_space_equates`]).
+
+use crate::cycle_spitter::generator::ScanlineUsage;
+use serde::Serialize;
+use std::fmt::Write;
+
+/// The label of the padding at the end of a scanline.
+pub const TAIL_LABEL: &str = "tail";
+
+/// Where a scanline section starts and how much of it is free.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SectionSpace {
+    /// The scanline, counted from 0.
+    pub scanline: usize,
+    /// The index of the section within the scanline.
+    pub section: usize,
+    /// The section's label.
+    pub label: String,
+    /// The cycle at which the section starts.
+    pub start: usize,
+    /// The cycle at which the section's padding starts.
+    pub free_start: usize,
+    /// The cycles of padding.
+    pub free_cycles: usize,
+}
+
+/// Lists the sections of every scanline in `usage`, including the tail padding of scanlines
+/// that have one.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::generator::{ScanlineUsage, SectionUsage};
+/// use cycle_spitter::space::free_space;
+///
+/// let usage = ScanlineUsage {
+///     sections: vec![SectionUsage {
+///         label: "left".to_string(),
+///         template_cycles: 12,
+///         user_cycles: 20,
+///         padding_cycles: 8,
+///     }],
+///     tail_padding: 0,
+/// };
+/// let space = free_space(&[usage]);
+/// assert_eq!((space[0].free_start, space[0].free_cycles), (32, 8));
+/// ```
+pub fn free_space(usage: &[ScanlineUsage]) -> Vec<SectionSpace> {
+    let mut space = Vec::new();
+    for (scanline, line) in usage.iter().enumerate() {
+        let mut start = 0;
+        for (section, usage) in line.sections.iter().enumerate() {
+            let free_start = start + usage.template_cycles + usage.user_cycles;
+            space.push(SectionSpace {
+                scanline,
+                section,
+                label: usage.label.clone(),
+                start,
+                free_start,
+                free_cycles: usage.padding_cycles,
+            });
+            start = free_start + usage.padding_cycles;
+        }
+        if line.tail_padding > 0 {
+            space.push(SectionSpace {
+                scanline,
+                section: line.sections.len(),
+                label: TAIL_LABEL.to_string(),
+                start,
+                free_start: start,
+                free_cycles: line.tail_padding,
+            });
+        }
+    }
+    space
+}
+
+/// Renders `space` as a pretty-printed JSON array.
+pub fn free_space_json(space: &[SectionSpace]) -> String {
+    serde_json::to_string_pretty(space).unwrap_or_default() + "\n"
+}
+
+/// Renders `space` as assembler equates, three per section: `<PREFIX>_<scanline>_<section>_START`,
+/// `..._FREE_START` and `..._FREE`.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::space::{SectionSpace, free_space_equates};
+///
+/// let space = SectionSpace {
+///     scanline: 3,
+///     section: 1,
+///     label: "right".to_string(),
+///     start: 100,
+///     free_start: 140,
+///     free_cycles: 16,
+/// };
+/// assert!(free_space_equates(&[space], "SL").contains("SL_3_1_FREE\tequ 16\n"));
+/// ```
+pub fn free_space_equates(space: &[SectionSpace], prefix: &str) -> String {
+    let mut equates = String::from("; Free cycles per scanline section\n");
+    for entry in space {
+        let name = format!("{}_{}_{}", prefix, entry.scanline, entry.section);
+        let _ = writeln!(
+            equates,
+            "; scanline {} {} section",
+            entry.scanline, entry.label
+        );
+        let _ = writeln!(equates, "{}_START\tequ {}", name, entry.start);
+        let _ = writeln!(equates, "{}_FREE_START\tequ {}", name, entry.free_start);
+        let _ = writeln!(equates, "{}_FREE\tequ {}", name, entry.free_cycles);
+    }
+    equates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cycle_spitter::generator::SectionUsage;
+
+    fn section(label: &str, template: usize, user: usize, padding: usize) -> SectionUsage {
+        SectionUsage {
+            label: label.to_string(),
+            template_cycles: template,
+            user_cycles: user,
+            padding_cycles: padding,
+        }
+    }
+
+    #[test]
+    fn test_free_space() {
+        let usage = vec![
+            ScanlineUsage {
+                sections: vec![section("left", 12, 40, 4), section("right", 24, 0, 16)],
+                tail_padding: 0,
+            },
+            ScanlineUsage {
+                sections: vec![section("left", 12, 44, 0)],
+                tail_padding: 40,
+            },
+        ];
+        let space = free_space(&usage);
+        let summary: Vec<_> = space
+            .iter()
+            .map(|s| {
+                (
+                    s.scanline,
+                    s.label.as_str(),
+                    s.start,
+                    s.free_start,
+                    s.free_cycles,
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (0, "left", 0, 52, 4),
+                (0, "right", 56, 80, 16),
+                (1, "left", 0, 56, 0),
+                (1, "tail", 56, 56, 40),
+            ]
+        );
+
+        let json = free_space_json(&space);
+        assert!(json.contains("\"free_start\": 80"));
+        let equates = free_space_equates(&space, "FREE");
+        assert!(equates.contains("; scanline 1 tail section\nFREE_1_1_START\tequ 56\n"));
+    }
+}
@@ -0,0 +1,71 @@
+// src/cycle_spitter/split.rs
+
+//! The one-call entry point for build tools and editor plugins.
+//!
+//! [`split`] runs the same pipeline as the `cycleSpitter` binary without its command line:
+//! the input is flattened (continuation lines joined, `REPT` blocks expanded within the default
+//! limits), the template is parsed against the built-in cycle database, and the input is
+//! distributed over scanlines. Callers that need more control use the building blocks
+//! directly, e.g. [`parse_template`](crate::parse_template),
+//! [`process_block`](crate::process_block) and [`accumulate_chunk`](crate::accumulate_chunk).
+
+use crate::cycle_spitter::block::ExpansionLimits;
+use crate::cycle_spitter::database::CycleDatabase;
+use crate::cycle_spitter::error::CycleSpitterError;
+use crate::cycle_spitter::generator::{Generation, generate_with_observer};
+use crate::cycle_spitter::location::Locator;
+use crate::cycle_spitter::observer::StderrObserver;
+use crate::cycle_spitter::options::Options;
+use crate::cycle_spitter::runner::flatten;
+use crate::cycle_spitter::source::{SourceProvider, StringSource};
+use crate::cycle_spitter::template::{TemplateSet, parse_template_with};
+
+/// Splits the assembly source `input` into scanlines laid out by `template`, both given as
+/// file contents. Warnings are printed to standard error, those about an input line prefixed
+/// with its position (`input:line:column`).
+///
+/// # Errors
+/// - If `input` has unbalanced block directives or expands beyond the default limits.
+/// - If `template` cannot be parsed ([`CycleSpitterError::Template`]).
+/// - For the reasons listed at [`generate`](crate::generator::generate).
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::options::Options;
+///
+/// let template = "move.w d0,d1 ; stabilizer\ndcb.w 4,$4e71";
+/// let options = Options::builder().cycles_per_line(20).build();
+/// let generation = cycle_spitter::split("rept 2\nmove.w d1,d2\nendr", template, &options)
+///     .unwrap();
+/// assert_eq!(generation.scanlines, 1);
+/// assert!(generation.lines.iter().any(|line| line.starts_with("move.w d1,d2")));
+/// ```
+pub fn split(
+    input: &str,
+    template: &str,
+    options: &Options,
+) -> Result<Generation, CycleSpitterError> {
+    let db = CycleDatabase::builtin();
+    let (lines, map) = StringSource::new("input", input).located_lines()?;
+    let (flat_lines, map) = flatten("input", &lines, &map, &ExpansionLimits::default())?;
+    let sections = parse_template_with(template, &db, options)
+        .map_err(|e| CycleSpitterError::Template(e.to_string()))?;
+    let templates = TemplateSet::from(sections);
+    let mut observer = Locator {
+        map: &map,
+        inner: &mut StderrObserver,
+    };
+    generate_with_observer(&flat_lines, &templates, &db, options, &mut observer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_rejects_unbalanced_blocks() {
+        let options = Options::default();
+        let err = split("rept 2\nnop", "dcb.w 4,$4e71", &options).unwrap_err();
+        assert_eq!(err.to_string(), "input:1: REPT without matching ENDR");
+    }
+}
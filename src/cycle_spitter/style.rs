@@ -0,0 +1,205 @@
+// src/cycle_spitter/style.rs
+
+//! Laying out generated lines to a team's formatting standard.
+//!
+//! By default, generated instructions keep the spacing of the input they were copied from.
+//! A [`Style`] instead puts every part of a line into a column of its own: labels in column 0,
+//! mnemonics at the first tab stop, operands and trailing comments at the configured columns.
+//! Columns are counted from 0; a part that would start at or beyond its column is separated
+//! from the previous one by a single tab or space instead. Comments are never changed, only
+//! moved: comment lines are kept as they are, and a trailing comment keeps its text, including
+//! the annotations of the generator.
+
+use crate::cycle_spitter::symbols::{code_of, split_definition};
+
+/// The width of a tab stop unless configured otherwise.
+pub const DEFAULT_TAB_WIDTH: usize = 8;
+
+/// How lines are indented and aligned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Indent {
+    /// Tabs, with tab stops every `n` columns.
+    Tabs(usize),
+    /// Spaces, with the mnemonic indented by `n` columns.
+    Spaces(usize),
+}
+
+impl Indent {
+    /// The number of columns of one indentation step.
+    fn width(self) -> usize {
+        match self {
+            Indent::Tabs(width) | Indent::Spaces(width) => width.max(1),
+        }
+    }
+}
+
+/// Whether labels are written with a trailing colon.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LabelStyle {
+    /// `loop:`
+    #[default]
+    Colon,
+    /// `loop`, in column 0.
+    Bare,
+}
+
+/// The layout of formatted lines, see the module documentation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Style {
+    pub indent: Indent,
+    /// The column of the operands, `None` to follow the mnemonic after one separator.
+    pub operand_column: Option<usize>,
+    /// The column of trailing comments, `None` to follow the code after one separator.
+    pub comment_column: Option<usize>,
+    pub labels: LabelStyle,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Style {
+            indent: Indent::Tabs(DEFAULT_TAB_WIDTH),
+            operand_column: None,
+            comment_column: None,
+            labels: LabelStyle::Colon,
+        }
+    }
+}
+
+impl Style {
+    /// Appends whitespace to `line` up to `column`, or a single separator if `line` already
+    /// reaches it.
+    fn pad_to(&self, line: &mut String, column: usize) {
+        let width = self.indent.width();
+        let mut current = line.chars().fold(0, |current, c| match c {
+            '\t' => (current / width + 1) * width,
+            _ => current + 1,
+        });
+        let (separator, step): (char, fn(usize, usize) -> usize) = match self.indent {
+            Indent::Tabs(_) => ('\t', |current, width| (current / width + 1) * width),
+            Indent::Spaces(_) => (' ', |current, _| current + 1),
+        };
+        loop {
+            line.push(separator);
+            current = step(current, width);
+            if current >= column {
+                break;
+            }
+        }
+    }
+
+    /// Lays out one generated line.
+    ///
+    /// # Example
+    /// ```rust
+    /// use cycle_spitter::style::{Indent, LabelStyle, Style};
+    ///
+    /// let style = Style {
+    ///     indent: Indent::Spaces(8),
+    ///     operand_column: Some(16),
+    ///     comment_column: Some(32),
+    ///     labels: LabelStyle::Bare,
+    /// };
+    /// assert_eq!(
+    ///     style.format_line(".loop:\tmove.w  d0,d1 ; copy"),
+    ///     ".loop   move.w  d0,d1           ; copy"
+    /// );
+    /// assert_eq!(style.format_line("; as is"), "; as is");
+    /// ```
+    pub fn format_line(&self, line: &str) -> String {
+        if line.trim().is_empty() || line.trim_start().starts_with(';') {
+            return line.to_string();
+        }
+        let code = code_of(line);
+        let comment = line[code.len()..].trim();
+        let definition = split_definition(code);
+
+        let mut formatted = String::new();
+        let rest = match definition.name {
+            Some(name) if !definition.assignment => {
+                formatted.push_str(name);
+                if self.labels == LabelStyle::Colon {
+                    formatted.push(':');
+                }
+                definition.rest.trim()
+            }
+            // An assignment: the symbol, then `equ`, `set` or `=` as the mnemonic
+            Some(name) => {
+                formatted.push_str(name);
+                code.trim_start()[name.len()..].trim()
+            }
+            None => code.trim(),
+        };
+        if !rest.is_empty() {
+            let (mnemonic, operands) = rest
+                .split_once(char::is_whitespace)
+                .map_or((rest, ""), |(mnemonic, operands)| {
+                    (mnemonic, operands.trim())
+                });
+            let (mnemonic, operands) = match mnemonic.strip_prefix('=') {
+                Some(value) if !value.is_empty() => ("=", rest[1..].trim()),
+                _ => (mnemonic, operands),
+            };
+            self.pad_to(&mut formatted, self.indent.width());
+            formatted.push_str(mnemonic);
+            if !operands.is_empty() {
+                let column = self.operand_column.unwrap_or(0);
+                self.pad_to(&mut formatted, column);
+                formatted.push_str(operands);
+            }
+        }
+        if !comment.is_empty() {
+            if !formatted.is_empty() {
+                self.pad_to(&mut formatted, self.comment_column.unwrap_or(0));
+            }
+            formatted.push_str(comment);
+        }
+        formatted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_line_with_tabs() {
+        let style = Style {
+            operand_column: Some(16),
+            comment_column: Some(40),
+            ..Style::default()
+        };
+        assert_eq!(
+            style.format_line("lea     charBuffer,a0\t;\t(12)\tlea.l xxx.l,an\t[24]"),
+            "\tlea\tcharBuffer,a0\t\t;\t(12)\tlea.l xxx.l,an\t[24]"
+        );
+        // Labels too long for the indentation are followed by a single tab.
+        assert_eq!(
+            style.format_line("scroll_loop:\tdbra d7,scroll_loop"),
+            "scroll_loop:\tdbra\td7,scroll_loop"
+        );
+        assert_eq!(
+            style.format_line("add                 set 224"),
+            "add\tset\t224"
+        );
+        assert_eq!(style.format_line("size=8"), "size\t=\t8");
+        assert_eq!(style.format_line("rts"), "\trts");
+        assert_eq!(style.format_line("\tdc.b \"a;b\",0"), "\tdc.b\t\"a;b\",0");
+        assert_eq!(style.format_line("\t; comment"), "\t; comment");
+    }
+
+    #[test]
+    fn test_format_line_labels() {
+        let colon = Style {
+            indent: Indent::Spaces(2),
+            ..Style::default()
+        };
+        assert_eq!(colon.format_line(".loop:"), ".loop:");
+        assert_eq!(colon.format_line(".loop: nop ; x"), ".loop: nop ; x");
+        let bare = Style {
+            labels: LabelStyle::Bare,
+            ..colon
+        };
+        assert_eq!(bare.format_line(".loop:"), ".loop");
+        assert_eq!(bare.format_line("x equ 1"), "x equ 1");
+    }
+}
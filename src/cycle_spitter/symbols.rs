@@ -0,0 +1,209 @@
+// src/cycle_spitter/symbols.rs
+
+//! Symbols referenced by code but defined elsewhere.
+//!
+//! Generated code is included into a surrounding source that has to define every label and
+//! equate the timed region refers to. [`external_symbols`] lists them: all symbols used in
+//! operands that are not defined in the region itself (as `label:`, or with `equ`, `set` or
+//! `=`). Registers, numbers, strings and local labels (`.loop`) are not symbols in this sense.
+//!
+//! [`Dialect`] renders the list as import directives, so the assembler checks the symbols, too.
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::collections::BTreeSet;
+
+static DEFINITION_RE: Lazy<Regex> = Lazy::new(|| {
+    // Matches symbol definitions at the start of a line
+    // Example matches: `loop:`, `.loop: tst.w d0`, `WIDTH equ 160`, `count set 4`, `size = 8`
+    // - `(?P<name>...)`: The defined symbol
+    // - `(?P<label>:)`: Present for labels, absent for assignments
+    // - `(?P<rest>.*)`: The remainder of the line, an instruction or the defining expression
+    Regex::new(
+        r"(?i)^\s*(?P<name>[a-z_.][a-z0-9_.]*)(?:(?P<label>:)|\s+(?:equ|set)\s|\s*=)(?P<rest>.*)$",
+    )
+    .unwrap()
+});
+
+static TOKEN_RE: Lazy<Regex> = Lazy::new(|| {
+    // Splits operands into tokens, capturing only the symbol names
+    // Example: `lea WIDTH*2+$10(a0,d0.w),a1` yields `WIDTH`, `a0`, `d0`, `.w` and `a1`
+    // - `\$[0-9a-f]+|%[01]+|\d\w*`: Hexadecimal, binary and decimal numbers
+    // - `'[^']*'|"[^"]*"`: Strings
+    // - `(?P<name>[a-z_.][a-z0-9_]*)`: Names, including registers and size suffixes
+    Regex::new(r#"(?i)\$[0-9a-f]+|%[01]+|\d\w*|'[^']*'|"[^"]*"|(?P<name>[a-z_.][a-z0-9_]*)"#)
+        .unwrap()
+});
+
+static REGISTER_RE: Lazy<Regex> = Lazy::new(|| {
+    // Matches register names and size suffixes, which look like symbols but are not
+    // Example matches: `d0`, `A7`, `sp`, `ccr`, `.w`
+    Regex::new(r"(?i)^(?:[da][0-7]|sp|ssp|usp|pc|sr|ccr|\.[bwls])$").unwrap()
+});
+
+/// Directives whose operands are not symbols (section names, options, file names, imports).
+pub(crate) const NON_SYMBOL_DIRECTIVES: [&str; 11] = [
+    "section", "text", "data", "bss", "opt", "include", "incbin", "xref", "xdef", "global", "globl",
+];
+
+/// An assembler dialect, selecting the syntax of import directives.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dialect {
+    /// Devpac and compatible assemblers (vasm with Motorola syntax, rmac): `xref name`.
+    Devpac,
+    /// The GNU assembler: `.extern name`.
+    Gnu,
+}
+
+impl Dialect {
+    /// Parses a dialect name (`devpac`, `vasm`, `gnu` or `gas`).
+    pub fn parse(name: &str) -> Option<Dialect> {
+        match name.to_lowercase().as_str() {
+            "devpac" | "vasm" | "rmac" => Some(Dialect::Devpac),
+            "gnu" | "gas" => Some(Dialect::Gnu),
+            _ => None,
+        }
+    }
+
+    /// Returns the directive importing `symbol`.
+    pub fn import(self, symbol: &str) -> String {
+        match self {
+            Dialect::Devpac => format!("\txref\t{}", symbol),
+            Dialect::Gnu => format!("\t.extern\t{}", symbol),
+        }
+    }
+}
+
+/// Returns the code of `line` without its comment, honouring `;` inside strings.
+pub(crate) fn code_of(line: &str) -> &str {
+    let mut quote = None;
+    for (index, c) in line.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == ';' => return &line[..index],
+            None => {}
+        }
+    }
+    line
+}
+
+/// A line split into the symbol it defines and the remaining text.
+pub(crate) struct Definition<'a> {
+    pub(crate) name: Option<&'a str>,
+    /// Whether `rest` is an assigned expression rather than an instruction.
+    pub(crate) assignment: bool,
+    pub(crate) rest: &'a str,
+}
+
+/// Splits off the symbol defined by `code`, if any.
+pub(crate) fn split_definition(code: &str) -> Definition<'_> {
+    match DEFINITION_RE.captures(code) {
+        Some(caps) => Definition {
+            name: caps.name("name").map(|m| m.as_str()),
+            assignment: caps.name("label").is_none(),
+            rest: caps.name("rest").map_or("", |m| m.as_str()),
+        },
+        None => Definition {
+            name: None,
+            assignment: false,
+            rest: code,
+        },
+    }
+}
+
+/// Returns the names in `operands` that are symbols rather than registers, numbers, strings or
+/// local labels.
+pub(crate) fn symbol_names(operands: &str) -> impl Iterator<Item = &str> {
+    TOKEN_RE
+        .captures_iter(operands)
+        .filter_map(|caps| caps.name("name"))
+        .map(|token| token.as_str())
+        .filter(|token| !REGISTER_RE.is_match(token) && !token.starts_with('.'))
+}
+
+/// Lists the symbols `lines` refer to without defining them, sorted by name.
+///
+/// A line defines a symbol with a `name:` label or a `name equ`, `name set` or `name = `
+/// assignment; every other name in an operand or an assigned expression is a reference.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::symbols::external_symbols;
+///
+/// let lines: Vec<String> = [
+///     "loop: move.w WIDTH(a0),d0 ; copy",
+///     "lea table,a1",
+///     "dbra d7,loop",
+/// ]
+/// .map(String::from)
+/// .to_vec();
+/// assert_eq!(external_symbols(&lines), ["WIDTH", "table"]);
+/// ```
+pub fn external_symbols(lines: &[String]) -> Vec<String> {
+    let mut defined = BTreeSet::new();
+    let mut referenced = BTreeSet::new();
+    for line in lines {
+        let definition = split_definition(code_of(line));
+        if let Some(name) = definition.name {
+            defined.insert(name);
+        }
+        let rest = definition.rest.trim();
+        // An assignment's remainder is the expression; otherwise it starts with a mnemonic
+        let operands = if definition.assignment {
+            rest
+        } else {
+            let mut parts = rest.splitn(2, char::is_whitespace);
+            let mnemonic = parts.next().unwrap_or("").to_lowercase();
+            let mnemonic = mnemonic.trim_start_matches('.');
+            if NON_SYMBOL_DIRECTIVES.contains(&mnemonic) {
+                continue;
+            }
+            parts.next().unwrap_or("")
+        };
+        referenced.extend(symbol_names(operands));
+    }
+    referenced
+        .difference(&defined)
+        .map(|symbol| symbol.to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(raw: &[&str]) -> Vec<String> {
+        raw.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_external_symbols() {
+        let symbols = external_symbols(&lines(&[
+            "WIDTH equ 160",
+            "OFFSET = WIDTH*2+BASE",
+            "count set 4",
+            ".loop:\tmove.l (a0,d0.w),WIDTH(a1)\t; scratch: not_a_symbol",
+            "\tmove.w #count-1,d7",
+            "\tmove.b d7,$ffff820a.w",
+            "\tlea screen+OFFSET(pc),a2",
+            "\tdbra d7,.loop",
+            "\tmovem.l d0-d3/a4,-(sp)",
+            "\tdc.b \"a;b\",msg_end-msg",
+            "\tsection text",
+            "\tincbin data.bin",
+            "; jsr commented_out",
+        ]));
+        assert_eq!(symbols, ["BASE", "msg", "msg_end", "screen"]);
+    }
+
+    #[test]
+    fn test_dialect() {
+        assert_eq!(Dialect::parse("VASM"), Some(Dialect::Devpac));
+        assert_eq!(Dialect::parse("gas"), Some(Dialect::Gnu));
+        assert_eq!(Dialect::parse("masm"), None);
+        assert_eq!(Dialect::Devpac.import("screen"), "\txref\tscreen");
+        assert_eq!(Dialect::Gnu.import("screen"), "\t.extern\tscreen");
+    }
+}
@@ -0,0 +1,323 @@
+// src/cycle_spitter/synthesis.rs
+
+//! Synthesizing templates from the cycle positions of sync and resolution switches.
+//!
+//! Overscan techniques are described by the cycles at which the display frequency and
+//! resolution are switched, e.g. `60hz at 376, 50hz at 388, hi-res at 444/456`. Every entry is
+//! `<switch> at <cycle>[/<cycle>]`, where the optional second cycle switches back (to 50 Hz or
+//! low resolution). Entries are separated by commas or newlines; `#` starts a comment.
+//!
+//! Cycles are the positions where the switching instructions start. The switches use the
+//! classic `d7 = 2` register trick: a byte write of `d7` stores 2 (50 Hz, high resolution), a
+//! word write stores the high byte 0 (60 Hz, low resolution).
+//!
+//! The gaps between the switches become the `dcb.w` code windows of the template. A template
+//! starts with injection code, so the synthesized scanline starts at the earliest switch and the
+//! gap before it is appended to the last window.
+
+use crate::cycle_spitter::cycles::lookup_cycles;
+use crate::cycle_spitter::database::CycleDatabase;
+use std::error::Error;
+use std::fmt;
+
+/// The cycles of a NOP in template code windows.
+const NOP_CYCLES: usize = 4;
+
+/// A write to the Shifter's sync mode or resolution register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Switch {
+    Hz60,
+    Hz50,
+    HighRes,
+    LowRes,
+}
+
+impl Switch {
+    /// Parses a switch name (`60hz`, `50hz`, `hi-res`, `lo-res` and a few spellings thereof).
+    pub fn parse(name: &str) -> Option<Switch> {
+        match name.to_lowercase().replace(' ', "").as_str() {
+            "60hz" | "60" => Some(Switch::Hz60),
+            "50hz" | "50" => Some(Switch::Hz50),
+            "hi-res" | "hires" | "high-res" | "highres" => Some(Switch::HighRes),
+            "lo-res" | "lores" | "low-res" | "lowres" => Some(Switch::LowRes),
+            _ => None,
+        }
+    }
+
+    /// The switch restoring the state changed by this one.
+    pub fn counterpart(self) -> Switch {
+        match self {
+            Switch::Hz60 => Switch::Hz50,
+            Switch::Hz50 => Switch::Hz60,
+            Switch::HighRes => Switch::LowRes,
+            Switch::LowRes => Switch::HighRes,
+        }
+    }
+
+    /// The instruction performing the switch, given `d7 = 2`.
+    pub fn instruction(self) -> &'static str {
+        match self {
+            Switch::Hz60 => "move.w\td7,$ffff820a.w",
+            Switch::Hz50 => "move.b\td7,$ffff820a.w",
+            Switch::HighRes => "move.b\td7,$ffff8260.w",
+            Switch::LowRes => "move.w\td7,$ffff8260.w",
+        }
+    }
+}
+
+impl fmt::Display for Switch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Switch::Hz60 => "60hz",
+            Switch::Hz50 => "50hz",
+            Switch::HighRes => "hi-res",
+            Switch::LowRes => "lo-res",
+        })
+    }
+}
+
+/// A switch starting at a cycle of the scanline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SwitchPosition {
+    pub switch: Switch,
+    pub cycle: usize,
+}
+
+/// Parses a switch spec (see the module documentation) into positions sorted by cycle.
+///
+/// # Errors
+/// If an entry is malformed or names an unknown switch.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::synthesis::{Switch, parse_switch_spec};
+///
+/// let switches = parse_switch_spec("60Hz at 376/388, hi-res at 0").unwrap();
+/// assert_eq!(switches[0].switch, Switch::HighRes);
+/// assert_eq!(switches[2].switch, Switch::Hz50);
+/// assert_eq!(switches[2].cycle, 388);
+/// ```
+pub fn parse_switch_spec(spec: &str) -> Result<Vec<SwitchPosition>, Box<dyn Error>> {
+    let mut switches = Vec::new();
+    let entries = spec
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or(""))
+        .flat_map(|line| line.split(','))
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty());
+    for entry in entries {
+        let (name, cycles) = entry
+            .rsplit_once(" at ")
+            .ok_or_else(|| format!("Expected '<switch> at <cycle>', found '{}'", entry))?;
+        let switch = Switch::parse(name.trim())
+            .ok_or_else(|| format!("Unknown switch '{}' in '{}'", name.trim(), entry))?;
+        let mut positions = cycles.split('/').map(|cycle| {
+            cycle
+                .trim()
+                .parse::<usize>()
+                .map_err(|_| format!("Invalid cycle '{}' in '{}'", cycle.trim(), entry))
+        });
+        switches.push(SwitchPosition {
+            switch,
+            cycle: positions.next().unwrap()?,
+        });
+        if let Some(back) = positions.next() {
+            switches.push(SwitchPosition {
+                switch: switch.counterpart(),
+                cycle: back?,
+            });
+        }
+        if positions.next().is_some() {
+            return Err(format!("At most two cycles allowed in '{}'", entry).into());
+        }
+    }
+    switches.sort_by_key(|s| s.cycle);
+    Ok(switches)
+}
+
+/// Synthesizes a template placing `switches` in a scanline of `cycles_per_line` cycles.
+///
+/// # Returns
+/// The template source, in the format read by `parse_template`.
+///
+/// # Errors
+/// If there are no switches, a switch lies outside the scanline, two switches overlap or a gap
+/// between switches cannot be filled with NOPs.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::database::CycleDatabase;
+/// use cycle_spitter::synthesis::{parse_switch_spec, synthesize_template};
+/// use cycle_spitter::template::parse_template;
+///
+/// let db = CycleDatabase::builtin();
+/// let switches = parse_switch_spec("60hz at 376/388").unwrap();
+/// let template = synthesize_template(&switches, 512, &db).unwrap();
+/// let sections = parse_template(&template, &db).unwrap();
+/// assert_eq!(sections[0].nop_cycles, 512 - 24);
+/// ```
+pub fn synthesize_template(
+    switches: &[SwitchPosition],
+    cycles_per_line: usize,
+    db: &CycleDatabase,
+) -> Result<String, Box<dyn Error>> {
+    let first = switches.first().ok_or("No switches given")?;
+    let mut timed = Vec::with_capacity(switches.len());
+    for position in switches {
+        if position.cycle >= cycles_per_line {
+            return Err(format!(
+                "{} at {} lies outside the scanline of {} cycles",
+                position.switch, position.cycle, cycles_per_line
+            )
+            .into());
+        }
+        let cycles = lookup_cycles(position.switch.instruction(), db).base();
+        timed.push((position, cycles));
+    }
+
+    // The gap after every switch, the last one wrapping around to the first switch
+    let mut gaps = Vec::with_capacity(timed.len());
+    for (index, (position, cycles)) in timed.iter().enumerate() {
+        let end = position.cycle + cycles;
+        let next = timed
+            .get(index + 1)
+            .map_or(first.cycle + cycles_per_line, |(next, _)| next.cycle);
+        if end > next {
+            let blocking = timed.get(index + 1).map_or(*first, |(next, _)| **next);
+            return Err(format!(
+                "{} at {} takes {} cycles and overlaps {} at {}",
+                position.switch, position.cycle, cycles, blocking.switch, blocking.cycle
+            )
+            .into());
+        }
+        if (next - end) % NOP_CYCLES != 0 {
+            return Err(format!(
+                "The gap of {} cycles after {} at {} is not a multiple of {} cycles",
+                next - end,
+                position.switch,
+                position.cycle,
+                NOP_CYCLES
+            )
+            .into());
+        }
+        gaps.push(next - end);
+    }
+
+    let summary: Vec<String> = switches
+        .iter()
+        .map(|s| format!("{} at {}", s.switch, s.cycle))
+        .collect();
+    let mut lines = vec![
+        "; =============================================================".to_string(),
+        format!(
+            "; Synthesized template ({} cycles per scanline)",
+            cycles_per_line
+        ),
+        format!("; Switches: {}", summary.join(", ")),
+        "; d7 must hold 2: byte writes select 50 Hz and high resolution,".to_string(),
+        "; word writes 60 Hz and low resolution".to_string(),
+    ];
+    if first.cycle > 0 {
+        lines.push(format!(
+            "; Cycle 0 of this template is cycle {} of the scanline",
+            first.cycle
+        ));
+    }
+    let mut section_start = true;
+    for ((position, _), gap) in timed.iter().zip(gaps) {
+        if section_start {
+            lines.push(
+                "; -------------------------------------------------------------".to_string(),
+            );
+        }
+        let comment = if section_start {
+            format!("\t\t\t; {} at {}", position.switch, position.cycle)
+        } else {
+            String::new()
+        };
+        lines.push(format!("\t\t{}{}", position.switch.instruction(), comment));
+        section_start = gap > 0;
+        if section_start {
+            lines.push(format!("\t\tdcb.w\t{},$4e71", gap / NOP_CYCLES));
+        }
+    }
+    lines.push("; =============================================================".to_string());
+    Ok(lines.join("\n") + "\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cycle_spitter::template::parse_template;
+
+    #[test]
+    fn test_parse_switch_spec() {
+        let switches =
+            parse_switch_spec("60Hz at 376\n50 Hz at 388 # right border\nhires at 444/456")
+                .unwrap();
+        let parsed: Vec<(Switch, usize)> = switches.iter().map(|s| (s.switch, s.cycle)).collect();
+        assert_eq!(
+            parsed,
+            [
+                (Switch::Hz60, 376),
+                (Switch::Hz50, 388),
+                (Switch::HighRes, 444),
+                (Switch::LowRes, 456)
+            ]
+        );
+        assert!(parse_switch_spec("70hz at 3").is_err());
+        assert!(parse_switch_spec("60hz 376").is_err());
+        assert!(parse_switch_spec("60hz at 1/2/3").is_err());
+        assert!(parse_switch_spec("60hz at x").is_err());
+    }
+
+    #[test]
+    fn test_synthesize_example_template() {
+        let db = CycleDatabase::builtin();
+        let switches =
+            parse_switch_spec("hi-res at 0/12, 60hz at 376/388, hi-res at 444/456").unwrap();
+        let template = synthesize_template(&switches, 512, &db).unwrap();
+        let sections = parse_template(&template, &db).unwrap();
+        let example = parse_template(include_str!("../../examples/template.s"), &db).unwrap();
+
+        let layout = |sections: &[crate::cycle_spitter::template::TemplateSection]| {
+            sections
+                .iter()
+                .map(|s| {
+                    let code: usize = s.injection_code.iter().map(|(_, c)| c).sum();
+                    (code, s.nop_cycles)
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(layout(&sections), layout(&example));
+        assert_eq!(sections[1].label, "60hz at 376");
+        assert!(!template.contains("Cycle 0 of this template"));
+    }
+
+    #[test]
+    fn test_synthesize_rotates_and_validates() {
+        let db = CycleDatabase::builtin();
+        let switches = parse_switch_spec("60hz at 376/388").unwrap();
+        let template = synthesize_template(&switches, 512, &db).unwrap();
+        assert!(template.contains("; Cycle 0 of this template is cycle 376 of the scanline"));
+
+        let error = |spec: &str| {
+            synthesize_template(&parse_switch_spec(spec).unwrap(), 512, &db)
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(
+            error("60hz at 376/384"),
+            "60hz at 376 takes 12 cycles and overlaps 50hz at 384"
+        );
+        assert_eq!(
+            error("60hz at 376/390"),
+            "The gap of 2 cycles after 60hz at 376 is not a multiple of 4 cycles"
+        );
+        assert_eq!(
+            error("hi-res at 512"),
+            "hi-res at 512 lies outside the scanline of 512 cycles"
+        );
+        assert!(synthesize_template(&[], 512, &db).is_err());
+    }
+}
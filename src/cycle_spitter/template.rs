@@ -1,8 +1,14 @@
 // src/cycle_spitter/template.rs
 
+use crate::cycle_spitter::database::CycleDatabase;
 use crate::cycle_spitter::helpers::{extract_cycle_count, format_accumulated_instruction};
+use crate::cycle_spitter::literal::{LITERAL_PATTERN, parse_literal};
+use crate::cycle_spitter::options::{AnnotationFormat, Options};
+use crate::cycle_spitter::pragma::{HOT_MARKER, has_marker};
+use crate::cycle_spitter::source::normalize_line_endings;
 use once_cell::sync::Lazy;
 use regex::Regex;
+use serde::{Deserialize, Serialize};
 use std::error::Error;
 
 /// Represents a section of a parsed template.
@@ -11,14 +17,154 @@ use std::error::Error;
 ///   and its associated cycle count (usize).
 /// - `nop_cycles`: The number of NOP (No Operation Placeholder) cycles in the section.
 /// - `label`: A label identifying the section.
-#[derive(Debug)]
+/// - `hot`: Whether the code window accepts instructions marked `;@critical`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct TemplateSection {
     pub injection_code: Vec<(String, usize)>, // (code, cycles)
     pub nop_cycles: usize,
     pub label: String,
+    pub hot: bool,
 }
 
-static NOP_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"dcb\.w\s*(\d+),\s*\$4e71").unwrap());
+impl TemplateSection {
+    /// Creates a section of `injection_code` (code and cycles of every line) followed by a
+    /// window of `nop_cycles` for user code, as parsing a template would.
+    ///
+    /// # Example
+    /// ```rust
+    /// use cycle_spitter::database::CycleDatabase;
+    /// use cycle_spitter::generator::generate;
+    /// use cycle_spitter::options::Options;
+    /// use cycle_spitter::template::{TemplateSection, TemplateSet};
+    ///
+    /// let templates = TemplateSet::from(vec![TemplateSection::new(
+    ///     "left",
+    ///     vec![("move.w d0,d1".to_string(), 4)],
+    ///     16,
+    /// )]);
+    /// let options = Options::builder().cycles_per_line(20).build();
+    /// let input = vec!["move.w d1,d2".to_string()];
+    /// let generation = generate(&input, &templates, &CycleDatabase::builtin(), &options).unwrap();
+    /// assert_eq!(generation.scanlines, 1);
+    /// ```
+    pub fn new(label: &str, injection_code: Vec<(String, usize)>, nop_cycles: usize) -> Self {
+        TemplateSection {
+            injection_code,
+            nop_cycles,
+            label: label.to_string(),
+            hot: false,
+        }
+    }
+
+    /// Creates a section without injection code that holds `nop_cycles` of user code. A single
+    /// window spanning the scanline budget splits user code without any template.
+    pub fn code_window(nop_cycles: usize, label: &str) -> Self {
+        Self::new(label, Vec::new(), nop_cycles)
+    }
+
+    /// Returns `true` if the injection code refers to the scanline index (see [`expand_line`]).
+    pub fn uses_line(&self) -> bool {
+        self.injection_code
+            .iter()
+            .any(|(code, _)| LINE_RE.is_match(code.split(';').next().unwrap_or("")))
+    }
+}
+
+static LINE_RE: Lazy<Regex> = Lazy::new(|| {
+    // Matches the scanline index placeholder of template code
+    // Example matches: `LINE` in `move.w #LINE*2,d0` or `LINE*160(a0)`; not `LINES` or `line`
+    Regex::new(r"\bLINE\b").unwrap()
+});
+
+/// Replaces `LINE` in the code of a template line by the index of the scanline it is emitted
+/// for, leaving the comment as is. The assembler evaluates expressions like `#LINE*2`; the
+/// cycles are unaffected, as immediates and displacements cost the same whatever their value.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::template::expand_line;
+///
+/// assert_eq!(
+///     expand_line("move.w #LINE*2,d0 ; (8) LINE", 5),
+///     "move.w #5*2,d0 ; (8) LINE"
+/// );
+/// ```
+pub fn expand_line(code: &str, scanline: usize) -> String {
+    let (instruction, comment) = match code.find(';') {
+        Some(index) => code.split_at(index),
+        None => (code, ""),
+    };
+    format!(
+        "{}{}",
+        LINE_RE.replace_all(instruction, scanline.to_string().as_str()),
+        comment
+    )
+}
+
+/// A set of named templates, one of which is applied to each scanline.
+///
+/// The first template registered is used until the input selects another one with a
+/// `;@use-template <name>` pragma (see the `pragma` module).
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct TemplateSet {
+    templates: Vec<(String, Vec<TemplateSection>)>,
+}
+
+impl TemplateSet {
+    /// The name given to the template of a set created from a single template.
+    pub const DEFAULT_NAME: &'static str = "default";
+
+    /// Creates an empty set.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `sections` under `name`.
+    ///
+    /// # Errors
+    /// If a template with the same name is already registered.
+    pub fn insert(
+        &mut self,
+        name: &str,
+        sections: Vec<TemplateSection>,
+    ) -> Result<(), Box<dyn Error>> {
+        if self.get(name).is_some() {
+            return Err(format!("Template '{}' is registered more than once", name).into());
+        }
+        self.templates.push((name.to_string(), sections));
+        Ok(())
+    }
+
+    /// Returns the sections of the template registered under `name`.
+    pub fn get(&self, name: &str) -> Option<&[TemplateSection]> {
+        self.templates
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, sections)| sections.as_slice())
+    }
+
+    /// Returns the name of the template applied until the input selects another one.
+    pub fn initial(&self) -> Option<&str> {
+        self.templates.first().map(|(name, _)| name.as_str())
+    }
+
+    /// Returns the names of all registered templates in registration order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.templates.iter().map(|(name, _)| name.as_str())
+    }
+}
+
+impl From<Vec<TemplateSection>> for TemplateSet {
+    /// Creates a set holding a single template named [`TemplateSet::DEFAULT_NAME`].
+    fn from(sections: Vec<TemplateSection>) -> Self {
+        TemplateSet {
+            templates: vec![(Self::DEFAULT_NAME.to_string(), sections)],
+        }
+    }
+}
+
+static NOP_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(&format!(r"dcb\.w\s*({}),\s*\$4e71", LITERAL_PATTERN)).unwrap());
 
 static COMMENT_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r";\s*(.*)").unwrap());
 
@@ -28,6 +174,7 @@ static PAREN_NUM_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\(\s*\d+\s*\)").unw
 ///
 /// # Arguments
 /// - `template_content`: A string slice containing the content of the template to parse.
+/// - `db`: The cycle database used to look up the template's instructions.
 ///
 /// # Returns
 /// A `Result` containing:
@@ -37,12 +184,16 @@ static PAREN_NUM_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\(\s*\d+\s*\)").unw
 /// # Functionality
 /// The function processes the template content line by line:
 /// - Lines containing NOP (No Operation Placeholder) instructions, identified by the pattern
-///   `dcb.w <count>, $4e71`, are used to calculate the associated cycles (`count * 4`). Each
+///   `dcb.w <count>, $4e71`, are used to calculate the associated cycles (`count * 4`, see
+///   [`parse_template_with`] for other NOP costs). Each
 ///   NOP section closes the previous block of code, and a new section is created.
 /// - Lines containing other types of instructions are associated with a cycle count extracted
 ///   using the provided `number_re` pattern (if it matches).
 /// - Inline comments are used to identify and assign labels to sections.
+/// - A `;@hot` comment, on the NOP line or on its own line before it, marks the section's code
+///   window as hot (see the `pragma` module).
 /// - Unrecognized or empty lines are ignored.
+/// - Line endings may be `\n`, `\r\n` or `\r`, and trailing whitespace is ignored.
 ///
 /// At the end of the process, any remaining code block is added as the last section.
 ///
@@ -57,15 +208,16 @@ static PAREN_NUM_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\(\s*\d+\s*\)").unw
 ///
 /// # Example Usage
 /// ```rust
-/// use regex::Regex;
-/// use your_crate::cycle_spitter::template::{parse_template, TemplateSection};
+/// use cycle_spitter::database::CycleDatabase;
+/// use cycle_spitter::template::parse_template;
 ///
 /// let content = r#"
-///     dcb.w 5, $4e71
-///     move.w #$1234, D0 ; Move instruction
-///     dcb.w 3, $4e71
+///     move.w #$1234,d0 ; Move instruction
+///     dcb.w 5,$4e71
+///     move.w d0,d1
+///     dcb.w 3,$4e71
 /// "#;
-/// let sections = parse_template(content)?;
+/// let sections = parse_template(content, &CycleDatabase::builtin()).unwrap();
 /// for section in sections {
 ///     println!("{:?}", section);
 /// }
@@ -75,19 +227,42 @@ static PAREN_NUM_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\(\s*\d+\s*\)").unw
 /// The function returns an error in the following cases:
 /// - If the `Regex` cannot be compiled or fails to capture required groups.
 /// - If parsing a numeric value (e.g., cycle count) from captured groups fails.
-pub fn parse_template(template_content: &str) -> Result<Vec<TemplateSection>, Box<dyn Error>> {
+pub fn parse_template(
+    template_content: &str,
+    db: &CycleDatabase,
+) -> Result<Vec<TemplateSection>, Box<dyn Error>> {
+    parse_template_with(template_content, db, &Options::default())
+}
+
+/// Parses a template like [`parse_template`], counting every NOP of a `dcb.w` block with
+/// `Options::nop_cycles`.
+///
+/// # Errors
+/// As [`parse_template`].
+pub fn parse_template_with(
+    template_content: &str,
+    db: &CycleDatabase,
+    options: &Options,
+) -> Result<Vec<TemplateSection>, Box<dyn Error>> {
+    // Templates edited on other platforms may end their lines with `\r\n` or `\r`
+    let template_content = normalize_line_endings(template_content);
     // Pre-allocate vectors based on estimated size
     let line_count = template_content.lines().count();
     let mut sections = Vec::with_capacity(line_count / 4); // Rough estimate: one section per 4 lines
     let mut current_code = Vec::with_capacity(4); // Most sections have a few instructions
     let mut current_label = String::with_capacity(32); // Reasonable size for labels
 
+    let mut current_hot = false;
+
     let mut cycle_offset: usize = 0;
     for line in template_content.lines() {
         let trimmed = line.trim();
         if trimmed.is_empty() {
             continue;
         }
+        if has_marker(trimmed, HOT_MARKER) {
+            current_hot = true;
+        }
 
         // Handle set lines first, before any cycle extraction
         if trimmed.contains(" set ") {
@@ -97,18 +272,21 @@ pub fn parse_template(template_content: &str) -> Result<Vec<TemplateSection>, Bo
         }
 
         if let Some(caps) = NOP_RE.captures(trimmed) {
-            let count = caps.get(1).unwrap().as_str().parse::<usize>()?;
-            let cycles = count * 4;
+            let count = parse_literal(&caps[1])
+                .ok_or_else(|| format!("Invalid NOP count in '{}'", trimmed))?;
+            let cycles = count * options.nop_cycles();
 
             if !current_code.is_empty() {
                 sections.push(TemplateSection {
                     injection_code: current_code,
                     nop_cycles: cycles,
                     label: current_label,
+                    hot: current_hot,
                 });
                 current_code = Vec::with_capacity(4);
                 current_label = String::with_capacity(32);
             }
+            current_hot = false;
             continue;
         }
 
@@ -120,15 +298,15 @@ pub fn parse_template(template_content: &str) -> Result<Vec<TemplateSection>, Bo
                 || PAREN_NUM_RE.is_match(l)
         };
 
-        if let Some(cycle_count) = extract_cycle_count(trimmed, skip_predicate) {
+        if let Some(cycle_count) = extract_cycle_count(trimmed, db, skip_predicate) {
             current_label = check_comment_line(current_label.clone(), trimmed, &sections);
-            let commented_output =
-                format_accumulated_instruction(trimmed, &cycle_count, cycle_offset);
-            let caclucated_cycles = if cycle_count.get_reg_count() > 1 {
-                cycle_count.base() + (cycle_count.cycles_per_reg() * cycle_count.get_reg_count())
-            } else {
-                cycle_count.base()
-            };
+            let commented_output = format_accumulated_instruction(
+                trimmed,
+                &cycle_count,
+                cycle_offset,
+                AnnotationFormat::Full,
+            );
+            let caclucated_cycles = cycle_count.total();
             current_code.push((commented_output, caclucated_cycles));
             cycle_offset += caclucated_cycles
         } else {
@@ -141,12 +319,82 @@ pub fn parse_template(template_content: &str) -> Result<Vec<TemplateSection>, Bo
             injection_code: current_code,
             nop_cycles: 0,
             label: current_label,
+            hot: false,
         });
     }
 
     Ok(sections)
 }
 
+/// The cycles of a template section.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SectionBudget {
+    pub label: String,
+    /// The cycles of the injected template code.
+    pub injected: usize,
+    /// The cycles of the NOP window left to user code.
+    pub nop_budget: usize,
+    pub hot: bool,
+}
+
+/// Returns the cycles of every section of a template.
+pub fn section_budgets(sections: &[TemplateSection]) -> Vec<SectionBudget> {
+    sections
+        .iter()
+        .map(|section| SectionBudget {
+            label: section.label.clone(),
+            injected: section
+                .injection_code
+                .iter()
+                .map(|(_, cycles)| cycles)
+                .sum(),
+            nop_budget: section.nop_cycles,
+            hot: section.hot,
+        })
+        .collect()
+}
+
+/// Checks that the sections of a template add up to a scanline of `cycles_per_line` cycles and
+/// returns their total. Generation lays out a template that does not as if it did, so every
+/// scanline of the output is off by the difference.
+///
+/// # Errors
+/// If the template has no sections or they do not add up to `cycles_per_line`.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::database::CycleDatabase;
+/// use cycle_spitter::template::{parse_template, validate_template};
+///
+/// let db = CycleDatabase::builtin();
+/// let sections = parse_template("move.w d0,d1 ; left\ndcb.w 3,$4e71", &db).unwrap();
+/// assert_eq!(validate_template(&sections, 16).unwrap(), 16);
+/// assert_eq!(
+///     validate_template(&sections, 512).unwrap_err().to_string(),
+///     "The sections add up to 16 cycles, not 512"
+/// );
+/// ```
+pub fn validate_template(
+    sections: &[TemplateSection],
+    cycles_per_line: usize,
+) -> Result<usize, Box<dyn Error>> {
+    if sections.is_empty() {
+        return Err("The template has no sections".into());
+    }
+    let total: usize = section_budgets(sections)
+        .iter()
+        .map(|budget| budget.injected + budget.nop_budget)
+        .sum();
+    if total != cycles_per_line {
+        return Err(format!(
+            "The sections add up to {} cycles, not {}",
+            total, cycles_per_line
+        )
+        .into());
+    }
+    Ok(total)
+}
+
 fn check_comment_line(
     mut current_label: String,
     trimmed: &str,
@@ -166,6 +414,27 @@ fn check_comment_line(
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_parse_template_ignores_line_endings_and_trailing_whitespace() {
+        let db = CycleDatabase::builtin();
+        let content = "move.w d0,d1 ; left\ndcb.w 4,$4e71\nmove.b d0,(a0) ; right\ndcb.w 2,$4e71\n";
+        let expected = parse_template(content, &db).unwrap();
+        assert_eq!(expected.len(), 2);
+
+        for variant in [
+            content.replace('\n', "\r\n"),
+            content.replace('\n', "\r"),
+            content.replace('\n', " \t\r\n"),
+        ] {
+            assert_eq!(
+                parse_template(&variant, &db).unwrap(),
+                expected,
+                "{:?}",
+                variant
+            );
+        }
+    }
+
     #[test]
     fn test_parse_template_instruction_with_cycles() {
         let content = r#"
@@ -173,7 +442,7 @@ mod tests {
             dcb.w 2,$4e71
         "#;
         // Using a regex that captures only decimal numbers.
-        let sections = parse_template(content).unwrap();
+        let sections = parse_template(content, &CycleDatabase::builtin()).unwrap();
 
         // Expect one section, whose injection code was built from the move instruction.
         // The move instruction gets normalized to append the cycle count extracted from it.
@@ -197,7 +466,7 @@ mod tests {
             move.w #$9,D2 ; Label for section
             dcb.w 6,$4e71
         "#;
-        let sections = parse_template(content).unwrap();
+        let sections = parse_template(content, &CycleDatabase::builtin()).unwrap();
 
         // Expect two sections.
         //
@@ -237,7 +506,7 @@ mod tests {
 
             dcb.w 1,$4e71
         "#;
-        let sections = parse_template(content).unwrap();
+        let sections = parse_template(content, &CycleDatabase::builtin()).unwrap();
 
         // There should be one section with one instruction and nop_cycles = 1 * 4 = 4.
         assert_eq!(sections.len(), 1);
@@ -256,7 +525,7 @@ mod tests {
             move.w #$100,D4 ; Inline comment
             dcb.w 7,$4e71 ; Another comment
         "#;
-        let sections = parse_template(content).unwrap();
+        let sections = parse_template(content, &CycleDatabase::builtin()).unwrap();
 
         // Expect one section with the inline comment determining the label.
         // NOP cycles should equal 7 * 4 = 28.
@@ -276,10 +545,89 @@ mod tests {
             ; This is a comment line
             ; Another comment line
         "#;
-        let sections = parse_template(content).unwrap();
+        let sections = parse_template(content, &CycleDatabase::builtin()).unwrap();
 
         // Only comment lines are provided. As they are filtered out,
         // no sections should be created.
         assert_eq!(sections.len(), 0);
     }
+
+    #[test]
+    fn test_template_set() {
+        let db = CycleDatabase::builtin();
+        let mut set = TemplateSet::new();
+        assert_eq!(set.initial(), None);
+        set.insert(
+            "main",
+            parse_template("move.w d0,d1\ndcb.w 2,$4e71", &db).unwrap(),
+        )
+        .unwrap();
+        set.insert(
+            "alt",
+            parse_template("move.l d0,d1\ndcb.w 3,$4e71", &db).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(set.initial(), Some("main"));
+        assert_eq!(set.get("alt").unwrap()[0].nop_cycles, 12);
+        assert!(set.get("other").is_none());
+        assert_eq!(set.names().collect::<Vec<_>>(), vec!["main", "alt"]);
+        assert!(set.insert("main", Vec::new()).is_err());
+
+        let single = TemplateSet::from(Vec::new());
+        assert_eq!(single.initial(), Some(TemplateSet::DEFAULT_NAME));
+    }
+
+    #[test]
+    fn test_hot_sections() {
+        let content = "move.w d0,d1 ; left border\ndcb.w 4,$4e71\n;@hot\nmove.w d0,d1 ; hbl\ndcb.w 2,$4e71\nmove.w d0,d1 ; right\ndcb.w 3,$4e71 ;@hot\nmove.w d0,d1";
+        let sections = parse_template(content, &CycleDatabase::builtin()).unwrap();
+        let hot: Vec<bool> = sections.iter().map(|s| s.hot).collect();
+        assert_eq!(hot, [false, true, true, false]);
+        assert_eq!(sections[1].label, "hbl");
+    }
+
+    #[test]
+    fn test_parse_template_with_nop_cycles() {
+        let options = Options::builder().nop_cycles(6).build();
+        let sections = parse_template_with(
+            "move.w d0,d1\ndcb.w 4,$4e71",
+            &CycleDatabase::builtin(),
+            &options,
+        )
+        .unwrap();
+        assert_eq!(sections[0].nop_cycles, 24);
+    }
+
+    #[test]
+    fn test_parse_template_with_hex_and_binary_nop_counts() {
+        let db = CycleDatabase::builtin();
+        let sections = parse_template(
+            "nop
+dcb.w $10,$4e71
+nop
+dcb.w %101,$4e71",
+            &db,
+        )
+        .unwrap();
+        let cycles: Vec<usize> = sections.iter().map(|s| s.nop_cycles).collect();
+        assert_eq!(cycles, [64, 20]);
+    }
+
+    #[test]
+    fn test_validate_template_sums_sections() {
+        let db = CycleDatabase::builtin();
+        let content = "move.w d0,d1 ; left\ndcb.w 10,$4e71\n;@hot\nnop ; right\ndcb.w 2,$4e71";
+        let sections = parse_template(content, &db).unwrap();
+        let budgets = section_budgets(&sections);
+        assert_eq!(
+            budgets
+                .iter()
+                .map(|b| (b.label.as_str(), b.injected, b.nop_budget, b.hot))
+                .collect::<Vec<_>>(),
+            [("left", 4, 40, false), ("right", 4, 8, true)]
+        );
+        assert_eq!(validate_template(&sections, 56).unwrap(), 56);
+        assert!(validate_template(&sections, 512).is_err());
+        assert!(validate_template(&[], 0).is_err());
+    }
 }
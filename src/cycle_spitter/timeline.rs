@@ -0,0 +1,178 @@
+// src/cycle_spitter/timeline.rs
+
+//! The timing structure of the generated scanlines, for video overlays.
+//!
+//! An overlay tool draws on top of emulator video output which code runs at which screen
+//! position. [`timeline`] turns the scanlines of a generation into the cycle ranges it needs:
+//! per scanline of the frame, the range of every section, split into spans of template, user,
+//! padding and jump code. Lines outside the template sections (the padding at the end of the
+//! scanline and the closing jump) form a final `tail` section. Cycles are scanline offsets as
+//! annotated in the output.
+
+use crate::cycle_spitter::models::{LineKind, Scanline};
+use crate::cycle_spitter::space::TAIL_LABEL;
+use serde::{Deserialize, Serialize};
+
+/// The timing structure of all generated scanlines.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Timeline {
+    /// The cycles of every scanline.
+    pub cycles_per_line: usize,
+    pub scanlines: Vec<TimelineScanline>,
+}
+
+/// The sections of one scanline.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimelineScanline {
+    /// The generated scanline, counted from 0.
+    pub scanline: usize,
+    /// The scanline of the frame, i.e. the vertical screen position.
+    pub line: usize,
+    pub sections: Vec<TimelineSection>,
+}
+
+/// The cycles `start..end` of a section.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimelineSection {
+    pub label: String,
+    pub start: usize,
+    pub end: usize,
+    pub spans: Vec<Span>,
+}
+
+/// The cycles `start..end` taken by consecutive lines of the same kind.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Span {
+    pub kind: LineKind,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Collects the cycle ranges of the sections of every scanline in `model`. Sections without
+/// timed lines are left out.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::models::{Chunk, Instruction, LineKind, Scanline};
+/// use cycle_spitter::timeline::timeline;
+///
+/// let scanline = Scanline {
+///     line: 34,
+///     cycles: 16,
+///     chunks: vec![Chunk {
+///         label: Some("left".to_string()),
+///         instructions: vec![
+///             Instruction::timed("\tmove.w d0,d1", LineKind::User, 4, 0),
+///             Instruction::timed("\tnop", LineKind::Padding, 4, 4),
+///             Instruction::timed("\tnop", LineKind::Padding, 4, 8),
+///         ],
+///     }],
+/// };
+/// let timeline = timeline(&[scanline], 512);
+/// let section = &timeline.scanlines[0].sections[0];
+/// assert_eq!((section.start, section.end), (0, 12));
+/// assert_eq!((section.spans[1].kind, section.spans[1].end), (LineKind::Padding, 12));
+/// ```
+pub fn timeline(model: &[Scanline], cycles_per_line: usize) -> Timeline {
+    let scanlines = model
+        .iter()
+        .enumerate()
+        .map(|(scanline, line)| {
+            let mut sections: Vec<TimelineSection> = Vec::new();
+            for chunk in &line.chunks {
+                let mut spans: Vec<Span> = Vec::new();
+                for instruction in &chunk.instructions {
+                    let Some(start) = instruction.offset else {
+                        continue;
+                    };
+                    let end = start + instruction.cycles;
+                    match spans.last_mut() {
+                        Some(span) if span.kind == instruction.kind && span.end == start => {
+                            span.end = end;
+                        }
+                        _ => spans.push(Span {
+                            kind: instruction.kind,
+                            start,
+                            end,
+                        }),
+                    }
+                }
+                let (Some(first), Some(last)) = (spans.first(), spans.last()) else {
+                    continue;
+                };
+                let label = chunk.label.as_deref().unwrap_or(TAIL_LABEL);
+                let (start, end) = (first.start, last.end);
+                // The padding at the end of the scanline and the closing jump are separate
+                // chunks, but one tail section.
+                match sections.last_mut() {
+                    Some(tail) if chunk.label.is_none() && tail.label == TAIL_LABEL => {
+                        tail.end = end;
+                        tail.spans.extend(spans);
+                    }
+                    _ => sections.push(TimelineSection {
+                        label: label.to_string(),
+                        start,
+                        end,
+                        spans,
+                    }),
+                }
+            }
+            TimelineScanline {
+                scanline,
+                line: line.line,
+                sections,
+            }
+        })
+        .collect();
+    Timeline {
+        cycles_per_line,
+        scanlines,
+    }
+}
+
+/// Renders `timeline` as pretty-printed JSON.
+pub fn timeline_json(timeline: &Timeline) -> String {
+    serde_json::to_string_pretty(timeline).unwrap_or_default() + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cycle_spitter::models::{Chunk, Instruction};
+
+    #[test]
+    fn test_timeline_joins_tail_chunks() {
+        let scanline = Scanline {
+            line: 0,
+            cycles: 24,
+            chunks: vec![
+                Chunk {
+                    label: Some("left".to_string()),
+                    instructions: vec![
+                        Instruction::timed("\tmove.w d0,d1", LineKind::Template, 4, 0),
+                        Instruction::other("; comment", None),
+                        Instruction::timed("\tmove.w d1,d2", LineKind::User, 4, 4),
+                    ],
+                },
+                Chunk {
+                    label: None,
+                    instructions: vec![Instruction::timed("\tnop", LineKind::Padding, 4, 8)],
+                },
+                Chunk {
+                    label: None,
+                    instructions: vec![
+                        Instruction::timed("\tjmp next", LineKind::Jump, 12, 12),
+                        Instruction::other("; total", None),
+                    ],
+                },
+            ],
+        };
+        let timeline = timeline(&[scanline], 24);
+        let sections = &timeline.scanlines[0].sections;
+        assert_eq!(sections.len(), 2);
+        assert_eq!(sections[0].spans.len(), 2);
+        assert_eq!(sections[1].label, TAIL_LABEL);
+        assert_eq!((sections[1].start, sections[1].end), (8, 24));
+        assert_eq!(sections[1].spans[1].kind, LineKind::Jump);
+    }
+}
@@ -0,0 +1,203 @@
+// src/cycle_spitter/trace.rs
+
+//! A log of every packing decision.
+//!
+//! When the output does not look as expected, the question is usually why a line ended up in
+//! the scanline it did. Filling a section's code window takes one decision per timed input line:
+//! the line is *accepted* if its cycles fit into what is left of the window and *deferred* to a
+//! later section if they do not, if it waits for a hot section (`;@critical`) or if it is a
+//! pragma, which takes effect at the next scanline; whatever is left of the window is then
+//! *padded*. The generator reports each decision to [`Observer::on_packing_decision`] as a
+//! [`PackingDecision`]; a [`PackingTrace`] collects them and [`render_trace`] prints them one
+//! per line, e.g.
+//!
+//! ```text
+//! input.s:3:2: scanline 0, left: 'move.l d0,d1' (4 cycles), free 8 -> 4: accepted
+//! input.s:4:2: scanline 0, left: 'move.w (a0),d1' (8 cycles), free 4 -> 4: deferred, it does not fit
+//! input.s:4:2: scanline 0, left: padding (4 cycles), free 4 -> 0: padded, ahead of 'move.w (a0),d1'
+//! ```
+//!
+//! Comments, `set` lines and data directives take no cycles and no decision. Reordering packers
+//! decide before generation (see the `packer` module); the trace shows the lines in the order
+//! they were packed into.
+
+use crate::cycle_spitter::diagnostics::Diagnostic;
+use crate::cycle_spitter::location::SourceLocation;
+use crate::cycle_spitter::observer::Observer;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::ops::ControlFlow;
+
+/// Why a line was left for a later section.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Deferral {
+    /// The line takes more cycles than are free.
+    DoesNotFit,
+    /// The line is marked `;@critical` and the section is not hot.
+    NotHot,
+    /// The line is a pragma, which takes effect at the next scanline.
+    Pragma,
+}
+
+/// What was decided.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Verdict {
+    /// The line was placed into the section.
+    Accepted,
+    /// The line was left for a later section.
+    Deferred(Deferral),
+    /// The free cycles left were padded.
+    Padded,
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Verdict::Accepted => write!(f, "accepted"),
+            Verdict::Deferred(Deferral::DoesNotFit) => write!(f, "deferred, it does not fit"),
+            Verdict::Deferred(Deferral::NotHot) => {
+                write!(
+                    f,
+                    "deferred, a critical instruction waits for a hot section"
+                )
+            }
+            Verdict::Deferred(Deferral::Pragma) => {
+                write!(f, "deferred, a pragma takes effect at the next scanline")
+            }
+            Verdict::Padded => write!(f, "padded"),
+        }
+    }
+}
+
+/// A decision as the accumulator takes it, without the scanline and section it is taken for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Decision {
+    /// The input line decided on; for padding, the deferred line it precedes, if any.
+    pub index: Option<usize>,
+    /// The cycles of the line, or of the padding.
+    pub cycles: usize,
+    pub free_before: usize,
+    pub free_after: usize,
+    pub verdict: Verdict,
+}
+
+/// One decision taken while filling the code window of a section.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PackingDecision {
+    /// The generated scanline, counted from 0.
+    pub scanline: usize,
+    /// The label of the template section.
+    pub section: String,
+    /// The index of the input line decided on, counting from 0 in the flattened input. For
+    /// padding, the deferred line it precedes; `None` at the end of the input.
+    pub index: Option<usize>,
+    /// The position of that line in the source, once located (see the `location` module).
+    pub location: Option<SourceLocation>,
+    /// The code of the line, without its comment.
+    pub line: String,
+    /// The cycles of the line, or of the padding.
+    pub cycles: usize,
+    /// The cycles free in the code window before the decision.
+    pub free_before: usize,
+    /// The cycles free in the code window after the decision.
+    pub free_after: usize,
+    pub verdict: Verdict,
+}
+
+/// Renders the decision as a line of the trace, prefixed with the source position if located.
+impl fmt::Display for PackingDecision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(location) = &self.location {
+            write!(f, "{}: ", location)?;
+        }
+        write!(f, "scanline {}, {}: ", self.scanline, self.section)?;
+        match self.verdict {
+            Verdict::Padded => write!(f, "padding")?,
+            _ => write!(f, "'{}'", self.line)?,
+        }
+        write!(
+            f,
+            " ({} cycles), free {} -> {}: {}",
+            self.cycles, self.free_before, self.free_after, self.verdict
+        )?;
+        match self.verdict {
+            Verdict::Padded if self.index.is_some() => write!(f, ", ahead of '{}'", self.line),
+            Verdict::Padded => write!(f, ", end of the input"),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Records the packing decisions of a run and forwards all events to `inner`.
+pub struct PackingTrace<'a> {
+    pub inner: &'a mut dyn Observer,
+    pub decisions: Vec<PackingDecision>,
+}
+
+impl<'a> PackingTrace<'a> {
+    pub fn new(inner: &'a mut dyn Observer) -> Self {
+        PackingTrace {
+            inner,
+            decisions: Vec::new(),
+        }
+    }
+}
+
+impl Observer for PackingTrace<'_> {
+    fn on_scanline_complete(&mut self, scanline: usize, cycles: usize) -> ControlFlow<()> {
+        self.inner.on_scanline_complete(scanline, cycles)
+    }
+
+    fn on_warning(&mut self, message: &str) {
+        self.inner.on_warning(message);
+    }
+
+    fn on_diagnostic(&mut self, diagnostic: &Diagnostic) {
+        self.inner.on_diagnostic(diagnostic);
+    }
+
+    fn on_padding_inserted(&mut self, scanline: usize, cycles: usize) {
+        self.inner.on_padding_inserted(scanline, cycles);
+    }
+
+    fn on_packing_decision(&mut self, decision: &PackingDecision) {
+        self.decisions.push(decision.clone());
+        self.inner.on_packing_decision(decision);
+    }
+}
+
+/// Renders `decisions` one per line.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::database::CycleDatabase;
+/// use cycle_spitter::generator::generate_with_observer;
+/// use cycle_spitter::observer::NullObserver;
+/// use cycle_spitter::options::Options;
+/// use cycle_spitter::template::{TemplateSection, TemplateSet};
+/// use cycle_spitter::trace::{PackingTrace, render_trace};
+///
+/// let templates = TemplateSet::from(vec![TemplateSection::code_window(8, "main")]);
+/// let options = Options::builder().cycles_per_line(8).build();
+/// let input = vec!["move.w d0,d1".to_string(), "move.w (a0),d1".to_string()];
+/// let mut inner = NullObserver;
+/// let mut trace = PackingTrace::new(&mut inner);
+/// generate_with_observer(&input, &templates, &CycleDatabase::builtin(), &options, &mut trace)
+///     .unwrap();
+/// assert_eq!(
+///     render_trace(&trace.decisions).lines().take(3).collect::<Vec<_>>(),
+///     [
+///         "scanline 0, main: 'move.w d0,d1' (4 cycles), free 8 -> 4: accepted",
+///         "scanline 0, main: 'move.w (a0),d1' (8 cycles), free 4 -> 4: deferred, it does not fit",
+///         "scanline 0, main: padding (4 cycles), free 4 -> 0: padded, ahead of 'move.w (a0),d1'",
+///     ]
+/// );
+/// ```
+pub fn render_trace(decisions: &[PackingDecision]) -> String {
+    decisions
+        .iter()
+        .map(|decision| format!("{}\n", decision))
+        .collect()
+}
@@ -0,0 +1,214 @@
+// src/cycle_spitter/video.rs
+
+//! Generated video base updates.
+//!
+//! Many fullscreen effects point the video counter (`$ffff8205/07/09`, writable on the STE) at
+//! a new address on every scanline or every few scanlines. [`VideoBaseUpdate`] describes such an
+//! update; the generator injects its three byte writes into a section of every scanline it
+//! applies to, taking their cycles from the section's code window:
+//!
+//! ```text
+//!     move.b  #((screen+(LINE+1)*160)>>16)&$ff,$ffff8205.w
+//!     move.b  #((screen+(LINE+1)*160)>>8)&$ff,$ffff8207.w
+//!     move.b  #(screen+(LINE+1)*160)&$ff,$ffff8209.w
+//! ```
+//!
+//! `LINE` is expanded to the scanline index like in any template code (see
+//! [`crate::template::expand_line`]), so the scanline after the update starts at
+//! `symbol + (LINE + 1) * stride`. The writes are placed at the start of the code window of the
+//! last section unless another section is named, as the last section usually runs after the
+//! right border, outside of the display.
+
+use crate::cycle_spitter::cycles::lookup_cycles;
+use crate::cycle_spitter::database::CycleDatabase;
+use crate::cycle_spitter::helpers::format_accumulated_instruction;
+use crate::cycle_spitter::options::AnnotationFormat;
+use crate::cycle_spitter::template::TemplateSection;
+use std::error::Error;
+
+/// The video counter registers, high byte first, with the shift of their byte of the address.
+const COUNTER_REGISTERS: [(&str, u32); 3] =
+    [("$ffff8205.w", 16), ("$ffff8207.w", 8), ("$ffff8209.w", 0)];
+
+/// A video base update applied to every `every`th scanline of the frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VideoBaseUpdate {
+    /// The symbol of the address the first scanline starts at.
+    pub symbol: String,
+    /// The bytes between the starts of two consecutive scanlines.
+    pub stride: usize,
+    /// Apply the update to every scanline whose index is a multiple of `every`.
+    pub every: usize,
+    /// The label of the section to inject the writes into, the last one if `None`.
+    pub section: Option<String>,
+}
+
+impl VideoBaseUpdate {
+    /// Parses an update spec `SYMBOL:STRIDE[:EVERY]`, e.g. `screen:160` or `screen:230:2`.
+    ///
+    /// # Errors
+    /// If the spec is malformed or `EVERY` is 0.
+    ///
+    /// # Example
+    /// ```rust
+    /// use cycle_spitter::video::VideoBaseUpdate;
+    ///
+    /// let update = VideoBaseUpdate::parse("screen:230:2").unwrap();
+    /// assert_eq!((update.stride, update.every), (230, 2));
+    /// assert!(update.applies_to(4) && !update.applies_to(5));
+    /// ```
+    pub fn parse(spec: &str) -> Result<Self, Box<dyn Error>> {
+        let invalid = || {
+            format!(
+                "Invalid video base update '{}' (SYMBOL:STRIDE[:EVERY])",
+                spec
+            )
+        };
+        let parts: Vec<&str> = spec.split(':').map(str::trim).collect();
+        let (symbol, stride, every) = match parts[..] {
+            [symbol, stride] => (symbol, stride, "1"),
+            [symbol, stride, every] => (symbol, stride, every),
+            _ => return Err(invalid().into()),
+        };
+        let stride = stride.parse().map_err(|_| invalid())?;
+        let every = every.parse().map_err(|_| invalid())?;
+        if symbol.is_empty() || every == 0 {
+            return Err(invalid().into());
+        }
+        Ok(VideoBaseUpdate {
+            symbol: symbol.to_string(),
+            stride,
+            every,
+            section: None,
+        })
+    }
+
+    /// Returns `true` if the update is applied to the frame scanline `scanline`.
+    pub fn applies_to(&self, scanline: usize) -> bool {
+        scanline.is_multiple_of(self.every)
+    }
+
+    /// Returns the instructions writing the video counter, with `LINE` left for expansion.
+    pub fn instructions(&self) -> Vec<String> {
+        let address = format!("({}+(LINE+1)*{})", self.symbol, self.stride);
+        COUNTER_REGISTERS
+            .iter()
+            .map(|(register, shift)| {
+                let byte = if *shift > 0 {
+                    format!("({}>>{})", address, shift)
+                } else {
+                    address.clone()
+                };
+                format!("move.b\t#{}&$ff,{}", byte, register)
+            })
+            .collect()
+    }
+}
+
+/// Returns `sections` with the writes of `update` injected at the start of the code window of
+/// the update's section.
+///
+/// # Errors
+/// If the section does not exist or its code window is shorter than the writes.
+pub(crate) fn inject_video_base(
+    sections: &[TemplateSection],
+    update: &VideoBaseUpdate,
+    db: &CycleDatabase,
+) -> Result<Vec<TemplateSection>, Box<dyn Error>> {
+    let target = match &update.section {
+        Some(label) => sections
+            .iter()
+            .position(|s| &s.label == label)
+            .ok_or_else(|| format!("No section '{}' for the video base update", label))?,
+        None => sections
+            .len()
+            .checked_sub(1)
+            .ok_or("No section for the video base update")?,
+    };
+
+    let mut offset: usize = sections[..=target]
+        .iter()
+        .map(|s| s.injection_code.iter().map(|(_, c)| c).sum::<usize>() + s.nop_cycles)
+        .sum::<usize>()
+        - sections[target].nop_cycles;
+    let mut writes = Vec::new();
+    for instruction in update.instructions() {
+        let cycles = lookup_cycles(&instruction, db);
+        let total = cycles.total();
+        writes.push((
+            format_accumulated_instruction(&instruction, &cycles, offset, AnnotationFormat::Full),
+            total,
+        ));
+        offset += total;
+    }
+    let needed: usize = writes.iter().map(|(_, c)| c).sum();
+    let section = &sections[target];
+    if needed > section.nop_cycles {
+        return Err(format!(
+            "The video base update takes {} cycles, but the {} section leaves {}",
+            needed, section.label, section.nop_cycles
+        )
+        .into());
+    }
+
+    Ok(sections
+        .iter()
+        .enumerate()
+        .map(|(index, section)| {
+            let mut injection_code = section.injection_code.clone();
+            let mut nop_cycles = section.nop_cycles;
+            if index == target {
+                injection_code.extend(writes.iter().cloned());
+                nop_cycles -= needed;
+            }
+            TemplateSection {
+                injection_code,
+                nop_cycles,
+                label: section.label.clone(),
+                hot: section.hot,
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cycle_spitter::template::{expand_line, parse_template};
+
+    #[test]
+    fn test_parse_rejects_malformed_specs() {
+        for spec in ["screen", "screen:wide", "screen:160:0", ":160", "a:1:2:3"] {
+            assert!(VideoBaseUpdate::parse(spec).is_err(), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn test_inject_video_base() {
+        let db = CycleDatabase::builtin();
+        let sections = parse_template(
+            "move.b d7,$ffff8260.w ; left\ndcb.w 20,$4e71\nmove.w d7,$ffff820a.w ; right\ndcb.w 16,$4e71\n",
+            &db,
+        )
+        .unwrap();
+        let update = VideoBaseUpdate::parse("screen:160").unwrap();
+        let injected = inject_video_base(&sections, &update, &db).unwrap();
+        assert_eq!(injected[0].nop_cycles, 80);
+        assert_eq!(injected[1].nop_cycles, 64 - 48);
+        let writes = &injected[1].injection_code[1..];
+        assert_eq!(writes.len(), 3);
+        assert!(writes.iter().all(|(_, cycles)| *cycles == 16));
+        assert!(
+            expand_line(&writes[0].0, 9)
+                .starts_with("move.b\t#((screen+(9+1)*160)>>16)&$ff,$ffff8205.w\t;")
+        );
+        assert!(writes[0].0.ends_with("move.b #xxx,xxx.w\t[104]"));
+
+        let too_short = VideoBaseUpdate {
+            section: Some("left".to_string()),
+            ..VideoBaseUpdate::parse("screen:160").unwrap()
+        };
+        let sections = parse_template("nop ; left\ndcb.w 4,$4e71\n", &db).unwrap();
+        assert!(inject_video_base(&sections, &too_short, &db).is_err());
+    }
+}
@@ -0,0 +1,190 @@
+// src/cycle_spitter/wasm.rs
+
+//! A JavaScript interface for running the splitter in a browser.
+//!
+//! Built for `wasm32-unknown-unknown` with the `wasm` feature and packaged with
+//! `wasm-pack build --target web -- --features wasm`, the crate exports a [`Playground`] class:
+//! created once with an optional cycle database overlay and adjustment rules, it splits, annotates
+//! and looks up code given as strings, so a page can show the scanline packing of pasted code
+//! as it is typed. Nothing touches the file system; results are plain JavaScript objects.
+//!
+//! ```js
+//! import init, { Playground } from "./pkg/cycle_spitter.js";
+//!
+//! await init();
+//! const playground = new Playground();
+//! const result = playground.split(code, template, 512);
+//! console.log(result.scanlines, result.warnings, result.output);
+//! console.log(playground.lookup("move.l (a0)+,(a1)+").cycles);
+//! ```
+
+use crate::cycle_spitter::adjust::apply_adjustments;
+use crate::cycle_spitter::annotate::annotate;
+use crate::cycle_spitter::cycles::{lookup_cycles, normalize_line_ext};
+use crate::cycle_spitter::database::CycleDatabase;
+use crate::cycle_spitter::diagnostics::Diagnostics;
+use crate::cycle_spitter::emitter::{AsmEmitter, Emitter};
+use crate::cycle_spitter::generator::ScanlineUsage;
+use crate::cycle_spitter::options::{AnnotationFormat, OptionsBuilder};
+use crate::cycle_spitter::runner::CycleSpitterBuilder;
+use crate::cycle_spitter::source::StringSource;
+use serde::Serialize;
+use std::error::Error;
+use wasm_bindgen::prelude::*;
+
+/// The result of [`Playground::split`].
+#[derive(Debug, Serialize)]
+pub struct SplitReport {
+    /// The generated assembler source, as the command line tool prints it.
+    pub output: String,
+    pub scanlines: usize,
+    /// How the cycles of every scanline are spent.
+    pub usage: Vec<ScanlineUsage>,
+    /// The warnings, prefixed with the position of their input line where they refer to one.
+    pub warnings: Vec<String>,
+}
+
+/// The result of [`Playground::lookup`].
+#[derive(Debug, Serialize)]
+pub struct Lookup {
+    /// The normalized instruction the database is looked up with.
+    pub normalized: String,
+    /// `false` if the instruction is missing from the database and counts as 0 cycles.
+    pub known: bool,
+    /// The database values: plain, `not-taken/taken` for branches, base and cycles per
+    /// register for register lists.
+    pub cycles: Vec<usize>,
+    /// The cycles counted for the instruction.
+    pub total: usize,
+}
+
+/// The splitter with its cycle database, for use from JavaScript.
+#[wasm_bindgen]
+pub struct Playground {
+    db: CycleDatabase,
+}
+
+#[wasm_bindgen]
+impl Playground {
+    /// Creates a playground using the bundled cycle database, extended by the JSON overlay
+    /// `cycle_db` and corrected by the adjustment rules `adjustments` if given (the contents
+    /// of the `--cycle-db` and `--adjust` files).
+    #[wasm_bindgen(constructor)]
+    pub fn new(
+        cycle_db: Option<String>,
+        adjustments: Option<String>,
+    ) -> Result<Playground, JsError> {
+        Self::with_database(cycle_db.as_deref(), adjustments.as_deref())
+            .map_err(|e| JsError::new(&e.to_string()))
+    }
+
+    /// Splits `input` into scanlines of `cycles_per_line` cycles (0 for the 512 of a 50 Hz ST)
+    /// laid out by `template`. Returns a [`SplitReport`] object.
+    pub fn split(
+        &self,
+        input: &str,
+        template: &str,
+        cycles_per_line: usize,
+    ) -> Result<JsValue, JsError> {
+        let report = self
+            .split_report(input, template, cycles_per_line)
+            .map_err(|e| JsError::new(&e.to_string()))?;
+        Ok(serde_wasm_bindgen::to_value(&report)?)
+    }
+
+    /// Returns `input` with the cycles and offset of every instruction appended (see
+    /// [`annotate`]).
+    pub fn annotate(&self, input: &str) -> String {
+        annotate(input, &self.db, AnnotationFormat::Full)
+    }
+
+    /// Looks up the cycles of `instruction`. Returns a [`Lookup`] object.
+    pub fn lookup(&self, instruction: &str) -> Result<JsValue, JsError> {
+        Ok(serde_wasm_bindgen::to_value(
+            &self.lookup_entry(instruction),
+        )?)
+    }
+}
+
+impl Playground {
+    fn with_database(
+        cycle_db: Option<&str>,
+        adjustments: Option<&str>,
+    ) -> Result<Playground, Box<dyn Error>> {
+        let mut db = CycleDatabase::builtin();
+        if let Some(overlay) = cycle_db {
+            db.extend(
+                CycleDatabase::from_json_str(overlay)
+                    .map_err(|e| format!("Invalid cycle database: {}", e))?,
+            );
+        }
+        if let Some(adjustments) = adjustments {
+            apply_adjustments(adjustments, &mut db)
+                .map_err(|e| format!("Invalid adjustments: {}", e))?;
+        }
+        Ok(Playground { db })
+    }
+
+    fn split_report(
+        &self,
+        input: &str,
+        template: &str,
+        cycles_per_line: usize,
+    ) -> Result<SplitReport, Box<dyn Error>> {
+        let mut options = OptionsBuilder::default();
+        if cycles_per_line > 0 {
+            options = options.cycles_per_line(cycles_per_line);
+        }
+        let spitter = CycleSpitterBuilder::new()
+            .input(StringSource::new("input", input))
+            .template(StringSource::new("template", template))
+            .database(self.db.clone())
+            .options(options)
+            .build()?;
+        let mut diagnostics = Diagnostics::default();
+        let generation = spitter.run_with_observer(&mut diagnostics)?;
+        Ok(SplitReport {
+            output: AsmEmitter.emit(&spitter.document(&generation)),
+            scanlines: generation.scanlines,
+            usage: generation.usage,
+            warnings: diagnostics.iter().map(|d| d.to_string()).collect(),
+        })
+    }
+
+    fn lookup_entry(&self, instruction: &str) -> Lookup {
+        let normalized = normalize_line_ext(instruction).0;
+        let cycles = lookup_cycles(instruction, &self.db);
+        Lookup {
+            known: self.db.contains(&normalized),
+            normalized,
+            cycles: cycles.get_cycles(),
+            total: cycles.total(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_playground_splits_and_looks_up() {
+        let overlay = r#"{"frobnicate.w dn": [12]}"#;
+        let playground = Playground::with_database(Some(overlay), None).unwrap();
+        let report = playground
+            .split_report(
+                "move.w d1,d2\nfrobnicate d0",
+                "move.w d0,d1\ndcb.w 4,$4e71",
+                20,
+            )
+            .unwrap();
+        assert_eq!(report.scanlines, 1);
+        assert!(report.output.contains("frobnicate d0"));
+        assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+
+        let lookup = playground.lookup_entry("frobnicate d3");
+        assert!(lookup.known);
+        assert_eq!(lookup.total, 12);
+        assert!(!playground.lookup_entry("frobnicate (a0)").known);
+    }
+}
@@ -0,0 +1,399 @@
+// src/cycle_spitter/whatif.rs
+
+//! Previewing how a single edit of the input re-flows the scanlines.
+//!
+//! When code is tuned close to a full frame, the interesting question about an edit is how far
+//! it ripples: inserting an instruction may push a few instructions into the next scanline,
+//! which may push others further, until the scanline boundaries fall back into step. [`what_if`]
+//! answers it without regenerating the whole input. The scanline containing the edit is found
+//! in the previously generated output; from there, the old and the edited input are generated
+//! side by side, one scanline at a time, until both start a scanline at the same (shifted) input
+//! line. All scanlines after that point are unchanged.
+//!
+//! Edits address the input by its lines after `REPT` expansion, which are the lines of the source
+//! unless a `REPT` block or a continued line precedes the edit. The output must have been
+//! generated from the same input, templates and options; packers reordering the input are not
+//! supported.
+
+use crate::cycle_spitter::checksum::TOTAL_CYCLES_PREFIX;
+use crate::cycle_spitter::data::misaligned_code;
+use crate::cycle_spitter::database::CycleDatabase;
+use crate::cycle_spitter::frame::RegionTracker;
+use crate::cycle_spitter::generator::{ResumeState, ScanlineUsage, resume_with_observer};
+use crate::cycle_spitter::helpers::strip_annotations;
+use crate::cycle_spitter::observer::Observer;
+use crate::cycle_spitter::options::{Options, Packer};
+use crate::cycle_spitter::pragma::{Pragma, parse_budget, parse_pragma};
+use crate::cycle_spitter::template::TemplateSet;
+use std::error::Error;
+use std::ops::ControlFlow;
+
+/// A proposed change of one input line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Edit {
+    /// Insert `instruction` before the line at `index` (0-based); an index past the end
+    /// appends it.
+    Insert { index: usize, instruction: String },
+    /// Remove the line at `index` (0-based).
+    Remove { index: usize },
+}
+
+impl Edit {
+    /// The index of the edited line.
+    pub fn index(&self) -> usize {
+        match self {
+            Edit::Insert { index, .. } | Edit::Remove { index } => *index,
+        }
+    }
+
+    /// Returns `lines` with the edit applied.
+    ///
+    /// # Errors
+    /// If the line to remove does not exist.
+    pub fn apply(&self, lines: &[String]) -> Result<Vec<String>, Box<dyn Error>> {
+        let mut edited = lines.to_vec();
+        match self {
+            Edit::Insert { index, instruction } => {
+                edited.insert((*index).min(lines.len()), instruction.clone())
+            }
+            Edit::Remove { index } if *index < lines.len() => {
+                edited.remove(*index);
+            }
+            Edit::Remove { index } => {
+                return Err(format!(
+                    "Cannot remove line {}: the input has {} lines",
+                    index + 1,
+                    lines.len()
+                )
+                .into());
+            }
+        }
+        Ok(edited)
+    }
+
+    /// Where the old input line at `index` is found after the edit.
+    fn shift(&self, index: usize) -> usize {
+        match self {
+            Edit::Insert { index: at, .. } if index >= *at => index + 1,
+            Edit::Remove { index: at } if index > *at => index - 1,
+            _ => index,
+        }
+    }
+}
+
+/// A scanline generated differently after the edit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReflowedScanline {
+    /// The scanline, counted from 0.
+    pub scanline: usize,
+    /// The padding cycles before the edit, or `None` if the scanline did not exist.
+    pub old_padding: Option<usize>,
+    /// The padding cycles after the edit, or `None` if the scanline no longer exists.
+    pub new_padding: Option<usize>,
+}
+
+impl ReflowedScanline {
+    /// The change in padding cycles, counting a missing scanline as no padding.
+    pub fn padding_delta(&self) -> isize {
+        self.new_padding.unwrap_or(0) as isize - self.old_padding.unwrap_or(0) as isize
+    }
+}
+
+/// The effect of an edit on the generated scanlines.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Reflow {
+    /// The scanlines from the one holding the edit up to the last one generated differently.
+    pub scanlines: Vec<ReflowedScanline>,
+    /// The change in the number of scanlines.
+    pub scanline_delta: isize,
+}
+
+/// Returns the input index at which every scanline of a previously generated `output` starts,
+/// by following the input lines through the output. Lines are compared without annotations and
+/// with whitespace runs collapsed, as the command line tool re-indents its output.
+///
+/// # Errors
+/// If some input line is not found in the output, i.e. the output was not generated from
+/// `flat_lines`.
+pub fn scanline_starts(
+    output: &[String],
+    flat_lines: &[String],
+) -> Result<Vec<usize>, Box<dyn Error>> {
+    let key = |line: &str| {
+        strip_annotations(line)
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    let mut starts = vec![0];
+    let mut index = 0;
+    for line in output {
+        if line.trim_start().starts_with(TOTAL_CYCLES_PREFIX) {
+            starts.push(index);
+        } else if flat_lines
+            .get(index)
+            .is_some_and(|input| key(line) == key(input))
+        {
+            index += 1;
+        }
+    }
+    if let Some(missing) = flat_lines.get(index) {
+        return Err(format!(
+            "The output was not generated from this input: line {} ('{}') is missing",
+            index + 1,
+            missing.trim()
+        )
+        .into());
+    }
+    // The last boundary is the end of the input.
+    starts.pop();
+    Ok(starts)
+}
+
+/// Stops generation after every scanline.
+struct OneScanline;
+
+impl Observer for OneScanline {
+    fn on_scanline_complete(&mut self, _scanline: usize, _cycles: usize) -> ControlFlow<()> {
+        ControlFlow::Break(())
+    }
+}
+
+/// Reconstructs the generator state at the start of scanline `scanline`, replaying the region
+/// changes and pragmas of the scanlines before it.
+fn state_at(
+    flat_lines: &[String],
+    starts: &[usize],
+    scanline: usize,
+    templates: &TemplateSet,
+    options: &Options,
+) -> Result<ResumeState, Box<dyn Error>> {
+    let mut template = templates
+        .initial()
+        .ok_or("No template registered")?
+        .to_string();
+    let mut budget = None;
+    let mut regions = RegionTracker::default();
+    for line in 0..scanline {
+        if let Some(name) = regions.advance(options.frame_layout(), options.start_line() + line) {
+            template = name.to_string();
+        }
+        let end = starts.get(line + 1).copied().unwrap_or(flat_lines.len());
+        for pragma in flat_lines[starts[line]..end]
+            .iter()
+            .filter_map(|line| parse_pragma(line))
+        {
+            match pragma {
+                Pragma::UseTemplate(name) => template = name,
+                Pragma::Budget(argument) => budget = parse_budget(&argument)?,
+                Pragma::RawScanline | Pragma::SplitHere => {}
+            }
+        }
+    }
+    Ok(ResumeState {
+        next_index: starts[scanline],
+        scanlines: scanline,
+        template: Some(template),
+        budget,
+    })
+}
+
+/// Inserts the `even` directives the generator would insert, so input indices stay valid
+/// across resumed generations.
+fn aligned(lines: Vec<String>) -> Vec<String> {
+    let mut lines = lines;
+    for position in misaligned_code(&lines).into_iter().rev() {
+        lines.insert(position, "\teven".to_string());
+    }
+    lines
+}
+
+/// Generates one scanline from `state`, returning its usage and the state after it, or `None`
+/// if the input is exhausted.
+fn step(
+    lines: &[String],
+    state: &ResumeState,
+    templates: &TemplateSet,
+    db: &CycleDatabase,
+    options: &Options,
+) -> Result<Option<(ScanlineUsage, ResumeState)>, Box<dyn Error>> {
+    if state.is_complete(lines) {
+        return Ok(None);
+    }
+    let generation = resume_with_observer(
+        lines,
+        templates,
+        db,
+        options,
+        &mut OneScanline,
+        state.clone(),
+    )?;
+    Ok(generation
+        .usage
+        .into_iter()
+        .next()
+        .map(|usage| (usage, generation.state)))
+}
+
+/// Previews the scanlines re-flowed by `edit` of `flat_lines`, given the `output` previously
+/// generated from them (see the module documentation).
+///
+/// # Errors
+/// - If a packer reordering the input is selected or the edit removes a line that does not exist.
+/// - If `output` was not generated from `flat_lines`.
+/// - If generating the affected scanlines fails.
+///
+/// # Example
+/// ```rust
+/// use cycle_spitter::database::CycleDatabase;
+/// use cycle_spitter::generator::generate;
+/// use cycle_spitter::options::Options;
+/// use cycle_spitter::template::{TemplateSection, TemplateSet};
+/// use cycle_spitter::whatif::{Edit, what_if};
+///
+/// let db = CycleDatabase::builtin();
+/// let templates = TemplateSet::from(vec![TemplateSection::code_window(16, "code")]);
+/// let options = Options::builder().cycles_per_line(16).build();
+/// let input: Vec<String> = vec!["move.w d0,d1".to_string(); 6];
+/// let output = generate(&input, &templates, &db, &options).unwrap().lines;
+///
+/// let edit = Edit::Remove { index: 0 };
+/// let reflow = what_if(&input, &output, &edit, &templates, &db, &options).unwrap();
+/// assert_eq!(reflow.scanlines.len(), 2);
+/// assert_eq!(reflow.scanlines[1].padding_delta(), 4);
+/// ```
+pub fn what_if(
+    flat_lines: &[String],
+    output: &[String],
+    edit: &Edit,
+    templates: &TemplateSet,
+    db: &CycleDatabase,
+    options: &Options,
+) -> Result<Reflow, Box<dyn Error>> {
+    if options.packer() != Packer::Greedy {
+        return Err(
+            "Reordering packers change the input; preview edits with the greedy one".into(),
+        );
+    }
+    let old_lines = aligned(
+        flat_lines
+            .iter()
+            .map(|line| strip_annotations(line).to_string())
+            .collect(),
+    );
+    let new_lines = aligned(edit.apply(&old_lines)?);
+    let starts = scanline_starts(output, &old_lines)?;
+    let first = starts
+        .iter()
+        .rposition(|&start| start <= edit.index())
+        .unwrap_or(0);
+
+    let mut old_state = Some(state_at(&old_lines, &starts, first, templates, options)?);
+    let mut new_state = old_state.clone();
+    let mut reflow = Reflow::default();
+    while old_state.is_some() || new_state.is_some() {
+        let old = match &old_state {
+            Some(state) => step(&old_lines, state, templates, db, options)?,
+            None => None,
+        };
+        let new = match &new_state {
+            Some(state) => step(&new_lines, state, templates, db, options)?,
+            None => None,
+        };
+        if old.is_none() && new.is_none() {
+            break;
+        }
+        reflow.scanline_delta += new.is_some() as isize - old.is_some() as isize;
+        reflow.scanlines.push(ReflowedScanline {
+            scanline: first + reflow.scanlines.len(),
+            old_padding: old.as_ref().map(|(usage, _)| usage.padding_cycles()),
+            new_padding: new.as_ref().map(|(usage, _)| usage.padding_cycles()),
+        });
+        old_state = old.map(|(_, state)| state);
+        new_state = new.map(|(_, state)| state);
+
+        // Once both inputs start a scanline at the same line, past the edit, with the same
+        // template and budget, the rest is generated alike.
+        if let (Some(old), Some(new)) = (&old_state, &new_state)
+            && old.next_index > edit.index()
+            && new.next_index == edit.shift(old.next_index)
+            && old.template == new.template
+            && old.budget == new.budget
+        {
+            break;
+        }
+    }
+    Ok(reflow)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cycle_spitter::generator::generate;
+    use crate::cycle_spitter::template::TemplateSection;
+
+    fn lines(raw: &[&str]) -> Vec<String> {
+        raw.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_scanline_starts() {
+        let db = CycleDatabase::builtin();
+        let templates = TemplateSet::from(vec![TemplateSection::code_window(8, "code")]);
+        let options = Options::builder().cycles_per_line(8).build();
+        let input = lines(&["move.w d0,d1", "; comment", "move.w d1,d2", "move.w d2,d3"]);
+        let output = generate(&input, &templates, &db, &options).unwrap().lines;
+        assert_eq!(scanline_starts(&output, &input).unwrap(), [0, 3]);
+        assert!(scanline_starts(&output[..4], &input).is_err());
+    }
+
+    #[test]
+    fn test_what_if_stops_once_scanlines_fall_back_into_step() {
+        let db = CycleDatabase::builtin();
+        let templates = TemplateSet::from(vec![TemplateSection::code_window(16, "code")]);
+        let options = Options::builder().cycles_per_line(16).build();
+        // Four scanlines: 4+4+8, 8+8, 4+4+4+4, 4
+        let input = lines(&[
+            "move.w d0,d1",
+            "move.w d0,d1",
+            "move.l d0,d1 ; (8)",
+            "move.l d0,d1 ; (8)",
+            "move.l d0,d1 ; (8)",
+            "move.w d0,d1",
+            "move.w d0,d1",
+            "move.w d0,d1",
+            "move.w d0,d1",
+            "move.w d0,d1",
+        ]);
+        let output = generate(&input, &templates, &db, &options).unwrap().lines;
+
+        // An extra 4-cycle instruction in the second scanline pushes an 8-cycle one into the
+        // third scanline, which pushes 4 cycles into the last one.
+        let edit = Edit::Insert {
+            index: 3,
+            instruction: "move.w d0,d1".to_string(),
+        };
+        let reflow = what_if(&input, &output, &edit, &templates, &db, &options).unwrap();
+        let summary: Vec<_> = reflow
+            .scanlines
+            .iter()
+            .map(|s| (s.scanline, s.old_padding, s.new_padding))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (1, Some(0), Some(4)),
+                (2, Some(0), Some(0)),
+                (3, Some(12), Some(4)),
+            ]
+        );
+        assert_eq!(reflow.scanline_delta, 0);
+
+        // Removing an instruction of the first scanline only changes its padding.
+        let edit = Edit::Remove { index: 0 };
+        let reflow = what_if(&input, &output, &edit, &templates, &db, &options).unwrap();
+        assert_eq!(reflow.scanlines.len(), 1);
+        assert_eq!(reflow.scanlines[0].padding_delta(), 4);
+        assert_eq!(reflow.scanline_delta, 0);
+    }
+}
@@ -0,0 +1,13 @@
+// src/lib.rs
+//! # cycleSpitter
+//!
+//! Library interface of the cycle-accurate scanline splitter for Atari ST fullscreen
+//! programming. The `cycleSpitter` binary is built on top of this crate; external tooling
+//! can use the same building blocks directly, or run the whole pipeline with [`split`].
+//!
+//! ## Stability
+//! The normalization grammar produced by [`normalize_line_ext`] is part of the public API
+//! and versioned through [`NORMALIZATION_VERSION`]. See the [`cycles`] module for details.
+mod cycle_spitter;
+
+pub use cycle_spitter::*;
@@ -1,7 +1,8 @@
 // src/main.rs
-mod cycle_spitter;
-use clap::Parser;
-use std::path::PathBuf;
+use clap::{Parser, Subcommand};
+use cycle_spitter::cache::{DEFAULT_CACHE_DIR, ScanlineCache, fingerprint};
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
 
 /// A cycle-accurate scanline splitter tool for Atari ST fullscreen programming
 ///
@@ -17,21 +18,513 @@ use std::path::PathBuf;
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    /// Input assembly file with optional (overridecycle annotations in comments (e.g., "move.l (a0)+,(a1) ; (20)")
-    #[arg(short, long, default_value = "sample.s")]
+    /// Input assembly file with optional (overridecycle annotations in comments (e.g., "move.l (a0)+,(a1) ; (20)"),
+    /// `-` for standard input
+    #[arg(value_parser = path_arg, short, long, default_value = "sample.s")]
     input: PathBuf,
 
-    /// Label for total scanlines summary in output
-    #[arg(short, long, default_value = "SCANLINES_CONSUMED")]
-    label: String,
+    /// Template file containing border removal and stabilizer code. Repeat as `NAME=PATH` to
+    /// register alternate templates selected with `;@use-template NAME`; the first one is used
+    /// until the input switches templates
+    #[arg(short, long, value_name = "[NAME=]PATH", default_value = "template.s")]
+    template: Vec<String>,
+
+    /// Split the input into plain scanline-sized chunks without injecting any template
+    #[arg(long, conflicts_with = "template")]
+    no_template: bool,
 
-    /// Template file containing border removal and stabilizer code
-    #[arg(short, long, default_value = "template.s")]
-    template: PathBuf,
+    /// Redirect the sync and shift mode writes of the templates to a harmless register
+    /// (default: the last palette entry, $ff825e) with the same timing, for debugging in
+    /// emulators and monitors that do not cope with overscan
+    #[arg(long, value_name = "ADDRESS", num_args = 0..=1, default_missing_value = "$ff825e")]
+    safe: Option<String>,
 
     /// Number of cycles per scanline (default: 512 for Atari ST)
-    #[arg(short, long, default_value_t = 512)]
+    #[arg(short, long, default_value_t = CpuProfile::default().cycles_per_line())]
     cycles: usize,
+
+    /// Cycles of one padding NOP, also used to count the `dcb.w N,$4e71` blocks of templates
+    /// (default: 4 for Atari ST)
+    #[arg(long, value_name = "CYCLES")]
+    nop_cycles: Option<usize>,
+
+    /// Split lines holding several instructions (separated by `;` or `:`) into one line per
+    /// instruction, so each one is counted and placed on its own
+    #[arg(long)]
+    split_statements: bool,
+
+    /// Fail if expanding the REPT blocks of an input yields more than N lines
+    #[arg(long, value_name = "N", default_value_t = ExpansionLimits::DEFAULT_MAX_LINES)]
+    max_expanded_lines: usize,
+
+    /// Fail if REPT blocks are nested more than N levels deep
+    #[arg(long, value_name = "N", default_value_t = ExpansionLimits::DEFAULT_MAX_DEPTH)]
+    max_rept_depth: usize,
+
+    /// Fail if expanding the REPT blocks of an input yields more than N bytes
+    #[arg(long, value_name = "N", default_value_t = ExpansionLimits::DEFAULT_MAX_BYTES)]
+    max_expanded_bytes: usize,
+
+    /// Use cycle comments in the styles of legacy sources (";12", "; 12 cycles", "; cycles: 12")
+    /// as overrides, reporting where they disagree with the cycle database
+    #[arg(long)]
+    legacy_cycles: bool,
+
+    /// Cycle database overlay whose entries extend and replace those of the bundled database
+    #[arg(value_parser = path_arg, long, value_name = "FILE")]
+    cycle_db: Option<PathBuf>,
+
+    /// File of project-local corrections applied after every cycle lookup, one
+    /// `adjust "<instruction>" +<cycles>` rule per line
+    #[arg(value_parser = path_arg, long, value_name = "FILE")]
+    adjust: Option<PathBuf>,
+
+    /// Time `trap #VECTOR` as its exception entry plus CYCLES for its handler, `rte` included;
+    /// repeat for several vectors
+    #[arg(long, value_name = "VECTOR=CYCLES")]
+    trap_handler: Vec<String>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+impl Args {
+    /// Returns the flags of the split: those given to `split`, or their defaults when running
+    /// without it.
+    fn split(&self) -> Cow<'_, SplitArgs> {
+        match &self.command {
+            Some(Command::Split(split)) => Cow::Borrowed(split),
+            _ => Cow::Owned(SplitArgs::default()),
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Split the input into scanlines, as without a subcommand, with the flags that shape and
+    /// write the generated code
+    Split(Box<SplitArgs>),
+    /// Print the input with the cycles and offset of every instruction, without splitting it
+    /// into scanlines
+    Annotate {
+        /// Source to annotate (default: --input)
+        #[arg(value_parser = path_arg)]
+        file: Option<PathBuf>,
+        /// Leave out the normalized lookup keys
+        #[arg(long)]
+        compact: bool,
+    },
+    /// Split the input and print how the cycles of every scanline are spent instead of the
+    /// generated code
+    Stats {
+        /// Print the summaries as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print the normalized form, base and per-register cycles and total of one instruction
+    Lookup {
+        /// The instruction, e.g. "movem.l d0-d7/a0-a6,-(sp)"
+        instruction: String,
+    },
+    /// Print the cycle database key every instruction of the input is looked up with and
+    /// whether the database has it
+    Normalize {
+        /// Source to normalize (default: --input)
+        #[arg(value_parser = path_arg)]
+        file: Option<PathBuf>,
+        /// Print only the instructions missing from the database
+        #[arg(long)]
+        missing: bool,
+    },
+    /// Report the scanline, section, offset and output line of every generated line
+    /// containing a label or instruction text
+    Find {
+        /// The label or instruction text, e.g. ".loop" or "lsl.w (a0)+"
+        pattern: String,
+        /// Previously generated file to search (default: split the input first)
+        #[arg(value_parser = path_arg)]
+        file: Option<PathBuf>,
+    },
+    /// Parse a template, print the injected cycles and NOP budget of every section and check
+    /// that they add up to a scanline of --cycles cycles
+    #[command(alias = "validate")]
+    ValidateTemplate {
+        /// Template file (default: the first --template)
+        #[arg(value_parser = path_arg)]
+        template: Option<PathBuf>,
+    },
+    /// Re-check the scanline checksums of a previously generated file
+    Verify {
+        /// Generated file to verify
+        #[arg(value_parser = path_arg)]
+        file: PathBuf,
+    },
+    /// Check that every key of the cycle database normalizes to itself, reporting keys that
+    /// the normalizer can never produce
+    Selftest,
+    /// Generate the input with two templates and compare scanlines, padding and overflows
+    CompareTemplates {
+        /// First template file
+        #[arg(value_parser = path_arg)]
+        first: PathBuf,
+        /// Second template file
+        #[arg(value_parser = path_arg)]
+        second: PathBuf,
+    },
+    /// Print a template switching sync and resolution at the given cycles of a scanline of
+    /// --cycles cycles, e.g. "60hz at 376/388, hi-res at 444/456"
+    GenTemplate {
+        /// The switch positions, or a file holding them
+        spec: String,
+    },
+    /// Collect the (n) cycle overrides of a hand-annotated source that differ from the cycle
+    /// database into a TOML file
+    LearnOverrides {
+        /// Hand-annotated reference file
+        #[arg(value_parser = path_arg)]
+        reference: PathBuf,
+        /// File to write the overrides to
+        #[arg(value_parser = path_arg, short, long, default_value = "overrides.toml")]
+        output: PathBuf,
+    },
+    /// Propose a frame layout from the sync and shift mode writes of a Hatari trace
+    /// (--trace video_sync,video_res)
+    ImportHatari {
+        /// Hatari trace log
+        #[arg(value_parser = path_arg)]
+        trace: PathBuf,
+        /// Directory to write a synthesized template for every proposed template to
+        #[arg(value_parser = path_arg, long, value_name = "DIR")]
+        template_dir: Option<PathBuf>,
+    },
+    /// Preview which scanlines of a generated file re-flow if one line of --input is inserted or
+    /// removed, and how their padding changes
+    WhatIf {
+        /// File previously generated from --input with the same templates and shared options and
+        /// the defaults of the split options
+        #[arg(value_parser = path_arg)]
+        output: PathBuf,
+        /// Insert an instruction before line N of the expanded input (after the last line if N
+        /// is past the end)
+        #[arg(long, num_args = 2, value_names = ["N", "INSTRUCTION"], conflicts_with = "remove")]
+        insert: Vec<String>,
+        /// Remove line N of the expanded input
+        #[arg(long, value_name = "N", required_unless_present = "insert")]
+        remove: Option<usize>,
+    },
+    /// Maintain a cycle database overlay
+    Db {
+        #[command(subcommand)]
+        action: DbCommand,
+    },
+}
+
+/// The flags of the `split` subcommand, which shape and write the generated code. The other
+/// subcommands that generate the input use their defaults.
+#[derive(clap::Args, Debug, Clone)]
+struct SplitArgs {
+    /// Generate scanlines from several input files in turn (e.g. `a.s,b.s`: odd scanlines from
+    /// a.s, even ones from b.s) instead of from --input
+    #[arg(value_parser = path_arg, long, value_name = "FILES", value_delimiter = ',', num_args = 1.., conflicts_with_all = ["cache", "size_report"])]
+    interleave: Vec<PathBuf>,
+
+    /// Label for total scanlines summary in output
+    #[arg(short, long, default_value = DEFAULT_LABEL)]
+    label: String,
+
+    /// Pad with another single-word instruction than `nop`, given as
+    /// INSTRUCTION:$OPCODE:CYCLES, e.g. 'tst.w (a7):$4a57:8'
+    #[arg(long, value_name = "SPEC")]
+    padding_instruction: Option<String>,
+
+    /// Frame layout file assigning templates and scanline lengths to scanline ranges of the frame
+    /// and IRQ windows (one `FIRST[-[LAST]] TEMPLATE [CYCLES] [irq=START+CYCLES ...]` region
+    /// per line)
+    #[arg(value_parser = path_arg, long, value_name = "FILE")]
+    frame_layout: Option<PathBuf>,
+
+    /// Lay out the generated code as if it started at scanline N of the frame layout
+    #[arg(long, value_name = "N", default_value_t = 0, requires = "frame_layout")]
+    start_line: usize,
+
+    /// Cycles of the first scanline taken by code before the generated block; template code
+    /// before this point is left out
+    #[arg(long, value_name = "CYCLES", default_value_t = 0)]
+    entry_offset: usize,
+
+    /// Cycles of the last scanline taken by code after the generated block; template code
+    /// after this point is left out
+    #[arg(long, value_name = "CYCLES", default_value_t = 0)]
+    exit_offset: usize,
+
+    /// Loop the generated block every frame: place LABEL at its start and end the last
+    /// scanline with `jmp LABEL`, padded so that the jump back, --loop-wait and the entry offset
+    /// add up to exactly one scanline
+    #[arg(long = "loop", value_name = "LABEL", conflicts_with = "exit_offset")]
+    loop_label: Option<String>,
+
+    /// Cycles of the code run between the jump back and the first scanline, e.g. a padded VBL
+    /// wait
+    #[arg(
+        long,
+        value_name = "CYCLES",
+        default_value_t = 0,
+        requires = "loop_label"
+    )]
+    loop_wait: usize,
+
+    /// Cut the generated block into chunks of N scanlines, each chunk jumping to the next at the
+    /// end of its last scanline
+    #[arg(long, value_name = "N", conflicts_with_all = ["interleave", "keep_from"])]
+    chunk_scanlines: Option<usize>,
+
+    /// Prefix of the chunk labels (PREFIX_1, PREFIX_2, ...) and files
+    #[arg(
+        long,
+        value_name = "PREFIX",
+        default_value = "chunk",
+        requires = "chunk_scanlines"
+    )]
+    chunk_label: String,
+
+    /// Write every chunk to a file of its own in DIR instead of printing the whole output
+    #[arg(value_parser = path_arg, long, value_name = "DIR", requires = "chunk_scanlines")]
+    chunk_dir: Option<PathBuf>,
+
+    /// Write every scanline (or group of scanlines) to an include file of its own in DIR, plus a
+    /// master include `scanlines.i`, instead of printing the whole output
+    #[arg(value_parser = path_arg, long, value_name = "DIR", conflicts_with_all = ["chunk_scanlines", "keep_from"])]
+    emit_includes: Option<PathBuf>,
+
+    /// Scanlines per include file written by --emit-includes
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 1,
+        requires = "emit_includes"
+    )]
+    include_group: usize,
+
+    /// Cycles of every scanline to keep free as a safety margin (padded with NOPs), so debug
+    /// code can be added later without re-flowing the scanlines
+    #[arg(long, value_name = "CYCLES", default_value_t = 0)]
+    margin: usize,
+
+    /// Fail if a scanline pads more than CYCLES in a row, listing the scanlines that do
+    #[arg(long, value_name = "CYCLES")]
+    max_consecutive_padding: Option<usize>,
+
+    /// Append a checksum comment to every scanline (checked by the `verify` subcommand)
+    #[arg(long)]
+    checksum: bool,
+
+    /// Embed the warnings raised for a scanline as comments at its end instead of printing them
+    #[arg(long)]
+    warnings_in_output: bool,
+
+    /// Previously generated file whose ;@keep-begin/;@keep-end regions are preserved verbatim
+    #[arg(value_parser = path_arg, long, value_name = "FILE")]
+    keep_from: Option<PathBuf>,
+
+    /// Reuse unchanged scanlines from previous runs, cached in DIR (default: .cyclespitter-cache)
+    #[arg(value_parser = path_arg, long, value_name = "DIR", num_args = 0..=1, default_missing_value = DEFAULT_CACHE_DIR)]
+    cache: Option<PathBuf>,
+
+    /// Write a chart of the cycles used and padded per section and scanline to FILE
+    /// (SVG for a .svg extension, gnuplot data otherwise)
+    #[arg(value_parser = path_arg, long, value_name = "FILE")]
+    graph: Option<PathBuf>,
+
+    /// Write the start cycle and free cycles of every scanline section to FILE, for code
+    /// generated at runtime (JSON for a .json extension, assembler equates otherwise)
+    #[arg(value_parser = path_arg, long, value_name = "FILE")]
+    free_cycles: Option<PathBuf>,
+
+    /// Write the offsets of the absolute long references a relocator has to patch to FILE
+    /// (big-endian 32-bit words for a .bin extension, a text listing otherwise)
+    #[arg(value_parser = path_arg, long, value_name = "FILE")]
+    fixups: Option<PathBuf>,
+
+    /// Write the cycle ranges of the sections of every scanline to FILE as JSON, for overlays
+    /// showing the timing structure on top of emulator video output
+    #[arg(value_parser = path_arg, long, value_name = "FILE")]
+    timeline: Option<PathBuf>,
+
+    /// Write every packing decision to FILE: each line considered, its section, the cycles free
+    /// before and after it and whether it was accepted, deferred (and why) or padded around
+    #[arg(value_parser = path_arg, long, value_name = "FILE")]
+    trace_packing: Option<PathBuf>,
+
+    /// Print a one-character-per-scanline utilization map to standard error
+    #[arg(long)]
+    heatmap: bool,
+
+    /// How user code is packed into sections: `greedy` keeps the source order, `first-fit`
+    /// moves the first independent instructions that fit forward to fill gaps that would
+    /// otherwise be padded, `optimal` the combination that fills them best
+    #[arg(long, default_value = "greedy", value_parser = ["greedy", "first-fit", "optimal"])]
+    packer: String,
+
+    /// Number of instructions the first-fit and optimal packers look ahead
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_PACKER_WINDOW)]
+    packer_window: usize,
+
+    /// Print the byte footprint of data directives (ds, dcb, incbin) to standard error
+    #[arg(long)]
+    size_report: bool,
+
+    /// Indent with N spaces instead of tabs
+    #[arg(long, value_name = "N")]
+    indent_spaces: Option<usize>,
+
+    /// Width of a tab stop when aligning columns with tabs (default: 8)
+    #[arg(long, value_name = "N", conflicts_with = "indent_spaces")]
+    tab_width: Option<usize>,
+
+    /// Start the operands of every instruction in column N
+    #[arg(long, value_name = "N")]
+    operand_column: Option<usize>,
+
+    /// Start trailing comments in column N
+    #[arg(long, value_name = "N")]
+    comment_column: Option<usize>,
+
+    /// Write labels with (`colon`) or without (`bare`) a trailing colon
+    #[arg(long, value_parser = ["colon", "bare"])]
+    label_style: Option<String>,
+
+    /// Annotation of user instructions: cycles, lookup key and scanline offset (full), cycles
+    /// and offset (compact), or cycles and the cycles left in section and scanline (remaining)
+    #[arg(long, default_value = "full", value_parser = ["full", "compact", "remaining"])]
+    annotations: String,
+
+    /// Comments in the output: the total cycles of every scanline (normal), or also its
+    /// utilization and padding (verbose)
+    #[arg(long, default_value = "normal", value_parser = ["normal", "verbose"])]
+    verbosity: String,
+
+    /// Start every scanline with a separator comment drawn with CHAR, showing the scanline and
+    /// its cycle range within the frame
+    #[arg(long, value_name = "CHAR", num_args = 0..=1, default_missing_value = "=")]
+    separators: Option<char>,
+
+    /// Write the video counter ($ffff8205/07/09) on every EVERYth scanline (default: every one),
+    /// so the next scanline starts at SYMBOL + (LINE + 1) * STRIDE
+    #[arg(long, value_name = "SYMBOL:STRIDE[:EVERY]")]
+    video_base: Option<String>,
+
+    /// Template section to place the video counter writes in (default: the last one)
+    #[arg(long, value_name = "LABEL", requires = "video_base")]
+    video_base_section: Option<String>,
+
+    /// Fail if the input needs more than N scanlines, e.g. more than the frame can display
+    #[arg(long, value_name = "N")]
+    max_scanlines: Option<usize>,
+
+    /// What to do when the input runs out before the frame ends (--max-scanlines or the last
+    /// region of --frame-layout): `stop`, `pad` the remaining scanlines with template and NOPs,
+    /// or `repeat` the input from the beginning
+    #[arg(long, default_value = "stop", value_parser = ["stop", "pad", "repeat"])]
+    tail: String,
+
+    /// Write the output to FILE instead of standard output. The file is replaced only once the
+    /// output is complete, so an interrupted run leaves the previous file intact
+    #[arg(value_parser = path_arg, short, long, value_name = "FILE", conflicts_with_all = ["chunk_dir", "emit_includes"])]
+    output: Option<PathBuf>,
+
+    /// Output format: assembler source, a JSON document, an HTML page or a CSV table of the
+    /// generated lines
+    #[arg(long, default_value = "asm", value_parser = ["asm", "json", "html", "csv"])]
+    format: String,
+
+    /// Run the whole pipeline without writing any code, print the scanline count, total and
+    /// padding cycles, overflows and unknown instructions, and fail if there were problems
+    #[arg(long, conflicts_with_all = ["listing", "output"])]
+    check: bool,
+
+    /// Keep running and regenerate the --output file whenever the input, a template, the frame
+    /// layout, the --keep-from file, the cycle database overlay or the adjustment rules change
+    #[arg(long, requires = "output")]
+    watch: bool,
+
+    /// Write a listing of the input instead of the generated source: every input line followed
+    /// by the scanline and section it was placed in and its cycle window
+    #[arg(long, conflicts_with_all = ["interleave", "emit_includes", "chunk_dir"])]
+    listing: bool,
+
+    /// Print the symbols the generated code references but does not define to standard error
+    #[arg(long)]
+    symbols: bool,
+
+    /// Import the symbols the generated code references but does not define, with the import
+    /// directive of DIALECT (devpac, vasm and rmac: xref; gnu and gas: .extern)
+    #[arg(long, value_name = "DIALECT", value_parser = ["devpac", "vasm", "rmac", "gnu", "gas"])]
+    xref: Option<String>,
+
+    /// Pad spare cycles with a varied mix of no-effect instructions instead of NOPs, chosen
+    /// reproducibly from SEED
+    #[arg(long, value_name = "SEED")]
+    filler_seed: Option<u64>,
+
+    /// Compare padding cycles, scanline count and overflows against the baseline stored in FILE
+    /// (created by the first run) and fail if any of them regresses
+    #[arg(value_parser = path_arg, long, value_name = "FILE")]
+    baseline: Option<PathBuf>,
+
+    /// Growth in percent of the baseline tolerated for scanlines and padding cycles
+    #[arg(
+        long,
+        value_name = "PERCENT",
+        default_value_t = 0.0,
+        requires = "baseline"
+    )]
+    baseline_threshold: f64,
+
+    /// Only warn about baseline regressions instead of failing
+    #[arg(long, requires = "baseline")]
+    baseline_warn: bool,
+
+    /// Overwrite the baseline with the statistics of this run
+    #[arg(long, requires = "baseline")]
+    update_baseline: bool,
+}
+
+impl Default for SplitArgs {
+    fn default() -> Self {
+        match Args::parse_from(["cycleSpitter", "split"]).command {
+            Some(Command::Split(split)) => *split,
+            _ => unreachable!("`split` parses as the split subcommand"),
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum DbCommand {
+    /// Add an instruction missing from the database, e.g. `db add "add.w dn,dn" 4 --source
+    /// "MC68000UM table 8-1"`
+    Add {
+        /// Normalized instruction key
+        key: String,
+        /// Cycle values: `N`, `NOT_TAKEN TAKEN` for branches, `BASE PER_REG` for register
+        /// lists or `BEST WORST` for ranges
+        #[arg(required = true, num_args = 1..=3)]
+        cycles: Vec<usize>,
+        /// How to read the cycle values (plain, branch, reglist or range; default: inferred)
+        #[arg(long)]
+        kind: Option<String>,
+        /// Where the timing comes from, e.g. a manual table or a measurement
+        #[arg(long)]
+        source: Option<String>,
+        /// Free-form remarks on the timing
+        #[arg(long)]
+        notes: Option<String>,
+        /// Replace an existing entry instead of refusing
+        #[arg(long)]
+        replace: bool,
+        /// Overlay file to add the entry to (default: --cycle-db or cycles.overlay.json)
+        #[arg(value_parser = path_arg, short, long)]
+        output: Option<PathBuf>,
+    },
 }
 
 /// Main program for the "cycleSpitter" generation tool.
@@ -50,7 +543,7 @@ struct Args {
 ///      code for each scanline, handling sections and nop cycles.
 /// 3. **Assembly File Processing**:
 ///    - Reads the input assembly file line by line, trims it, and preprocesses it
-///      into a flat structure for easier processing (via `process_block`).
+///      into a flat structure for easier processing (via `expand_block`).
 ///    - Breaks the input into chunks that fit into scanlines of `SCANLINE_CYCLES`
 ///      cycles, inserting padding and annotations when necessary.
 /// 4. **Output Generation**:
@@ -62,7 +555,7 @@ struct Args {
 ///
 /// ### Example Usage:
 /// ```sh
-/// $ ./cycle_spitter --input input.s --label SCANLINES_OUTPUT --template template.s --cycles 512
+/// $ ./cycle_spitter --input input.s --template template.s --cycles 512 split --label SCANLINES_OUTPUT
 /// ```
 ///
 /// ### Output Notes:
@@ -74,112 +567,1824 @@ struct Args {
 /// - Warns if a scanline exceeds the defined cycle limit.
 ///
 /// Author: slippy / vectronix (c) 2025
+use notify::event::ModifyKind;
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::collections::BTreeSet;
 use std::fs;
+use std::io::IsTerminal;
+use std::sync::mpsc;
+use std::time::Duration;
 
-use crate::cycle_spitter::accumulator::accumulate_chunk;
-use crate::cycle_spitter::block::process_block;
-use crate::cycle_spitter::regexes::REG_LABEL_RE;
-use crate::cycle_spitter::template::parse_template;
+use cycle_spitter::adjust::apply_adjustments;
+use cycle_spitter::annotate::{annotate, lookup_keys};
+use cycle_spitter::baseline::{RunStats, compare};
+use cycle_spitter::block::{
+    ExpansionLimits, expand_block_with_origins, join_continuations_with_origins,
+    split_statements_with_origins, validate_block_structure,
+};
+use cycle_spitter::capacity::check_capacity;
+use cycle_spitter::checksum::verify;
+use cycle_spitter::cycles::{lookup_cycles, normalize_line_ext};
+use cycle_spitter::data::size_report;
+use cycle_spitter::database::{CycleDatabase, CycleEntry, CycleKind};
+use cycle_spitter::diagnostics::{DiagnosticKind, Diagnostics, WARNING_COMMENT_PREFIX};
+use cycle_spitter::emitter::{AsmEmitter, Document, Emitter, emitter_for};
+use cycle_spitter::exception::parse_trap_handler;
+use cycle_spitter::find::{Found, find_in_output};
+use cycle_spitter::fixups::find_fixups;
+use cycle_spitter::frame::FrameLayout;
+use cycle_spitter::generator::{
+    Generation, ResumeState, ScanlineUsage, generate_cached, generate_with_observer,
+};
+use cycle_spitter::graph::{utilization_gnuplot, utilization_heatmap, utilization_svg};
+use cycle_spitter::hardware::neutralize_sync_writes;
+use cycle_spitter::hatari::{FrameProposal, parse_trace, propose_frame};
+use cycle_spitter::includes::split_includes;
+use cycle_spitter::interleave::generate_interleaved;
+use cycle_spitter::keep::{apply_kept_regions, parse_kept_regions};
+use cycle_spitter::learn::learn_overrides;
+use cycle_spitter::legacy::import_legacy_cycles;
+use cycle_spitter::listing::listing;
+use cycle_spitter::location::{Locator, SourceMap};
+use cycle_spitter::observer::{NullObserver, Observer, StderrObserver};
+use cycle_spitter::options::{
+    AnnotationFormat, CpuProfile, DEFAULT_PACKER_WINDOW, Options, Packer, PaddingInstruction,
+    PaddingStyle, Tail, Verbosity,
+};
+use cycle_spitter::output::write_atomically;
+use cycle_spitter::padding::long_padding_runs;
+use cycle_spitter::regexes::REG_LABEL_RE;
+use cycle_spitter::runner::{DEFAULT_LABEL, StageCache};
+use cycle_spitter::seam::{ChunkChain, LoopSeam};
+use cycle_spitter::selftest::{check_key, self_test};
+use cycle_spitter::source::{
+    FileSource, SourceProvider, StdinSource, StringSource, native_path, normalize_line_endings,
+};
+use cycle_spitter::space::{free_space, free_space_equates, free_space_json};
+use cycle_spitter::style::{DEFAULT_TAB_WIDTH, Indent, LabelStyle, Style};
+use cycle_spitter::symbols::{Dialect, external_symbols};
+use cycle_spitter::synthesis::{parse_switch_spec, synthesize_template};
+use cycle_spitter::template::{
+    TemplateSection, TemplateSet, parse_template_with, section_budgets, validate_template,
+};
+use cycle_spitter::timeline::{timeline, timeline_json};
+use cycle_spitter::trace::{PackingTrace, render_trace};
+use cycle_spitter::video::VideoBaseUpdate;
+use cycle_spitter::whatif::{Edit, what_if};
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Parse command-line arguments
     let args = Args::parse();
+    let result = match &args.command {
+        Some(Command::Split(split)) if split.watch => watch(&args, split),
+        _ => run_cli(&args, &mut Stages::default()),
+    };
+    // Report errors by their message, whatever type the library raised them as.
+    result.map_err(|e| e.to_string().into())
+}
 
-    // Parse the template
-    let template_content = fs::read_to_string(&args.template).map_err(|e| {
-        format!(
-            "Failed to read template file '{}': {}",
-            args.template.display(),
-            e
-        )
-    })?;
-    let template_sections = parse_template(&template_content)?;
+/// The results of the stages of a run kept for the next one by `--watch`: the flattened inputs
+/// and the parsed templates, each redone only when the text of its file changed.
+#[derive(Default)]
+struct Stages {
+    inputs: StageCache<(Vec<String>, SourceMap)>,
+    templates: StageCache<Vec<TemplateSection>>,
+}
 
-    // Read and process the input file
-    let content = fs::read_to_string(&args.input).map_err(|e| {
-        format!(
-            "Failed to read input file '{}': {}",
-            args.input.display(),
-            e
-        )
-    })?;
-    let raw_lines: Vec<String> = content.lines().map(|s| s.trim().to_string()).collect();
-    let (flat_lines, _) = process_block(&raw_lines, 0);
+/// Runs the pipeline for `--watch`: once, then again whenever one of the files it reads changes.
+/// A failed run is reported and the watch goes on, as the next edit may fix it. The stages of
+/// unchanged files are kept between the runs, except after a change of the cycle database,
+/// which all of them depend on.
+fn watch(args: &Args, split: &SplitArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let output = split.output.as_ref().ok_or("--watch requires --output")?;
+    let (files, databases) = watched_files(args, split)?;
+
+    // Editors often save by replacing a file, which ends a watch on the file itself, so the
+    // directories are watched and their events filtered.
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)?;
+    let dirs: BTreeSet<&Path> = files.iter().filter_map(|file| file.parent()).collect();
+    for dir in dirs {
+        watcher
+            .watch(dir, RecursiveMode::NonRecursive)
+            .map_err(|e| format!("Failed to watch '{}': {}", dir.display(), e))?;
+    }
+    let mut stages = Stages::default();
+    loop {
+        match run_cli(args, &mut stages) {
+            Ok(()) => eprintln!(
+                "Output written to '{}', watching {} file(s) for changes",
+                output.display(),
+                files.len()
+            ),
+            Err(e) => eprintln!("Error: {}", e),
+        }
+        let changed = wait_for_change(&receiver, &files)?;
+        if changed.iter().any(|path| databases.contains(path)) {
+            stages = Stages::default();
+        }
+    }
+}
+
+/// Returns the absolute paths of the files a run of `--watch` reads, and of them those of the
+/// cycle database, which every stage depends on. Standard input cannot be watched.
+fn watched_files(
+    args: &Args,
+    split: &SplitArgs,
+) -> Result<(Vec<PathBuf>, Vec<PathBuf>), Box<dyn std::error::Error>> {
+    let mut files = if split.interleave.is_empty() {
+        vec![args.input.clone()]
+    } else {
+        split.interleave.clone()
+    };
+    if files.iter().any(|path| path.as_os_str() == "-") {
+        return Err("--watch cannot watch standard input, give the input as a file".into());
+    }
+    if !args.no_template {
+        files.extend(
+            args.template
+                .iter()
+                .map(|spec| native_path(split_template_spec(spec).1)),
+        );
+    }
+    files.extend(split.frame_layout.iter().cloned());
+    // Keeping the regions of the output itself must not rerun on every write of the output.
+    files.extend(
+        split
+            .keep_from
+            .iter()
+            .filter(|path| Some(*path) != split.output.as_ref())
+            .cloned(),
+    );
+    let databases = args
+        .cycle_db
+        .iter()
+        .chain(&args.adjust)
+        .map(std::path::absolute)
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut files = files
+        .iter()
+        .map(std::path::absolute)
+        .collect::<Result<Vec<_>, _>>()?;
+    files.extend(databases.iter().cloned());
+    Ok((files, databases))
+}
+
+/// Blocks until some of `files` are written, created, renamed or removed, waits for the burst
+/// of events of a save to settle and returns the files changed meanwhile.
+fn wait_for_change(
+    receiver: &mpsc::Receiver<notify::Result<notify::Event>>,
+    files: &[PathBuf],
+) -> Result<BTreeSet<PathBuf>, Box<dyn std::error::Error>> {
+    let collect = |changed: &mut BTreeSet<PathBuf>, event: notify::Result<notify::Event>| {
+        // Reading the files, as every run does, raises access events
+        if let Ok(event) = event
+            && matches!(
+                event.kind,
+                EventKind::Create(_)
+                    | EventKind::Modify(
+                        ModifyKind::Data(_) | ModifyKind::Name(_) | ModifyKind::Any
+                    )
+                    | EventKind::Remove(_)
+            )
+        {
+            changed.extend(event.paths.into_iter().filter(|path| files.contains(path)));
+        }
+    };
+    let mut changed = BTreeSet::new();
+    while changed.is_empty() {
+        collect(
+            &mut changed,
+            receiver.recv().map_err(|_| "The file watcher stopped")?,
+        );
+    }
+    while let Ok(event) = receiver.recv_timeout(Duration::from_millis(100)) {
+        collect(&mut changed, event);
+    }
+    Ok(changed)
+}
+
+fn run_cli(args: &Args, stages: &mut Stages) -> Result<(), Box<dyn std::error::Error>> {
+    match &args.command {
+        Some(Command::Annotate { file, compact }) => {
+            return print_annotated(args, file.as_ref().unwrap_or(&args.input), *compact);
+        }
+        Some(Command::Lookup { instruction }) => return print_lookup(args, instruction),
+        Some(Command::Normalize { file, missing }) => {
+            return print_lookup_keys(args, file.as_ref().unwrap_or(&args.input), *missing);
+        }
+        Some(Command::Find {
+            pattern,
+            file: Some(file),
+        }) => {
+            let content = read_text(file, "generated file")?;
+            return print_found(&find_in_output(&content, pattern), pattern);
+        }
+        Some(Command::ValidateTemplate { template }) => {
+            return validate(args, template.as_ref());
+        }
+        Some(Command::Verify { file }) => return verify_file(file),
+        Some(Command::Selftest) => return run_self_test(),
+        Some(Command::CompareTemplates { first, second }) => {
+            return compare_templates(args, first, second);
+        }
+        Some(Command::GenTemplate { spec }) => return generate_template(spec, args.cycles),
+        Some(Command::LearnOverrides { reference, output }) => {
+            return write_learned_overrides(reference, output);
+        }
+        Some(Command::ImportHatari {
+            trace,
+            template_dir,
+        }) => return import_hatari(trace, template_dir.as_ref()),
+        Some(Command::WhatIf {
+            output,
+            insert,
+            remove,
+        }) => return preview_edit(args, output, insert, *remove),
+        Some(Command::Db { action }) => return run_db_command(args, action),
+        Some(Command::Split(_) | Command::Stats { .. } | Command::Find { .. }) | None => {}
+    }
+    let split = args.split();
+
+    // Load the cycle database shared by template parsing and accumulation
+    let db = load_database(args)?;
+
+    // Parse the templates
+    let options = build_options(args, &split)?;
+    let templates = load_templates(args, &db, &options, &mut stages.templates)?;
 
-    let mut final_output: Vec<String> = Vec::new();
-    let mut current_index = 0;
-    let mut line_count = 0;
+    // Read and process the input file(s)
+    let inputs = if split.interleave.is_empty() {
+        vec![args.input.clone()]
+    } else {
+        split.interleave.clone()
+    };
+    let (flat_inputs, maps): (Vec<Vec<String>>, Vec<SourceMap>) = inputs
+        .iter()
+        .map(|path| read_input(path, args, &db, &mut stages.inputs))
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .unzip();
+    let flat_lines = &flat_inputs[0];
+    // Warnings about an input line start with its position; interleaved inputs share the
+    // scanlines, so their warnings are left unlocated.
+    // A check reports the warnings along with its summary.
+    let mut diagnostics = Diagnostics::default();
+    let mut stderr = StderrObserver;
+    let mut trace = PackingTrace::new(if split.check {
+        &mut diagnostics
+    } else {
+        &mut stderr
+    });
+    let mut observer = Locator {
+        map: &maps[0],
+        inner: &mut trace,
+    };
 
-    while current_index < flat_lines.len() {
-        let mut scanline_offset = 0;
-        let mut scanline_cycles = 0;
+    if split.size_report {
+        let base = args.input.parent().unwrap_or(std::path::Path::new("."));
+        let report = size_report(flat_lines, |path| {
+            fs::metadata(base.join(native_path(path)))
+                .ok()
+                .map(|m| m.len() as usize)
+        });
+        eprintln!("Data directives:");
+        for entry in &report.entries {
+            match entry.bytes {
+                Some(bytes) => eprintln!("  {:>8} bytes  {}", bytes, entry.line),
+                None => eprintln!("  {:>8}        {}", "unknown", entry.line),
+            }
+        }
+        eprintln!(
+            "  {:>8} bytes total ({} of unknown size)",
+            report.total_bytes(),
+            report.unknown().count()
+        );
+    }
+
+    for overrun in check_capacity(&flat_inputs, &templates, &db, &options)? {
+        eprintln!("Warning: {}: {}", inputs[overrun.stream].display(), overrun);
+    }
+    let mut consumption = Vec::new();
+    let generation = if !split.interleave.is_empty() {
+        let interleaving =
+            generate_interleaved(&flat_inputs, &templates, &db, &options, observer.inner)?;
+        consumption = interleaving.streams;
+        Generation {
+            lines: interleaving.lines,
+            scanlines: interleaving.scanlines,
+            aborted: interleaving.aborted,
+            cancelled: interleaving.cancelled,
+            state: ResumeState::default(),
+            usage: interleaving.usage,
+            model: interleaving.model,
+        }
+    } else if let Some(dir) = &split.cache {
+        let mut cache = ScanlineCache::load(dir, fingerprint(&db, &templates, &options));
+        let generation = generate_cached(
+            flat_lines,
+            &templates,
+            &db,
+            &options,
+            &mut observer,
+            &mut cache,
+        )?;
+        cache
+            .save(dir)
+            .map_err(|e| format!("Failed to write cache '{}': {}", dir.display(), e))?;
+        generation
+    } else {
+        generate_with_observer(flat_lines, &templates, &db, &options, &mut observer)?
+    };
+    if let Some(path) = &split.trace_packing {
+        write_atomically(path, |out| {
+            out.write_all(render_trace(&trace.decisions).as_bytes())
+        })
+        .map_err(|e| format!("Failed to write packing trace '{}': {}", path.display(), e))?;
+    }
 
-        for section in &template_sections {
-            for (i, (code, cycles)) in section.injection_code.iter().enumerate() {
-                let annotated = if i == 0 {
-                    format!("{}\t[{}]", code, scanline_offset)
+    if let Some(Command::Stats { json }) = &args.command {
+        return print_stats(&generation, *json);
+    }
+    if split.check {
+        return check(&generation, &diagnostics);
+    }
+    // A run failing the padding limit leaves the previous output and its side files intact.
+    if let Some(max) = split.max_consecutive_padding {
+        let runs = long_padding_runs(&generation.model, max);
+        for run in &runs {
+            eprintln!("Padding: {}", run);
+        }
+        if !runs.is_empty() {
+            return Err(format!(
+                "{} scanline(s) pad more than {} consecutive cycles",
+                runs.len(),
+                max
+            )
+            .into());
+        }
+    }
+    if split.listing {
+        let listing = listing(flat_lines, &generation.model);
+        return match &split.output {
+            Some(path) => write_atomically(path, |out| out.write_all(listing.as_bytes()))
+                .map_err(|e| format!("Failed to write listing '{}': {}", path.display(), e).into()),
+            None => {
+                print!("{}", listing);
+                Ok(())
+            }
+        };
+    }
+
+    if let Some(graph) = &split.graph {
+        let chart = if graph
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("svg"))
+        {
+            utilization_svg(&generation.usage, options.cycles_per_line())
+        } else {
+            utilization_gnuplot(&generation.usage)
+        };
+        fs::write(graph, chart)
+            .map_err(|e| format!("Failed to write graph '{}': {}", graph.display(), e))?;
+    }
+
+    if let Some(path) = &split.free_cycles {
+        let space = free_space(&generation.usage);
+        let export = if path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("json"))
+        {
+            free_space_json(&space)
+        } else {
+            free_space_equates(&space, "FREE")
+        };
+        fs::write(path, export)
+            .map_err(|e| format!("Failed to write free cycles '{}': {}", path.display(), e))?;
+    }
+
+    if let Some(path) = &split.fixups {
+        let fixups = find_fixups(&generation.lines);
+        for issue in &fixups.issues {
+            eprintln!(
+                "Warning: Relocation: {} ('{}')",
+                issue.reason,
+                generation.lines[issue.index].trim()
+            );
+        }
+        let export = if path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("bin"))
+        {
+            fixups.to_bytes()
+        } else {
+            fixups.listing(&generation.lines).into_bytes()
+        };
+        fs::write(path, export)
+            .map_err(|e| format!("Failed to write fixups '{}': {}", path.display(), e))?;
+    }
+
+    if let Some(path) = &split.timeline {
+        let timeline = timeline(&generation.model, options.cycles_per_line());
+        fs::write(path, timeline_json(&timeline))
+            .map_err(|e| format!("Failed to write timeline '{}': {}", path.display(), e))?;
+    }
+
+    if split.heatmap {
+        let color = std::io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none();
+        eprint!(
+            "{}",
+            utilization_heatmap(&generation.usage, options.cycles_per_line(), color)
+        );
+    }
+
+    let externals = external_symbols(&generation.lines);
+    if split.symbols {
+        eprintln!("External symbols: {}", externals.len());
+        for symbol in &externals {
+            eprintln!("  {}", symbol);
+        }
+    }
+
+    let mut rendered: Vec<String> = match output_style(&split) {
+        Some(style) => generation
+            .lines
+            .iter()
+            .map(|l| style.format_line(l))
+            .collect(),
+        None => generation.lines.iter().map(|l| render_line(l)).collect(),
+    };
+    if let Some(keep_from) = &split.keep_from {
+        let previous = read_text(keep_from, "previous output")?;
+        let regions = parse_kept_regions(&previous, &db, &options)
+            .map_err(|e| format!("{}: {}", keep_from.display(), e))?;
+        for region in &regions {
+            for (i, cycles) in region.scanline_cycles.iter().enumerate() {
+                if *cycles < options.cycles_per_line() {
+                    eprintln!(
+                        "Warning: Kept scanline {} uses {} of {} cycles",
+                        region.first_scanline + i,
+                        cycles,
+                        options.cycles_per_line()
+                    );
+                }
+            }
+        }
+        rendered = apply_kept_regions(rendered, &regions)?;
+    }
+
+    let mut header = vec![
+        "This file is generated using".to_string(),
+        "cycleSpitter (c) 2025 - slippy / vectronix".to_string(),
+        format!("Total scanlines created: {}", generation.scanlines),
+    ];
+    if args.no_template {
+        header.push("Template used: none".to_string());
+    } else if let [spec] = args.template.as_slice() {
+        header.push(format!("Template used: {}", split_template_spec(spec).1));
+    } else {
+        header.push(format!("Templates used: {}", args.template.join(", ")));
+    }
+    if let Some(address) = parse_safe_address(args)? {
+        header.push(format!(
+            "Safe mode: sync and shift mode writes redirected to ${:06x}",
+            address
+        ));
+    }
+    for (path, consumed) in split.interleave.iter().zip(&consumption) {
+        header.push(format!(
+            "Interleaved input: {} ({} lines in {} scanlines)",
+            path.display(),
+            consumed.lines,
+            consumed.scanlines
+        ));
+    }
+    let imports = match split.xref.as_deref().and_then(Dialect::parse) {
+        Some(dialect) => externals.iter().map(|s| dialect.import(s)).collect(),
+        None => Vec::new(),
+    };
+    let document = Document {
+        header,
+        label: split.label.clone(),
+        scanlines: generation.scanlines,
+        imports,
+        lines: rendered,
+    };
+    if let Some(Command::Find { pattern, .. }) = &args.command {
+        let content = AsmEmitter.emit(&document);
+        return print_found(&find_in_output(&content, pattern), pattern);
+    }
+    let emitter = emitter_for(&split.format).ok_or("Unknown output format")?;
+    if let Some(dir) = &split.emit_includes {
+        if split.format != "asm" {
+            return Err(format!(
+                "--emit-includes writes assembly and cannot be combined with --format {}",
+                split.format
+            )
+            .into());
+        }
+        let files = split_includes(&document, &generation.model, split.include_group)?;
+        fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create '{}': {}", dir.display(), e))?;
+        let mut unchanged = 0;
+        for (name, content) in &files {
+            let path = dir.join(name);
+            // Untouched files keep their timestamps, so the build only reassembles the
+            // scanlines that changed.
+            if fs::read_to_string(&path).is_ok_and(|old| old == *content) {
+                unchanged += 1;
+                continue;
+            }
+            fs::write(&path, content)
+                .map_err(|e| format!("Failed to write '{}': {}", path.display(), e))?;
+        }
+        eprintln!(
+            "{} include file(s) written to '{}' ({} unchanged)",
+            files.len() - unchanged,
+            dir.display(),
+            unchanged
+        );
+    } else {
+        match (&split.chunk_dir, options.chunk_chain()) {
+            (Some(dir), Some(chain)) => {
+                let extension = if split.format == "asm" {
+                    "s"
                 } else {
-                    code.clone()
+                    &split.format
                 };
-                final_output.push(annotated);
-                scanline_offset += cycles;
-                scanline_cycles += cycles;
+                let chunks = split_chunks(document, chain, &generation.usage);
+                fs::create_dir_all(dir)
+                    .map_err(|e| format!("Failed to create '{}': {}", dir.display(), e))?;
+                for (index, chunk) in chunks.iter().enumerate() {
+                    let path = dir.join(format!("{}.{}", chain.label(index), extension));
+                    fs::write(&path, emitter.emit(chunk))
+                        .map_err(|e| format!("Failed to write '{}': {}", path.display(), e))?;
+                }
+                eprintln!("{} chunk(s) written to '{}'", chunks.len(), dir.display());
+            }
+            _ => match &split.output {
+                Some(path) => write_atomically(path, |out| emitter.write_to(&document, out))
+                    .map_err(|e| format!("Failed to write output '{}': {}", path.display(), e))?,
+                None => emitter
+                    .write_to(&document, &mut std::io::stdout().lock())
+                    .map_err(|e| format!("Failed to write output: {}", e))?,
+            },
+        }
+    }
+    if split.warnings_in_output {
+        let embedded = generation
+            .lines
+            .iter()
+            .filter(|l| l.starts_with(WARNING_COMMENT_PREFIX))
+            .count();
+        if embedded > 0 {
+            eprintln!("{} warning(s) embedded in the output", embedded);
+        }
+    }
+
+    if let Some(path) = &split.baseline {
+        let stats = RunStats::from_usage(&generation.usage, &options);
+        if split.update_baseline || !path.exists() {
+            stats.save(path)?;
+            eprintln!("Baseline written to '{}'", path.display());
+        } else {
+            let regressions = compare(&RunStats::load(path)?, &stats, split.baseline_threshold);
+            for regression in &regressions {
+                eprintln!("Baseline: {}", regression);
+            }
+            if !regressions.is_empty() && !split.baseline_warn {
+                return Err(format!(
+                    "{} metric(s) regressed against baseline '{}'",
+                    regressions.len(),
+                    path.display()
+                )
+                .into());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Splits `document` at the chunk labels of `chain`. The first chunk keeps the scanline count
+/// of the whole output; every other chunk states its own scanlines in `<label>_<chunk>`.
+fn split_chunks(document: Document, chain: &ChunkChain, usage: &[ScanlineUsage]) -> Vec<Document> {
+    let chunk_count = usage.len().div_ceil(chain.every).max(1);
+    let mut chunks = Vec::new();
+    let mut lines = document.lines.into_iter().peekable();
+    for index in 0..chunk_count {
+        let next = format!("{}:", chain.label(index + 1));
+        let mut chunk_lines = Vec::new();
+        while let Some(line) = lines.next_if(|line| line.trim() != next) {
+            chunk_lines.push(line);
+        }
+        let mut header = document.header.clone();
+        header.push(format!("Chunk {} of {}", index + 1, chunk_count));
+        let (label, scanlines) = if index == 0 {
+            (document.label.clone(), document.scanlines)
+        } else {
+            (
+                format!("{}_{}", document.label, index),
+                (usage.len() - index * chain.every).min(chain.every),
+            )
+        };
+        chunks.push(Document {
+            header,
+            label,
+            scanlines,
+            imports: document.imports.clone(),
+            lines: chunk_lines,
+        });
+    }
+    chunks
+}
+
+/// Builds the generation options from the command-line arguments.
+fn build_options(args: &Args, split: &SplitArgs) -> Result<Options, Box<dyn std::error::Error>> {
+    let mut builder = Options::builder();
+    if let Some(seed) = split.filler_seed {
+        builder = builder.padding_style(PaddingStyle::Filler { seed });
+    }
+    if let Some(rule) = split.separators {
+        builder = builder.separator(rule);
+    }
+    if let Some(spec) = &split.video_base {
+        let mut update = VideoBaseUpdate::parse(spec)?;
+        update.section = split.video_base_section.clone();
+        builder = builder.video_base(update);
+    }
+    if let Some(every) = split.chunk_scanlines {
+        builder = builder.chunk_chain(ChunkChain::new(every, &split.chunk_label)?);
+    }
+    if let Some(label) = &split.loop_label {
+        builder = builder.loop_seam(LoopSeam::new(label, split.loop_wait));
+    }
+    if let Some(max) = split.max_scanlines {
+        builder = builder.max_scanlines(max);
+    }
+    if let Some(cycles) = args.nop_cycles {
+        if cycles == 0 {
+            return Err("A NOP takes at least one cycle".into());
+        }
+        builder = builder.nop_cycles(cycles);
+    }
+    if let Some(spec) = &split.padding_instruction {
+        builder = builder.padding_instruction(PaddingInstruction::parse(spec)?);
+    }
+    if let Some(path) = &split.frame_layout {
+        let content = read_text(path, "frame layout")?;
+        let layout =
+            FrameLayout::parse(&content).map_err(|e| format!("{}: {}", path.display(), e))?;
+        builder = builder.frame_layout(layout);
+    } else {
+        // The scanline lengths of a frame layout bound the offsets as they are generated.
+        for (offset, flag) in [(split.entry_offset, "entry"), (split.exit_offset, "exit")] {
+            if offset > 0 && offset >= args.cycles {
+                return Err(format!(
+                    "--{}-offset {} leaves nothing of the {} cycles of a scanline",
+                    flag, offset, args.cycles
+                )
+                .into());
             }
+        }
+    }
+    Ok(builder
+        .cycles_per_line(args.cycles)
+        .checksums(split.checksum)
+        .warning_comments(split.warnings_in_output)
+        .margin(split.margin)
+        .start_line(split.start_line)
+        .entry_offset(split.entry_offset)
+        .exit_offset(split.exit_offset)
+        .packer(match split.packer.as_str() {
+            "first-fit" => Packer::FirstFit,
+            "optimal" => Packer::Optimal,
+            _ => Packer::Greedy,
+        })
+        .packer_window(split.packer_window)
+        .tail(match split.tail.as_str() {
+            "pad" => Tail::Pad,
+            "repeat" => Tail::Repeat,
+            _ => Tail::Stop,
+        })
+        .annotation_format(match split.annotations.as_str() {
+            "compact" => AnnotationFormat::Compact,
+            "remaining" => AnnotationFormat::Remaining,
+            _ => AnnotationFormat::Full,
+        })
+        .verbosity(match split.verbosity.as_str() {
+            "verbose" => Verbosity::Verbose,
+            _ => Verbosity::Normal,
+        })
+        .build())
+}
+
+/// Reads the text file at `path`, described as `what` in errors, with its line endings
+/// normalized to `\n`.
+fn read_text(path: &Path, what: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {} '{}': {}", what, path.display(), e))?;
+    Ok(normalize_line_endings(&content).into_owned())
+}
+
+/// Converts a path argument to the separators of the platform (see `native_path`).
+fn path_arg(path: &str) -> Result<PathBuf, std::convert::Infallible> {
+    Ok(native_path(path))
+}
+
+/// Returns the source of an input path, standard input for `-`.
+fn input_source(path: &PathBuf) -> Box<dyn SourceProvider> {
+    if path.as_os_str() == "-" {
+        Box::new(StdinSource)
+    } else {
+        Box::new(FileSource::new(path))
+    }
+}
+
+/// Reads an input and flattens it for generation: trims the lines, joins continuation lines,
+/// optionally splits multi-statement lines and expands `REPT` blocks within the expansion
+/// limits. Inputs with unbalanced block directives are rejected. Returns the flattened lines
+/// and the map locating them in the input; an input read as before is taken from `cache`.
+fn read_input(
+    path: &PathBuf,
+    args: &Args,
+    db: &CycleDatabase,
+    cache: &mut StageCache<(Vec<String>, SourceMap)>,
+) -> Result<(Vec<String>, SourceMap), Box<dyn std::error::Error>> {
+    let source = input_source(path);
+    let name = source.name();
+    let flattened = cache.get_or_run(&name, source.read()?, |content| {
+        flatten_input(&StringSource::new(&name, content), args, db)
+    })?;
+    Ok(flattened.clone())
+}
+
+/// Flattens the input read from `source` as described at `read_input`.
+fn flatten_input(
+    source: &StringSource,
+    args: &Args,
+    db: &CycleDatabase,
+) -> Result<(Vec<String>, SourceMap), Box<dyn std::error::Error>> {
+    let name = source.name();
+    let (raw_lines, map) = source.located_lines()?;
+    let issues = validate_block_structure(&raw_lines);
+    for issue in &issues {
+        eprintln!("{}:{}: {}", name, issue.line, issue.problem);
+    }
+    if !issues.is_empty() {
+        return Err(format!("{} block directive problem(s) in '{}'", issues.len(), name).into());
+    }
+    let raw_lines = if args.legacy_cycles {
+        let import = import_legacy_cycles(&raw_lines, db);
+        for disagreement in &import.disagreements {
+            eprintln!("{}:{}: {}", name, disagreement.line, disagreement);
+        }
+        eprintln!(
+            "{}: {} legacy cycle comment(s) imported as overrides",
+            name, import.imported
+        );
+        import.lines
+    } else {
+        raw_lines
+    };
+    let (mut raw_lines, origins) = join_continuations_with_origins(&raw_lines);
+    let mut map = map.remap(&origins);
+    if args.split_statements {
+        let (split, origins) = split_statements_with_origins(&raw_lines, db);
+        raw_lines = split;
+        map = map.remap_columns(&origins);
+    }
+    let limits = ExpansionLimits {
+        max_lines: args.max_expanded_lines,
+        max_depth: args.max_rept_depth,
+        max_bytes: args.max_expanded_bytes,
+    };
+    let (flat_lines, origins) =
+        expand_block_with_origins(&raw_lines, &limits).map_err(|e| format!("{}: {}", name, e))?;
+    Ok((flat_lines, map.remap(&origins)))
+}
 
-            final_output.push(format!("; --- {} section ---", section.label));
-
-            if section.nop_cycles > 0 && current_index < flat_lines.len() {
-                let (chunk, new_idx, new_offset) = accumulate_chunk(
-                    &flat_lines,
-                    current_index,
-                    section.nop_cycles,
-                    scanline_offset,
-                );
-                scanline_offset = new_offset;
-                scanline_cycles += section.nop_cycles;
-                current_index = new_idx;
-                final_output.extend(chunk);
+/// Loads the templates given by `--template`, or the single code window of `--no-template`.
+/// Templates read as before are taken from `cache`.
+fn load_templates(
+    args: &Args,
+    db: &CycleDatabase,
+    options: &Options,
+    cache: &mut StageCache<Vec<TemplateSection>>,
+) -> Result<TemplateSet, Box<dyn std::error::Error>> {
+    let mut templates = TemplateSet::new();
+    if args.no_template {
+        // The whole scanline is a single user code window
+        templates.insert(
+            TemplateSet::DEFAULT_NAME,
+            vec![TemplateSection::code_window(args.cycles, "user code")],
+        )?;
+    } else {
+        let safe = parse_safe_address(args)?;
+        for spec in &args.template {
+            let (name, path) = split_template_spec(spec);
+            let content = read_text(&native_path(path), "template file")?;
+            let sections = cache
+                .get_or_run(path, content, |content| {
+                    parse_template_text(content, safe, db, options)
+                })?
+                .clone();
+            if let Err(e) = validate_template(&sections, options.cycles_per_line()) {
+                eprintln!("Warning: {}: {}", path, e);
             }
-            final_output.push(format!("; Calculated cycles: {}", scanline_offset));
+            templates.insert(name, sections)?;
+        }
+    }
+    Ok(templates)
+}
+
+/// Reads and parses a template file. With a `safe` dummy address, sync and shift mode writes
+/// are redirected to it first (see `--safe`).
+fn load_template(
+    path: &str,
+    safe: Option<u32>,
+    db: &CycleDatabase,
+    options: &Options,
+) -> Result<Vec<TemplateSection>, Box<dyn std::error::Error>> {
+    let content = read_text(&native_path(path), "template file")?;
+    parse_template_text(&content, safe, db, options)
+}
+
+/// Parses the `content` of a template file as described at `load_template`.
+fn parse_template_text(
+    content: &str,
+    safe: Option<u32>,
+    db: &CycleDatabase,
+    options: &Options,
+) -> Result<Vec<TemplateSection>, Box<dyn std::error::Error>> {
+    match safe {
+        Some(dummy) => {
+            let content = content
+                .lines()
+                .map(|line| neutralize_sync_writes(line, dummy).unwrap_or_else(|| line.to_string()))
+                .collect::<Vec<_>>()
+                .join("\n");
+            parse_template_with(&content, db, options)
         }
+        None => parse_template_with(content, db, options),
+    }
+}
+
+/// Parses the `--safe` address, which must lie in the I/O area to keep short addressing.
+fn parse_safe_address(args: &Args) -> Result<Option<u32>, Box<dyn std::error::Error>> {
+    let Some(address) = &args.safe else {
+        return Ok(None);
+    };
+    let value = u32::from_str_radix(address.trim_start_matches('$'), 16)
+        .ok()
+        .map(|value| value & 0xffffff)
+        .filter(|value| *value >= 0xff8000)
+        .ok_or_else(|| {
+            format!(
+                "Invalid --safe address '{}' (expected a hardware register from $ff8000 on)",
+                address
+            )
+        })?;
+    Ok(Some(value))
+}
+
+/// Splits a `--template` value into the template name and path. A value without a name
+/// registers the template under `TemplateSet::DEFAULT_NAME`.
+fn split_template_spec(spec: &str) -> (&str, &str) {
+    spec.split_once('=')
+        .unwrap_or((TemplateSet::DEFAULT_NAME, spec))
+}
+
+/// Returns the layout requested by the formatting options, or `None` to keep the spacing of
+/// the input.
+fn output_style(split: &SplitArgs) -> Option<Style> {
+    if split.indent_spaces.is_none()
+        && split.tab_width.is_none()
+        && split.operand_column.is_none()
+        && split.comment_column.is_none()
+        && split.label_style.is_none()
+    {
+        return None;
+    }
+    Some(Style {
+        indent: match split.indent_spaces {
+            Some(width) => Indent::Spaces(width),
+            None => Indent::Tabs(split.tab_width.unwrap_or(DEFAULT_TAB_WIDTH)),
+        },
+        operand_column: split.operand_column,
+        comment_column: split.comment_column,
+        labels: match split.label_style.as_deref() {
+            Some("bare") => LabelStyle::Bare,
+            _ => LabelStyle::Colon,
+        },
+    })
+}
+
+/// Formats a generated line for output: comments and symbol definitions are printed as-is,
+/// labels start in column 0 and instructions are indented by a tab.
+fn render_line(line: &str) -> String {
+    if line.trim().starts_with(";") || line.contains(" equ ") || line.contains(" set ") {
+        line.to_string()
+    } else if let Some(caps) = REG_LABEL_RE.captures(line) {
+        format!("{}\t{}", &caps[1], caps[2].trim())
+    } else {
+        format!("\t{}", line)
+    }
+}
+
+/// The overlay written by `db add` unless told otherwise.
+const DEFAULT_OVERLAY: &str = "cycles.overlay.json";
+
+/// Reads a cycle database file.
+fn read_database(path: &Path) -> Result<CycleDatabase, Box<dyn std::error::Error>> {
+    let content = read_text(path, "cycle database")?;
+    CycleDatabase::from_json_str(&content)
+        .map_err(|e| format!("Invalid cycle database '{}': {}", path.display(), e).into())
+}
+
+/// Loads the bundled cycle database, extended by the --cycle-db overlay, corrected by the
+/// --adjust rules and given the --trap-handler cycles if given.
+fn load_database(args: &Args) -> Result<CycleDatabase, Box<dyn std::error::Error>> {
+    let mut db = CycleDatabase::builtin();
+    if let Some(path) = &args.cycle_db {
+        db.extend(read_database(path)?);
+    }
+    if let Some(path) = &args.adjust {
+        let content = read_text(path, "adjustments")?;
+        apply_adjustments(&content, &mut db).map_err(|e| format!("{}: {}", path.display(), e))?;
+    }
+    for spec in &args.trap_handler {
+        let (vector, cycles) = parse_trap_handler(spec)?;
+        db.set_trap_handler(vector, cycles);
+    }
+    Ok(db)
+}
+
+/// Prints the `annotate` subcommand's view of `path`.
+fn print_annotated(
+    args: &Args,
+    path: &PathBuf,
+    compact: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let db = load_database(args)?;
+    let content = input_source(path).read()?;
+    let format = if compact {
+        AnnotationFormat::Compact
+    } else {
+        AnnotationFormat::Full
+    };
+    println!("{}", annotate(&content, &db, format));
+    Ok(())
+}
+
+/// Prints the generated lines found by the `find` subcommand, failing if there are none.
+fn print_found(found: &[Found], pattern: &str) -> Result<(), Box<dyn std::error::Error>> {
+    if found.is_empty() {
+        return Err(format!("'{}' not found in the generated code", pattern).into());
+    }
+    for entry in found {
+        let offset = entry
+            .offset
+            .map_or(String::new(), |offset| format!(", offset {}", offset));
+        println!(
+            "line {}: scanline {}, section {}{}: {}",
+            entry.line_number,
+            entry.scanline,
+            entry.section.as_deref().unwrap_or("-"),
+            offset,
+            entry.text
+        );
+    }
+    Ok(())
+}
+
+/// Prints the lookup keys of the `normalize` subcommand, marking those missing from the cycle
+/// database.
+fn print_lookup_keys(
+    args: &Args,
+    path: &PathBuf,
+    missing: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let db = load_database(args)?;
+    let content = input_source(path).read()?;
+    for key in lookup_keys(&content, &db) {
+        if missing && key.known {
+            continue;
+        }
+        println!(
+            "{:>5}  {}  ->  {}{}",
+            key.line_number,
+            key.code,
+            key.normalized,
+            if key.known { "" } else { "  (missing)" }
+        );
+    }
+    Ok(())
+}
+
+/// Prints the summary of --check after the warnings of the run, failing if any scanline
+/// overflowed, missed its cycles or used an unknown instruction.
+fn check(
+    generation: &Generation,
+    diagnostics: &Diagnostics,
+) -> Result<(), Box<dyn std::error::Error>> {
+    for diagnostic in diagnostics.iter() {
+        StderrObserver.on_diagnostic(diagnostic);
+    }
+    let count = |kind| diagnostics.of_kind(kind).count();
+    let (overflows, mismatches, unknown) = (
+        count(DiagnosticKind::ScanlineOverflow),
+        count(DiagnosticKind::CycleMismatch),
+        count(DiagnosticKind::UnknownInstruction),
+    );
+    print!("{}", check_summary(generation, diagnostics));
+    if overflows + mismatches + unknown > 0 {
+        return Err(format!(
+            "Check failed: {} overflow(s), {} cycle mismatch(es), {} unknown instruction(s)",
+            overflows, mismatches, unknown
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Formats the summary lines printed by --check.
+fn check_summary(generation: &Generation, diagnostics: &Diagnostics) -> String {
+    let count = |kind| diagnostics.of_kind(kind).count();
+    let total: usize = generation.model.iter().map(|s| s.cycles).sum();
+    let padding: usize = generation.usage.iter().map(|u| u.padding_cycles()).sum();
+    format!(
+        "scanlines         {}\n\
+         total cycles      {}\n\
+         padding cycles    {} ({}%)\n\
+         overflows         {}\n\
+         cycle mismatches  {}\n\
+         unknown           {}\n\
+         other warnings    {}\n",
+        generation.scanlines,
+        total,
+        padding,
+        (padding * 100).checked_div(total).unwrap_or(0),
+        count(DiagnosticKind::ScanlineOverflow),
+        count(DiagnosticKind::CycleMismatch),
+        count(DiagnosticKind::UnknownInstruction),
+        count(DiagnosticKind::Other)
+    )
+}
+
+/// Prints the per-scanline summaries of the `stats` subcommand.
+fn print_stats(generation: &Generation, json: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let scanlines = generation.usage.iter().zip(&generation.model);
+    if json {
+        let summaries: Vec<serde_json::Value> = scanlines
+            .enumerate()
+            .map(|(index, (usage, scanline))| {
+                serde_json::json!({
+                    "scanline": index,
+                    "line": scanline.line,
+                    "cycles": scanline.cycles,
+                    "template": usage.template_cycles(),
+                    "user": usage.user_cycles(),
+                    "padding": usage.padding_cycles(),
+                })
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&summaries)?);
+        return Ok(());
+    }
+    println!(
+        "{:>8} {:>6} {:>9} {:>6} {:>8} {:>7}",
+        "scanline", "line", "template", "user", "padding", "cycles"
+    );
+    for (index, (usage, scanline)) in scanlines.enumerate() {
+        println!(
+            "{:>8} {:>6} {:>9} {:>6} {:>8} {:>7}",
+            index,
+            scanline.line,
+            usage.template_cycles(),
+            usage.user_cycles(),
+            usage.padding_cycles(),
+            scanline.cycles
+        );
+    }
+    let total =
+        |cycles: fn(&ScanlineUsage) -> usize| generation.usage.iter().map(cycles).sum::<usize>();
+    println!(
+        "{:>8} {:>6} {:>9} {:>6} {:>8} {:>7}",
+        "total",
+        "",
+        total(ScanlineUsage::template_cycles),
+        total(ScanlineUsage::user_cycles),
+        total(ScanlineUsage::padding_cycles),
+        generation.model.iter().map(|s| s.cycles).sum::<usize>()
+    );
+    Ok(())
+}
+
+/// Prints the normalized form and cycles of `instruction` for the `lookup` subcommand.
+fn print_lookup(args: &Args, instruction: &str) -> Result<(), Box<dyn std::error::Error>> {
+    print!("{}", lookup_report(&load_database(args)?, instruction)?);
+    Ok(())
+}
+
+/// Formats the normalized form, base and per-register cycles and total of `instruction`,
+/// failing if the database does not have it.
+fn lookup_report(
+    db: &CycleDatabase,
+    instruction: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let normalized = normalize_line_ext(instruction).0;
+    if !db.contains(&normalized) {
+        return Err(format!(
+            "'{}' (normalized '{}') is not in the cycle database",
+            instruction.trim(),
+            normalized
+        )
+        .into());
+    }
+    let cycles = lookup_cycles(instruction, db);
+    let per_register = if cycles.kind() == CycleKind::Reglist {
+        format!(
+            "{} x {} register(s)",
+            cycles.cycles_per_reg(),
+            cycles.get_reg_count()
+        )
+    } else {
+        "-".to_string()
+    };
+    let total = match cycles.source().marker() {
+        Some(marker) => format!("{} ({})", cycles.total(), marker),
+        None => cycles.total().to_string(),
+    };
+    Ok(format!(
+        "normalized    {}\nbase          {}\nper register  {}\ntotal         {}\n",
+        normalized,
+        cycles.base(),
+        per_register,
+        total
+    ))
+}
+
+/// Prints the sections of a template for the `validate-template` subcommand and checks that they
+/// add up to a scanline.
+fn validate(args: &Args, template: Option<&PathBuf>) -> Result<(), Box<dyn std::error::Error>> {
+    let db = load_database(args)?;
+    let options = build_options(args, &args.split())?;
+    let path = match template {
+        Some(path) => path.to_string_lossy().into_owned(),
+        None => split_template_spec(args.template.first().ok_or("No template given")?)
+            .1
+            .to_string(),
+    };
+    let sections = load_template(&path, parse_safe_address(args)?, &db, &options)
+        .map_err(|e| format!("{}: {}", path, e))?;
+    println!(
+        "{:<24} {:>8} {:>10} {:>7}",
+        "section", "injected", "nop budget", "total"
+    );
+    for budget in section_budgets(&sections) {
+        println!(
+            "{:<24} {:>8} {:>10} {:>7}{}",
+            budget.label,
+            budget.injected,
+            budget.nop_budget,
+            budget.injected + budget.nop_budget,
+            if budget.hot { "  hot" } else { "" }
+        );
+    }
+    let total = validate_template(&sections, options.cycles_per_line())
+        .map_err(|e| format!("{}: {}", path, e))?;
+    println!("{} section(s), {} cycles", sections.len(), total);
+    Ok(())
+}
+
+/// Runs a `db` subcommand.
+fn run_db_command(args: &Args, action: &DbCommand) -> Result<(), Box<dyn std::error::Error>> {
+    match action {
+        DbCommand::Add {
+            key,
+            cycles,
+            kind,
+            source,
+            notes,
+            replace,
+            output,
+        } => {
+            let path = output
+                .clone()
+                .or_else(|| args.cycle_db.clone())
+                .unwrap_or_else(|| PathBuf::from(DEFAULT_OVERLAY));
+            let mut overlay = if path.exists() {
+                read_database(&path)?
+            } else {
+                CycleDatabase::default()
+            };
+            let builtin = CycleDatabase::builtin();
 
-        if scanline_cycles < args.cycles {
-            let remaining = args.cycles - scanline_cycles;
-            let nop_count = remaining / 4;
-            if nop_count > 0 {
-                final_output.push(format!(
-                    "\tdcb.w\t{},$4e71\t; Pad to {} cycles ({} cycles)",
-                    nop_count, args.cycles, remaining
-                ));
+            if let Some(issue) = check_key(key, &builtin) {
+                return Err(format!("Invalid key: {}", issue).into());
             }
-            scanline_cycles = args.cycles;
-        } else if scanline_cycles > args.cycles {
+            if !replace {
+                if let Some(existing) = overlay.get(key) {
+                    return Err(format!(
+                        "'{}' is already in '{}' as {:?} (use --replace to change it)",
+                        key,
+                        path.display(),
+                        existing
+                    )
+                    .into());
+                }
+                if let Some(existing) = builtin.get(key) {
+                    return Err(format!(
+                        "'{}' is already in the bundled database as {:?} (use --replace to override it)",
+                        key, existing
+                    )
+                    .into());
+                }
+            }
+
+            let kind = match kind {
+                Some(kind) => CycleKind::parse(kind).ok_or_else(|| {
+                    format!(
+                        "Unknown kind '{}' (expected plain, branch, reglist or range)",
+                        kind
+                    )
+                })?,
+                None => CycleKind::infer(key, cycles),
+            };
+            let mut entry = CycleEntry::from_values(kind, cycles);
+            if let Some(problem) = entry.missing_value() {
+                return Err(format!("Invalid cycles for '{}': {}", key, problem).into());
+            }
+            entry.source = source.clone();
+            entry.notes = notes.clone();
+            overlay.insert(key.clone(), entry);
+
+            fs::write(&path, overlay.to_json_string()).map_err(|e| {
+                format!("Failed to write cycle database '{}': {}", path.display(), e)
+            })?;
             eprintln!(
-                "Warning: Scanline overflow by {} cycles!",
-                scanline_cycles - args.cycles
+                "'{}' added to '{}' ({} entries)",
+                key,
+                path.display(),
+                overlay.len()
             );
+            Ok(())
         }
+    }
+}
 
-        final_output.push(format!("; Total cycles for scanline: {}", scanline_cycles));
-        line_count += 1;
+/// Round-trips the keys of the builtin cycle database through the normalizer, failing if any
+/// key does not normalize to itself.
+fn run_self_test() -> Result<(), Box<dyn std::error::Error>> {
+    let report = self_test(&CycleDatabase::builtin());
+    for issue in &report.issues {
+        eprintln!("{}", issue);
     }
+    if !report.is_ok() {
+        return Err(format!(
+            "{} of {} database keys failed the self-test",
+            report.issues.len(),
+            report.checked
+        )
+        .into());
+    }
+    println!("{} database keys normalize to themselves", report.checked);
+    Ok(())
+}
 
-    println!("; ------------------------------------------");
-    println!("; This file is generated using");
-    println!("; cycleSpitter (c) 2025 - slippy / vectronix");
-    println!("; Total scanlines created: {}", line_count);
-    println!("; Template used: {}", args.template.display());
-    println!("; ------------------------------------------");
-    println!("{}\tequ {}", args.label, line_count);
-    for line in final_output {
-        if line.trim().starts_with(";") || line.contains(" equ ") || line.contains(" set ") {
-            println!("{}", line);
-        } else if let Some(caps) = REG_LABEL_RE.captures(&line) {
-            println!("{}\t{}", &caps[1], caps[2].to_string().clone().trim());
-        } else {
-            println!("\t{}", line);
+/// Generates the input with the `first` and `second` template and prints the statistics of both
+/// runs side by side.
+fn compare_templates(
+    args: &Args,
+    first: &PathBuf,
+    second: &PathBuf,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let db = load_database(args)?;
+    let (flat_lines, _) = read_input(&args.input, args, &db, &mut StageCache::default())?;
+    let options = build_options(args, &args.split())?;
+    let mut stats = Vec::new();
+    let safe = parse_safe_address(args)?;
+    for path in [first, second] {
+        let templates =
+            TemplateSet::from(load_template(&path.to_string_lossy(), safe, &db, &options)?);
+        let generation =
+            generate_with_observer(&flat_lines, &templates, &db, &options, &mut NullObserver)
+                .map_err(|e| format!("{}: {}", path.display(), e))?;
+        stats.push(RunStats::from_usage(&generation.usage, &options));
+    }
+
+    let names = [first.display().to_string(), second.display().to_string()];
+    print!("{}", comparison_table(&names, [&stats[0], &stats[1]]));
+    Ok(())
+}
+
+/// Formats the statistics of the runs with two templates named `names` side by side, with the
+/// difference of the second to the first.
+fn comparison_table(names: &[String; 2], stats: [&RunStats; 2]) -> String {
+    let width = names.iter().map(String::len).max().unwrap_or(0).max(10) + 2;
+    let mut table = format!(
+        "{:<16}{:>width$}{:>width$}{:>12}\n",
+        "", names[0], names[1], "difference"
+    );
+    let metrics = [
+        ("scanlines", stats[0].scanlines, stats[1].scanlines),
+        (
+            "padding cycles",
+            stats[0].padding_cycles,
+            stats[1].padding_cycles,
+        ),
+        ("overflows", stats[0].overflows, stats[1].overflows),
+    ];
+    for (metric, a, b) in metrics {
+        table.push_str(&format!(
+            "{:<16}{:>width$}{:>width$}{:>+12}\n",
+            metric,
+            a,
+            b,
+            b as i64 - a as i64
+        ));
+    }
+    table
+}
+
+/// Previews the re-flow of inserting or removing one line of the input (see the `whatif`
+/// module) against a previously generated `output`. `N` counts the lines of the expanded input
+/// from 1.
+fn preview_edit(
+    args: &Args,
+    output: &Path,
+    insert: &[String],
+    remove: Option<usize>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let line_number = |n: &str| match n.parse::<usize>() {
+        Ok(n) if n > 0 => Ok(n - 1),
+        _ => Err(format!("Invalid line number '{}'", n)),
+    };
+    let edit = match (insert, remove) {
+        ([n, instruction], _) => Edit::Insert {
+            index: line_number(n)?,
+            instruction: instruction.clone(),
+        },
+        (_, Some(n)) => Edit::Remove {
+            index: n.checked_sub(1).ok_or("Invalid line number '0'")?,
+        },
+        _ => return Err("Give the edit with --insert N INSTRUCTION or --remove N".into()),
+    };
+
+    let db = load_database(args)?;
+    let options = build_options(args, &args.split())?;
+    let templates = load_templates(args, &db, &options, &mut StageCache::default())?;
+    let (flat_lines, _) = read_input(&args.input, args, &db, &mut StageCache::default())?;
+    let generated: Vec<String> = read_text(output, "generated file")?
+        .lines()
+        .map(str::to_string)
+        .collect();
+    let reflow = what_if(&flat_lines, &generated, &edit, &templates, &db, &options)?;
+
+    println!(
+        "{:>10}{:>10}{:>10}{:>8}",
+        "scanline", "padding", "after", "delta"
+    );
+    let padding = |cycles: Option<usize>| cycles.map_or("-".to_string(), |c| c.to_string());
+    for scanline in &reflow.scanlines {
+        println!(
+            "{:>10}{:>10}{:>10}{:>+8}",
+            scanline.scanline,
+            padding(scanline.old_padding),
+            padding(scanline.new_padding),
+            scanline.padding_delta()
+        );
+    }
+    let total: isize = reflow.scanlines.iter().map(|s| s.padding_delta()).sum();
+    println!(
+        "{} scanline(s) re-flow, padding {:+} cycles, scanlines {:+}",
+        reflow.scanlines.len(),
+        total,
+        reflow.scanline_delta
+    );
+    Ok(())
+}
+
+/// Synthesizes a template from a switch spec, given inline or as a file, and prints it.
+fn generate_template(spec: &str, cycles: usize) -> Result<(), Box<dyn std::error::Error>> {
+    let spec = if native_path(spec).is_file() {
+        read_text(&native_path(spec), "switch spec")?
+    } else {
+        spec.to_string()
+    };
+    let switches = parse_switch_spec(&spec)?;
+    print!(
+        "{}",
+        synthesize_template(&switches, cycles, &CycleDatabase::builtin())?
+    );
+    Ok(())
+}
+
+/// Prints the frame layout proposed from a Hatari trace and optionally synthesizes its
+/// templates into `template_dir`.
+fn import_hatari(
+    trace: &Path,
+    template_dir: Option<&PathBuf>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let content = read_text(trace, "trace")?;
+    let writes = parse_trace(&content).map_err(|e| format!("{}: {}", trace.display(), e))?;
+    let proposal = propose_frame(&writes);
+    print!("{}", proposal.to_layout());
+
+    if let Some(dir) = template_dir {
+        fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create directory '{}': {}", dir.display(), e))?;
+        let db = CycleDatabase::builtin();
+        let mut written = vec![false; proposal.templates.len()];
+        for region in &proposal.regions {
+            // Every template is synthesized for the budget of its first region
+            if std::mem::replace(&mut written[region.template], true) {
+                continue;
+            }
+            let name = FrameProposal::template_name(region.template);
+            let switches = &proposal.templates[region.template];
+            // A template that cannot be synthesized is reported, the others are still written
+            match synthesize_template(switches, region.cycles, &db) {
+                Ok(template) => {
+                    let path = dir.join(format!("{}.s", name));
+                    fs::write(&path, template).map_err(|e| {
+                        format!("Failed to write template '{}': {}", path.display(), e)
+                    })?;
+                    eprintln!("Template {} written to '{}'", name, path.display());
+                }
+                Err(e) => eprintln!("Warning: Template {} not synthesized: {}", name, e),
+            }
         }
     }
+    Ok(())
+}
 
+/// Learns the cycle overrides of a reference file and writes them to `output` as TOML.
+fn write_learned_overrides(
+    reference: &Path,
+    output: &PathBuf,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let content = read_text(reference, "reference file")?;
+    let lines: Vec<String> = content.lines().map(str::to_string).collect();
+    let learned = learn_overrides(&lines, &CycleDatabase::builtin());
+    fs::write(output, learned.to_toml(&reference.display().to_string()))
+        .map_err(|e| format!("Failed to write overrides '{}': {}", output.display(), e))?;
+    eprintln!(
+        "{} normalized and {} line overrides written to '{}' ({} matched the database)",
+        learned.normalized.len(),
+        learned.lines.len(),
+        output.display(),
+        learned.redundant
+    );
     Ok(())
 }
+
+/// Verifies the scanline checksums of a generated file, failing if any scanline was modified.
+fn verify_file(file: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let content = read_text(file, "file")?;
+    let report = verify(&content);
+
+    for mismatch in &report.mismatches {
+        eprintln!(
+            "{}:{}: scanline {} was modified (checksum {} expected, {} found)",
+            file.display(),
+            mismatch.line_number,
+            mismatch.scanline,
+            mismatch.expected,
+            mismatch.actual
+        );
+    }
+    if report.checked == 0 {
+        return Err(format!(
+            "No scanline checksums found in '{}' (generate with --checksum)",
+            file.display()
+        )
+        .into());
+    }
+    if !report.is_ok() {
+        return Err(format!(
+            "{} of {} scanlines failed verification",
+            report.mismatches.len(),
+            report.checked
+        )
+        .into());
+    }
+    println!("{} scanlines verified", report.checked);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+    use clap::error::ErrorKind;
+
+    /// One invocation of every subcommand with a flag of its own.
+    const SUBCOMMANDS: &[&[&str]] = &[
+        &["split", "--loop", "L"],
+        &["annotate", "--compact"],
+        &["stats", "--json"],
+        &["lookup", "nop"],
+        &["normalize", "--missing"],
+        &["find", ".loop", "out.s"],
+        &["validate-template", "template.s"],
+        &["verify", "out.s"],
+        &["selftest"],
+        &["compare-templates", "a.s", "b.s"],
+        &["gen-template", "60hz at 376/388"],
+        &["learn-overrides", "reference.s", "-o", "overrides.toml"],
+        &["import-hatari", "trace.log", "--template-dir", "templates"],
+        &["what-if", "out.s", "--remove", "3"],
+        &["db", "add", "nop", "4"],
+    ];
+
+    fn parse(argv: &[&str]) -> Result<Args, clap::Error> {
+        Args::try_parse_from(
+            ["cycleSpitter", "-i", "effect.s", "-c", "508"]
+                .iter()
+                .chain(argv),
+        )
+    }
+
+    /// Generates `input` with the example template into scanlines of `cycles` cycles, as a run
+    /// with --check does, and returns the outcome and summary of the check.
+    fn run_check(
+        input: &[&str],
+        cycles: usize,
+    ) -> (Result<(), Box<dyn std::error::Error>>, String) {
+        let db = CycleDatabase::builtin();
+        let options = Options::builder().cycles_per_line(cycles).build();
+        let template = include_str!("../examples/template.s");
+        let templates = TemplateSet::from(parse_template_with(template, &db, &options).unwrap());
+        let lines: Vec<String> = input.iter().map(|l| l.to_string()).collect();
+        let mut diagnostics = Diagnostics::default();
+        let generation =
+            generate_with_observer(&lines, &templates, &db, &options, &mut diagnostics).unwrap();
+        (
+            check(&generation, &diagnostics),
+            check_summary(&generation, &diagnostics),
+        )
+    }
+
+    #[test]
+    fn test_check_passes_a_clean_run() {
+        let (result, summary) = run_check(&["move.w d0,d1"; 200], 512);
+        assert!(result.is_ok());
+        assert_eq!(
+            summary,
+            "scanlines         2\n\
+             total cycles      1024\n\
+             padding cycles    80 (7%)\n\
+             overflows         0\n\
+             cycle mismatches  0\n\
+             unknown           0\n\
+             other warnings    0\n"
+        );
+    }
+
+    #[test]
+    fn test_check_fails_on_unknown_instructions() {
+        let (result, summary) = run_check(&["move.w d0,d1", "frobnicate d0", "nop"], 512);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Check failed: 0 overflow(s), 0 cycle mismatch(es), 1 unknown instruction(s)"
+        );
+        assert!(summary.contains("unknown           1\n"), "{}", summary);
+    }
+
+    #[test]
+    fn test_check_fails_on_overflows() {
+        let (result, summary) = run_check(&["move.w d0,d1"], 256);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Check failed: 1 overflow(s), 0 cycle mismatch(es), 0 unknown instruction(s)"
+        );
+        assert!(summary.contains("overflows         1\n"), "{}", summary);
+    }
+
+    /// The files watched by `--watch` with the shared flags and `split` flags of `argv`.
+    fn watched(argv: &[&str]) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+        let args = parse(argv)?;
+        let (files, _) = watched_files(&args, &args.split())?;
+        let relative = |path: PathBuf| {
+            path.strip_prefix(std::env::current_dir().unwrap())
+                .unwrap()
+                .to_path_buf()
+        };
+        Ok(files.into_iter().map(relative).collect())
+    }
+
+    #[test]
+    fn test_watch_rejects_standard_input() {
+        for input in [
+            &["-i", "-", "split"][..],
+            &["split", "--interleave", "a.s,-"],
+        ] {
+            let argv = [&["cycleSpitter"], input, &["-o", "out.s", "--watch"]].concat();
+            let args = Args::try_parse_from(argv).unwrap();
+            assert_eq!(
+                watched_files(&args, &args.split()).unwrap_err().to_string(),
+                "--watch cannot watch standard input, give the input as a file"
+            );
+        }
+    }
+
+    #[test]
+    fn test_watch_covers_every_file_read() {
+        let files = watched(&[
+            "-t",
+            "main=main.s",
+            "-t",
+            "open=open.s",
+            "--cycle-db",
+            "overlay.json",
+            "--adjust",
+            "rules.txt",
+            "split",
+            "--frame-layout",
+            "frame.txt",
+            "--keep-from",
+            "previous.s",
+            "-o",
+            "out.s",
+            "--watch",
+        ])
+        .unwrap();
+        let expected = [
+            "effect.s",
+            "main.s",
+            "open.s",
+            "frame.txt",
+            "previous.s",
+            "overlay.json",
+            "rules.txt",
+        ];
+        assert_eq!(files, expected.map(PathBuf::from));
+    }
+
+    #[test]
+    fn test_watch_skips_keeping_the_output_itself() {
+        let files = watched(&["split", "--keep-from", "out.s", "-o", "out.s", "--watch"]).unwrap();
+        assert_eq!(files, ["effect.s", "template.s"].map(PathBuf::from));
+    }
+
+    #[test]
+    fn test_comparison_table_lines_up_both_runs_and_their_difference() {
+        let names = ["template.s".to_string(), "left_only.s".to_string()];
+        let first = RunStats {
+            scanlines: 22,
+            padding_cycles: 764,
+            overflows: 0,
+        };
+        let second = RunStats {
+            scanlines: 23,
+            padding_cycles: 1204,
+            overflows: 0,
+        };
+        assert_eq!(
+            comparison_table(&names, [&first, &second]),
+            "                   template.s  left_only.s  difference\n\
+             scanlines                  22           23          +1\n\
+             padding cycles            764         1204        +440\n\
+             overflows                   0            0          +0\n"
+        );
+        // A shorter second template gains scanlines back
+        assert!(
+            comparison_table(&names, [&second, &first])
+                .contains("scanlines                  23           22          -1\n")
+        );
+    }
+
+    #[test]
+    fn test_db_add_validates_the_key_and_writes_the_overlay() {
+        let path =
+            std::env::temp_dir().join(format!("cyclespitter-db-add-{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let add = |argv: &[&str]| {
+            let argv = [&["db", "add"], argv, &["-o", path.to_str().unwrap()]].concat();
+            let args = parse(&argv).unwrap();
+            let Some(Command::Db { action }) = &args.command else {
+                panic!("not parsed as db: {:?}", args.command);
+            };
+            run_db_command(&args, action).map_err(|e| e.to_string())
+        };
+
+        add(&["frob.w dn,dn", "4", "--source", "measured"]).unwrap();
+        assert_eq!(
+            read_database(&path).unwrap().get("frob.w dn,dn"),
+            Some(vec![4])
+        );
+        let error = add(&["frob.w dn,dn", "6"]).unwrap_err();
+        assert!(error.contains("use --replace to change it"), "{}", error);
+        add(&["frob.w dn,dn", "6", "--replace"]).unwrap();
+        assert_eq!(
+            read_database(&path).unwrap().get("frob.w dn,dn"),
+            Some(vec![6])
+        );
+
+        let error = add(&["add.w dn,dn", "4"]).unwrap_err();
+        assert!(
+            error.contains("already in the bundled database"),
+            "{}",
+            error
+        );
+        assert_eq!(
+            add(&["move.w d0,d1", "4"]).unwrap_err(),
+            "Invalid key: 'move.w d0,d1' is not in normalized form and unreachable"
+        );
+        let error = add(&["frob.l dn,dn", "4", "--kind", "maybe"]).unwrap_err();
+        assert!(error.starts_with("Unknown kind 'maybe'"), "{}", error);
+        assert_eq!(read_database(&path).unwrap().len(), 1);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_lookup_reports_base_per_register_and_total_cycles() {
+        let mut db = CycleDatabase::builtin();
+        assert_eq!(
+            lookup_report(&db, "movem.l d0-d7/a0-a6,-(sp)").unwrap(),
+            "normalized    movem.l reglist,-(an)\n\
+             base          8\n\
+             per register  8 x 15 register(s)\n\
+             total         128\n"
+        );
+        assert_eq!(
+            lookup_report(&db, "move.w d0,d1").unwrap(),
+            "normalized    move.w dn,dn\n\
+             base          4\n\
+             per register  -\n\
+             total         4\n"
+        );
+        apply_adjustments("adjust \"move.w dn,dn\" +4", &mut db).unwrap();
+        assert!(
+            lookup_report(&db, "move.w d0,d1")
+                .unwrap()
+                .ends_with("total         8 (adj)\n")
+        );
+        assert_eq!(
+            lookup_report(&db, "frobnicate d0").unwrap_err().to_string(),
+            "'frobnicate d0' (normalized 'frobnicate.w dn') is not in the cycle database"
+        );
+    }
+
+    #[test]
+    fn test_args_are_consistent() {
+        Args::command().debug_assert();
+    }
+
+    #[test]
+    fn test_every_subcommand_parses_with_the_shared_flags() {
+        for argv in SUBCOMMANDS {
+            let args = parse(argv).unwrap_or_else(|e| panic!("{:?}: {}", argv, e));
+            assert_eq!(args.input, PathBuf::from("effect.s"));
+            assert_eq!(args.cycles, 508);
+        }
+    }
+
+    #[test]
+    fn test_split_owns_the_split_flags() {
+        let args = parse(&["split", "--loop", "L", "--packer", "optimal", "-o", "out.s"]).unwrap();
+        let Some(Command::Split(split)) = &args.command else {
+            panic!("not parsed as split: {:?}", args.command);
+        };
+        assert_eq!(split.loop_label.as_deref(), Some("L"));
+        assert_eq!(split.packer, "optimal");
+        assert_eq!(split.output, Some(PathBuf::from("out.s")));
+        assert_eq!(args.split().packer, "optimal");
+    }
+
+    #[test]
+    fn test_no_subcommand_splits_with_the_defaults() {
+        let args = parse(&[]).unwrap();
+        assert!(args.command.is_none());
+        let split = args.split();
+        assert_eq!(split.packer, "greedy");
+        assert_eq!(split.loop_label, None);
+        assert_eq!(split.label, DEFAULT_LABEL);
+        assert_eq!(split.format, "asm");
+    }
+
+    #[test]
+    fn test_subcommands_reject_the_split_flags() {
+        for argv in &SUBCOMMANDS[1..] {
+            let argv = [*argv, &["--packer", "greedy"]].concat();
+            let e = parse(&argv)
+                .err()
+                .unwrap_or_else(|| panic!("{:?} accepted", argv));
+            assert_eq!(e.kind(), ErrorKind::UnknownArgument, "{:?}", argv);
+        }
+        // The split flags are not global either
+        for argv in [&["--packer", "greedy", "stats"][..], &["--loop", "L"]] {
+            assert_eq!(parse(argv).unwrap_err().kind(), ErrorKind::UnknownArgument);
+        }
+    }
+
+    #[test]
+    fn test_split_rejects_the_flags_of_other_subcommands() {
+        for flag in ["--json", "--compact", "--missing", "--template-dir"] {
+            let e = parse(&["split", flag]).unwrap_err();
+            assert_eq!(e.kind(), ErrorKind::UnknownArgument, "{}", flag);
+        }
+    }
+}